@@ -0,0 +1,438 @@
+//! Static numbers about a stylesheet bundle: how big it is, how many rules and
+//! selectors it has, how specific they are, how much of it is `!important`,
+//! and which selectors are likely to be slow to match.
+//!
+//! Selectors are matched right to left, so the cost of a selector mostly
+//! depends on its last compound (the key) and on how far up the tree the
+//! browser has to look when the key matches. The patterns flagged here are
+//! heuristics, not measurements; `bench` measures.
+
+use crate::compat::outline;
+use crate::css_format::split_top_level;
+use crate::css_imports::{is_ident_byte, skip_string, ResolvedStylesheet};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+
+/// Descendant combinators a selector may chain before it is flagged.
+const MAX_DESCENDANTS: usize = 3;
+
+/// Specificity as `(ids, classes, types)`; compares like the cascade does.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Specificity(pub u32, pub u32, pub u32);
+
+impl Specificity {
+    /// Parse `a,b,c`.
+    pub fn parse(text: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let parts: Vec<&str> = text.split(',').map(str::trim).collect();
+        match parts.as_slice() {
+            [a, b, c] => Ok(Specificity(a.parse()?, b.parse()?, c.parse()?)),
+            _ => Err(format!("Invalid specificity '{}', expected a,b,c", text).into()),
+        }
+    }
+
+    fn add(&mut self, other: Specificity) {
+        self.0 += other.0;
+        self.1 += other.1;
+        self.2 += other.2;
+    }
+}
+
+impl fmt::Display for Specificity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{},{},{}", self.0, self.1, self.2)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ExpensivePattern {
+    /// The key is `*` or only pseudo-classes, behind a combinator: every
+    /// element in the window has its ancestors or siblings checked.
+    UniversalKey,
+    /// `:has()` is matched again whenever the elements it looks at change.
+    Has,
+    /// A long chain of descendant combinators.
+    DeepDescendants,
+    /// `[attr*=value]` searches the whole attribute value.
+    SubstringAttribute,
+}
+
+impl ExpensivePattern {
+    fn describe(&self) -> &'static str {
+        match self {
+            ExpensivePattern::UniversalKey => "universal key selector is checked on every element",
+            ExpensivePattern::Has => ":has() is re-matched whenever its subject's contents change",
+            ExpensivePattern::DeepDescendants => "long descendant chain",
+            ExpensivePattern::SubstringAttribute => "substring attribute match",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ExpensiveSelector {
+    pub selector: String,
+    pub pattern: ExpensivePattern,
+    /// 1-based line in the bundled stylesheet.
+    pub line: usize,
+    /// `file:line` in the original sources.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SpecificityCount {
+    pub specificity: Specificity,
+    pub selectors: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AnalyzeReport {
+    /// Size of the bundle with its imports inlined.
+    pub bytes: usize,
+    pub files: usize,
+    pub rules: usize,
+    pub selectors: usize,
+    pub declarations: usize,
+    pub important: usize,
+    /// Selectors per specificity, most specific first.
+    pub specificity: Vec<SpecificityCount>,
+    /// The most specific selector.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub most_specific: Option<String>,
+    pub expensive: Vec<ExpensiveSelector>,
+}
+
+impl AnalyzeReport {
+    pub fn max_specificity(&self) -> Option<Specificity> {
+        self.specificity.first().map(|count| count.specificity)
+    }
+
+    pub fn to_text(&self) -> String {
+        let percent = |n: usize, of: usize| (n * 100).checked_div(of).unwrap_or(0);
+        let mut text = format!(
+            "Size           {:.1} KiB ({} bytes, {} files)\n\
+             Rules          {}\n\
+             Selectors      {}\n\
+             Declarations   {}\n\
+             !important     {} ({}% of declarations)\n",
+            self.bytes as f64 / 1024.0,
+            self.bytes,
+            self.files,
+            self.rules,
+            self.selectors,
+            self.declarations,
+            self.important,
+            percent(self.important, self.declarations),
+        );
+
+        if let (Some(max), Some(selector)) = (self.max_specificity(), &self.most_specific) {
+            text.push_str(&format!("Specificity    highest {} ({})\n", max, selector));
+            let mut common: Vec<&SpecificityCount> = self.specificity.iter().collect();
+            common.sort_by_key(|count| std::cmp::Reverse(count.selectors));
+            for count in common.iter().take(8) {
+                text.push_str(&format!(
+                    "  {:<12} {:>5}  {:>3}%\n",
+                    count.specificity.to_string(),
+                    count.selectors,
+                    percent(count.selectors, self.selectors)
+                ));
+            }
+        }
+
+        if !self.expensive.is_empty() {
+            text.push_str(&format!("Expensive      {}\n", self.expensive.len()));
+            for found in &self.expensive {
+                let location = found
+                    .location
+                    .clone()
+                    .unwrap_or_else(|| format!("line {}", found.line));
+                text.push_str(&format!(
+                    "  {}: {} ({})\n",
+                    location,
+                    found.selector,
+                    found.pattern.describe()
+                ));
+            }
+        }
+        text
+    }
+}
+
+/// Limits for using `analyze` as a CI gate. Unset limits are not checked.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct AnalyzeThresholds {
+    pub max_bytes: Option<usize>,
+    pub max_selectors: Option<usize>,
+    pub max_important: Option<usize>,
+    pub max_specificity: Option<Specificity>,
+    pub max_expensive: Option<usize>,
+}
+
+impl AnalyzeThresholds {
+    /// Overwrite the limits set in `other`.
+    pub fn merge(&mut self, other: AnalyzeThresholds) {
+        self.max_bytes = other.max_bytes.or(self.max_bytes);
+        self.max_selectors = other.max_selectors.or(self.max_selectors);
+        self.max_important = other.max_important.or(self.max_important);
+        self.max_specificity = other.max_specificity.or(self.max_specificity);
+        self.max_expensive = other.max_expensive.or(self.max_expensive);
+    }
+
+    /// Every limit `report` exceeds, described.
+    pub fn violations(&self, report: &AnalyzeReport) -> Vec<String> {
+        let mut violations = Vec::new();
+        let mut check = |name: &str, value: usize, max: Option<usize>| {
+            if let Some(max) = max.filter(|&max| value > max) {
+                violations.push(format!("{} {} exceeds {}", name, value, max));
+            }
+        };
+        check("size", report.bytes, self.max_bytes);
+        check("selectors", report.selectors, self.max_selectors);
+        check("!important", report.important, self.max_important);
+        check(
+            "expensive selectors",
+            report.expensive.len(),
+            self.max_expensive,
+        );
+        if let (Some(max), Some(highest)) = (self.max_specificity, report.max_specificity()) {
+            if highest > max {
+                violations.push(format!("specificity {} exceeds {}", highest, max));
+            }
+        }
+        violations
+    }
+}
+
+/// Analyze a resolved stylesheet. Locations are reported relative to `root`
+/// when given.
+pub fn analyze(resolved: &ResolvedStylesheet, root: Option<&Path>) -> AnalyzeReport {
+    let outline = outline(&resolved.css);
+    let mut distribution: BTreeMap<Specificity, usize> = BTreeMap::new();
+    let mut most_specific: Option<(Specificity, &str)> = None;
+    let mut expensive = Vec::new();
+
+    for (selector, line) in &outline.selectors {
+        let specificity = specificity(selector);
+        *distribution.entry(specificity).or_default() += 1;
+        if most_specific.is_none_or(|(max, _)| specificity > max) {
+            most_specific = Some((specificity, selector));
+        }
+        for pattern in expensive_patterns(selector) {
+            expensive.push(ExpensiveSelector {
+                selector: selector.clone(),
+                pattern,
+                line: *line,
+                location: resolved.source_map.describe(*line, root),
+            });
+        }
+    }
+
+    AnalyzeReport {
+        bytes: resolved.css.len(),
+        files: resolved.files.len(),
+        rules: outline.rules,
+        selectors: outline.selectors.len(),
+        declarations: outline.declarations.len(),
+        important: outline.important.len(),
+        specificity: distribution
+            .into_iter()
+            .rev()
+            .map(|(specificity, selectors)| SpecificityCount {
+                specificity,
+                selectors,
+            })
+            .collect(),
+        most_specific: most_specific.map(|(_, selector)| selector.to_string()),
+        expensive,
+    }
+}
+
+/// The specificity of a single (not comma-separated) selector.
+pub fn specificity(selector: &str) -> Specificity {
+    let bytes = selector.as_bytes();
+    let mut total = Specificity::default();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'#' => {
+                total.0 += 1;
+                i = skip_ident(bytes, i + 1);
+            }
+            b'.' => {
+                total.1 += 1;
+                i = skip_ident(bytes, i + 1);
+            }
+            b'[' => {
+                total.1 += 1;
+                i = skip_brackets(bytes, i);
+            }
+            b':' => {
+                let element = bytes.get(i + 1) == Some(&b':');
+                let start = if element { i + 2 } else { i + 1 };
+                let end = skip_ident(bytes, start);
+                let name = selector[start..end].to_ascii_lowercase();
+                let (argument, next) = if bytes.get(end) == Some(&b'(') {
+                    let close = skip_brackets(bytes, end);
+                    (Some(&selector[end + 1..close - 1]), close)
+                } else {
+                    (None, end)
+                };
+                i = next;
+
+                if element
+                    || matches!(
+                        name.as_str(),
+                        "before" | "after" | "first-line" | "first-letter"
+                    )
+                {
+                    total.2 += 1;
+                    continue;
+                }
+                match (name.as_str(), argument) {
+                    ("where", _) => {}
+                    ("is" | "not" | "has" | "matches" | "-moz-any", Some(argument)) => {
+                        total.add(
+                            split_top_level(argument, ',')
+                                .into_iter()
+                                .map(specificity)
+                                .max()
+                                .unwrap_or_default(),
+                        );
+                    }
+                    _ => total.1 += 1,
+                }
+            }
+            b'"' | b'\'' => i = skip_string(bytes, i),
+            b'\\' => {
+                total.2 += 1;
+                i = skip_ident(bytes, i);
+            }
+            b if is_ident_byte(b) => {
+                let end = skip_ident(bytes, i);
+                // `ns|type` counts once, as the type
+                if bytes.get(end) != Some(&b'|') {
+                    total.2 += 1;
+                }
+                i = end;
+            }
+            _ => i += 1,
+        }
+    }
+    total
+}
+
+/// The expensive patterns a selector uses, each once.
+fn expensive_patterns(selector: &str) -> Vec<ExpensivePattern> {
+    let mut patterns = Vec::new();
+    let compounds = compounds(selector);
+    let descendants = compounds
+        .iter()
+        .filter(|(combinator, _)| *combinator == Some(' '))
+        .count();
+
+    if let Some((_, key)) = compounds.last() {
+        if compounds.len() > 1 && is_universal(key) {
+            patterns.push(ExpensivePattern::UniversalKey);
+        }
+    }
+    if selector.to_ascii_lowercase().contains(":has(") {
+        patterns.push(ExpensivePattern::Has);
+    }
+    if descendants > MAX_DESCENDANTS {
+        patterns.push(ExpensivePattern::DeepDescendants);
+    }
+    if selector.contains("*=") {
+        patterns.push(ExpensivePattern::SubstringAttribute);
+    }
+    patterns
+}
+
+/// Split a selector into compounds, each with the combinator before it.
+fn compounds(selector: &str) -> Vec<(Option<char>, &str)> {
+    let bytes = selector.trim().as_bytes();
+    let selector = selector.trim();
+    let mut compounds = Vec::new();
+    let mut combinator = None;
+    let mut start = 0;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'(' | b'[' => {
+                i = skip_brackets(bytes, i);
+                continue;
+            }
+            b'"' | b'\'' => {
+                i = skip_string(bytes, i);
+                continue;
+            }
+            b'\\' => i += 1,
+            b' ' | b'\t' | b'\n' | b'\r' | b'>' | b'+' | b'~' => {
+                if start < i {
+                    compounds.push((combinator, &selector[start..i]));
+                    combinator = Some(' ');
+                }
+                if !bytes[i].is_ascii_whitespace() {
+                    combinator = Some(bytes[i] as char);
+                }
+                start = i + 1;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    if start < bytes.len() {
+        compounds.push((combinator, &selector[start..]));
+    }
+    compounds
+}
+
+/// Whether a compound has nothing to narrow it down but `*` or pseudo-classes.
+fn is_universal(compound: &str) -> bool {
+    let compound = compound.trim_start_matches('*').to_ascii_lowercase();
+    let forwards = [":is(", ":where(", ":matches(", ":-moz-any("];
+    compound.is_empty()
+        || (compound.starts_with(':') && !forwards.iter().any(|f| compound.starts_with(f)))
+}
+
+fn skip_ident(bytes: &[u8], mut i: usize) -> usize {
+    while i < bytes.len() {
+        if bytes[i] == b'\\' {
+            i += 2;
+        } else if is_ident_byte(bytes[i]) {
+            i += 1;
+        } else {
+            break;
+        }
+    }
+    i.min(bytes.len())
+}
+
+/// The offset past the bracket or parenthesis opened at `start`.
+fn skip_brackets(bytes: &[u8], start: usize) -> usize {
+    let mut depth = 0usize;
+    let mut i = start;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'"' | b'\'' => {
+                i = skip_string(bytes, i);
+                continue;
+            }
+            b'\\' => i += 1,
+            b'(' | b'[' => depth += 1,
+            b')' | b']' => {
+                depth -= 1;
+                if depth == 0 {
+                    return i + 1;
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    bytes.len()
+}
@@ -0,0 +1,88 @@
+//! Firefox forks the tool knows how to drive.
+//!
+//! Forks keep most of Firefox's chrome, but may rename the main window type,
+//! add their own theming hooks or listen on another port. A profile collects
+//! those differences. [`AppProfile::detect`] picks one from the connected
+//! browser's name; `--app` picks one by hand.
+
+use crate::marionette_client::BrowserInfo;
+use crate::targets::{ThemingTarget, ZEN_TARGETS};
+
+#[derive(Debug, Clone)]
+pub struct AppProfile {
+    /// Short name used with `--app`, e.g. `zen`.
+    pub name: &'static str,
+    /// `windowtype` of the main browser window, used to look it up with
+    /// `Services.wm`.
+    pub window_type: &'static str,
+    /// The Marionette port the browser uses unless configured otherwise.
+    pub default_port: u16,
+    /// Theming hooks of the fork, checked by `targets` next to Firefox's own.
+    pub extra_targets: &'static [ThemingTarget],
+}
+
+const FIREFOX: AppProfile = AppProfile {
+    name: "firefox",
+    window_type: "navigator:browser",
+    default_port: 2828,
+    extra_targets: &[],
+};
+
+const PROFILES: &[AppProfile] = &[
+    FIREFOX,
+    AppProfile {
+        name: "floorp",
+        ..FIREFOX
+    },
+    AppProfile {
+        name: "librewolf",
+        ..FIREFOX
+    },
+    AppProfile {
+        name: "waterfox",
+        ..FIREFOX
+    },
+    AppProfile {
+        name: "zen",
+        extra_targets: ZEN_TARGETS,
+        ..FIREFOX
+    },
+];
+
+impl Default for AppProfile {
+    fn default() -> Self {
+        FIREFOX
+    }
+}
+
+impl AppProfile {
+    pub fn names() -> Vec<&'static str> {
+        PROFILES.iter().map(|profile| profile.name).collect()
+    }
+
+    pub fn named(name: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        PROFILES
+            .iter()
+            .find(|profile| profile.name.eq_ignore_ascii_case(name))
+            .cloned()
+            .ok_or_else(|| {
+                format!(
+                    "Unknown app '{}' (expected one of: {})",
+                    name,
+                    Self::names().join(", ")
+                )
+                .into()
+            })
+    }
+
+    /// The profile for the browser `info` describes, from its application
+    /// name; plain Firefox for browsers not listed.
+    pub fn detect(info: &BrowserInfo) -> Self {
+        let name = info.name.to_lowercase();
+        PROFILES
+            .iter()
+            .find(|profile| name.starts_with(profile.name))
+            .cloned()
+            .unwrap_or_default()
+    }
+}
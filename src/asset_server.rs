@@ -0,0 +1,129 @@
+//! A static file server for theme assets under development, for
+//! `serve-assets`.
+//!
+//! CSS can refer to `http://localhost:8900/icons/back.svg` while an icon is
+//! being reworked, instead of registering a chrome.manifest for it. Every
+//! response is sent with `Cache-Control: no-store`, so the browser fetches the
+//! file again each time the sheet is reloaded. `export --served-dir` turns
+//! those URLs back into local files for release.
+//!
+//! Requests are handled one at a time, which is plenty for one browser.
+
+use crate::css_assets::mime_type;
+use crate::paths::canonicalize;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Component, Path, PathBuf};
+
+pub const DEFAULT_PORT: u16 = 8900;
+
+/// The URL prefix assets are served under on `port`.
+pub fn served_url(port: u16) -> String {
+    format!("http://localhost:{}/", port)
+}
+
+pub struct AssetServer {
+    root: PathBuf,
+    listener: TcpListener,
+}
+
+impl AssetServer {
+    /// Serve the files under `root` on `port`, reachable from this machine
+    /// only.
+    pub fn bind(root: &Path, port: u16) -> Result<Self, Box<dyn std::error::Error>> {
+        let root = canonicalize(root)?;
+        if !root.is_dir() {
+            return Err(format!("Not a directory: {}", root.display()).into());
+        }
+        let listener = TcpListener::bind(("127.0.0.1", port))
+            .map_err(|e| format!("Cannot listen on port {}: {}", port, e))?;
+        Ok(AssetServer { root, listener })
+    }
+
+    pub fn url(&self) -> Result<String, Box<dyn std::error::Error>> {
+        Ok(served_url(self.listener.local_addr()?.port()))
+    }
+
+    /// Answer requests until the process is stopped, printing one line per
+    /// request.
+    pub fn run(&self) -> Result<(), Box<dyn std::error::Error>> {
+        for stream in self.listener.incoming() {
+            match stream.map(|stream| self.respond(stream)) {
+                Ok(Ok(line)) => println!("{}", line),
+                Ok(Err(e)) | Err(e) => eprintln!("Warning: {}", e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Answer one request. Returns the line to log.
+    fn respond(&self, mut stream: TcpStream) -> std::io::Result<String> {
+        let mut reader = BufReader::new(&stream);
+        let mut request = String::new();
+        reader.read_line(&mut request)?;
+        // The headers say nothing a static server needs
+        let mut header = String::new();
+        while reader.read_line(&mut header)? > 2 {
+            header.clear();
+        }
+
+        let mut parts = request.split_whitespace();
+        let method = parts.next().unwrap_or("");
+        let target = parts.next().unwrap_or("/");
+        let (status, body, content_type) = match method {
+            "GET" | "HEAD" => match self.file_for(target) {
+                Some(file) => match fs::read(&file) {
+                    Ok(body) => ("200 OK", body, mime_type(&file)),
+                    Err(_) => ("404 Not Found", Vec::new(), "text/plain"),
+                },
+                None => ("404 Not Found", Vec::new(), "text/plain"),
+            },
+            _ => ("405 Method Not Allowed", Vec::new(), "text/plain"),
+        };
+
+        write!(
+            stream,
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nAccess-Control-Allow-Origin: *\r\nConnection: close\r\n\r\n",
+            status,
+            content_type,
+            body.len()
+        )?;
+        if method != "HEAD" {
+            stream.write_all(&body)?;
+        }
+        Ok(format!("{} {} {}", method, target, status))
+    }
+
+    /// The file a request target names, if it is a file under the root.
+    fn file_for(&self, target: &str) -> Option<PathBuf> {
+        let path = target.split(['?', '#']).next().unwrap_or(target);
+        let path = percent_decode(path)?;
+        let relative = Path::new(path.trim_start_matches('/'));
+        if relative
+            .components()
+            .any(|c| !matches!(c, Component::Normal(_)))
+        {
+            return None;
+        }
+        let file = canonicalize(&self.root.join(relative)).ok()?;
+        (file.starts_with(&self.root) && file.is_file()).then_some(file)
+    }
+}
+
+fn percent_decode(path: &str) -> Option<String> {
+    let bytes = path.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = path.get(i + 1..i + 3)?;
+            out.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(out).ok()
+}
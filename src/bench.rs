@@ -0,0 +1,155 @@
+//! Measure what a theme costs the browser UI: the same window operations are
+//! timed without the sheet and with it, and the difference is its cost.
+//!
+//! Each operation is followed by a forced style flush, layout flush and layer
+//! update, timed separately in the chrome context. The numbers are medians,
+//! which makes them stable enough to compare a theme against the baseline on
+//! the same machine, not across machines.
+
+use crate::chrome_css_manager::ChromeCSSManager;
+use crate::chrome_js;
+use crate::marionette_client::MarionetteConnection;
+use serde::Serialize;
+use serde_json::Value;
+use std::path::Path;
+
+const BENCH_SHEET_ID: &str = "mus-uc-bench";
+
+/// Median milliseconds spent in each phase after an operation.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct PhaseTimings {
+    pub style: f64,
+    pub layout: f64,
+    pub paint: f64,
+}
+
+impl PhaseTimings {
+    pub fn total(&self) -> f64 {
+        self.style + self.layout + self.paint
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OperationResult {
+    pub name: String,
+    pub baseline: PhaseTimings,
+    pub theme: PhaseTimings,
+}
+
+impl OperationResult {
+    /// How much slower the operation is with the theme, in percent.
+    pub fn change_percent(&self) -> f64 {
+        let baseline = self.baseline.total();
+        if baseline == 0.0 {
+            return 0.0;
+        }
+        (self.theme.total() - baseline) / baseline * 100.0
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BenchReport {
+    pub iterations: u32,
+    pub operations: Vec<OperationResult>,
+}
+
+impl BenchReport {
+    pub fn to_text(&self) -> String {
+        let mut text = format!(
+            "{:<16} {:>10} {:>10} {:>8}   (median of {} runs, ms: style + layout + paint)\n",
+            "Operation", "Baseline", "Theme", "Change", self.iterations
+        );
+        for op in &self.operations {
+            text.push_str(&format!(
+                "{:<16} {:>10.2} {:>10.2} {:>+7.0}%   style {:.2} -> {:.2}, layout {:.2} -> {:.2}, paint {:.2} -> {:.2}\n",
+                op.name,
+                op.baseline.total(),
+                op.theme.total(),
+                op.change_percent(),
+                op.baseline.style,
+                op.theme.style,
+                op.baseline.layout,
+                op.theme.layout,
+                op.baseline.paint,
+                op.theme.paint,
+            ));
+        }
+        text
+    }
+}
+
+/// Time the operations with the browser as it is, then with `path` loaded,
+/// and unload it again.
+pub fn bench_file(
+    manager: &mut ChromeCSSManager,
+    path: &Path,
+    iterations: u32,
+) -> Result<BenchReport, Box<dyn std::error::Error>> {
+    let baseline = manager
+        .connection()
+        .with(|c| measure_operations(c, iterations))?;
+    manager.load_css_file(path, Some(BENCH_SHEET_ID))?;
+    let theme = manager
+        .connection()
+        .with(|c| measure_operations(c, iterations));
+    manager.unload_css(BENCH_SHEET_ID)?;
+    let theme = theme?;
+
+    let operations = baseline
+        .into_iter()
+        .zip(theme)
+        .map(|((name, baseline), (_, theme))| OperationResult {
+            name,
+            baseline,
+            theme,
+        })
+        .collect();
+    Ok(BenchReport {
+        iterations,
+        operations,
+    })
+}
+
+/// Run each operation `iterations` times in the most recent browser window.
+/// The connection must be in the chrome context.
+pub fn measure_operations(
+    connection: &mut MarionetteConnection,
+    iterations: u32,
+) -> Result<Vec<(String, PhaseTimings)>, Box<dyn std::error::Error>> {
+    let window_type = connection.app_profile()?.window_type;
+    let script = chrome_js::render(
+        chrome_js::BENCH,
+        &[
+            ("windowType", Value::from(window_type)),
+            ("iterations", Value::from(iterations)),
+        ],
+    );
+    let result = connection.execute_script(&script, None)?;
+    let runs: Vec<(String, Vec<[f64; 3]>)> = serde_json::from_value(result)
+        .map_err(|e| format!("Unexpected timings from Firefox: {}", e))?;
+    Ok(runs
+        .into_iter()
+        .map(|(name, timings)| {
+            let phase = |i: usize| median(timings.iter().map(|t| t[i]).collect());
+            let medians = PhaseTimings {
+                style: phase(0),
+                layout: phase(1),
+                paint: phase(2),
+            };
+            (name, medians)
+        })
+        .collect())
+}
+
+fn median(mut values: Vec<f64>) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    values.sort_by(|a, b| a.total_cmp(b));
+    let mid = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        (values[mid - 1] + values[mid]) / 2.0
+    } else {
+        values[mid]
+    }
+}
@@ -0,0 +1,184 @@
+//! A [`Connection`] over WebDriver BiDi, the WebSocket protocol that newer
+//! Firefox and geckodriver stacks favour over classic Marionette.
+//!
+//! Firefox serves BiDi when started with `--remote-debugging-port 9222`. The
+//! client opens a session on `ws://host:port/session` and runs scripts with
+//! `script.callFunction` in a browser window, found with
+//! `browsingContext.getTree` and Firefox's `moz:scope` extension. Chrome
+//! windows are only listed with the `remote.experimental.enabled` pref set.
+//!
+//! Results come back as JSON, and a returned promise is awaited. Marionette
+//! commands are not available.
+
+use crate::connection::{Connection, Stream};
+use serde_json::{json, Value};
+use std::time::Duration;
+use tungstenite::client::IntoClientRequest;
+use tungstenite::{Message, WebSocket};
+
+pub struct BidiClient {
+    socket: WebSocket<Stream>,
+    next_id: u64,
+    chrome: String,
+    content: Option<String>,
+    /// The browsing context scripts run in.
+    context: String,
+    /// From `session.new`.
+    capabilities: Value,
+}
+
+impl BidiClient {
+    /// Open a session over `stream`. `host` is only sent in the WebSocket
+    /// handshake, where Firefox checks it against `remote.allowed-hosts`.
+    pub(crate) fn handshake(
+        stream: Stream,
+        host: &str,
+        port: u16,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        stream.set_timeouts(Some(Duration::from_secs(60)))?;
+        let request = format!("ws://{}:{}/session", host, port).into_client_request()?;
+        let (socket, _) = tungstenite::client(request, stream)
+            .map_err(|e| format!("WebDriver BiDi handshake failed: {}", e))?;
+        let mut client = BidiClient {
+            socket,
+            next_id: 0,
+            chrome: String::new(),
+            content: None,
+            context: String::new(),
+            capabilities: Value::Null,
+        };
+
+        let session = client.command("session.new", json!({ "capabilities": {} }))?;
+        client.capabilities = session["capabilities"].clone();
+        let tree = client.command("browsingContext.getTree", json!({ "moz:scope": "chrome" }))?;
+        let windows = tree["contexts"].as_array().cloned().unwrap_or_default();
+        let window = windows
+            .iter()
+            .find(|w| {
+                w["url"]
+                    .as_str()
+                    .is_some_and(|url| url.starts_with("chrome://browser/"))
+            })
+            .or(windows.first())
+            .and_then(|w| w["context"].as_str())
+            .ok_or("No browser window over WebDriver BiDi; is remote.experimental.enabled set?")?;
+        client.chrome = window.to_string();
+        client.context = client.chrome.clone();
+        Ok(client)
+    }
+
+    /// Send a command and wait for its result, skipping events.
+    fn command(
+        &mut self,
+        method: &str,
+        params: Value,
+    ) -> Result<Value, Box<dyn std::error::Error>> {
+        self.next_id += 1;
+        let id = self.next_id;
+        let packet = json!({ "id": id, "method": method, "params": params });
+        self.socket.send(Message::text(packet.to_string()))?;
+        loop {
+            let text = match self.socket.read()? {
+                Message::Text(text) => text,
+                Message::Close(_) => return Err("Firefox closed the WebDriver BiDi session".into()),
+                _ => continue,
+            };
+            let reply: Value = serde_json::from_str(&text)?;
+            if reply["id"] != id {
+                continue;
+            }
+            if reply["type"] == "error" {
+                return Err(format!(
+                    "WebDriver BiDi error: {}: {}",
+                    reply["error"].as_str().unwrap_or("unknown error"),
+                    reply["message"].as_str().unwrap_or("")
+                )
+                .into());
+            }
+            return Ok(reply["result"].clone());
+        }
+    }
+
+    /// The first top-level tab, looked up once.
+    fn content_context(&mut self) -> Result<String, Box<dyn std::error::Error>> {
+        if let Some(context) = &self.content {
+            return Ok(context.clone());
+        }
+        let tree = self.command("browsingContext.getTree", json!({ "maxDepth": 0 }))?;
+        let context = tree["contexts"][0]["context"]
+            .as_str()
+            .ok_or("Firefox has no open tab")?
+            .to_string();
+        self.content = Some(context.clone());
+        Ok(context)
+    }
+}
+
+impl Connection for BidiClient {
+    fn capabilities(&self) -> Option<&Value> {
+        Some(&self.capabilities).filter(|caps| caps.is_object())
+    }
+
+    fn set_context(&mut self, context: &str) -> Result<(), Box<dyn std::error::Error>> {
+        self.context = match context {
+            "chrome" => self.chrome.clone(),
+            "content" => self.content_context()?,
+            other => return Err(format!("Unknown context: {}", other).into()),
+        };
+        Ok(())
+    }
+
+    fn execute_script(
+        &mut self,
+        script: &str,
+        args: Option<Vec<Value>>,
+    ) -> Result<Value, Box<dyn std::error::Error>> {
+        // Run the script like Marionette does, and bring the result back as
+        // JSON text rather than BiDi's serialized remote values
+        let function = format!(
+            "async function () {{\n\
+             const value = await (function () {{\n{}\n}}).apply(null, {});\n\
+             return JSON.stringify({{ value }});\n\
+             }}",
+            script,
+            Value::Array(args.unwrap_or_default())
+        );
+        let result = self.command(
+            "script.callFunction",
+            json!({
+                "functionDeclaration": function,
+                "awaitPromise": true,
+                "target": { "context": self.context },
+            }),
+        )?;
+        if result["type"] == "exception" {
+            let text = result["exceptionDetails"]["text"]
+                .as_str()
+                .unwrap_or("unknown error");
+            return Err(format!("Script error: {}", text).into());
+        }
+        let json = result["result"]["value"]
+            .as_str()
+            .ok_or("Unexpected script result over WebDriver BiDi")?;
+        let value: Value = serde_json::from_str(json)?;
+        Ok(value.get("value").cloned().unwrap_or(Value::Null))
+    }
+
+    fn send_command(
+        &mut self,
+        name: &str,
+        _params: Value,
+    ) -> Result<Value, Box<dyn std::error::Error>> {
+        Err(format!(
+            "{} needs Marionette; it is not available over WebDriver BiDi",
+            name
+        )
+        .into())
+    }
+}
+
+impl Drop for BidiClient {
+    fn drop(&mut self) {
+        let _ = self.command("session.end", json!({}));
+    }
+}
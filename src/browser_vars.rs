@@ -0,0 +1,82 @@
+//! Placeholders in sheets filled with values from the running browser, for
+//! `load --browser-vars` and `watch --browser-vars`.
+//!
+//! ```css
+//! /* {{firefox.version}} */
+//! :root { --my-accent: {{system.accent}}; }
+//! #nav-bar { padding-block: calc({{dpr}} * 1px); }
+//! ```
+//!
+//! The placeholders look like those of the chrome scripts (see
+//! [`chrome_js`](crate::chrome_js)). Values are read each time the sheet is
+//! loaded, so a reload while watching picks up a changed theme or density.
+
+use crate::chrome_js;
+use crate::marionette_client::MarionetteConnection;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// The variables and their values in the browser behind `connection`. The
+/// connection must be in the chrome context.
+pub fn read_variables(
+    connection: &mut MarionetteConnection,
+) -> Result<BTreeMap<String, String>, Box<dyn std::error::Error>> {
+    let window_type = connection.app_profile()?.window_type;
+    let script = chrome_js::render(
+        chrome_js::BROWSER_VARIABLES,
+        &[("windowType", Value::from(window_type))],
+    );
+    Ok(serde_json::from_value(
+        connection.execute_script(&script, None)?,
+    )?)
+}
+
+/// Replace each `{{name}}` in `css` with its value. Spaces inside the braces
+/// are allowed; `{{` not followed by a name and `}}` is left alone. An
+/// unknown name is an error, naming the line and `source` if given.
+pub fn fill_variables(
+    css: &str,
+    values: &BTreeMap<String, String>,
+    source: Option<&Path>,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let mut out = String::with_capacity(css.len());
+    let mut rest = css;
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let Some((name, tail)) = placeholder(after) else {
+            out.push_str("{{");
+            rest = after;
+            continue;
+        };
+        let Some(value) = values.get(name) else {
+            let line = css[..css.len() - rest.len() + start].matches('\n').count() + 1;
+            let location = match source {
+                Some(path) => format!("{}:{}", path.display(), line),
+                None => format!("line {}", line),
+            };
+            return Err(format!(
+                "Unknown browser variable {{{{{}}}}} at {} (run `vars` to list them)",
+                name, location
+            )
+            .into());
+        };
+        out.push_str(value);
+        rest = tail;
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// The name of the placeholder `text` starts inside of, and what follows
+/// its closing braces.
+fn placeholder(text: &str) -> Option<(&str, &str)> {
+    let end = text.find("}}")?;
+    let name = text[..end].trim();
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'));
+    valid.then(|| (name, &text[end + 2..]))
+}
@@ -1,126 +1,1492 @@
-use crate::chrome_manifest::ChromeManifestRegistrar;
-use crate::marionette_client::{MarionetteConnection, MarionetteSettings};
-use notify::{Event, EventKind, RecursiveMode, Watcher};
-use std::collections::HashMap;
-use std::path::Path;
-use std::sync::mpsc::channel;
+use crate::browser_vars::{fill_variables, read_variables};
+use crate::chrome_js;
+use crate::chrome_manifest::{ChromeManifest, ChromeManifestRegistrar, ManifestEntry};
+use crate::compat::{check_compat, CompatReport};
+use crate::conflicts::{find_conflicts, ConflictReport};
+use crate::console_log::{ConsoleLog, ConsoleRecord};
+use crate::content_pages::PageCheck;
+use crate::css_imports::{CyclePolicy, ImportCache, ImportResolver, ResolvedStylesheet};
+use crate::dev_prefs::{self, PrefStatus};
+use crate::gallery::ScreenshotMeta;
+use crate::hooks::{HookContext, HookEvent, Hooks};
+use crate::marionette_client::{
+    locate_error, MarionetteConnection, MarionetteSettings, ReconnectPolicy, StackFrame,
+};
+use crate::native_capture;
+use crate::notifications::{Notifier, NotifyLevel};
+use crate::op_queue::QueueSettings;
+use crate::paths::canonicalize;
+use crate::persist::{sheet_file, PersistStore};
+use crate::post_process::PostProcess;
+use crate::preprocess::Preprocessor;
+use crate::project_config::{WatchKind, WatchTarget};
+use crate::retry::RetryPolicy;
+use crate::screenshot::{probe_color, take_screenshot, CaptureMode, PixelColor, ProbePoint};
+use crate::shared_connection::SharedConnection;
+use crate::sheet_state::SheetState;
+use crate::source_map::SourceMap;
+use crate::term_status::TermStatus;
+use crate::wait::{wait_for, WaitCondition};
+use crate::watch::{
+    ConsoleLogHandler, Engine, MetricsFile, RollbackPolicy, ScriptHandler, SheetHandler,
+    SheetMetrics,
+};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
+/// A CSS parse error Firefox reported for a loaded sheet.
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct CssError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+/// A sheet as registered in the browser.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct RegisteredSheet {
+    pub css: String,
+    /// The file the sheet was loaded from, if any.
+    pub source: Option<String>,
+    #[serde(rename = "type", default)]
+    pub sheet_type: SheetType,
+}
+
+/// A sheet read back from the browser by [`ChromeCSSManager::pull_sheet`].
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct PulledSheet {
+    pub css: String,
+    /// Whether `css` was serialized from the rules the window applies, which
+    /// drops comments and formatting. Otherwise it is the CSS as registered.
+    pub live: bool,
+}
+
+/// A `data:` sheet found registered in the browser by
+/// [`ChromeCSSManager::scan_sheets`].
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct ScannedSheet {
+    /// The sheet ID from the URI marker; `None` for sheets without one, which
+    /// come from older versions or other tools.
+    pub id: Option<String>,
+    #[serde(rename = "type")]
+    pub sheet_type: SheetType,
+    /// Whether the harness knows the sheet, so `list` and `clear` see it.
+    pub tracked: bool,
+}
+
+/// An operation in the harness's audit trail, from
+/// [`ChromeCSSManager::audit_log`].
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct AuditEntry {
+    /// Milliseconds since the Unix epoch, by the browser's clock.
+    pub time: u64,
+    /// `load`, `unload` or `clear`.
+    pub op: String,
+    /// The sheet loaded or unloaded; `None` for `clear`.
+    pub id: Option<String>,
+    /// How many sheets `clear` removed.
+    pub count: Option<usize>,
+    /// The process that asked, e.g. `mus-uc watch (pid 4242)`; `None` for
+    /// harnesses driven by something other than this tool.
+    pub caller: Option<String>,
+}
+
+/// The outcome of loading one file with [`ChromeCSSManager::load_many`].
+#[derive(Debug)]
+pub struct BulkLoadResult {
+    pub path: PathBuf,
+    /// The registered sheet ID, or why the file could not be loaded.
+    pub result: Result<String, String>,
+    pub resolved: Option<ResolvedStylesheet>,
+}
+
+/// Which nsIStyleSheetService level a sheet is registered at.
+#[derive(Debug, Clone, Copy, PartialEq, Default, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SheetType {
+    /// Like userChrome.css. Matches the browser UI and web content.
+    #[default]
+    User,
+    /// Applies before user sheets and can style native anonymous content
+    /// such as scrollbars.
+    Agent,
+    /// Applies like a document's own stylesheets.
+    Author,
+}
+
+impl SheetType {
+    pub fn parse(name: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        match name {
+            "user" => Ok(SheetType::User),
+            "agent" => Ok(SheetType::Agent),
+            "author" => Ok(SheetType::Author),
+            other => Err(format!(
+                "Unknown sheet type '{}' (expected user, agent or author)",
+                other
+            )
+            .into()),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SheetType::User => "user",
+            SheetType::Agent => "agent",
+            SheetType::Author => "author",
+        }
+    }
+}
+
+/// Configures and connects a [`ChromeCSSManager`], e.g.
+/// `ChromeCSSManager::builder().port(6000).build()?`.
+pub struct ChromeCSSManagerBuilder {
+    settings: MarionetteSettings,
+    initialize: bool,
+    state_dir: Option<PathBuf>,
+    sheet_type: SheetType,
+    preprocessors: Vec<Box<dyn Preprocessor>>,
+    browser_variables: bool,
+    reconnect_policy: ReconnectPolicy,
+    caller: Option<String>,
+    hooks: Hooks,
+}
+
+impl Default for ChromeCSSManagerBuilder {
+    fn default() -> Self {
+        ChromeCSSManagerBuilder {
+            settings: MarionetteSettings::new(),
+            initialize: true,
+            state_dir: None,
+            sheet_type: SheetType::default(),
+            preprocessors: Vec::new(),
+            browser_variables: false,
+            reconnect_policy: ReconnectPolicy::default(),
+            caller: None,
+            hooks: Hooks::default(),
+        }
+    }
+}
+
+impl ChromeCSSManagerBuilder {
+    pub fn settings(mut self, settings: MarionetteSettings) -> Self {
+        self.settings = settings;
+        self
+    }
+
+    pub fn host(mut self, host: &str) -> Self {
+        self.settings.host = host.to_string();
+        self
+    }
+
+    pub fn port(mut self, port: u16) -> Self {
+        self.settings.port = port;
+        self
+    }
+
+    /// Install the chrome harness while building. On by default; without it,
+    /// the harness is installed by the first operation that needs it.
+    pub fn initialize(mut self, initialize: bool) -> Self {
+        self.initialize = initialize;
+        self
+    }
+
+    /// Where sheet metadata is kept instead of [`SheetState::dir`].
+    pub fn state_dir(mut self, dir: &Path) -> Self {
+        self.state_dir = Some(dir.to_path_buf());
+        self
+    }
+
+    /// The level sheets are registered at. [`SheetType::User`] by default.
+    pub fn sheet_type(mut self, sheet_type: SheetType) -> Self {
+        self.sheet_type = sheet_type;
+        self
+    }
+
+    /// Add a preprocessor; they run in the order added.
+    pub fn preprocessor(mut self, preprocessor: impl Preprocessor + 'static) -> Self {
+        self.preprocessors.push(Box::new(preprocessor));
+        self
+    }
+
+    /// Fill `{{name}}` placeholders in sheets with values read from the
+    /// browser as each is loaded, before the preprocessors run. See
+    /// [`browser_vars`](crate::browser_vars).
+    pub fn browser_variables(mut self, enabled: bool) -> Self {
+        self.browser_variables = enabled;
+        self
+    }
+
+    /// How connecting, and reconnecting while watching, handle an unreachable browser.
+    pub fn reconnect_policy(mut self, policy: ReconnectPolicy) -> Self {
+        self.reconnect_policy = policy;
+        self
+    }
+
+    /// How this process is named in the harness's audit trail (see
+    /// [`ChromeCSSManager::audit_log`]). By default the crate name and
+    /// process ID.
+    pub fn caller(mut self, caller: &str) -> Self {
+        self.caller = Some(caller.to_string());
+        self
+    }
+
+    /// Hooks to run at points in the session, from the `[hooks]` table of
+    /// `mus-uc.toml`. `on_connect` runs once the browser is reached.
+    pub fn hooks(mut self, hooks: Hooks) -> Self {
+        self.hooks = hooks;
+        self
+    }
+
+    pub fn build(self) -> Result<ChromeCSSManager, Box<dyn std::error::Error>> {
+        let mut connection = self.reconnect_policy.connect(&self.settings)?;
+        connection.set_context("chrome")?;
+        let mut manager = ChromeCSSManager::new_with_connection(connection);
+        manager.settings = self.settings;
+        manager.state_dir = self.state_dir;
+        manager.sheet_type = self.sheet_type;
+        manager.preprocessors = self.preprocessors;
+        manager.browser_variables = self.browser_variables;
+        manager.reconnect_policy = self.reconnect_policy;
+        if let Some(caller) = self.caller {
+            manager.caller = caller;
+        }
+        manager.hooks = self.hooks;
+        if self.initialize {
+            manager.initialize_chrome_context()?;
+        }
+        manager.run_hooks(HookEvent::Connect, &HookContext::default());
+        Ok(manager)
+    }
+}
+
+/// Bumped whenever the harness methods change, so harnesses injected by
+/// other versions of the tool are upgraded.
+const HARNESS_VERSION: u32 = 8;
+
+/// How often a watch pings the browser unless told otherwise.
+const DEFAULT_HEARTBEAT: Duration = Duration::from_secs(5);
+
+/// How many operations the harness keeps in its audit trail.
+const AUDIT_LIMIT: usize = 200;
+
+/// Key of the value a harness script returns when the harness is missing or
+/// from another version.
+const HARNESS_STATUS: &str = "__musUcHarness";
+
 pub struct ChromeCSSManager {
-    connection: MarionetteConnection,
+    connection: SharedConnection,
+    settings: MarionetteSettings,
+    reconnect_policy: ReconnectPolicy,
+    state_dir: Option<PathBuf>,
+    sheet_type: SheetType,
+    preprocessors: Vec<Box<dyn Preprocessor>>,
+    browser_variables: bool,
+    caller: String,
     loaded_sheets: HashMap<String, String>,
+    source_maps: HashMap<String, SourceMap>,
     manifest_registrar: ChromeManifestRegistrar,
+    cycle_policy: CyclePolicy,
+    notifier: Notifier,
+    term_status: TermStatus,
+    heartbeat: Option<Duration>,
+    poll_interval: Option<Duration>,
+    console_log: Option<ConsoleLog>,
+    profile_dir: Option<PathBuf>,
+    rollback_policy: RollbackPolicy,
+    metrics_file: Option<MetricsFile>,
+    retry_policy: RetryPolicy,
+    post_process: PostProcess,
+    capture_mode: CaptureMode,
+    persist: bool,
+    hooks: Hooks,
+    /// The profile's persisted sheets, or why they cannot be read; read on
+    /// first use.
+    persisted: Option<Result<(PersistStore, Vec<String>), String>>,
 }
 
 impl ChromeCSSManager {
+    /// Connect to Firefox on the default port without installing the
+    /// harness. See [`Self::builder`] for other options.
     pub fn new() -> Result<Self, Box<dyn std::error::Error>> {
-        let mut connection = MarionetteConnection::connect(&MarionetteSettings::new())?;
-        connection.set_context("chrome")?;
-        Ok(Self::new_with_connection(connection))
+        Self::builder().initialize(false).build()
+    }
+
+    pub fn builder() -> ChromeCSSManagerBuilder {
+        ChromeCSSManagerBuilder::default()
     }
 
     pub fn new_with_connection(connection: MarionetteConnection) -> Self {
+        Self::new_with_shared(SharedConnection::new(connection))
+    }
+
+    /// A manager using a connection other managers hold as well. The
+    /// connection must be in the chrome context before sheets are loaded.
+    pub fn new_with_shared(connection: SharedConnection) -> Self {
         ChromeCSSManager {
             connection,
+            settings: MarionetteSettings::new(),
+            reconnect_policy: ReconnectPolicy::default(),
+            state_dir: None,
+            sheet_type: SheetType::default(),
+            preprocessors: Vec::new(),
+            browser_variables: false,
+            caller: format!("{} pid {}", env!("CARGO_PKG_NAME"), std::process::id()),
             loaded_sheets: HashMap::new(),
+            source_maps: HashMap::new(),
             manifest_registrar: ChromeManifestRegistrar::new(),
+            cycle_policy: CyclePolicy::default(),
+            notifier: Notifier::default(),
+            term_status: TermStatus::default(),
+            heartbeat: Some(DEFAULT_HEARTBEAT),
+            poll_interval: None,
+            console_log: None,
+            profile_dir: None,
+            rollback_policy: RollbackPolicy::default(),
+            metrics_file: None,
+            retry_policy: RetryPolicy::default(),
+            post_process: PostProcess::default(),
+            capture_mode: CaptureMode::default(),
+            persist: false,
+            hooks: Hooks::default(),
+            persisted: None,
         }
     }
 
-    pub fn connection_mut(&mut self) -> &mut MarionetteConnection {
-        &mut self.connection
+    /// Choose how circular `@import`s are handled when loading files.
+    pub fn set_cycle_policy(&mut self, policy: CyclePolicy) {
+        self.cycle_policy = policy;
     }
 
-    pub fn initialize_chrome_context(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        let chrome_script = r#"
-            if (typeof window.chromeCssManager === 'undefined') {
-                window.chromeCssManager = {
-                    sheets: new Map(),
-                    sss: Cc["@mozilla.org/content/style-sheet-service;1"]
-                         .getService(Ci.nsIStyleSheetService),
-
-                    load(css, id) {
-                        const sheetId = id || `sheet-${Date.now()}`;
-                        const uri = Services.io.newURI(`data:text/css;charset=utf-8,${encodeURIComponent(css)}`);
-                        
-                        this.sss.loadAndRegisterSheet(uri, this.sss.USER_SHEET);
-                        this.sheets.set(sheetId, uri);
-                        return sheetId;
-                    },
-
-                    unload(id) {
-                        const uri = this.sheets.get(id);
-                        if (!uri) return false;
-
-                        if (this.sss.sheetRegistered(uri, this.sss.USER_SHEET)) {
-                            this.sss.unregisterSheet(uri, this.sss.USER_SHEET);
-                        }
-                        this.sheets.delete(id);
-                        return true;
-                    },
+    /// Which watch events raise desktop notifications. Off by default.
+    pub fn set_notify_level(&mut self, level: NotifyLevel) {
+        self.notifier = Notifier::new(level);
+    }
 
-                    clear() {
-                        for (const id of this.sheets.keys()) {
-                            this.unload(id);
-                        }
-                    }
-                };
+    /// Space out and limit the sheet operations on this manager's connection,
+    /// for every manager sharing it. See [`crate::op_queue`].
+    pub fn set_queue_settings(&mut self, settings: QueueSettings) {
+        self.connection.queue().set_settings(settings);
+    }
+
+    /// Show the state of each reload in the terminal's title and progress
+    /// indicator while watching. Off by default.
+    pub fn set_term_status(&mut self, enabled: bool) {
+        self.term_status = TermStatus::new(enabled);
+    }
+
+    /// How often a watch checks that the browser still answers, so a dead
+    /// connection is found and the reconnect policy applied without waiting
+    /// for the next reload. Every 5 seconds by default; `None` turns it off.
+    pub fn set_heartbeat(&mut self, interval: Option<Duration>) {
+        self.heartbeat = interval;
+    }
+
+    /// Find file changes while watching by reading the files every
+    /// `interval` instead of through the platform's change notifications.
+    /// `None`, the default, polls only where those do not work. See
+    /// [`crate::poll_watcher`].
+    pub fn set_poll_interval(&mut self, interval: Option<Duration>) {
+        self.poll_interval = interval;
+    }
+
+    /// Check that the browser answers, and how long it took.
+    pub fn ping(&self) -> Result<Duration, Box<dyn std::error::Error>> {
+        let started = std::time::Instant::now();
+        self.connection.execute_script("return true;", None)?;
+        Ok(started.elapsed())
+    }
+
+    /// Append browser console messages to `log` while watching.
+    pub fn set_console_log(&mut self, log: ConsoleLog) {
+        self.console_log = Some(log);
+    }
+
+    /// Record a Gecko profile of each reload while watching, saved in `dir`.
+    pub fn set_profile_dir(&mut self, dir: &Path) {
+        self.profile_dir = Some(dir.to_path_buf());
+    }
+
+    pub fn profile_dir(&self) -> Option<&Path> {
+        self.profile_dir.as_deref()
+    }
+
+    /// When a reload while watching is undone. Reloads are never undone by
+    /// default.
+    pub fn set_rollback_policy(&mut self, policy: RollbackPolicy) {
+        self.rollback_policy = policy;
+    }
+
+    pub fn rollback_policy(&self) -> &RollbackPolicy {
+        &self.rollback_policy
+    }
+
+    /// Write the metrics of every watched sheet to `path` as JSON after each
+    /// load while watching.
+    pub fn set_metrics_file(&mut self, path: &Path) {
+        self.metrics_file = Some(MetricsFile::new(path));
+    }
+
+    /// Also write sheets loaded from now on to the profile, so the browser
+    /// loads them at startup. See [`crate::persist`].
+    pub fn set_persist(&mut self, persist: bool) {
+        self.persist = persist;
+    }
+
+    /// How element-dependent operations wait for the UI. No retries by
+    /// default.
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.retry_policy = policy;
+    }
+
+    pub fn retry_policy(&self) -> RetryPolicy {
+        self.retry_policy
+    }
+
+    /// Crop, scale down or recompress what [`Self::save_screenshot`] writes.
+    /// Captures are written as Firefox encoded them by default.
+    pub fn set_post_process(&mut self, post_process: PostProcess) {
+        self.post_process = post_process;
+    }
+
+    /// Have [`Self::screenshot`] captured by Firefox (the default) or by the
+    /// OS, which also gets the native titlebar.
+    pub fn set_capture_mode(&mut self, mode: CaptureMode) {
+        self.capture_mode = mode;
+    }
+
+    /// Replace the hooks given to the builder.
+    pub fn set_hooks(&mut self, hooks: Hooks) {
+        self.hooks = hooks;
+    }
+
+    /// Run the hooks for `event`. See [`crate::hooks`].
+    pub fn run_hooks(&self, event: HookEvent, context: &HookContext) {
+        self.hooks.run(event, context, &self.connection);
+    }
+
+    pub(crate) fn record_metrics(&mut self, metrics: &SheetMetrics) {
+        if let Some(file) = &mut self.metrics_file {
+            if let Err(e) = file.update(metrics) {
+                eprintln!("Could not write the metrics file: {}", e);
             }
-            return "initialized";
-        "#;
+        }
+    }
+
+    pub fn resolve_file(
+        &self,
+        path: &Path,
+    ) -> Result<ResolvedStylesheet, Box<dyn std::error::Error>> {
+        self.resolve_file_with_cache(path, &mut ImportCache::new())
+    }
+
+    pub fn resolve_file_with_cache(
+        &self,
+        path: &Path,
+        cache: &mut ImportCache,
+    ) -> Result<ResolvedStylesheet, Box<dyn std::error::Error>> {
+        ImportResolver::new()
+            .with_cycle_policy(self.cycle_policy)
+            .resolve_with_cache(path, cache)
+    }
+
+    /// The connection this manager uses, for sharing with other managers.
+    pub fn connection(&self) -> &SharedConnection {
+        &self.connection
+    }
+
+    /// Where the browser is reached, e.g. `localhost:2828`.
+    pub fn address(&self) -> String {
+        self.settings.address()
+    }
 
-        self.connection.execute_script(chrome_script, None)?;
+    pub fn reconnect_policy(&self) -> ReconnectPolicy {
+        self.reconnect_policy
+    }
+
+    /// Connect again with the same settings, e.g. after Firefox restarted,
+    /// and reinstall the harness. Sheets the browser lost are not reloaded.
+    pub fn reconnect(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let mut connection = self.reconnect_policy.connect(&self.settings)?;
+        connection.set_context("chrome")?;
+        self.connection.replace(connection)?;
+        self.initialize_chrome_context()?;
+        self.run_hooks(HookEvent::Connect, &HookContext::default());
+        Ok(())
+    }
+
+    /// Restart the browser and connect to the new one. Sheets loaded through
+    /// the connection are gone afterwards, except persisted ones (see
+    /// [`crate::persist`]). Waits for the browser as the reconnect policy
+    /// allows, or up to 30 seconds without one.
+    pub fn restart(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        self.connection
+            .with(|c| {
+                c.send_command(
+                    "Marionette:Quit",
+                    serde_json::json!({ "flags": ["eAttemptQuit", "eRestart"] }),
+                )
+            })
+            .map_err(|e| format!("Could not restart the browser: {}", e))?;
+        // The old browser keeps listening for a moment while it shuts down
+        std::thread::sleep(Duration::from_secs(1));
+        let policy = match self.reconnect_policy {
+            ReconnectPolicy::Never => ReconnectPolicy::Retry {
+                attempts: 60,
+                delay: Duration::from_millis(500),
+            },
+            policy => policy,
+        };
+        let mut connection = policy.connect(&self.settings)?;
+        connection.set_context("chrome")?;
+        self.connection.replace(connection)?;
+        self.run_hooks(HookEvent::Connect, &HookContext::default());
         Ok(())
     }
 
+    /// The directory holding sheet metadata: the one set on the builder, or
+    /// [`SheetState::dir`].
+    pub fn state_dir(&self) -> PathBuf {
+        self.state_dir.clone().unwrap_or_else(SheetState::dir)
+    }
+
+    pub fn sheet_type(&self) -> SheetType {
+        self.sheet_type
+    }
+
+    fn preprocess(
+        &self,
+        css: &str,
+        source: Option<&Path>,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let mut css = css.to_string();
+        if self.browser_variables && css.contains("{{") {
+            let values = self.connection.with(read_variables)?;
+            css = fill_variables(&css, &values, source)?;
+        }
+        self.preprocessors
+            .iter()
+            .try_fold(css, |css, preprocessor| preprocessor.process(css, source))
+    }
+
+    /// Install the chrome harness (`window.chromeCssManager`) unless the window
+    /// already has this version of it. A harness from another version is
+    /// replaced, keeping its sheets. Other operations do this on demand, so
+    /// calling it is only needed to install the harness ahead of time.
+    pub fn initialize_chrome_context(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let script = chrome_js::render(
+            chrome_js::HARNESS,
+            &[
+                ("version", serde_json::Value::from(HARNESS_VERSION)),
+                ("auditLimit", serde_json::Value::from(AUDIT_LIMIT)),
+            ],
+        );
+        self.connection.execute_script(&script, None)?;
+        Ok(())
+    }
+
+    /// Run a script that uses `window.chromeCssManager`, installing the harness
+    /// first if the window lacks it (on first use, or after a browser restart
+    /// wiped it along with every sheet) or upgrading one from another version.
+    /// Operations take their turn in the connection's
+    /// [`OpQueue`](crate::op_queue::OpQueue).
+    fn harness_script(
+        &mut self,
+        script: &str,
+        args: Option<Vec<serde_json::Value>>,
+    ) -> Result<serde_json::Value, Box<dyn std::error::Error>> {
+        let queue = self.connection.queue().clone();
+        queue
+            .run(|| self.run_harness_script(script, args))
+            .map_err(|e| locate_error(e, harness_frame))
+    }
+
+    fn run_harness_script(
+        &mut self,
+        script: &str,
+        args: Option<Vec<serde_json::Value>>,
+    ) -> Result<serde_json::Value, Box<dyn std::error::Error>> {
+        let guard = chrome_js::render(
+            chrome_js::HARNESS_GUARD,
+            &[
+                ("status", serde_json::Value::from(HARNESS_STATUS)),
+                ("version", serde_json::Value::from(HARNESS_VERSION)),
+                ("caller", serde_json::Value::from(self.caller.as_str())),
+            ],
+        );
+        let guarded = format!("{}\n{}", guard, script);
+        let result = self.connection.execute_script(&guarded, args.clone())?;
+        let status = match result.get(HARNESS_STATUS).and_then(|s| s.as_str()) {
+            Some(status) => status.to_string(),
+            None => return Ok(result),
+        };
+
+        if status == "missing" && !self.loaded_sheets.is_empty() {
+            eprintln!("Firefox no longer has the sheets loaded earlier (restarted?)");
+            self.loaded_sheets.clear();
+            self.source_maps.clear();
+        }
+        self.initialize_chrome_context()?;
+        self.connection.execute_script(&guarded, args)
+    }
+
     pub fn load_css(
         &mut self,
         css_content: &str,
         id: Option<&str>,
     ) -> Result<String, Box<dyn std::error::Error>> {
-        let id_param = id.map(|s| format!(", '{}'", s)).unwrap_or_default();
-        let script = format!(
-            "return window.chromeCssManager.load(`{}`{});",
-            css_content.replace('`', r"\`"),
-            id_param
-        );
+        // Preprocessed first, so the ID is derived from the CSS the browser holds
+        let css = self.preprocess(css_content, None)?;
+        let id = match id {
+            Some(id) => id.to_string(),
+            None => self.generate_id(None, &css)?,
+        };
+        self.register_preprocessed(css, &id)
+    }
 
-        let result = self.connection.execute_script(&script, None)?;
+    /// Preprocess `css` and register it under `id`.
+    fn register_css(
+        &mut self,
+        css: &str,
+        id: &str,
+        source: Option<&Path>,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let css = self.preprocess(css, source)?;
+        self.register_preprocessed(css, id)
+    }
+
+    fn register_preprocessed(
+        &mut self,
+        css: String,
+        id: &str,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let result = self.harness_script(
+            "return window.chromeCssManager.load(arguments[0], arguments[1], arguments[2]);",
+            Some(vec![
+                serde_json::Value::from(css.as_str()),
+                serde_json::Value::from(id),
+                serde_json::Value::from(self.sheet_type.as_str()),
+            ]),
+        )?;
         let sheet_id = result.as_str().unwrap_or("unknown").to_string();
-        self.loaded_sheets
-            .insert(sheet_id.clone(), css_content.to_string());
+        self.update_persisted(&sheet_id, &css);
+        self.loaded_sheets.insert(sheet_id.clone(), css);
+
+        Ok(sheet_id)
+    }
+
+    /// Load a stylesheet from disk, inlining its local `@import`s.
+    pub fn load_css_file(
+        &mut self,
+        path: &Path,
+        id: Option<&str>,
+    ) -> Result<(String, ResolvedStylesheet), Box<dyn std::error::Error>> {
+        self.load_css_file_with_cache(path, id, &mut ImportCache::new())
+    }
 
+    /// Like [`Self::load_css_file`], reusing sources cached from earlier loads.
+    pub fn load_css_file_with_cache(
+        &mut self,
+        path: &Path,
+        id: Option<&str>,
+        cache: &mut ImportCache,
+    ) -> Result<(String, ResolvedStylesheet), Box<dyn std::error::Error>> {
+        let resolved = self.resolve_file_with_cache(path, cache)?;
+        let sheet_id = self.load_resolved(path, &resolved, id)?;
+        Ok((sheet_id, resolved))
+    }
+
+    /// Register a stylesheet resolved from `path`, e.g. by [`Self::resolve_file`].
+    pub fn load_resolved(
+        &mut self,
+        path: &Path,
+        resolved: &ResolvedStylesheet,
+        id: Option<&str>,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let id = match id {
+            Some(id) => id.to_string(),
+            None => self.generate_id(Some(path), &resolved.css)?,
+        };
+        let sheet_id = self.register_css(&resolved.css, &id, Some(path))?;
+        self.harness_script(
+            "window.chromeCssManager.sources.set(arguments[0], arguments[1]);",
+            Some(vec![
+                serde_json::Value::String(sheet_id.clone()),
+                serde_json::Value::String(canonicalize(path)?.to_string_lossy().into_owned()),
+            ]),
+        )?;
+        self.source_maps
+            .insert(sheet_id.clone(), resolved.source_map.clone());
         Ok(sheet_id)
     }
 
+    /// Load several files at once. Files are read and preprocessed in parallel
+    /// and registered with a single chrome script; each sheet gets an ID from
+    /// [`Self::generate_id`]. A failure in one file does not stop the others.
+    pub fn load_many<P: AsRef<Path> + Sync>(
+        &mut self,
+        paths: &[P],
+    ) -> Result<Vec<BulkLoadResult>, Box<dyn std::error::Error>> {
+        let policy = self.cycle_policy;
+        let resolve = |path: &Path| {
+            ImportResolver::new()
+                .with_cycle_policy(policy)
+                .resolve(path)
+                .map_err(|e| e.to_string())
+        };
+
+        let workers = std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1)
+            .min(paths.len().max(1));
+        let chunk_size = paths.len().div_ceil(workers).max(1);
+
+        let resolved: Vec<Result<ResolvedStylesheet, String>> = std::thread::scope(|scope| {
+            let handles: Vec<_> = paths
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    std::thread::Builder::new().spawn_scoped(scope, move || {
+                        chunk
+                            .iter()
+                            .map(|p| resolve(p.as_ref()))
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+
+            handles
+                .into_iter()
+                .zip(paths.chunks(chunk_size))
+                .flat_map(|(handle, chunk)| match handle {
+                    Ok(handle) => handle.join().unwrap_or_else(|_| {
+                        vec![Err("worker thread panicked".to_string()); chunk.len()]
+                    }),
+                    // No thread support (e.g. WASI): resolve on this thread
+                    Err(_) => chunk.iter().map(|p| resolve(p.as_ref())).collect(),
+                })
+                .collect()
+        });
+
+        let mut taken = self.sheet_sources()?;
+        let mut results = Vec::with_capacity(paths.len());
+        let mut entries = Vec::new();
+        let mut processed = Vec::new();
+        for (path, resolved) in paths.iter().zip(resolved) {
+            let path = path.as_ref().to_path_buf();
+            match resolved {
+                Ok(resolved) => {
+                    let source = canonicalize(&path)
+                        .map(|p| p.to_string_lossy().into_owned())
+                        .ok();
+                    let id = pick_id(
+                        &id_base(Some(&path), &resolved.css),
+                        source.as_deref(),
+                        &taken,
+                    );
+                    let css = match self.preprocess(&resolved.css, Some(&path)) {
+                        Ok(css) => css,
+                        Err(e) => {
+                            results.push(BulkLoadResult {
+                                path,
+                                result: Err(e.to_string()),
+                                resolved: Some(resolved),
+                            });
+                            continue;
+                        }
+                    };
+                    taken.insert(id.clone(), source.clone());
+                    processed.push(css.clone());
+                    entries.push(serde_json::json!({
+                        "css": css,
+                        "id": id,
+                        "source": source,
+                        "type": self.sheet_type.as_str(),
+                    }));
+                    results.push(BulkLoadResult {
+                        path,
+                        result: Ok(id),
+                        resolved: Some(resolved),
+                    });
+                }
+                Err(e) => results.push(BulkLoadResult {
+                    path,
+                    result: Err(e),
+                    resolved: None,
+                }),
+            }
+        }
+
+        if entries.is_empty() {
+            return Ok(results);
+        }
+
+        let outcome = self.harness_script(
+            "return window.chromeCssManager.loadMany(arguments[0]);",
+            Some(vec![serde_json::Value::Array(entries)]),
+        )?;
+        let outcome = outcome.as_array().cloned().unwrap_or_default();
+
+        let mut outcomes = outcome.into_iter().zip(processed);
+        for entry in results.iter_mut().filter(|r| r.result.is_ok()) {
+            let Some((outcome, css)) = outcomes.next() else {
+                entry.result = Err("no result from browser".to_string());
+                continue;
+            };
+            if let Some(error) = outcome.get("error").and_then(|e| e.as_str()) {
+                entry.result = Err(error.to_string());
+                continue;
+            }
+            let (Ok(id), Some(resolved)) = (&entry.result, &entry.resolved) else {
+                continue;
+            };
+            self.update_persisted(id, &css);
+            self.loaded_sheets.insert(id.clone(), css);
+            self.source_maps
+                .insert(id.clone(), resolved.source_map.clone());
+        }
+
+        Ok(results)
+    }
+
+    /// Register sheets read back earlier with [`Self::fetch_sheet`], as they
+    /// were: same CSS, source and sheet type, without preprocessing. Returns
+    /// an error message for each sheet that failed.
+    pub fn restore_sheets(
+        &mut self,
+        sheets: &[(String, RegisteredSheet)],
+    ) -> Result<Vec<Option<String>>, Box<dyn std::error::Error>> {
+        let entries = sheets
+            .iter()
+            .map(|(id, sheet)| {
+                serde_json::json!({
+                    "css": sheet.css,
+                    "id": id,
+                    "source": sheet.source,
+                    "type": sheet.sheet_type.as_str(),
+                })
+            })
+            .collect();
+        let outcome = self.harness_script(
+            "return window.chromeCssManager.loadMany(arguments[0]);",
+            Some(vec![serde_json::Value::Array(entries)]),
+        )?;
+        let outcome = outcome.as_array().cloned().unwrap_or_default();
+
+        let mut errors = Vec::with_capacity(sheets.len());
+        for (i, (id, sheet)) in sheets.iter().enumerate() {
+            let error = match outcome.get(i) {
+                Some(result) => result
+                    .get("error")
+                    .and_then(|e| e.as_str())
+                    .map(String::from),
+                None => Some("no result from browser".to_string()),
+            };
+            if error.is_none() {
+                self.loaded_sheets.insert(id.clone(), sheet.css.clone());
+            }
+            errors.push(error);
+        }
+        Ok(errors)
+    }
+
+    /// ID for a sheet loaded without one: the file name plus a short hash of
+    /// its path (`navbar.css-3fa2`), or of the CSS itself for sheets without a
+    /// file (`stdin-9c01`). Loading the same file again yields the same ID and
+    /// so replaces the earlier sheet; if another source holds the ID, a numeric
+    /// suffix is added. Sheets without a file only share an ID with the same
+    /// CSS as registered.
+    pub fn generate_id(
+        &mut self,
+        source: Option<&Path>,
+        css: &str,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let mut taken = self.sheet_sources()?;
+        let base = id_base(source, css);
+        let owner = match source {
+            Some(path) => canonicalize(path)
+                .unwrap_or_else(|_| path.to_path_buf())
+                .to_string_lossy()
+                .into_owned(),
+            None => {
+                // The short hash in the ID can collide, so the sheets that
+                // might hold it are told apart by a hash of their whole CSS
+                let candidates: Vec<String> = taken
+                    .iter()
+                    .filter(|(id, owner)| {
+                        owner.is_none()
+                            && id
+                                .strip_prefix(base.as_str())
+                                .is_some_and(|rest| rest.is_empty() || rest.starts_with('-'))
+                    })
+                    .map(|(id, _)| id.clone())
+                    .collect();
+                for id in candidates {
+                    if let Some(sheet) = self.fetch_sheet(&id)? {
+                        taken.insert(id, Some(stdin_owner(&sheet.css)));
+                    }
+                }
+                stdin_owner(css)
+            }
+        };
+        Ok(pick_id(&base, Some(&owner), &taken))
+    }
+
+    /// Registered sheet IDs and the files they were loaded from.
+    fn sheet_sources(
+        &mut self,
+    ) -> Result<HashMap<String, Option<String>>, Box<dyn std::error::Error>> {
+        let result = self.harness_script(chrome_js::SHEET_SOURCES, None)?;
+        Ok(serde_json::from_value(result)?)
+    }
+
+    /// Re-read the file a sheet was loaded from and swap the sheet for the new
+    /// version. The new version is registered before the old one is removed.
+    pub fn reload(&mut self, id: &str) -> Result<ResolvedStylesheet, Box<dyn std::error::Error>> {
+        let sheet = self
+            .fetch_sheet(id)?
+            .ok_or_else(|| format!("No stylesheet loaded with ID: {}", id))?;
+        let source = sheet
+            .source
+            .ok_or_else(|| format!("Stylesheet {} was not loaded from a file", id))?;
+        let path = PathBuf::from(source);
+        let resolved = self.resolve_file(&path)?;
+        self.load_resolved(&path, &resolved, Some(id))?;
+        Ok(resolved)
+    }
+
     pub fn unload_css(&mut self, id: &str) -> Result<bool, Box<dyn std::error::Error>> {
-        let script = format!("return window.chromeCssManager.unload('{}');", id);
-        let result = self.connection.execute_script(&script, None)?;
+        let result = self.harness_script(
+            "return window.chromeCssManager.unload(arguments[0]);",
+            Some(vec![serde_json::Value::from(id)]),
+        )?;
         let success = result.as_bool().unwrap_or(false);
 
         if success {
             self.loaded_sheets.remove(id);
+            self.source_maps.remove(id);
+            self.forget_persisted(id);
         }
         Ok(success)
     }
 
+    /// The persisted sheets and where they are, read once per manager. Not
+    /// available with browsers that cannot be asked, e.g. over RDP.
+    fn persisted(
+        &mut self,
+    ) -> Result<&mut (PersistStore, Vec<String>), Box<dyn std::error::Error>> {
+        if self.persisted.is_none() {
+            let read = self.connection.with(|c| {
+                let store = PersistStore::open(c)?;
+                let files = store.files(c)?;
+                Ok((store, files))
+            });
+            self.persisted = Some(read.map_err(|e| e.to_string()));
+        }
+        match self.persisted.as_mut().unwrap() {
+            Ok(persisted) => Ok(persisted),
+            Err(e) => Err(e.clone().into()),
+        }
+    }
+
+    /// Write `id` to the profile if persisting, or if it was persisted before,
+    /// so the copy stays current.
+    fn update_persisted(&mut self, id: &str, css: &str) {
+        let persist = self.persist;
+        let connection = self.connection.clone();
+        let result = match self.persisted() {
+            Ok((store, files)) if persist || files.contains(&sheet_file(id)) => {
+                connection.with(|c| store.save(c, id, css)).map(|()| {
+                    if !files.contains(&sheet_file(id)) {
+                        files.push(sheet_file(id));
+                    }
+                })
+            }
+            Ok(_) => Ok(()),
+            Err(e) if persist => Err(e),
+            Err(_) => Ok(()),
+        };
+        if let Err(e) = result {
+            eprintln!("Warning: could not persist {}: {}", id, e);
+        }
+    }
+
+    fn forget_persisted(&mut self, id: &str) {
+        let connection = self.connection.clone();
+        let Ok((store, files)) = self.persisted() else {
+            return;
+        };
+        if !files.contains(&sheet_file(id)) {
+            return;
+        }
+        match connection.with(|c| store.remove(c, id)) {
+            Ok(_) => files.retain(|f| *f != sheet_file(id)),
+            Err(e) => eprintln!(
+                "Warning: could not remove the persisted copy of {}: {}",
+                id, e
+            ),
+        }
+    }
+
+    /// File names of the sheets persisted in the profile, in load order.
+    pub fn persisted_sheets(&mut self) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        Ok(self.persisted()?.1.clone())
+    }
+
+    /// Remove every persisted sheet from the profile. Loaded sheets stay.
+    pub fn clear_persisted(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let connection = self.connection.clone();
+        let (store, files) = self.persisted()?;
+        connection.with(|c| store.clear(c))?;
+        files.clear();
+        Ok(())
+    }
+
     pub fn clear_all(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        self.connection
-            .execute_script("window.chromeCssManager.clear();", None)?;
+        self.harness_script("window.chromeCssManager.clear();", None)?;
         self.loaded_sheets.clear();
+        self.source_maps.clear();
         Ok(())
     }
 
+    /// Every `data:text/css` sheet registered in the browser, whether the
+    /// harness knows it or not. Does not need the harness.
+    pub fn scan_sheets(&mut self) -> Result<Vec<ScannedSheet>, Box<dyn std::error::Error>> {
+        self.run_sheet_scan(false, false)
+    }
+
+    /// Unregister every sheet any instance of the tool registered, found by
+    /// the marker in its URI instead of through the harness, which is removed
+    /// as well. With `any_data_uri`, `data:text/css` sheets without the marker,
+    /// as registered by older versions, go too. Returns what was removed.
+    pub fn panic_reset(
+        &mut self,
+        any_data_uri: bool,
+    ) -> Result<Vec<ScannedSheet>, Box<dyn std::error::Error>> {
+        let removed = self.run_sheet_scan(true, any_data_uri)?;
+        self.loaded_sheets.clear();
+        self.source_maps.clear();
+        Ok(removed)
+    }
+
+    fn run_sheet_scan(
+        &mut self,
+        remove: bool,
+        any_data_uri: bool,
+    ) -> Result<Vec<ScannedSheet>, Box<dyn std::error::Error>> {
+        let script = chrome_js::render(
+            chrome_js::SHEET_SCAN,
+            &[
+                ("remove", serde_json::Value::from(remove)),
+                ("anyDataUri", serde_json::Value::from(any_data_uri)),
+            ],
+        );
+        let result = self.connection.execute_script(&script, None)?;
+        Ok(serde_json::from_value(result)?)
+    }
+
     pub fn list_loaded(&self) -> Vec<String> {
         self.loaded_sheets.keys().cloned().collect()
     }
 
+    /// Version of the harness installed in the browser, if any; harnesses from
+    /// before versioning count as 1. Unlike other calls, this never installs it.
+    pub fn harness_version(&self) -> Result<Option<u32>, Box<dyn std::error::Error>> {
+        let result = self.connection.execute_script(
+            "const harness = window.chromeCssManager; return harness ? harness.version ?? 1 : null;",
+            None,
+        )?;
+        Ok(result.as_u64().map(|v| v as u32))
+    }
+
+    /// Check the prefs userChrome development needs (see
+    /// [`crate::dev_prefs::DEV_PREFS`]) and, with `apply`, turn on the ones
+    /// that are off.
+    pub fn setup_prefs(&self, apply: bool) -> Result<Vec<PrefStatus>, Box<dyn std::error::Error>> {
+        let result = self
+            .connection
+            .execute_script(&dev_prefs::script(apply), None)?;
+        dev_prefs::statuses(result, apply)
+    }
+
+    /// The profile directory of the connected browser.
+    pub fn browser_profile_dir(&self) -> Result<PathBuf, Box<dyn std::error::Error>> {
+        Ok(PathBuf::from(
+            self.connection.with(|c| c.browser_profile_dir())?,
+        ))
+    }
+
+    /// IDs of every sheet registered in the browser, including those loaded by
+    /// other invocations.
+    pub fn registered_ids(&mut self) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        let result = self.harness_script("return window.chromeCssManager.list();", None)?;
+        Ok(serde_json::from_value(result)?)
+    }
+
+    /// The loads, unloads and clears the harness has seen, oldest first, from
+    /// this process and any other. The harness keeps the last 200; they go
+    /// when the browser restarts or `panic-reset` removes the harness.
+    pub fn audit_log(&mut self) -> Result<Vec<AuditEntry>, Box<dyn std::error::Error>> {
+        let result = self.harness_script("return window.chromeCssManager.audit;", None)?;
+        Ok(serde_json::from_value(result)?)
+    }
+
+    /// The content and source file of a registered sheet, as the browser sees it.
+    pub fn fetch_sheet(
+        &mut self,
+        id: &str,
+    ) -> Result<Option<RegisteredSheet>, Box<dyn std::error::Error>> {
+        let result = self.harness_script(
+            "return window.chromeCssManager.get(arguments[0]);",
+            Some(vec![serde_json::Value::String(id.to_string())]),
+        )?;
+        Ok(serde_json::from_value(result)?)
+    }
+
+    /// Declarations that registered sheets override in each other. See
+    /// [`crate::conflicts`]. Locations are reported relative to `root` when
+    /// given.
+    pub fn find_conflicts(
+        &mut self,
+        root: Option<&Path>,
+    ) -> Result<ConflictReport, Box<dyn std::error::Error>> {
+        let mut sheets = Vec::new();
+        for id in self.registered_ids()? {
+            if let Some(sheet) = self.fetch_sheet(&id)? {
+                sheets.push((id, sheet));
+            }
+        }
+        Ok(find_conflicts(&sheets, |id, line| {
+            self.source_maps.get(id)?.describe(line, root)
+        }))
+    }
+
+    /// Whether the sheet registered under `id` applies to the open tabs
+    /// showing `pages`. See [`crate::content_pages`].
+    pub fn check_pages(
+        &mut self,
+        id: &str,
+        pages: &[&str],
+    ) -> Result<Vec<PageCheck>, Box<dyn std::error::Error>> {
+        let window_type = self.connection.with(|c| c.app_profile())?.window_type;
+        let script = chrome_js::render(
+            chrome_js::CHECK_PAGES,
+            &[("windowType", serde_json::Value::from(window_type))],
+        );
+        let result = self.harness_script(
+            &script,
+            Some(vec![serde_json::Value::from(id), serde_json::json!(pages)]),
+        )?;
+        Ok(serde_json::from_value(result)?)
+    }
+
+    /// The sheet registered under `id` as the browser applies it now,
+    /// including edits made in the Browser Toolbox.
+    pub fn pull_sheet(
+        &mut self,
+        id: &str,
+    ) -> Result<Option<PulledSheet>, Box<dyn std::error::Error>> {
+        let window_type = self.connection.with(|c| c.app_profile())?.window_type;
+        let script = chrome_js::render(
+            chrome_js::PULL_SHEET,
+            &[("windowType", serde_json::Value::from(window_type))],
+        );
+        let result = self.harness_script(&script, Some(vec![serde_json::Value::from(id)]))?;
+        Ok(serde_json::from_value(result)?)
+    }
+
+    /// The CSS registered under `id`. The browser's copy is preferred, since
+    /// the sheet may have been replaced by another process; the copy this
+    /// manager loaded is only used when the browser cannot be asked.
+    pub fn get_css(&mut self, id: &str) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        match self.fetch_sheet(id) {
+            Ok(sheet) => Ok(sheet.map(|sheet| sheet.css)),
+            Err(e) => match self.loaded_sheets.get(id) {
+                Some(css) => Ok(Some(css.clone())),
+                None => Err(e),
+            },
+        }
+    }
+
+    /// A PNG of the browser window, or of the first element matching `selector`.
+    pub fn screenshot(
+        &self,
+        selector: Option<&str>,
+    ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let capture = || {
+            self.connection.with(|connection| match self.capture_mode {
+                CaptureMode::Browser => take_screenshot(connection, selector),
+                CaptureMode::Native => native_capture::capture(connection, selector),
+            })
+        };
+        match selector {
+            Some(_) => self.retry_policy.run(capture),
+            None => capture(),
+        }
+    }
+
+    /// The rendered color at `at` within the first element matching
+    /// `selector`, or within the window.
+    pub fn probe_color(
+        &self,
+        selector: Option<&str>,
+        at: ProbePoint,
+    ) -> Result<PixelColor, Box<dyn std::error::Error>> {
+        let probe = || {
+            self.connection
+                .with(|connection| probe_color(connection, selector, at))
+        };
+        match selector {
+            Some(_) => self.retry_policy.run(probe),
+            None => probe(),
+        }
+    }
+
+    /// Wait until `condition` holds in the browser window, for at most
+    /// `timeout`. Returns how long it took. See [`crate::wait`].
+    pub fn wait_for(
+        &self,
+        condition: &WaitCondition,
+        timeout: Duration,
+    ) -> Result<Duration, Box<dyn std::error::Error>> {
+        wait_for(&self.connection, condition, timeout)
+    }
+
+    /// Screenshot `selector`, or the whole window, to `path`, with a sidecar
+    /// describing the capture next to it (see [`crate::gallery`]). The
+    /// capture goes through [`Self::set_post_process`] first.
+    pub fn save_screenshot(
+        &self,
+        path: &Path,
+        selector: Option<&str>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let png = self.post_process.apply(&self.screenshot(selector)?)?;
+        let meta = self
+            .connection
+            .with(|connection| ScreenshotMeta::read(connection, selector))?;
+        std::fs::write(path, png)?;
+        meta.save(path)
+    }
+
+    /// The source map of a sheet loaded with [`Self::load_css_file`].
+    pub fn source_map(&self, id: &str) -> Option<&SourceMap> {
+        self.source_maps.get(id)
+    }
+
+    /// Check a resolved stylesheet for rules likely broken in the connected
+    /// Firefox. See [`crate::compat`].
+    pub fn check_compat(
+        &mut self,
+        resolved: &ResolvedStylesheet,
+        root: Option<&Path>,
+    ) -> Result<CompatReport, Box<dyn std::error::Error>> {
+        self.connection
+            .with(|connection| check_compat(connection, resolved, root))
+    }
+
+    /// Start buffering browser console messages in the chrome context. Only
+    /// messages logged from now on are captured.
+    pub fn start_console_capture(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        self.harness_script("window.chromeCssManager.startConsole();", None)?;
+        Ok(())
+    }
+
+    /// Console messages logged since the last call. Messages about sheets this
+    /// manager loaded from files carry their original `file:line`.
+    pub fn drain_console(&mut self) -> Result<Vec<ConsoleRecord>, Box<dyn std::error::Error>> {
+        let result = self.harness_script("return window.chromeCssManager.drainConsole();", None)?;
+        let mut records: Vec<ConsoleRecord> = serde_json::from_value(result)?;
+        for record in &mut records {
+            if let (Some(sheet), Some(line)) = (&record.sheet, record.line) {
+                record.origin = self
+                    .source_maps
+                    .get(sheet)
+                    .and_then(|map| map.describe(line, None));
+            }
+        }
+        Ok(records)
+    }
+
+    /// Start the Gecko profiler on the parent process's main, style and
+    /// graphics threads. Sheets loaded while it runs are marked in the profile.
+    pub fn start_profiler(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        self.harness_script("window.chromeCssManager.startProfiler();", None)?;
+        Ok(())
+    }
+
+    /// Stop the profiler and return the profile as JSON, for the Firefox
+    /// Profiler (profiler.firefox.com). `None` if it was not started with
+    /// [`Self::start_profiler`].
+    pub fn stop_profiler(&mut self) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        let result = self.harness_script("return window.chromeCssManager.stopProfiler();", None)?;
+        Ok(result.as_str().map(String::from))
+    }
+
+    /// CSS parse errors Firefox has reported for a loaded sheet. Line numbers
+    /// refer to the loaded bundle; use [`Self::source_map`] to translate them.
+    pub fn css_errors(&mut self, id: &str) -> Result<Vec<CssError>, Box<dyn std::error::Error>> {
+        let result = self.harness_script(
+            "return window.chromeCssManager.errors(arguments[0]);",
+            Some(vec![serde_json::Value::String(id.to_string())]),
+        )?;
+        Ok(serde_json::from_value(result)?)
+    }
+
+    /// Print the parse errors of a loaded sheet, mapped back to their source
+    /// files. Returns the printed errors as `location:column: message`.
+    pub fn report_css_errors(&mut self, id: &str, root: Option<&Path>) -> Vec<String> {
+        let errors = match self.css_errors(id) {
+            Ok(errors) => errors,
+            Err(e) => {
+                eprintln!("Could not fetch CSS errors: {}", e);
+                return Vec::new();
+            }
+        };
+        let mut reported = Vec::new();
+        for error in errors {
+            let location = self
+                .source_map(id)
+                .and_then(|map| map.describe(error.line, root))
+                .unwrap_or_else(|| format!("line {}", error.line));
+            let message = format!("{}:{}: {}", location, error.column, error.message);
+            eprintln!("CSS error at {}", message);
+            reported.push(message);
+        }
+        reported
+    }
+
+    pub(crate) fn notifier(&self) -> Notifier {
+        self.notifier
+    }
+
+    pub(crate) fn term_status(&self) -> TermStatus {
+        self.term_status
+    }
+
+    pub(crate) fn heartbeat(&self) -> Option<Duration> {
+        self.heartbeat
+    }
+
+    pub(crate) fn poll_interval(&self) -> Option<Duration> {
+        self.poll_interval
+    }
+
+    pub(crate) fn notify_css_errors(&self, path: &Path, errors: &[String]) {
+        if errors.is_empty() {
+            return;
+        }
+        let summary = match errors.len() {
+            1 => format!("1 CSS error in {}", file_name(path)),
+            n => format!("{} CSS errors in {}", n, file_name(path)),
+        };
+        let mut body = errors
+            .iter()
+            .take(3)
+            .cloned()
+            .collect::<Vec<_>>()
+            .join("\n");
+        if errors.len() > 3 {
+            body.push_str(&format!("\n… and {} more", errors.len() - 3));
+        }
+        self.notifier.error(&summary, &body);
+    }
+
     pub fn register_chrome_manifest(
         &mut self,
         manifest_path: &Path,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        self.manifest_registrar
-            .register_manifest(manifest_path, &mut self.connection)
+        let registrar = &mut self.manifest_registrar;
+        self.connection
+            .with(|connection| registrar.register_manifest(manifest_path, connection))?;
+        let path = self
+            .manifest_registrar
+            .get_registered_path()
+            .map(String::from);
+        self.harness_script(
+            "window.chromeCssManager.manifests.add(arguments[0]);",
+            Some(vec![serde_json::Value::from(path)]),
+        )?;
+        Ok(())
+    }
+
+    /// Every chrome.manifest registered through this tool since Firefox
+    /// started, in registration order.
+    pub fn registered_manifests(&mut self) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        let result = self.harness_script("return [...window.chromeCssManager.manifests];", None)?;
+        Ok(serde_json::from_value(result)?)
+    }
+
+    /// Map `resource://<alias>/` onto the local directory `dir`, without a
+    /// chrome.manifest. Registering an alias again moves it; aliases the
+    /// browser itself uses cannot be taken over. Returns the URL prefix.
+    pub fn register_resource(
+        &mut self,
+        alias: &str,
+        dir: &Path,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        if alias.is_empty()
+            || !alias.chars().all(|c| {
+                c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '-' | '_' | '.')
+            })
+        {
+            return Err(format!(
+                "Invalid resource alias '{}': use lowercase letters, digits, '-', '_' and '.'",
+                alias
+            )
+            .into());
+        }
+        let path = canonicalize(dir)?
+            .to_str()
+            .ok_or("Invalid path encoding")?
+            .to_string();
+        let result = self.harness_script(
+            "return window.chromeCssManager.registerResource(arguments[0], arguments[1]);",
+            Some(vec![
+                serde_json::Value::from(alias),
+                serde_json::Value::from(path),
+            ]),
+        )?;
+        Ok(result.as_str().unwrap_or_default().to_string())
+    }
+
+    /// Remove an alias added with [`Self::register_resource`]. Returns whether
+    /// it was registered.
+    pub fn unregister_resource(&mut self, alias: &str) -> Result<bool, Box<dyn std::error::Error>> {
+        let result = self.harness_script(
+            "return window.chromeCssManager.unregisterResource(arguments[0]);",
+            Some(vec![serde_json::Value::from(alias)]),
+        )?;
+        Ok(result.as_bool().unwrap_or(false))
+    }
+
+    /// Every `resource://` alias registered through this tool since Firefox
+    /// started, with its directory.
+    pub fn registered_resources(
+        &mut self,
+    ) -> Result<BTreeMap<String, String>, Box<dyn std::error::Error>> {
+        let result = self.harness_script(
+            "return Object.fromEntries(window.chromeCssManager.resources);",
+            None,
+        )?;
+        Ok(serde_json::from_value(result)?)
+    }
+
+    /// Where the `chrome://` and `resource://` URLs registered through this
+    /// tool point on disk. Manifests that cannot be read here are skipped.
+    pub fn registered_mappings(&mut self) -> Result<ChromeManifest, Box<dyn std::error::Error>> {
+        let mut mappings = ChromeManifest::default();
+        for path in self.registered_manifests()? {
+            if let Ok(manifest) = ChromeManifest::parse_file(Path::new(&path)) {
+                mappings.entries.extend(manifest.entries);
+            }
+        }
+        for (alias, dir) in self.registered_resources()? {
+            mappings.entries.push(ManifestEntry {
+                kind: "resource".to_string(),
+                package: alias,
+                dir: PathBuf::from(dir),
+            });
+        }
+        Ok(mappings)
     }
 
     pub fn get_registered_manifest(&self) -> Option<&str> {
@@ -132,50 +1498,131 @@ impl ChromeCSSManager {
         file_path: &str,
         id: Option<&str>,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        use std::fs;
+        let target = WatchTarget::css(
+            Path::new(file_path),
+            None,
+            Some(id.unwrap_or("watched-sheet")),
+        );
+        self.watch_targets(&[target])
+    }
 
-        let path = Path::new(file_path);
-        if !path.exists() {
-            return Err(format!("File not found: {}", file_path).into());
+    /// Watch every file under `dir` and reload `file_path` when a file in its
+    /// import graph changes. Only the changed files are read again; the rest of
+    /// the bundle is rebuilt from cached sources.
+    pub fn watch_directory_and_reload(
+        &mut self,
+        file_path: &str,
+        dir: &str,
+        id: Option<&str>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let target = WatchTarget::css(
+            Path::new(file_path),
+            Some(Path::new(dir)),
+            Some(id.unwrap_or("watched-sheet")),
+        );
+        self.watch_targets(&[target])
+    }
+
+    /// Watch several stylesheets and scripts in one event loop, such as the
+    /// targets of a `mus-uc.toml`. Returns only on error.
+    pub fn watch_targets(
+        &mut self,
+        targets: &[WatchTarget],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut engine = Engine::new();
+        for target in targets {
+            if !target.file.exists() {
+                return Err(format!("File not found: {}", target.file.display()).into());
+            }
+            if let Some(dir) = &target.dir {
+                if !dir.is_dir() {
+                    return Err(format!("Directory not found: {}", dir.display()).into());
+                }
+            }
+            match target.kind {
+                WatchKind::Css => engine.add_handler(SheetHandler::new(target)),
+                WatchKind::Script => engine.add_handler(ScriptHandler::new(&target.file)?),
+            }
+        }
+        if let Some(log) = self.console_log.take() {
+            engine.add_handler(ConsoleLogHandler::new(log));
         }
+        engine.run(self)
+    }
+}
 
-        let sheet_id = id.unwrap_or("watched-sheet").to_string();
+/// Place a frame in a method of the harness at its line of `harness.js`,
+/// which the installed harness matches line for line.
+fn harness_frame(frame: &StackFrame) -> Option<String> {
+    let method = format!("    {}(", frame.function);
+    let defined = !frame.function.is_empty()
+        && chrome_js::HARNESS
+            .lines()
+            .any(|line| line.starts_with(&method) && line.ends_with('{'));
+    defined.then(|| {
+        format!(
+            "src/js/harness.js:{}:{} in {}()",
+            frame.line, frame.column, frame.function
+        )
+    })
+}
 
-        // Load initial CSS
-        let css_content = fs::read_to_string(path)?;
-        self.load_css(&css_content, Some(&sheet_id))?;
-        println!("Initial CSS loaded with ID: {}", sheet_id);
+fn id_base(source: Option<&Path>, css: &str) -> String {
+    match source {
+        Some(path) => {
+            let canonical = canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+            format!(
+                "{}-{:04x}",
+                file_name(path),
+                fnv1a(canonical.to_string_lossy().as_bytes()) & 0xffff
+            )
+        }
+        None => format!("stdin-{:04x}", fnv1a(css.as_bytes()) & 0xffff),
+    }
+}
 
-        let (tx, rx) = channel();
-        let mut watcher = notify::recommended_watcher(move |res: Result<Event, notify::Error>| {
-            if let Ok(event) = res {
-                tx.send(event).ok();
-            }
-        })?;
+/// What owns a sheet without a file in [`pick_id`]: its CSS.
+fn stdin_owner(css: &str) -> String {
+    format!("stdin:{:08x}", fnv1a(css.as_bytes()))
+}
 
-        watcher.watch(path, RecursiveMode::NonRecursive)?;
+/// `base`, or `base-2`, `base-3`, ... if a sheet from another source holds it.
+fn pick_id(base: &str, source: Option<&str>, taken: &HashMap<String, Option<String>>) -> String {
+    let mut id = base.to_string();
+    let mut n = 2;
+    while taken
+        .get(&id)
+        .is_some_and(|owner| owner.as_deref() != source)
+    {
+        id = format!("{}-{}", base, n);
+        n += 1;
+    }
+    id
+}
 
-        loop {
-            match rx.recv_timeout(Duration::from_millis(100)) {
-                Ok(event) if matches!(event.kind, EventKind::Modify(_) | EventKind::Create(_)) => {
-                    println!("File changed, reloading CSS...");
-                    self.unload_css(&sheet_id)?;
-                    std::thread::sleep(Duration::from_millis(50));
+/// FNV-1a, which unlike `DefaultHasher` is stable across Rust releases.
+fn fnv1a(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c9dc5, |hash, &b| {
+        (hash ^ b as u32).wrapping_mul(0x01000193)
+    })
+}
 
-                    match fs::read_to_string(path) {
-                        Ok(css) => {
-                            self.load_css(&css, Some(&sheet_id))?;
-                            println!("CSS reloaded successfully");
-                        }
-                        Err(e) => eprintln!("Error reading file: {}", e),
-                    }
-                }
-                Ok(_) => {} // Other events, ignore
-                Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {}
-                Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => {
-                    return Err("File watcher disconnected".into());
-                }
-            }
-        }
+pub(crate) fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.display().to_string())
+}
+
+pub fn report_unresolved(resolved: &ResolvedStylesheet) {
+    for import in &resolved.unresolved {
+        eprintln!(
+            "Warning: unresolved @import '{}' in {}: {}",
+            import.url,
+            import.from.display(),
+            import.reason
+        );
+    }
+    for warning in &resolved.warnings {
+        eprintln!("Warning: {}", warning);
     }
 }
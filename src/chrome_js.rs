@@ -0,0 +1,111 @@
+//! Scripts run in the chrome context, kept as `.js` files under `src/js` and
+//! embedded at build time.
+//!
+//! Templates mark values with `{{name}}` placeholders. [`render`] fills them
+//! with JSON literals, so paths and selectors need no escaping. Placeholders
+//! stand where an expression can, which keeps the files parseable as they are
+//! (see `tests/chrome-js-test.js`).
+
+use serde_json::Value;
+
+pub const HARNESS: &str = include_str!("js/harness.js");
+pub const HARNESS_GUARD: &str = include_str!("js/harness_guard.js");
+pub const SHEET_SOURCES: &str = include_str!("js/sheet_sources.js");
+pub const REGISTER_MANIFEST: &str = include_str!("js/register_manifest.js");
+pub const SCREENSHOT: &str = include_str!("js/screenshot.js");
+pub const CAPTURE_CHUNK: &str = include_str!("js/capture_chunk.js");
+pub const PROBE_COLOR: &str = include_str!("js/probe_color.js");
+pub const SCREEN_RECT: &str = include_str!("js/screen_rect.js");
+pub const BROWSER_INFO: &str = include_str!("js/browser_info.js");
+pub const SIMULATE: &str = include_str!("js/simulate.js");
+pub const THEME_COLORS: &str = include_str!("js/theme_colors.js");
+pub const BROWSER_VARIABLES: &str = include_str!("js/browser_variables.js");
+pub const CONTRAST: &str = include_str!("js/contrast.js");
+pub const CI_PROFILE: &str = include_str!("js/ci_profile.js");
+pub const DENSITY: &str = include_str!("js/density.js");
+pub const INTERACT: &str = include_str!("js/interact.js");
+pub const WAIT_CONDITION: &str = include_str!("js/wait_condition.js");
+pub const FORCE_STATE: &str = include_str!("js/force_state.js");
+pub const SYMBOLS: &str = include_str!("js/symbols.js");
+pub const BENCH: &str = include_str!("js/bench.js");
+pub const SHEET_SCAN: &str = include_str!("js/sheet_scan.js");
+pub const PULL_SHEET: &str = include_str!("js/pull_sheet.js");
+pub const CHECK_PAGES: &str = include_str!("js/check_pages.js");
+pub const DEV_PREFS: &str = include_str!("js/dev_prefs.js");
+pub const READ_FILE: &str = include_str!("js/read_file.js");
+pub const WRITE_FILE: &str = include_str!("js/write_file.js");
+pub const REMOVE_FILE: &str = include_str!("js/remove_file.js");
+pub const CAPTURE_INFO: &str = include_str!("js/capture_info.js");
+pub const MEDIA_EMULATION: &str = include_str!("js/media_emulation.js");
+pub const LOCALE: &str = include_str!("js/locale.js");
+pub const MATRIX_CELL: &str = include_str!("js/matrix_cell.js");
+pub const HELPER_TOGGLE_SIDEBAR: &str = include_str!("js/helper_toggle_sidebar.js");
+pub const HELPER_OVERFLOW_MENU: &str = include_str!("js/helper_overflow_menu.js");
+pub const HELPER_WINDOW_INFO: &str = include_str!("js/helper_window_info.js");
+pub const HELPER_FLUSH_CACHES: &str = include_str!("js/helper_flush_caches.js");
+
+/// Replace each `{{name}}` in `template` with the JSON encoding of its value.
+///
+/// The template is read once from start to end, so a value that itself
+/// contains `{{...}}`, such as a selector or CSS from the user, is left as it
+/// is. Only the template is checked for placeholders without a value.
+pub fn render(template: &str, values: &[(&str, Value)]) -> String {
+    let mut script = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        script.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let name = after.find("}}").map(|end| &after[..end]).filter(|name| {
+            !name.is_empty() && name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_')
+        });
+        match name {
+            Some(name) => {
+                match values.iter().find(|(key, _)| *key == name) {
+                    Some((_, value)) => script.push_str(&value.to_string()),
+                    None => {
+                        debug_assert!(
+                            false,
+                            "unfilled placeholder {{{{{}}}}} in chrome script",
+                            name
+                        );
+                        script.push_str(&rest[start..start + name.len() + 4]);
+                    }
+                }
+                rest = &after[name.len() + 2..];
+            }
+            // Not a placeholder, e.g. `{{` in the script's own code
+            None => {
+                script.push_str("{{");
+                rest = after;
+            }
+        }
+    }
+    script.push_str(rest);
+    script
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_are_not_substituted_again() {
+        let script = render(
+            "f({{selector}}, {{css}});",
+            &[
+                ("selector", Value::from("a[title='{{css}}']")),
+                ("css", Value::from("{{selector}} { color: red }")),
+            ],
+        );
+        assert_eq!(
+            script,
+            r#"f("a[title='{{css}}']", "{{selector}} { color: red }");"#
+        );
+    }
+
+    #[test]
+    fn braces_that_are_not_placeholders_are_kept() {
+        let script = render("const o = {{ a: 1 }}; f({{x}});", &[("x", Value::from(1))]);
+        assert_eq!(script, "const o = {{ a: 1 }}; f(1);");
+    }
+}
@@ -1,4 +1,11 @@
-use std::path::Path;
+use crate::chrome_js;
+use crate::marionette_client::BrowserInfo;
+use crate::paths::{canonicalize, file_url_path};
+use serde_json::Value;
+use std::cmp::Ordering;
+use std::fmt;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
 
 #[derive(Default)]
 pub struct ChromeManifestRegistrar {
@@ -15,29 +22,21 @@ impl ChromeManifestRegistrar {
         manifest_path: &Path,
         connection: &mut crate::marionette_client::MarionetteConnection,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        let path_str = manifest_path
-            .canonicalize()?
+        let path_str = canonicalize(manifest_path)?
             .to_str()
             .ok_or("Invalid path encoding")?
             .to_string();
 
-        let script = format!(
-            r#"
-            try {{
-                const file = Cc['@mozilla.org/file/local;1'].createInstance(Ci.nsIFile);
-                file.initWithPath('{}');
-                const registrar = Components.manager.QueryInterface(Ci.nsIComponentRegistrar);
-                registrar.autoRegister(file);
-                return {{ success: true }};
-            }} catch (e) {{
-                return {{ success: false, error: e.toString() }};
-            }}
-        "#,
-            path_str
+        let script = chrome_js::render(
+            chrome_js::REGISTER_MANIFEST,
+            &[("path", Value::from(path_str.as_str()))],
         );
 
         let result = connection.execute_script(&script, None)?;
-        let success = result.get("success").and_then(|v| v.as_bool()).unwrap_or(false);
+        let success = result
+            .get("success")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
 
         if success {
             self.manifest_path = Some(path_str);
@@ -55,3 +54,602 @@ impl ChromeManifestRegistrar {
         self.manifest_path.as_deref()
     }
 }
+
+/// A package mapping declared by a `content`, `skin` or `resource` line.
+#[derive(Debug, Clone)]
+pub struct ManifestEntry {
+    pub kind: String,
+    pub package: String,
+    /// Absolute directory the package maps to.
+    pub dir: PathBuf,
+}
+
+impl ManifestEntry {
+    fn url_prefix(&self) -> String {
+        match self.kind.as_str() {
+            "resource" => format!("resource://{}/", self.package),
+            kind => format!("chrome://{}/{}/", self.package, kind),
+        }
+    }
+}
+
+/// Instructions and the number of arguments they take before their flags.
+const INSTRUCTIONS: &[(&str, usize)] = &[
+    ("content", 2),
+    ("locale", 3),
+    ("skin", 3),
+    ("resource", 2),
+    ("override", 2),
+    ("overlay", 2),
+    ("style", 2),
+    ("manifest", 1),
+    ("component", 2),
+    ("contract", 2),
+    ("category", 3),
+    ("interfaces", 1),
+    ("binary-component", 1),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FlagKind {
+    /// `os=WINNT`, `os!=WINNT`
+    String,
+    /// `appversion>=115`
+    Version,
+    /// `contentaccessible=yes`, or just `contentaccessible`
+    Bool,
+}
+
+const FLAGS: &[(&str, FlagKind)] = &[
+    ("application", FlagKind::String),
+    ("os", FlagKind::String),
+    ("abi", FlagKind::String),
+    ("process", FlagKind::String),
+    ("backgroundtask", FlagKind::String),
+    ("appversion", FlagKind::Version),
+    ("platformversion", FlagKind::Version),
+    ("osversion", FlagKind::Version),
+    ("contentaccessible", FlagKind::Bool),
+    ("remoteenabled", FlagKind::Bool),
+    ("remoterequired", FlagKind::Bool),
+    ("platform", FlagKind::Bool),
+    ("xpcnativewrappers", FlagKind::Bool),
+];
+
+/// Values of `os=` as Gecko names the platforms it builds for.
+const OS_NAMES: &[&str] = &[
+    "WINNT",
+    "Linux",
+    "Darwin",
+    "Android",
+    "FreeBSD",
+    "OpenBSD",
+    "NetBSD",
+    "SunOS",
+    "DragonFly",
+];
+
+/// What people write for `os=` instead of the Gecko name.
+const OS_ALIASES: &[(&str, &str)] = &[
+    ("windows", "WINNT"),
+    ("win", "WINNT"),
+    ("win32", "WINNT"),
+    ("win64", "WINNT"),
+    ("mac", "Darwin"),
+    ("macos", "Darwin"),
+    ("macosx", "Darwin"),
+    ("osx", "Darwin"),
+];
+
+const FIREFOX_ID: &str = "{ec8030f7-c20a-464f-9b0e-13a3a9e97384}";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FlagOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl FlagOp {
+    fn as_str(self) -> &'static str {
+        match self {
+            FlagOp::Eq => "=",
+            FlagOp::Ne => "!=",
+            FlagOp::Lt => "<",
+            FlagOp::Le => "<=",
+            FlagOp::Gt => ">",
+            FlagOp::Ge => ">=",
+        }
+    }
+}
+
+/// A flag limiting where a manifest line applies, like `os=WINNT` or
+/// `appversion>=115`. A bare flag such as `contentaccessible` has no
+/// operator.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ManifestFlag {
+    pub name: String,
+    pub op: Option<FlagOp>,
+    pub value: String,
+}
+
+impl ManifestFlag {
+    fn parse(token: &str) -> Self {
+        let Some(at) = token.find(['=', '!', '<', '>']) else {
+            return ManifestFlag {
+                name: token.to_string(),
+                op: None,
+                value: String::new(),
+            };
+        };
+        let (name, rest) = token.split_at(at);
+        let (op, value) = [
+            ("!=", FlagOp::Ne),
+            ("<=", FlagOp::Le),
+            (">=", FlagOp::Ge),
+            ("=", FlagOp::Eq),
+            ("<", FlagOp::Lt),
+            (">", FlagOp::Gt),
+        ]
+        .iter()
+        .find_map(|(text, op)| Some((Some(*op), rest.strip_prefix(text)?)))
+        .unwrap_or((None, rest));
+        ManifestFlag {
+            name: name.to_string(),
+            op,
+            value: value.to_string(),
+        }
+    }
+
+    fn kind(&self) -> Option<FlagKind> {
+        FLAGS
+            .iter()
+            .find(|(name, _)| *name == self.name)
+            .map(|(_, kind)| *kind)
+    }
+}
+
+impl fmt::Display for ManifestFlag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.op {
+            Some(op) => write!(f, "{}{}{}", self.name, op.as_str(), self.value),
+            None => f.write_str(&self.name),
+        }
+    }
+}
+
+/// One instruction of a chrome.manifest with its flags.
+#[derive(Debug, Clone)]
+pub struct ManifestLine {
+    /// 1-based.
+    pub line: usize,
+    /// The instruction and its arguments, e.g. `content mytheme ./`.
+    pub instruction: String,
+    pub flags: Vec<ManifestFlag>,
+}
+
+impl ManifestLine {
+    /// Why Firefox skips this line in the browser described by `info`, or
+    /// `None` if it applies. Flags the browser cannot be checked against
+    /// count as matching.
+    pub fn inactive_reason(&self, info: &BrowserInfo) -> Option<String> {
+        let mut failed = Vec::new();
+        for name in ["application", "os", "abi"] {
+            let actual = match name {
+                "application" => &info.app_id,
+                "os" => &info.platform,
+                _ => &info.abi,
+            };
+            let flags: Vec<&ManifestFlag> =
+                self.flags.iter().filter(|flag| flag.name == name).collect();
+            let wanted: Vec<&&ManifestFlag> = flags
+                .iter()
+                .filter(|flag| flag.op == Some(FlagOp::Eq))
+                .collect();
+            if !wanted.is_empty() && !wanted.iter().any(|flag| flag.value == *actual) {
+                failed.extend(wanted.iter().map(|flag| flag.to_string()));
+            }
+            failed.extend(
+                flags
+                    .iter()
+                    .filter(|flag| flag.op == Some(FlagOp::Ne) && flag.value == *actual)
+                    .map(|flag| flag.to_string()),
+            );
+        }
+        for flag in &self.flags {
+            let actual = match flag.name.as_str() {
+                "appversion" => &info.version,
+                "platformversion" => &info.platform_version,
+                "osversion" => &info.os_version,
+                _ => continue,
+            };
+            if actual.is_empty() {
+                continue;
+            }
+            let ordering = compare_versions(actual, &flag.value);
+            let holds = match flag.op {
+                Some(FlagOp::Eq) => ordering == Ordering::Equal,
+                Some(FlagOp::Lt) => ordering == Ordering::Less,
+                Some(FlagOp::Le) => ordering != Ordering::Greater,
+                Some(FlagOp::Gt) => ordering == Ordering::Greater,
+                Some(FlagOp::Ge) => ordering != Ordering::Less,
+                // Invalid, and reported by the parser
+                Some(FlagOp::Ne) | None => continue,
+            };
+            if !holds {
+                failed.push(flag.to_string());
+            }
+        }
+        if failed.is_empty() {
+            None
+        } else {
+            Some(format!("needs {}", failed.join(" ")))
+        }
+    }
+}
+
+/// A problem with a chrome.manifest line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ManifestIssue {
+    /// 1-based.
+    pub line: usize,
+    pub message: String,
+}
+
+/// A parsed chrome.manifest, used to map local files onto `chrome://` URLs
+/// and to check the flags on each line.
+#[derive(Debug, Clone, Default)]
+pub struct ChromeManifest {
+    pub entries: Vec<ManifestEntry>,
+    pub lines: Vec<ManifestLine>,
+    /// Lines Firefox ignores or is likely to apply differently than meant.
+    pub issues: Vec<ManifestIssue>,
+}
+
+impl ChromeManifest {
+    pub fn parse_file(manifest_path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let content = fs::read_to_string(manifest_path)?;
+        let base = canonicalize(manifest_path)?
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+        Ok(Self::parse(&content, &base))
+    }
+
+    pub fn parse(content: &str, base: &Path) -> Self {
+        let mut manifest = ChromeManifest::default();
+        for (index, line) in content.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let Some(&instruction) = fields.first() else {
+                continue;
+            };
+            let Some(&(_, arguments)) = INSTRUCTIONS.iter().find(|(name, _)| *name == instruction)
+            else {
+                manifest.issue(
+                    index,
+                    format!(
+                        "unknown instruction '{}'; Firefox ignores the line",
+                        instruction
+                    ),
+                );
+                continue;
+            };
+            if fields.len() <= arguments {
+                manifest.issue(
+                    index,
+                    format!(
+                        "'{}' takes {} argument(s); Firefox ignores the line",
+                        instruction, arguments
+                    ),
+                );
+                continue;
+            }
+            let flags: Vec<ManifestFlag> = fields[arguments + 1..]
+                .iter()
+                .map(|token| ManifestFlag::parse(token))
+                .collect();
+            for message in check_flags(instruction, &flags) {
+                manifest.issue(index, message);
+            }
+            manifest.lines.push(ManifestLine {
+                line: index + 1,
+                instruction: fields[..=arguments].join(" "),
+                flags,
+            });
+
+            let (kind, package, location) = match fields.as_slice() {
+                ["content", package, location, ..] => ("content", *package, *location),
+                ["skin", package, _provider, location, ..] => ("skin", *package, *location),
+                ["resource", package, location, ..] => ("resource", *package, *location),
+                _ => continue,
+            };
+            let dir = match file_url_path(location) {
+                Some(dir) => dir,
+                // jar: and other URI locations have no local directory
+                None if location.contains(':') && !Path::new(location).is_absolute() => continue,
+                None => base.join(location),
+            };
+            manifest.entries.push(ManifestEntry {
+                kind: kind.to_string(),
+                package: package.to_string(),
+                dir: normalize_path(&dir),
+            });
+        }
+        manifest
+    }
+
+    fn issue(&mut self, index: usize, message: String) {
+        self.issues.push(ManifestIssue {
+            line: index + 1,
+            message,
+        });
+    }
+
+    /// Lines Firefox skips in the browser described by `info`, with the
+    /// reason.
+    pub fn inactive_lines(&self, info: &BrowserInfo) -> Vec<(&ManifestLine, String)> {
+        self.lines
+            .iter()
+            .filter_map(|line| Some((line, line.inactive_reason(info)?)))
+            .collect()
+    }
+
+    /// The local file a `chrome://` or `resource://` URL loads, if it is in
+    /// one of the mapped directories.
+    pub fn file_for(&self, url: &str) -> Option<PathBuf> {
+        self.entries.iter().find_map(|entry| {
+            let relative = url.strip_prefix(&entry.url_prefix())?;
+            Some(normalize_path(&entry.dir.join(relative)))
+        })
+    }
+
+    /// The `chrome://` (or `resource://`) URL for a local file, preferring the most
+    /// specific package directory.
+    pub fn url_for(&self, file: &Path) -> Option<String> {
+        let file = normalize_path(file);
+        self.entries
+            .iter()
+            .filter(|entry| file.starts_with(&entry.dir))
+            .max_by_key(|entry| entry.dir.components().count())
+            .and_then(|entry| {
+                let relative = file.strip_prefix(&entry.dir).ok()?;
+                let relative: Vec<_> = relative
+                    .components()
+                    .map(|c| c.as_os_str().to_string_lossy().into_owned())
+                    .collect();
+                Some(format!("{}{}", entry.url_prefix(), relative.join("/")))
+            })
+    }
+}
+
+/// Problems with the flags of one `instruction` line.
+fn check_flags(instruction: &str, flags: &[ManifestFlag]) -> Vec<String> {
+    let mut issues = Vec::new();
+    for flag in flags {
+        let Some(kind) = flag.kind() else {
+            let message =
+                if flag.name.is_empty() || flag.name.starts_with(|c: char| c.is_ascii_digit()) {
+                    format!(
+                        "stray '{}': flags are written without spaces, e.g. appversion>=115",
+                        flag
+                    )
+                } else {
+                    match FLAGS
+                        .iter()
+                        .find(|(name, _)| name.eq_ignore_ascii_case(&flag.name))
+                    {
+                        Some((name, _)) => {
+                            format!("unknown flag '{}', did you mean '{}'?", flag.name, name)
+                        }
+                        None => format!("unknown flag '{}'", flag.name),
+                    }
+                };
+            issues.push(format!("{}; Firefox ignores the line", message));
+            continue;
+        };
+
+        let op_ok = match (kind, flag.op) {
+            (FlagKind::String, op) => matches!(op, Some(FlagOp::Eq | FlagOp::Ne)),
+            (FlagKind::Version, op) => matches!(op, Some(op) if op != FlagOp::Ne),
+            (FlagKind::Bool, op) => matches!(op, None | Some(FlagOp::Eq)),
+        };
+        if !op_ok {
+            let expected = match kind {
+                FlagKind::String => "= or !=",
+                FlagKind::Version => "=, <, <=, > or >=",
+                FlagKind::Bool => "=",
+            };
+            issues.push(format!(
+                "'{}': {} takes {}; Firefox ignores the line",
+                flag, flag.name, expected
+            ));
+            continue;
+        }
+        if flag.op.is_some() && flag.value.is_empty() {
+            issues.push(format!("'{}' has no value; Firefox ignores the line", flag));
+            continue;
+        }
+
+        match (kind, flag.name.as_str()) {
+            (FlagKind::String, "os") if !OS_NAMES.contains(&flag.value.as_str()) => {
+                let lower = flag.value.to_ascii_lowercase();
+                let suggestion = OS_NAMES
+                    .iter()
+                    .find(|name| name.eq_ignore_ascii_case(&flag.value))
+                    .copied()
+                    .or_else(|| {
+                        OS_ALIASES
+                            .iter()
+                            .find(|(alias, _)| *alias == lower)
+                            .map(|(_, name)| *name)
+                    });
+                issues.push(match suggestion {
+                    Some(name) => format!("'{}': Firefox calls this OS {}", flag, name),
+                    None => format!(
+                        "'{}' is not an OS Firefox reports; expected one of {}",
+                        flag,
+                        OS_NAMES.join(", ")
+                    ),
+                });
+            }
+            (FlagKind::String, "application")
+                if !flag.value.starts_with('{') && !flag.value.contains('@') =>
+            {
+                issues.push(format!(
+                    "'{}': application takes an application ID, Firefox's is {}",
+                    flag, FIREFOX_ID
+                ));
+            }
+            (FlagKind::String, "process") if !matches!(flag.value.as_str(), "main" | "content") => {
+                issues.push(format!("'{}': process is main or content", flag));
+            }
+            (FlagKind::Version, _) if !is_version(&flag.value) => {
+                issues.push(format!(
+                    "'{}' is not a version like 115, 115.0.2 or 128.*",
+                    flag
+                ));
+            }
+            (FlagKind::Bool, name) => {
+                if !matches!(flag.value.as_str(), "" | "yes" | "no" | "true" | "false") {
+                    issues.push(format!("'{}': {} is yes or no", flag, name));
+                }
+                let applies = match name {
+                    "platform" => &["content", "locale", "skin"][..],
+                    "xpcnativewrappers" => &[][..],
+                    _ => &["content"][..],
+                };
+                if name == "xpcnativewrappers" {
+                    issues.push(format!("'{}' is obsolete and has no effect", flag));
+                } else if !applies.contains(&instruction) {
+                    issues.push(format!(
+                        "'{}' has no effect on '{}' lines",
+                        flag, instruction
+                    ));
+                }
+            }
+            _ => {}
+        }
+    }
+    issues
+}
+
+fn is_version(value: &str) -> bool {
+    !value.is_empty()
+        && value.starts_with(|c: char| c.is_ascii_digit() || c == '*')
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '*' | '+' | '-'))
+}
+
+/// Compare two toolkit version strings like `115.0`, `128.0a1` or `3.*` the
+/// way Firefox does: part by part, with missing parts as 0, `*` above
+/// everything, and a pre-release like `128.0b2` below `128.0`.
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    let mut a = a.split('.');
+    let mut b = b.split('.');
+    loop {
+        match (a.next(), b.next()) {
+            (None, None) => return Ordering::Equal,
+            (x, y) => {
+                let ordering =
+                    VersionPart::parse(x.unwrap_or("0")).cmp(&VersionPart::parse(y.unwrap_or("0")));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+        }
+    }
+}
+
+/// One dot-separated part of a version: a number, a pre-release tag, a
+/// second number and anything left, e.g. `0b2` is 0, `b`, 2.
+#[derive(PartialEq, Eq)]
+struct VersionPart<'a> {
+    major: u64,
+    tag: &'a str,
+    minor: u64,
+    extra: &'a str,
+}
+
+impl<'a> VersionPart<'a> {
+    fn parse(part: &'a str) -> Self {
+        if part == "*" {
+            return VersionPart {
+                major: u64::MAX,
+                tag: "",
+                minor: 0,
+                extra: "",
+            };
+        }
+        let (major, rest) = leading_number(part);
+        // 1.0+ is 1.1pre
+        if rest == "+" {
+            return VersionPart {
+                major: major + 1,
+                tag: "pre",
+                minor: 0,
+                extra: "",
+            };
+        }
+        let tag_end = rest
+            .find(|c: char| c.is_ascii_digit() || c == '+' || c == '-')
+            .unwrap_or(rest.len());
+        let (tag, rest) = rest.split_at(tag_end);
+        let (minor, extra) = leading_number(rest);
+        VersionPart {
+            major,
+            tag,
+            minor,
+            extra,
+        }
+    }
+}
+
+impl Ord for VersionPart<'_> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // A part without a tag is the release, after its pre-releases
+        let tag = |a: &str, b: &str| match (a.is_empty(), b.is_empty()) {
+            (true, true) => Ordering::Equal,
+            (true, false) => Ordering::Greater,
+            (false, true) => Ordering::Less,
+            (false, false) => a.cmp(b),
+        };
+        self.major
+            .cmp(&other.major)
+            .then_with(|| tag(self.tag, other.tag))
+            .then_with(|| self.minor.cmp(&other.minor))
+            .then_with(|| tag(self.extra, other.extra))
+    }
+}
+
+impl PartialOrd for VersionPart<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+fn leading_number(text: &str) -> (u64, &str) {
+    let end = text
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(text.len());
+    (text[..end].parse().unwrap_or(0), &text[end..])
+}
+
+/// Lexically resolve `.` and `..` components without touching the filesystem.
+pub fn normalize_path(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                out.pop();
+            }
+            other => out.push(other.as_os_str()),
+        }
+    }
+    out
+}
@@ -0,0 +1,77 @@
+//! A rendering profile for screenshots that have to match between runs and
+//! machines, as in visual tests on CI.
+//!
+//! [`CiProfile::apply`] fixes what otherwise differs between captures: the
+//! window size, the device pixel ratio, default fonts, animations, and
+//! notifications that pop up over the toolbars. Prefs stay set until the
+//! browser exits.
+
+use crate::chrome_js;
+use crate::marionette_client::MarionetteConnection;
+use crate::window::{set_window_size, WindowRect};
+use serde_json::{json, Value};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CiProfile {
+    pub width: u32,
+    pub height: u32,
+    /// Set through `layout.css.devPixelsPerPx`, whatever the screen's scale.
+    pub device_pixel_ratio: f64,
+    /// Default proportional font size, in pixels.
+    pub font_size: u32,
+}
+
+impl Default for CiProfile {
+    fn default() -> Self {
+        CiProfile {
+            width: 1280,
+            height: 800,
+            device_pixel_ratio: 1.0,
+            font_size: 16,
+        }
+    }
+}
+
+impl CiProfile {
+    /// The prefs the profile sets, by name.
+    pub fn prefs(&self) -> Value {
+        json!({
+            // Animations and transitions
+            "ui.prefersReducedMotion": 1,
+            "toolkit.cosmeticAnimations.enabled": false,
+            // Scale
+            "layout.css.devPixelsPerPx": self.device_pixel_ratio.to_string(),
+            // Fonts
+            "font.default.x-western": "sans-serif",
+            "font.size.variable.x-western": self.font_size,
+            "font.size.monospace.x-western": 13,
+            "font.minimum-size.x-western": 0,
+            // Notifications and prompts
+            "permissions.default.desktop-notification": 2,
+            "permissions.default.geo": 2,
+            "browser.shell.checkDefaultBrowser": false,
+            "browser.startup.upgradeDialog.enabled": false,
+            "browser.uitour.enabled": false,
+        })
+    }
+
+    /// Set the prefs, close open notifications and resize the window. The
+    /// connection must be in the chrome context. Returns the window's size,
+    /// which Firefox may have clamped to the screen.
+    pub fn apply(
+        &self,
+        connection: &mut MarionetteConnection,
+    ) -> Result<WindowRect, Box<dyn std::error::Error>> {
+        let window_type = connection.app_profile()?.window_type;
+        let script = chrome_js::render(
+            chrome_js::CI_PROFILE,
+            &[
+                ("prefs", self.prefs()),
+                ("windowType", Value::from(window_type)),
+            ],
+        );
+        connection.execute_script(&script, None)?;
+        // After the pixel ratio, which changes how big a window fits
+        set_window_size(connection, self.width, self.height)
+    }
+}
@@ -1,9 +1,87 @@
-use crate::marionette_client::{MarionetteConnection, MarionetteSettings};
-use crate::{ChromeCSSManager, ScreenshotManager};
-use clap::{crate_version, App, Arg, SubCommand};
+use clap::{crate_version, App, AppSettings, Arg, ArgMatches, SubCommand};
+use mus_uc_devtools::analyze::{analyze, AnalyzeThresholds, Specificity};
+use mus_uc_devtools::app_profile::AppProfile;
+use mus_uc_devtools::asset_server::{served_url, AssetServer, DEFAULT_PORT};
+use mus_uc_devtools::bench::bench_file;
+use mus_uc_devtools::browser_vars::read_variables;
+use mus_uc_devtools::chrome_css_manager::{
+    report_unresolved, ChromeCSSManagerBuilder, RegisteredSheet, ScannedSheet, SheetType,
+};
+use mus_uc_devtools::chrome_manifest::ChromeManifest;
+use mus_uc_devtools::ci_profile::CiProfile;
+use mus_uc_devtools::compare::{blink_gif, capture_pair, capture_variant, side_by_side, Capture};
+use mus_uc_devtools::compat::report_compat;
+use mus_uc_devtools::config_layers::{Layers, Origin, UserConfig, KEYS};
+use mus_uc_devtools::connection::Backend;
+use mus_uc_devtools::console_log::{ConsoleFilter, ConsoleLog};
+#[cfg(not(target_family = "wasm"))]
+use mus_uc_devtools::container::{
+    mapped_address, prepare, FirefoxChannel, ImageOptions, CONTAINER_PORT,
+};
+use mus_uc_devtools::content_pages::page_filter;
+use mus_uc_devtools::contrast::{check_contrast, default_targets, ContrastTarget};
+use mus_uc_devtools::css_assets::{AssetMode, AssetRewriter};
+use mus_uc_devtools::css_format::CssFormat;
+use mus_uc_devtools::css_imports::{CyclePolicy, ImportResolver, ResolvedStylesheet};
+use mus_uc_devtools::dev_prefs::purpose;
+use mus_uc_devtools::event_log::EventLogLayer;
+use mus_uc_devtools::gallery::{grid_columns, write_gallery, write_grid};
+#[cfg(not(target_family = "wasm"))]
+use mus_uc_devtools::git_source::{commit_range, CommitInfo, GitSnapshot};
+use mus_uc_devtools::helpers::{parse_arg, Helper, HELPERS};
+use mus_uc_devtools::hooks::{HookContext, HookEvent, Hooks};
+use mus_uc_devtools::icons::{check_icon, collect_icons, CONTEXT_PROPERTIES_PREF};
+use mus_uc_devtools::instances::{discover, find_profile, parse_ports, Instance};
+use mus_uc_devtools::json_extract::extract;
+#[cfg(not(target_family = "wasm"))]
+use mus_uc_devtools::launcher::{launch, LaunchOptions, VirtualDisplay};
+use mus_uc_devtools::locale::{locale_status, reset_locale, set_locale, with_locale};
+use mus_uc_devtools::marionette_client::{locate_error, script_file_frame};
+use mus_uc_devtools::matrix::{with_cell, ColorScheme, MatrixCell, MatrixSettings};
+use mus_uc_devtools::media_emulation::{
+    emulate, media_state, reset as reset_media, with_emulation, MediaSetting,
+};
+use mus_uc_devtools::navigation::{navigate, new_tab, switch_tab, tab_handles};
+use mus_uc_devtools::notifications::NotifyLevel;
+use mus_uc_devtools::package::{build_package, FirefoxRange, PackageOptions};
+use mus_uc_devtools::paths::canonicalize;
+use mus_uc_devtools::plugins;
+use mus_uc_devtools::post_process::{CropRect, PostProcess};
+use mus_uc_devtools::preprocess::{DocumentFilter, LegacyNamespace};
+use mus_uc_devtools::profile_import::{
+    copy_through_browser, import_user_chrome, plan_import, user_chrome_path,
+};
+use mus_uc_devtools::project_config::{ProjectConfig, WatchKind, WatchTarget};
+use mus_uc_devtools::pseudo_state::{force_states, release_states, PseudoState};
+use mus_uc_devtools::retry::{RetryPolicy, RetrySettings};
+use mus_uc_devtools::scenario::{Scenario, Step};
+use mus_uc_devtools::screenshot::{parse_hex_color, CaptureMode, ProbePoint};
+use mus_uc_devtools::session::Session;
+use mus_uc_devtools::sheet_state::{DisabledSheet, SheetMetadata, SheetState};
+use mus_uc_devtools::simulate::{release, simulate, SimulateOptions, Surface};
+#[cfg(all(feature = "ssh", not(target_family = "wasm")))]
+use mus_uc_devtools::ssh_tunnel::{SshTarget, SshTunnel};
+use mus_uc_devtools::state_archive::{export_state, import_state, StateExport, StateImport};
+use mus_uc_devtools::symbols::dump_symbols;
+use mus_uc_devtools::targets::{check_targets, TargetStatus};
+use mus_uc_devtools::theme_colors::read_theme_colors;
+use mus_uc_devtools::wait::{parse_duration, WaitCondition, DEFAULT_TIMEOUT};
+use mus_uc_devtools::watch::RollbackPolicy;
+use mus_uc_devtools::watch_lock::active_watchers;
+use mus_uc_devtools::window::{
+    density, maximize_window, parse_size, set_density, set_window_size, window_rect, Density,
+};
+use mus_uc_devtools::wsl::windows_host;
+use mus_uc_devtools::{ChromeCSSManager, MarionetteConnection, MarionetteSettings};
+use similar::TextDiff;
+use std::collections::{BTreeMap, HashSet};
+use std::ffi::OsString;
 use std::fs;
-use std::io::{self, Read, Write};
-use std::path::Path;
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
 
 fn read_input(file: Option<&str>, prompt: &str) -> Result<String, Box<dyn std::error::Error>> {
     match file {
@@ -17,10 +95,673 @@ fn read_input(file: Option<&str>, prompt: &str) -> Result<String, Box<dyn std::e
     }
 }
 
+fn cycle_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("on-cycle")
+        .long("on-cycle")
+        .value_name("POLICY")
+        .help("What to do with circular @imports")
+        .possible_values(&["error", "warn"])
+        .default_value("error")
+        .takes_value(true)
+}
+
+fn cycle_policy(matches: &ArgMatches) -> Result<CyclePolicy, Box<dyn std::error::Error>> {
+    CyclePolicy::parse(matches.value_of("on-cycle").unwrap_or("error"))
+}
+
+/// `--legacy-namespace` and `--document-filter`, for commands that load sheets.
+fn wrapper_args<'a, 'b>() -> [Arg<'a, 'b>; 3] {
+    [
+        Arg::with_name("legacy-namespace")
+            .long("legacy-namespace")
+            .help("Declare the XUL namespace as the default, as older snippets expect"),
+        Arg::with_name("document-filter")
+            .long("document-filter")
+            .value_name("URL")
+            .help("Only apply to this document, with @-moz-document (repeatable)")
+            .multiple(true)
+            .number_of_values(1)
+            .takes_value(true),
+        Arg::with_name("browser-vars")
+            .long("browser-vars")
+            .help("Fill {{name}} placeholders with values from the browser, e.g. {{firefox.version}} (see `vars`)"),
+    ]
+}
+
+/// `--max-width`, `--optimize`, `--crop` and `--strip`, for commands that
+/// save screenshots. `gallery` takes the first two, for its diff images.
+fn post_process_args<'a, 'b>() -> [Arg<'a, 'b>; 4] {
+    [
+        Arg::with_name("max-width")
+            .long("max-width")
+            .value_name("PIXELS")
+            .help("Scale images wider than this down to it")
+            .takes_value(true),
+        Arg::with_name("optimize")
+            .long("optimize")
+            .help("Recompress images as small as they go without losing pixels"),
+        Arg::with_name("crop")
+            .long("crop")
+            .value_name("X,Y,WIDTH,HEIGHT")
+            .help("Keep only this part of each capture, in its pixels, before scaling")
+            .takes_value(true),
+        Arg::with_name("strip")
+            .long("strip")
+            .help("Drop the metadata Firefox writes into the PNGs"),
+    ]
+}
+
+fn post_process(matches: &ArgMatches) -> Result<PostProcess, Box<dyn std::error::Error>> {
+    let max_width = match matches.value_of("max-width") {
+        Some(value) => match value.parse() {
+            Ok(0) | Err(_) => return Err(format!("Invalid --max-width '{}'", value).into()),
+            Ok(width) => Some(width),
+        },
+        None => None,
+    };
+    Ok(PostProcess {
+        crop: matches.value_of("crop").map(CropRect::parse).transpose()?,
+        max_width,
+        strip: matches.is_present("strip"),
+        optimize: matches.is_present("optimize"),
+    })
+}
+
+/// `--all-profiles` and `--scan-ports`, shared by `load` and `watch`.
+fn broadcast_args<'a, 'b>() -> [Arg<'a, 'b>; 2] {
+    [
+        Arg::with_name("all-profiles")
+            .long("all-profiles")
+            .help("Apply to every running browser found in the local profiles, not just the one connected to"),
+        Arg::with_name("scan-ports")
+            .long("scan-ports")
+            .value_name("PORTS")
+            .help("Also look for browsers on these ports, e.g. 2828-2832,6000")
+            .requires("all-profiles")
+            .takes_value(true),
+    ]
+}
+
+/// The browsers to apply a command to with `--all-profiles`, or `None`
+/// without it.
+fn broadcast_instances(
+    matches: &ArgMatches,
+) -> Result<Option<Vec<Instance>>, Box<dyn std::error::Error>> {
+    if !matches.is_present("all-profiles") {
+        return Ok(None);
+    }
+    let settings = marionette_settings();
+    if settings.socket.is_some() {
+        return Err("--all-profiles cannot be used with --socket or --ssh".into());
+    }
+    let mut ports = vec![settings.port];
+    if let Some(spec) = matches.value_of("scan-ports") {
+        ports.extend(parse_ports(spec)?);
+    }
+    let instances = discover(&settings.host, &ports);
+    if instances.is_empty() {
+        return Err(format!(
+            "No browser is listening for Marionette on {}",
+            settings.host
+        )
+        .into());
+    }
+    println!("Found {} browser(s):", instances.len());
+    for instance in &instances {
+        println!("  - {}", instance);
+    }
+    Ok(Some(instances))
+}
+
+/// The connection settings for one of the [`broadcast_instances`].
+fn instance_settings(instance: &Instance) -> MarionetteSettings {
+    let mut settings = marionette_settings();
+    settings.host = instance.host.clone();
+    settings.port = instance.port;
+    settings
+}
+
+/// Add the preprocessors asked for with [`wrapper_args`] and `load --target`.
+fn with_wrappers(
+    mut builder: ChromeCSSManagerBuilder,
+    matches: &ArgMatches,
+) -> ChromeCSSManagerBuilder {
+    let documents: Vec<&str> = matches
+        .values_of("document-filter")
+        .map(|v| v.collect())
+        .unwrap_or_default();
+    let mut filter = DocumentFilter::new(&documents);
+    if let Some(pages) = matches.values_of("target") {
+        filter
+            .documents
+            .extend(page_filter(&pages.collect::<Vec<_>>()).documents);
+    }
+    if !filter.documents.is_empty() {
+        builder = builder.preprocessor(filter);
+    }
+    if matches.is_present("legacy-namespace") {
+        builder = builder.preprocessor(LegacyNamespace);
+    }
+    builder.browser_variables(matches.is_present("browser-vars"))
+}
+
+fn console_filter_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("filter")
+        .long("filter")
+        .value_name("KIND")
+        .help("Which console messages to keep (default: all)")
+        .possible_values(&["all", "css", "js"])
+        .takes_value(true)
+}
+
+fn console_filter(matches: &ArgMatches) -> Result<ConsoleFilter, Box<dyn std::error::Error>> {
+    ConsoleFilter::parse(matches.value_of("filter").unwrap_or("all"))
+}
+
+fn tag_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("tag")
+        .short("t")
+        .long("tag")
+        .value_name("TAG")
+        .help("Tag the stylesheet (repeatable)")
+        .multiple(true)
+        .number_of_values(1)
+        .takes_value(true)
+}
+
+fn group_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("group")
+        .long("group")
+        .value_name("GROUP")
+        .help("Put the stylesheet in a group, to switch it with `toggle --group`")
+        .takes_value(true)
+}
+
+fn description_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("description")
+        .long("description")
+        .value_name("TEXT")
+        .help("Short description shown by `list`")
+        .takes_value(true)
+}
+
+/// Run the `on_load` hooks for the sheet `id` loaded from `source`.
+fn run_load_hooks(manager: &ChromeCSSManager, id: &str, source: Option<&Path>) {
+    manager.run_hooks(
+        HookEvent::Load,
+        &HookContext {
+            id: Some(id.to_string()),
+            file: source.map(Path::to_path_buf),
+            errors: Vec::new(),
+        },
+    );
+}
+
+/// Remember where a sheet came from and how it is tagged and grouped, for
+/// `list`, `unload --tag` and `toggle`.
+fn record_sheet(manager: &ChromeCSSManager, id: &str, source: Option<&Path>, matches: &ArgMatches) {
+    let tags = matches
+        .values_of("tag")
+        .map(|values| values.map(String::from).collect())
+        .unwrap_or_default();
+    let description = matches.value_of("description").map(String::from);
+    let group = matches.value_of("group").map(String::from);
+    save_sheet_metadata(&manager.state_dir(), id, source, tags, description, group);
+}
+
+/// Record sheet `id` in the state file in `state_dir`.
+fn save_sheet_metadata(
+    state_dir: &Path,
+    id: &str,
+    source: Option<&Path>,
+    tags: Vec<String>,
+    description: Option<String>,
+    group: Option<String>,
+) {
+    let source = source.map(|path| {
+        canonicalize(path)
+            .unwrap_or_else(|_| path.to_path_buf())
+            .display()
+            .to_string()
+    });
+    let metadata = SheetMetadata {
+        group,
+        ..SheetMetadata::new(source, tags, description)
+    };
+    let result = SheetState::update_in(state_dir, |state| state.record(id, metadata));
+    if let Err(e) = result {
+        eprintln!(
+            "Warning: could not update {}: {}",
+            state_dir.join("sheets.json").display(),
+            e
+        );
+    }
+}
+
+/// Drop `ids` from the state file in `state_dir`.
+fn forget_sheets(state_dir: &Path, ids: &[String]) {
+    let result = SheetState::update_in(state_dir, |state| {
+        for id in ids {
+            state.sheets.remove(id);
+        }
+    });
+    if let Err(e) = result {
+        eprintln!(
+            "Warning: could not update {}: {}",
+            state_dir.join("sheets.json").display(),
+            e
+        );
+    }
+}
+
+/// Where to reach Marionette, set from the top-level options.
+static SETTINGS: OnceLock<MarionetteSettings> = OnceLock::new();
+
+fn marionette_settings() -> MarionetteSettings {
+    SETTINGS
+        .get()
+        .cloned()
+        .unwrap_or_else(MarionetteSettings::new)
+}
+
+/// The rendering profile applied before captures, set by `--ci`.
+static CI_PROFILE: OnceLock<CiProfile> = OnceLock::new();
+
+/// Apply the `--ci` profile, if given, before taking screenshots.
+fn prepare_capture(manager: &ChromeCSSManager) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(profile) = CI_PROFILE.get() else {
+        return Ok(());
+    };
+    let rect = manager.connection().with(|c| profile.apply(c))?;
+    if rect.width != profile.width || rect.height != profile.height {
+        eprintln!(
+            "Warning: the window is {}x{}, not {}x{}; the screen may be too small",
+            rect.width, rect.height, profile.width, profile.height
+        );
+    }
+    Ok(())
+}
+
+/// How this process appears in the harness's audit trail, e.g.
+/// `mus-uc watch (pid 4242)`; set from the subcommand.
+static CALLER: OnceLock<String> = OnceLock::new();
+
+fn caller() -> &'static str {
+    CALLER.get().map(String::as_str).unwrap_or("mus-uc")
+}
+
+fn connect_manager() -> Result<ChromeCSSManager, Box<dyn std::error::Error>> {
+    let mut manager = ChromeCSSManager::builder()
+        .settings(marionette_settings())
+        .caller(caller())
+        .hooks(hooks())
+        .build()?;
+    manager.set_retry_policy(retry_policy());
+    Ok(manager)
+}
+
+/// How long element-dependent commands wait for the UI, set by `--retry-*`
+/// and the `[retry]` table of `mus-uc.toml`.
+static RETRY: OnceLock<RetryPolicy> = OnceLock::new();
+
+fn retry_policy() -> RetryPolicy {
+    RETRY.get().copied().unwrap_or_default()
+}
+
+/// The retry policy the `retry.*` settings describe.
+fn retry_from(layers: &Layers) -> Result<RetryPolicy, Box<dyn std::error::Error>> {
+    let settings = RetrySettings {
+        attempts: layers
+            .parse::<u64>("retry.attempts")?
+            .map(|n| n.min(u32::MAX as u64) as u32),
+        interval: layers.parse("retry.interval")?,
+        timeout: layers.parse("retry.timeout")?,
+    };
+    Ok(settings.policy())
+}
+
+/// The `[hooks]` of the nearest `mus-uc.toml`, or of the one `watch` reads.
+static HOOKS: OnceLock<Hooks> = OnceLock::new();
+
+fn hooks() -> Hooks {
+    HOOKS.get().cloned().unwrap_or_default()
+}
+
+/// The `mus-uc.toml` whose settings apply to the whole command: the config
+/// `watch` reads (see [`project_config`]), or for other commands the nearest
+/// one.
+fn command_config(matches: &ArgMatches) -> Result<Option<PathBuf>, Box<dyn std::error::Error>> {
+    match matches.subcommand() {
+        ("watch", Some(sub_matches)) => project_config(sub_matches),
+        _ => Ok(ProjectConfig::find(&std::env::current_dir()?)),
+    }
+}
+
+/// The top-level options that set a layered setting, by key.
+const LAYERED_OPTIONS: &[(&str, &str)] = &[
+    ("host", "host"),
+    ("port", "port"),
+    ("socket", "socket"),
+    ("app", "app"),
+    ("backend", "backend"),
+    ("retry.attempts", "retry-attempts"),
+    ("retry.interval", "retry-interval"),
+    ("retry.timeout", "retry-timeout"),
+];
+
+/// The settings of the config files and the environment, overridden by the
+/// top-level options.
+fn layers_from(
+    matches: &ArgMatches,
+    project: Option<(&Path, &ProjectConfig)>,
+) -> Result<Layers, Box<dyn std::error::Error>> {
+    let mut layers =
+        Layers::load(project.map(|(path, config)| (path, &config.connection, &config.retry)));
+    for (key, option) in LAYERED_OPTIONS {
+        if let Some(value) = matches.value_of(option) {
+            layers.set(
+                key,
+                value.to_string(),
+                Origin::Flag(format!("--{}", option)),
+            );
+        }
+    }
+    if matches.is_present("wsl") {
+        layers.set(
+            "host",
+            windows_host()?.to_string(),
+            Origin::Flag("--wsl".to_string()),
+        );
+    }
+    if let Some(container) = matches.value_of("container") {
+        let (host, port) = container_address(container)?;
+        let origin = || Origin::Flag("--container".to_string());
+        layers.set("host", host, origin());
+        layers.set("port", port.to_string(), origin());
+    }
+    Ok(layers)
+}
+
+/// Where the host reaches Marionette in the container `NAME[:PORT]`.
+#[cfg(not(target_family = "wasm"))]
+fn container_address(container: &str) -> Result<(String, u16), Box<dyn std::error::Error>> {
+    let (name, port) = match container.rsplit_once(':') {
+        Some((name, port)) => (
+            name,
+            port.parse()
+                .map_err(|_| format!("Invalid port in --container {}", container))?,
+        ),
+        None => (container, CONTAINER_PORT),
+    };
+    mapped_address(name, port)
+}
+
+#[cfg(target_family = "wasm")]
+fn container_address(_container: &str) -> Result<(String, u16), Box<dyn std::error::Error>> {
+    Err("--container is not available in WASI builds".into())
+}
+
+/// Connection settings from the layered `host`, `port`, `socket`, `app` and
+/// `backend`, and the top-level `--ssh` option.
+fn settings_from(
+    layers: &Layers,
+    matches: &ArgMatches,
+) -> Result<MarionetteSettings, Box<dyn std::error::Error>> {
+    let from = |key: &str, e: Box<dyn std::error::Error>| -> Box<dyn std::error::Error> {
+        match layers.origin(key) {
+            Some(origin) => format!("{} (from {})", e, origin).into(),
+            None => e,
+        }
+    };
+    let mut settings = MarionetteSettings::new();
+    if let Some(backend) = layers.get("backend") {
+        settings.backend = Backend::parse(backend).map_err(|e| from("backend", e))?;
+        settings.port = settings.backend.default_port();
+    }
+    if let Some(app) = layers.get("app") {
+        let profile = AppProfile::named(app).map_err(|e| from("app", e))?;
+        settings.port = profile.default_port;
+        settings.app = Some(profile);
+    }
+    if let Some(port) = layers.parse("port")? {
+        settings.port = port;
+    }
+    if let Some(host) = layers.get("host") {
+        settings.host = host.to_string();
+    }
+    settings.socket = layers.socket().map(PathBuf::from);
+    if let Some(target) = matches.value_of("ssh") {
+        ssh_settings(target, &mut settings)?;
+    }
+    Ok(settings)
+}
+
+/// Forward a local port to `settings.port` on `target` and point `settings`
+/// at it. The backend and app stay as they are.
+#[cfg(all(feature = "ssh", not(target_family = "wasm")))]
+fn ssh_settings(
+    target: &str,
+    settings: &mut MarionetteSettings,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let tunnel = SshTunnel::open(&SshTarget::parse(target)?, settings.port)?;
+    settings.host = "127.0.0.1".to_string();
+    settings.port = tunnel.local_port();
+    settings.socket = None;
+    Ok(())
+}
+
+#[cfg(not(all(feature = "ssh", not(target_family = "wasm"))))]
+fn ssh_settings(
+    _target: &str,
+    _settings: &mut MarionetteSettings,
+) -> Result<(), Box<dyn std::error::Error>> {
+    Err("--ssh needs a native build with the ssh feature".into())
+}
+
+/// Watch every target of a project config: `-c`, the `-p` member of the
+/// workspace, or the nearest `mus-uc.toml` above the current directory. With
+/// `--all`, the targets of every workspace member are watched as well.
+fn watch_project(
+    manager: &mut ChromeCSSManager,
+    matches: &ArgMatches,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let path = project_config(matches)?
+        .ok_or("No --file given and no mus-uc.toml found in this directory or its parents")?;
+    let config = ProjectConfig::load(&path)?;
+    // Each target with the member it comes from, if any
+    let mut targets: Vec<(Option<String>, WatchTarget)> = Vec::new();
+    if matches.is_present("all") {
+        let members = config.members();
+        if members.is_empty() {
+            return Err(format!("{} has no [workspace] members", path.display()).into());
+        }
+        targets.extend(config.watch.into_iter().map(|target| (None, target)));
+        for (name, member) in members {
+            let member_targets = ProjectConfig::load(&member)?.watch;
+            targets.extend(
+                member_targets
+                    .into_iter()
+                    .map(|target| (Some(name.clone()), target)),
+            );
+        }
+    } else {
+        if config.watch.is_empty() && !config.workspace.members.is_empty() {
+            return Err(format!(
+                "{} has no [[watch]] targets of its own; use --all for every workspace member, or -p <member>",
+                path.display()
+            )
+            .into());
+        }
+        targets.extend(config.watch.into_iter().map(|target| (None, target)));
+    }
+    if targets.is_empty() {
+        return Err(format!("{} has no [[watch]] targets", path.display()).into());
+    }
+
+    let mut ids = HashSet::new();
+    for (member, target) in &mut targets {
+        if target.kind != WatchKind::Css {
+            continue;
+        }
+        let mut id = match &target.id {
+            Some(id) => id.clone(),
+            None => manager.generate_id(Some(&target.file), "")?,
+        };
+        if let Some(member) = member {
+            id = format!("{}/{}", member, id);
+        }
+        if !ids.insert(id.clone()) {
+            return Err(format!("Sheet ID '{}' is used by two targets", id).into());
+        }
+        target.id = Some(id);
+    }
+    let targets: Vec<WatchTarget> = targets.into_iter().map(|(_, target)| target).collect();
+
+    println!(
+        "Watching {} targets from {} (Ctrl+C to stop)...",
+        targets.len(),
+        path.display()
+    );
+    manager.watch_targets(&targets)
+}
+
+/// The project config a command reads: `-c`, the config of the `-p` member of
+/// the workspace, or the nearest `mus-uc.toml`.
+fn project_config(matches: &ArgMatches) -> Result<Option<PathBuf>, Box<dyn std::error::Error>> {
+    ProjectConfig::locate(
+        &std::env::current_dir()?,
+        matches.value_of("config").map(Path::new),
+        matches.value_of("member"),
+    )
+}
+
+fn member_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("member")
+        .short("p")
+        .long("member")
+        .value_name("NAME")
+        .help(
+            "Use the mus-uc.toml of this workspace member; with -c, of the workspace in that file",
+        )
+        .takes_value(true)
+}
+
+/// The connection options `--container` replaces.
+#[cfg(feature = "ssh")]
+const CONTAINER_CONFLICTS: &[&str] = &["host", "port", "socket", "wsl", "ssh"];
+#[cfg(not(feature = "ssh"))]
+const CONTAINER_CONFLICTS: &[&str] = &["host", "port", "socket", "wsl"];
+
 pub fn run_cli() -> Result<(), Box<dyn std::error::Error>> {
-    let matches = App::new("mus-uc-devtools")
+    let app_names = AppProfile::names();
+    let app = App::new("mus-uc-devtools")
         .version(crate_version!())
         .about("Loads userChrome CSS into Firefox chrome context via Marionette")
+        .after_help(
+            "Other commands run an executable named mus-uc-<COMMAND> from PATH (see `plugins`).",
+        )
+        .setting(AppSettings::AllowExternalSubcommands);
+    #[cfg(feature = "ssh")]
+    let app = app.arg(
+        Arg::with_name("ssh")
+            .long("ssh")
+            .value_name("USER@HOST")
+            .help("Reach Firefox on another machine through an SSH tunnel to its Marionette port")
+            .conflicts_with_all(&["host", "socket", "wsl"])
+            .takes_value(true),
+    );
+    let matches = app
+        .arg(
+            Arg::with_name("app")
+                .long("app")
+                .value_name("APP")
+                .help("Firefox fork to drive (default: detected from the browser)")
+                .possible_values(&app_names)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("backend")
+                .long("backend")
+                .value_name("BACKEND")
+                .help("Protocol to drive Firefox with (rdp: the DevTools server on port 6000; bidi: WebDriver BiDi on port 9222)")
+                .possible_values(&["marionette", "rdp", "bidi"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("retry-attempts")
+                .long("retry-attempts")
+                .value_name("N")
+                .help("Try element screenshots, forced states, simulated surfaces and scenario steps up to N times while the UI is missing")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("retry-interval")
+                .long("retry-interval")
+                .value_name("MS")
+                .help("Wait between those tries (default: 250)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("retry-timeout")
+                .long("retry-timeout")
+                .value_name("MS")
+                .help("Stop retrying after this long; retries until then if --retry-attempts is not given")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("log-file")
+                .long("log-file")
+                .value_name("FILE")
+                .help("Append a JSON line for the command's start and end, with its duration and result")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("ci")
+                .long("ci")
+                .help("Fix window size, pixel ratio, fonts and animations before screenshots"),
+        )
+        .arg(
+            Arg::with_name("read-only")
+                .long("read-only")
+                .help("Refuse commands that change the browser; listing, screenshots and queries still work")
+                .conflicts_with("ci"),
+        )
+        .arg(
+            Arg::with_name("host")
+                .long("host")
+                .value_name("HOST")
+                .help("Marionette host (default: localhost)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("port")
+                .long("port")
+                .value_name("PORT")
+                .help("Marionette port (default: 2828)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("socket")
+                .long("socket")
+                .value_name("PATH")
+                .help("Connect through a Unix socket forwarded to Marionette")
+                .conflicts_with_all(&["host", "port", "wsl"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("container")
+                .long("container")
+                .value_name("NAME[:PORT]")
+                .help("Connect to Firefox in a Docker container, through the host port Docker mapped to its Marionette port (default: 2828, as ci-image publishes it)")
+                .conflicts_with_all(CONTAINER_CONFLICTS)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("wsl")
+                .long("wsl")
+                .help("From inside WSL, connect to Firefox running on the Windows host")
+                .conflicts_with("host"),
+        )
         .subcommand(
             SubCommand::with_name("load")
                 .about("Load CSS from file or stdin")
@@ -29,7 +770,9 @@ pub fn run_cli() -> Result<(), Box<dyn std::error::Error>> {
                         .short("f")
                         .long("file")
                         .value_name("FILE")
-                        .help("CSS file to load")
+                        .help("CSS file to load (repeat to load several files at once)")
+                        .multiple(true)
+                        .number_of_values(1)
                         .takes_value(true),
                 )
                 .arg(
@@ -39,205 +782,4507 @@ pub fn run_cli() -> Result<(), Box<dyn std::error::Error>> {
                         .value_name("ID")
                         .help("Custom ID for the stylesheet")
                         .takes_value(true),
+                )
+                .arg(tag_arg())
+                .arg(description_arg())
+                .arg(group_arg())
+                .arg(persist_arg())
+                .arg(
+                    Arg::with_name("sheet-type")
+                        .long("sheet-type")
+                        .value_name("TYPE")
+                        .help("Style sheet level to register at")
+                        .possible_values(&["user", "agent", "author"])
+                        .default_value("user")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("strict")
+                        .long("strict")
+                        .help("Refuse to load files with compatibility warnings"),
+                )
+                .args(&wrapper_args())
+                .args(&broadcast_args())
+                .arg(
+                    Arg::with_name("target")
+                        .long("target")
+                        .value_name("PAGE")
+                        .help("Style an in-content page such as about:preferences, and check that it applies (repeatable)")
+                        .multiple(true)
+                        .number_of_values(1)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("git-ref")
+                        .long("git-ref")
+                        .value_name("REV")
+                        .help("Load the file as it was at a git revision")
+                        .requires("file")
+                        .takes_value(true),
+                )
+                .arg(cycle_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("icons")
+                .about("Check that SVG icons have a viewBox and follow the theme through context-fill")
+                .arg(
+                    Arg::with_name("PATH")
+                        .help("SVG file, directory of icons, or stylesheet whose icons to check")
+                        .required(true)
+                        .multiple(true),
+                )
+                .arg(
+                    Arg::with_name("fix")
+                        .long("fix")
+                        .help("Rewrite single-color icons to fill with context-fill"),
                 ),
         )
         .subcommand(
-            SubCommand::with_name("watch")
-                .about("Watch CSS file for changes and auto-reload")
+            SubCommand::with_name("analyze")
+                .about("Report size, selector counts, specificity and expensive selectors of a stylesheet")
                 .arg(
-                    Arg::with_name("file")
-                        .short("f")
-                        .long("file")
-                        .value_name("FILE")
-                        .help("CSS file to watch")
+                    Arg::with_name("FILE")
+                        .help("CSS file to analyze, with its imports")
                         .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .help("Print the report as JSON"),
+                )
+                .arg(
+                    Arg::with_name("config")
+                        .short("c")
+                        .long("config")
+                        .value_name("FILE")
+                        .help("Project config with [analyze] limits (default: nearest mus-uc.toml)")
                         .takes_value(true),
                 )
+                .arg(member_arg())
                 .arg(
-                    Arg::with_name("id")
-                        .short("i")
-                        .long("id")
-                        .value_name("ID")
-                        .help("Custom ID for the stylesheet")
+                    Arg::with_name("max-bytes")
+                        .long("max-bytes")
+                        .value_name("N")
+                        .help("Fail when the bundle is larger")
                         .takes_value(true),
-                ),
+                )
+                .arg(
+                    Arg::with_name("max-selectors")
+                        .long("max-selectors")
+                        .value_name("N")
+                        .help("Fail when there are more selectors")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("max-important")
+                        .long("max-important")
+                        .value_name("N")
+                        .help("Fail when more declarations are !important")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("max-specificity")
+                        .long("max-specificity")
+                        .value_name("A,B,C")
+                        .help("Fail when a selector is more specific")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("max-expensive")
+                        .long("max-expensive")
+                        .value_name("N")
+                        .help("Fail when more selectors are flagged as expensive")
+                        .takes_value(true),
+                )
+                .arg(cycle_arg()),
         )
         .subcommand(
-            SubCommand::with_name("register-manifest")
-                .about("Register chrome.manifest to enable chrome:// URIs in CSS imports")
+            SubCommand::with_name("bench")
+                .about("Measure the style, layout and paint cost of a stylesheet on common window operations")
                 .arg(
-                    Arg::with_name("manifest")
-                        .short("m")
-                        .long("manifest")
-                        .value_name("MANIFEST")
-                        .help("Path to chrome.manifest file")
+                    Arg::with_name("file")
+                        .short("f")
+                        .long("file")
+                        .value_name("FILE")
+                        .help("Stylesheet to measure")
                         .required(true)
                         .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("iterations")
+                        .short("n")
+                        .long("iterations")
+                        .value_name("N")
+                        .help("Runs of each operation")
+                        .default_value("20"),
+                )
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .help("Print the report as JSON"),
                 ),
         )
         .subcommand(
-            SubCommand::with_name("unload")
-                .about("Unload CSS by ID")
+            SubCommand::with_name("compare")
+                .about("Screenshot the UI with each of two stylesheets and put the captures side by side")
                 .arg(
-                    Arg::with_name("id")
+                    Arg::with_name("a")
                         .required(true)
-                        .help("ID of stylesheet to unload")
+                        .value_name("A")
+                        .help("First stylesheet, shown on the left or first")
                         .index(1),
-                ),
-        )
-        .subcommand(SubCommand::with_name("clear").about("Clear all loaded stylesheets"))
-        .subcommand(SubCommand::with_name("list").about("List all loaded stylesheets"))
-        .subcommand(SubCommand::with_name("interactive").about("Start interactive mode"))
-        .subcommand(
-            SubCommand::with_name("screenshot")
-                .about("Take a screenshot of the browser window")
+                )
+                .arg(
+                    Arg::with_name("b")
+                        .required_unless("variant")
+                        .value_name("B")
+                        .help("Second stylesheet; without it, A is compared with itself under --variant")
+                        .index(2),
+                )
+                .arg(
+                    Arg::with_name("variant")
+                        .long("variant")
+                        .value_name("SETTING")
+                        .help("Capture with this accessibility setting turned on")
+                        .possible_values(MediaSetting::NAMES)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("selector")
+                        .short("s")
+                        .long("selector")
+                        .value_name("SELECTOR")
+                        .help("CSS selector of the element to capture (default: the whole window)")
+                        .takes_value(true),
+                )
                 .arg(
                     Arg::with_name("output")
                         .short("o")
                         .long("output")
                         .value_name("FILE")
-                        .help("Output file path (default: screenshot.png)")
+                        .help("Where to save the comparison; a .gif file alternates between the two")
+                        .default_value("compare.png")
                         .takes_value(true),
                 )
                 .arg(
-                    Arg::with_name("selector")
-                        .short("s")
-                        .long("selector")
-                        .value_name("CSS_SELECTOR")
-                        .help("CSS selector to capture a specific element (default: full screen)")
+                    Arg::with_name("delay")
+                        .long("delay")
+                        .value_name("MS")
+                        .help("How long each capture shows in a GIF")
+                        .default_value("800")
                         .takes_value(true),
                 ),
         )
         .subcommand(
-            SubCommand::with_name("exec")
-                .about("Execute JavaScript in Firefox chrome context")
+            SubCommand::with_name("bisect")
+                .about("Load successive git revisions of a stylesheet and screenshot each one")
                 .arg(
                     Arg::with_name("file")
                         .short("f")
                         .long("file")
                         .value_name("FILE")
-                        .help("JavaScript file to execute")
+                        .help("CSS file to load at each revision")
+                        .required(true)
                         .takes_value(true),
                 )
                 .arg(
-                    Arg::with_name("args")
-                        .short("a")
-                        .long("args")
-                        .value_name("JSON")
-                        .help("Arguments to pass to the script as JSON array")
+                    Arg::with_name("good")
+                        .long("good")
+                        .value_name("REV")
+                        .help("Last revision known to look right")
+                        .required(true)
                         .takes_value(true),
-                ),
-        )
-        .get_matches();
+                )
+                .arg(
+                    Arg::with_name("bad")
+                        .long("bad")
+                        .value_name("REV")
+                        .help("Revision that looks wrong")
+                        .default_value("HEAD")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .value_name("DIR")
+                        .help("Directory for screenshots")
+                        .default_value("bisect-screenshots")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("selector")
+                        .short("s")
+                        .long("selector")
+                        .value_name("CSS_SELECTOR")
+                        .help("CSS selector to capture a specific element (default: full screen)")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("all")
+                        .long("all")
+                        .help("Screenshot every revision in the range instead of bisecting"),
+                )
+                .arg(cycle_arg())
+                .args(&post_process_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("gallery")
+                .about("Write an HTML report of a directory of screenshots, optionally compared with a baseline")
+                .arg(
+                    Arg::with_name("dir")
+                        .required(true)
+                        .value_name("DIR")
+                        .help("Directory of screenshots, searched recursively")
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("baseline")
+                        .short("b")
+                        .long("baseline")
+                        .value_name("DIR")
+                        .help("Directory of earlier screenshots to diff against, matched by path")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("grid")
+                        .long("grid")
+                        .help("Lay the screenshots out in a table with one column per subdirectory")
+                        .conflicts_with("baseline"),
+                )
+                .arg(
+                    Arg::with_name("out")
+                        .short("o")
+                        .long("out")
+                        .value_name("FILE")
+                        .help("Where to write the report")
+                        .default_value("report.html")
+                        .takes_value(true),
+                )
+                .args(&post_process_args()[..2]),
+        )
+        .subcommand(
+            SubCommand::with_name("watch")
+                .about("Watch CSS file for changes and auto-reload")
+                .arg(
+                    Arg::with_name("file")
+                        .short("f")
+                        .long("file")
+                        .value_name("FILE")
+                        .help("CSS file to watch (default: the targets in mus-uc.toml)")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("config")
+                        .short("c")
+                        .long("config")
+                        .value_name("FILE")
+                        .help("Project config listing the files to watch (default: nearest mus-uc.toml)")
+                        .conflicts_with("file")
+                        .takes_value(true),
+                )
+                .arg(member_arg().conflicts_with("file"))
+                .arg(
+                    Arg::with_name("all")
+                        .long("all")
+                        .help("Watch the targets of every workspace member, with sheet IDs prefixed by the member name")
+                        .conflicts_with_all(&["file", "member"]),
+                )
+                .arg(
+                    Arg::with_name("id")
+                        .short("i")
+                        .long("id")
+                        .value_name("ID")
+                        .help("Custom ID for the stylesheet")
+                        .requires("file")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("dir")
+                        .short("d")
+                        .long("dir")
+                        .value_name("DIR")
+                        .help("Watch every file under DIR and reload when an imported file changes")
+                        .requires("file")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("ignore")
+                        .long("ignore")
+                        .value_name("PATTERN")
+                        .help("Leave files under --dir matching this .gitignore-style pattern alone, e.g. \"node_modules/**\" (repeatable)")
+                        .requires("dir")
+                        .multiple(true)
+                        .number_of_values(1)
+                        .takes_value(true),
+                )
+                .arg(tag_arg().requires("file"))
+                .arg(description_arg().requires("file"))
+                .arg(group_arg().requires("file"))
+                .arg(persist_arg())
+                .arg(
+                    Arg::with_name("console-log")
+                        .long("console-log")
+                        .value_name("FILE")
+                        .help("Append browser console messages to FILE as JSON lines")
+                        .takes_value(true),
+                )
+                .arg(console_filter_arg().requires("console-log"))
+                .arg(
+                    Arg::with_name("notify")
+                        .long("notify")
+                        .value_name("LEVEL")
+                        .help("Desktop notifications: errors only, every reload, or none")
+                        .possible_values(&["errors", "all", "off"])
+                        .default_value("off")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("term-status")
+                        .long("term-status")
+                        .help("Show reloading, ok or error in the terminal title and progress indicator, e.g. for tmux or kitty"),
+                )
+                .arg(
+                    Arg::with_name("heartbeat")
+                        .long("heartbeat")
+                        .value_name("INTERVAL")
+                        .help("How often to check that the browser still answers, e.g. 2s; 0 turns it off (default: 5s)")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("poll")
+                        .long("poll")
+                        .value_name("INTERVAL")
+                        .help("Find changes by reading the files every INTERVAL, e.g. 1s, for network mounts and containers where change notifications do not arrive")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("profile-reload")
+                        .long("profile-reload")
+                        .help("Record a Gecko profile of each reload for the Firefox Profiler"),
+                )
+                .arg(
+                    Arg::with_name("profile-dir")
+                        .long("profile-dir")
+                        .value_name("DIR")
+                        .help("Where to save the profiles (default: mus-uc-profiles)")
+                        .requires("profile-reload")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("rollback-errors")
+                        .long("rollback-errors")
+                        .value_name("N")
+                        .help("Undo a reload that has more than N CSS errors")
+                        .takes_value(true),
+                )
+                .args(&wrapper_args())
+                .args(&broadcast_args())
+                .arg(
+                    Arg::with_name("verify")
+                        .long("verify")
+                        .value_name("FILE")
+                        .help("Chrome script run after each reload; undo the reload if it throws or returns false")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("metrics-json")
+                        .long("metrics-json")
+                        .value_name("FILE")
+                        .help("Write reload counts, latency, errors and connection state to FILE after each reload")
+                        .conflicts_with("all-profiles")
+                        .takes_value(true),
+                )
+                .arg(cycle_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("export")
+                .about("Bundle a stylesheet and its local imports into a single file")
+                .arg(
+                    Arg::with_name("file")
+                        .short("f")
+                        .long("file")
+                        .value_name("FILE")
+                        .help("Entry CSS file")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .value_name("FILE")
+                        .help("Output file path (default: stdout)")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("assets")
+                        .long("assets")
+                        .value_name("MODE")
+                        .help("How to rewrite relative url() references")
+                        .possible_values(&["keep", "rebase", "inline", "chrome"])
+                        .default_value("rebase")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("inline-limit")
+                        .long("inline-limit")
+                        .value_name("BYTES")
+                        .help("Largest asset to embed as a data: URI with --assets inline")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("manifest")
+                        .short("m")
+                        .long("manifest")
+                        .value_name("MANIFEST")
+                        .help("chrome.manifest used to map assets with --assets chrome")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("served-dir")
+                        .long("served-dir")
+                        .value_name("DIR")
+                        .help("Directory given to serve-assets; its URLs are rewritten like local files")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("served-url")
+                        .long("served-url")
+                        .value_name("URL")
+                        .help("URL the served directory was referred to by (default: http://localhost:8900/)")
+                        .requires("served-dir")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("source-map")
+                        .long("source-map")
+                        .help("Write a source map next to the output file"),
+                )
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .value_name("STYLE")
+                        .help("Reformat the bundle")
+                        .possible_values(&["preserve", "pretty", "compact"])
+                        .default_value("preserve")
+                        .takes_value(true),
+                )
+                .arg(cycle_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("serve-assets")
+                .about("Serve a directory over HTTP so CSS can use live-edited assets (Ctrl+C to stop)")
+                .arg(
+                    Arg::with_name("DIR")
+                        .help("Directory to serve")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("port")
+                        .long("port")
+                        .value_name("PORT")
+                        .help("Port to listen on (default: 8900)")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("launch")
+                .about("Start Firefox with Marionette on --port and keep it running until it exits")
+                .arg(
+                    Arg::with_name("firefox")
+                        .long("firefox")
+                        .value_name("PATH")
+                        .help("Firefox executable (default: firefox on PATH)")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("profile")
+                        .long("profile")
+                        .value_name("DIR")
+                        .help("Profile to run with (default: a fresh one, removed when Firefox exits)")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("virtual-display")
+                        .long("virtual-display")
+                        .value_name("MODE")
+                        .help("Without a desktop, start Xvfb, or run headless if it is not installed; xvfb or headless to force one")
+                        .possible_values(&["auto", "xvfb", "headless"])
+                        .min_values(0)
+                        .max_values(1)
+                        .require_equals(true),
+                )
+                .arg(
+                    Arg::with_name("size")
+                        .long("size")
+                        .value_name("WIDTHxHEIGHT")
+                        .help("Screen size of Xvfb or the headless window (default: 1920x1080)")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("timeout")
+                        .long("timeout")
+                        .value_name("DURATION")
+                        .help("How long to wait for Marionette, e.g. 30s (default: 30s)")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("ci-image")
+                .about("Set up a Docker image that runs Firefox for CI")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("prepare")
+                        .about("Write a Dockerfile, entrypoint and compose.yaml running Firefox on Xvfb with Marionette published")
+                        .arg(
+                            Arg::with_name("out")
+                                .short("o")
+                                .long("out")
+                                .value_name("DIR")
+                                .help("Directory to write the files to")
+                                .default_value("ci-image"),
+                        )
+                        .arg(
+                            Arg::with_name("name")
+                                .long("name")
+                                .value_name("NAME")
+                                .help("Compose service and container name, for --container")
+                                .default_value("mus-uc-firefox"),
+                        )
+                        .arg(
+                            Arg::with_name("binary")
+                                .long("binary")
+                                .value_name("FILE")
+                                .help("Linux mus-uc binary to copy into the image (default: this one, on Linux)")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("firefox")
+                                .long("firefox")
+                                .value_name("CHANNEL")
+                                .help("Firefox to install: release, from Mozilla's APT repository, or Debian's esr")
+                                .possible_values(&["release", "esr"])
+                                .default_value("release"),
+                        )
+                        .arg(
+                            Arg::with_name("base")
+                                .long("base")
+                                .value_name("IMAGE")
+                                .help("Base image; it needs apt-get")
+                                .default_value("debian:bookworm-slim"),
+                        )
+                        .arg(
+                            Arg::with_name("size")
+                                .long("size")
+                                .value_name("WIDTHxHEIGHT")
+                                .help("Screen size of the Xvfb display; MUS_UC_SIZE overrides it when the container starts")
+                                .default_value("1280x800"),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("export-session")
+                .about("Save the loaded sheets, manifests and resource aliases as a script or JSON to reproduce elsewhere")
+                .arg(
+                    Arg::with_name("output")
+                        .required(true)
+                        .value_name("FILE")
+                        .help("session.sh for a shell script, session.json for JSON")
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("panic-reset")
+                .about("Unregister every sheet any mus-uc-devtools process loaded, even ones `clear` cannot see")
+                .arg(
+                    Arg::with_name("any-data-uri")
+                        .long("any-data-uri")
+                        .help("Also remove data:text/css sheets without the tool's marker, e.g. from older versions"),
+                )
+                .arg(yes_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("package")
+                .about("Package an exported theme, its assets and metadata into a zip archive")
+                .arg(
+                    Arg::with_name("file")
+                        .short("f")
+                        .long("file")
+                        .value_name("FILE")
+                        .help("Entry CSS file")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("out")
+                        .short("o")
+                        .long("out")
+                        .value_name("ZIP")
+                        .help("Archive path (default: <name>-<version>.zip)")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("name")
+                        .long("name")
+                        .value_name("NAME")
+                        .help("Theme name (default: name of the entry file's directory)")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("version")
+                        .long("version")
+                        .value_name("VERSION")
+                        .help("Theme version")
+                        .default_value("0.1.0")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("description")
+                        .long("description")
+                        .value_name("TEXT")
+                        .help("Short description for theme.json")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("min-firefox")
+                        .long("min-firefox")
+                        .value_name("VERSION")
+                        .help("Oldest supported Firefox version")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("max-firefox")
+                        .long("max-firefox")
+                        .value_name("VERSION")
+                        .help("Newest supported Firefox version")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("manifest")
+                        .short("m")
+                        .long("manifest")
+                        .value_name("MANIFEST")
+                        .help("chrome.manifest to include in the archive")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .value_name("STYLE")
+                        .help("Reformat the bundled CSS")
+                        .possible_values(&["preserve", "pretty", "compact"])
+                        .default_value("preserve")
+                        .takes_value(true),
+                )
+                .arg(cycle_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("register-manifest")
+                .about("Register chrome.manifest to enable chrome:// URIs in CSS imports")
+                .arg(
+                    Arg::with_name("manifest")
+                        .short("m")
+                        .long("manifest")
+                        .value_name("MANIFEST")
+                        .help("Path to chrome.manifest file")
+                        .required(true)
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("register-resource")
+                .about("Map resource://ALIAS/ onto a directory, without a chrome.manifest")
+                .arg(
+                    Arg::with_name("alias")
+                        .value_name("ALIAS")
+                        .help("Host name of the resource:// URLs, e.g. icons")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("dir")
+                        .value_name("DIR")
+                        .help("Directory the URLs map to")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("unregister-resource")
+                .about("Remove a resource:// alias added with register-resource")
+                .arg(
+                    Arg::with_name("alias")
+                        .value_name("ALIAS")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("unload")
+                .about("Unload CSS by ID, tag or group")
+                .arg(
+                    Arg::with_name("id")
+                        .required_unless_one(&["tag", "group"])
+                        .conflicts_with_all(&["tag", "group"])
+                        .help("ID of stylesheet to unload")
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("tag")
+                        .short("t")
+                        .long("tag")
+                        .value_name("TAG")
+                        .help("Unload every stylesheet with this tag")
+                        .conflicts_with("group")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("group")
+                        .long("group")
+                        .value_name("GROUP")
+                        .help("Unload every stylesheet in this group, switched off or not")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("reload")
+                .about("Reload a stylesheet from the file it was loaded from")
+                .arg(
+                    Arg::with_name("id")
+                        .required(true)
+                        .help("ID of stylesheet to reload")
+                        .index(1),
+                )
+                .arg(cycle_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("show")
+                .about("Print the CSS of a loaded stylesheet")
+                .arg(
+                    Arg::with_name("id")
+                        .required(true)
+                        .help("ID of the loaded stylesheet")
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("pull")
+                .about("Save a loaded stylesheet as the browser has it, with edits made in the Browser Toolbox")
+                .arg(
+                    Arg::with_name("id")
+                        .required(true)
+                        .help("ID of the loaded stylesheet")
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("out")
+                        .short("o")
+                        .long("out")
+                        .value_name("FILE")
+                        .help("Write to FILE instead of printing")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("watch-browser")
+                        .long("watch-browser")
+                        .help("Keep polling and write the file again whenever the sheet changes")
+                        .requires("out"),
+                )
+                .arg(
+                    Arg::with_name("interval")
+                        .long("interval")
+                        .value_name("MS")
+                        .help("How often to poll with --watch-browser")
+                        .default_value("1000"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("diff")
+                .about("Compare a loaded stylesheet with the file on disk")
+                .arg(
+                    Arg::with_name("id")
+                        .required(true)
+                        .help("ID of the loaded stylesheet")
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("file")
+                        .short("f")
+                        .long("file")
+                        .value_name("FILE")
+                        .help(
+                            "File to compare against (default: the file the sheet was loaded from)",
+                        )
+                        .takes_value(true),
+                )
+                .arg(cycle_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("clear")
+                .about("Clear all loaded stylesheets")
+                .arg(
+                    Arg::with_name("persisted")
+                        .long("persisted")
+                        .help("Also remove the sheets saved to the profile with --persist"),
+                )
+                .arg(yes_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("console")
+                .about("Tail the browser console as JSON lines (Ctrl+C to stop)")
+                .arg(
+                    Arg::with_name("out")
+                        .short("o")
+                        .long("out")
+                        .value_name("FILE")
+                        .help("Append records to FILE instead of printing them")
+                        .takes_value(true),
+                )
+                .arg(console_filter_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("conflicts")
+                .about("Report declarations that loaded sheets override in each other")
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .help("Print the report as JSON"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("compat-check")
+                .about("Report rules likely broken in the connected Firefox version")
+                .arg(
+                    Arg::with_name("FILE")
+                        .help("CSS file to check")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("strict")
+                        .long("strict")
+                        .help("Exit with an error when there are warnings"),
+                )
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .help("Print the report as JSON"),
+                )
+                .arg(cycle_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("targets")
+                .about("Check common theming selectors against the connected Firefox")
+                .arg(
+                    Arg::with_name("area")
+                        .long("area")
+                        .value_name("AREA")
+                        .help("Only show one area (window, toolbox, tabs, navbar, urlbar, ...)")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("missing")
+                        .long("missing")
+                        .help("Only show selectors that match nothing"),
+                )
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .help("Print the report as JSON"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("status")
+                .about("Show the connected browser and what is loaded in it")
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .help("Print the status as JSON"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("audit")
+                .about("Show the last loads, unloads and clears in the browser, by any process")
+                .arg(
+                    Arg::with_name("last")
+                        .short("n")
+                        .long("last")
+                        .value_name("COUNT")
+                        .help("Only show the last COUNT operations")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .help("Print the operations as JSON"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("colors")
+                .about("Print the active theme's color variables and the system colors")
+                .arg(
+                    Arg::with_name("prefix")
+                        .long("prefix")
+                        .value_name("PREFIX")
+                        .help("Only variables starting with this, e.g. --lwt-")
+                        .allow_hyphen_values(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("no-system")
+                        .long("no-system")
+                        .help("Leave out the system colors"),
+                )
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .help("Print JSON instead of a CSS :root block"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("vars")
+                .about("Print the values {{name}} placeholders stand for with --browser-vars")
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .help("Print JSON"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("a11y-contrast")
+                .about("Report the WCAG contrast ratio of text in tabs, toolbars, the address bar and menus")
+                .arg(
+                    Arg::with_name("element")
+                        .short("e")
+                        .long("element")
+                        .value_name("SELECTOR")
+                        .help("Also check the text of this element against its background")
+                        .multiple(true)
+                        .number_of_values(1)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("min")
+                        .long("min")
+                        .value_name("RATIO")
+                        .help("Lowest acceptable ratio; 4.5 is what WCAG AA asks of body text")
+                        .default_value("4.5")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("strict")
+                        .long("strict")
+                        .help("Fail when any ratio is below --min"),
+                )
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .help("Print the results as JSON"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("a11y-emulate")
+                .about("Turn on accessibility settings such as reduced motion and high contrast in the browser, or show them")
+                .arg(
+                    Arg::with_name("settings")
+                        .value_name("SETTING")
+                        .help("Settings to turn on")
+                        .possible_values(MediaSetting::NAMES)
+                        .multiple(true),
+                )
+                .arg(
+                    Arg::with_name("off")
+                        .long("off")
+                        .help("Turn the settings off instead, whatever the system says")
+                        .requires("settings"),
+                )
+                .arg(
+                    Arg::with_name("reset")
+                        .long("reset")
+                        .help("Follow the system's settings again")
+                        .conflicts_with("settings"),
+                )
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .help("Print what the window matches as JSON"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("dump-symbols")
+                .about("Collect the IDs, classes and attributes of the browser UI for editor completion")
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .value_name("FORMAT")
+                        .help("json, or vscode for a .code-snippets file")
+                        .possible_values(&["json", "vscode"])
+                        .default_value("json"),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .value_name("FILE")
+                        .help("Write to a file instead of stdout")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("list")
+                .about("List all loaded stylesheets")
+                .arg(
+                    Arg::with_name("tag")
+                        .short("t")
+                        .long("tag")
+                        .value_name("TAG")
+                        .help("Only list stylesheets with this tag")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("group")
+                        .long("group")
+                        .value_name("GROUP")
+                        .help("Only list stylesheets in this group")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("toggle")
+                .about("Switch a group of stylesheets off, or back on")
+                .arg(
+                    Arg::with_name("group")
+                        .long("group")
+                        .value_name("GROUP")
+                        .help("Group to switch")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("on")
+                        .long("on")
+                        .help("Only switch the group on"),
+                )
+                .arg(
+                    Arg::with_name("off")
+                        .long("off")
+                        .conflicts_with("on")
+                        .help("Only switch the group off"),
+                )
+                .arg(
+                    Arg::with_name("config")
+                        .short("c")
+                        .long("config")
+                        .value_name("FILE")
+                        .help("Project config defining the group (default: nearest mus-uc.toml)")
+                        .takes_value(true),
+                )
+                .arg(member_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("tag")
+                .about("Change the tags or description of a loaded stylesheet")
+                .arg(
+                    Arg::with_name("id")
+                        .required(true)
+                        .help("ID of the stylesheet")
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("add")
+                        .short("a")
+                        .long("add")
+                        .value_name("TAG")
+                        .help("Tag to add (repeatable)")
+                        .multiple(true)
+                        .number_of_values(1)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("remove")
+                        .short("r")
+                        .long("remove")
+                        .value_name("TAG")
+                        .help("Tag to remove (repeatable)")
+                        .multiple(true)
+                        .number_of_values(1)
+                        .takes_value(true),
+                )
+                .arg(description_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("setup")
+                .about("Turn on the prefs userChrome development needs")
+                .arg(
+                    Arg::with_name("check")
+                        .long("check")
+                        .help("Only report the prefs; fail if any are off"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("import-profile")
+                .about("Split a profile's userChrome.css into sheets and load them")
+                .arg(
+                    Arg::with_name("profile")
+                        .short("p")
+                        .long("profile")
+                        .value_name("NAME")
+                        .help("Profile name or directory name from profiles.ini, or a profile path (default: the connected browser's profile)")
+                        .takes_value(true),
+                )
+                .arg(group_arg())
+                .arg(
+                    Arg::with_name("copy-to")
+                        .long("copy-to")
+                        .value_name("DIR")
+                        .help("Where to copy the chrome files when the profile cannot be read from here")
+                        .default_value("profile-chrome")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("dry-run")
+                        .long("dry-run")
+                        .help("Show how the file would be split without loading anything"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("state")
+                .about("Back up the state file, project config and baselines, or carry them to another machine")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("export")
+                        .about("Write a tar archive of the state")
+                        .arg(
+                            Arg::with_name("file")
+                                .value_name("FILE")
+                                .help("Archive to write, e.g. state.tar")
+                                .required(true)
+                                .index(1),
+                        )
+                        .arg(
+                            Arg::with_name("config")
+                                .short("c")
+                                .long("config")
+                                .value_name("FILE")
+                                .help("Project config to include (default: nearest mus-uc.toml)")
+                                .takes_value(true),
+                        )
+                        .arg(member_arg().conflicts_with("no-config"))
+                        .arg(
+                            Arg::with_name("no-config")
+                                .long("no-config")
+                                .help("Leave the project config out")
+                                .conflicts_with("config"),
+                        )
+                        .arg(
+                            Arg::with_name("baseline")
+                                .long("baseline")
+                                .value_name("DIR")
+                                .help("Include this directory of baseline screenshots; must be inside the project")
+                                .multiple(true)
+                                .number_of_values(1)
+                                .takes_value(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("import")
+                        .about("Unpack an archive written by state export")
+                        .arg(
+                            Arg::with_name("file")
+                                .value_name("FILE")
+                                .required(true)
+                                .index(1),
+                        )
+                        .arg(
+                            Arg::with_name("project")
+                                .long("project")
+                                .value_name("DIR")
+                                .help("Where to put the project config and baselines (default: the current directory)")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("force")
+                                .long("force")
+                                .help("Replace files that exist with different content"),
+                        ),
+                ),
+        )
+        .subcommand(SubCommand::with_name("interactive").about("Start interactive mode"))
+        .subcommand(
+            SubCommand::with_name("navigate")
+                .about("Load a page in the current tab, e.g. to test userContent.css")
+                .arg(
+                    Arg::with_name("url")
+                        .required(true)
+                        .help("URL to load")
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("new-tab")
+                .about("Open and select a new tab")
+                .arg(Arg::with_name("url").help("URL to load in it").index(1)),
+        )
+        .subcommand(
+            SubCommand::with_name("switch-tab")
+                .about("Select a tab by its position")
+                .arg(
+                    Arg::with_name("index")
+                        .help("Position of the tab, starting at 1")
+                        .required_unless_one(&["handle", "list"])
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("handle")
+                        .long("handle")
+                        .value_name("HANDLE")
+                        .help("Select the tab with this WebDriver handle instead")
+                        .conflicts_with("index")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("list")
+                        .long("list")
+                        .help("List the open tabs with their handles")
+                        .conflicts_with_all(&["index", "handle"]),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("window")
+                .about("Set the browser window size and UI density for repeatable screenshots")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("size")
+                        .about("Resize the window, or print its size")
+                        .arg(
+                            Arg::with_name("size")
+                                .value_name("WIDTHxHEIGHT")
+                                .help("Outer size, e.g. 1280x800")
+                                .index(1),
+                        ),
+                )
+                .subcommand(SubCommand::with_name("maximize").about("Maximize the window"))
+                .subcommand(
+                    SubCommand::with_name("compact")
+                        .about("Switch the toolbars to compact density and wait for the UI to restyle"),
+                )
+                .subcommand(
+                    SubCommand::with_name("density")
+                        .about("Switch the toolbar density and wait for the UI to restyle, or print the density in use")
+                        .arg(
+                            Arg::with_name("density")
+                                .possible_values(&["normal", "compact", "touch"])
+                                .index(1),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("locale")
+                .about("Show the browser's UI language, or switch it to check long strings and right-to-left layouts")
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .help("Print the locales as JSON"),
+                )
+                .subcommand(
+                    SubCommand::with_name("set")
+                        .about("Switch to a locale with a language pack installed, or the pseudo-locale accented or bidi")
+                        .arg(
+                            Arg::with_name("locale")
+                                .value_name("LOCALE")
+                                .help("e.g. de, ar, ja, accented or bidi")
+                                .required(true)
+                                .index(1),
+                        )
+                        .arg(
+                            Arg::with_name("restart")
+                                .long("restart")
+                                .help("Restart the browser so every string and the layout direction change"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("reset")
+                        .about("Go back to the locale the browser picks by itself")
+                        .arg(
+                            Arg::with_name("restart")
+                                .long("restart")
+                                .help("Restart the browser so every string and the layout direction change"),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("force-state")
+                .about("Lock pseudo-classes like :hover on a browser UI element")
+                .arg(
+                    Arg::with_name("selector")
+                        .value_name("SELECTOR")
+                        .required_unless("clear")
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("states")
+                        .value_name("STATE")
+                        .possible_values(PseudoState::NAMES)
+                        .multiple(true)
+                        .required_unless("clear")
+                        .index(2),
+                )
+                .arg(
+                    Arg::with_name("clear")
+                        .long("clear")
+                        .help("Release every state forced with force-state")
+                        .conflicts_with_all(&["selector", "states"]),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("simulate")
+                .about("Open a browser surface and keep it open for styling")
+                .arg(
+                    Arg::with_name("surface")
+                        .possible_values(Surface::NAMES)
+                        .required_unless("close")
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("query")
+                        .short("q")
+                        .long("query")
+                        .value_name("TEXT")
+                        .help("Text to search for in the urlbar")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("no-pin")
+                        .long("no-pin")
+                        .help("Let popups close when the browser loses focus"),
+                )
+                .arg(
+                    Arg::with_name("density")
+                        .long("density")
+                        .value_name("DENSITY")
+                        .help("Switch the toolbar density first, e.g. to style compact menus")
+                        .possible_values(&["normal", "compact", "touch"])
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("close")
+                        .long("close")
+                        .help("Close everything opened with simulate and stop pinning popups")
+                        .conflicts_with_all(&["surface", "query", "no-pin", "density"]),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("wait")
+                .about("Wait until an element, attribute or JavaScript condition holds in the browser window")
+                .arg(
+                    Arg::with_name("selector")
+                        .short("s")
+                        .long("selector")
+                        .value_name("CSS_SELECTOR")
+                        .help("Wait for an element matching this")
+                        .required_unless("js")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("attribute")
+                        .short("a")
+                        .long("attribute")
+                        .value_name("NAME")
+                        .help("Wait for the element to have this attribute")
+                        .requires("selector")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("value")
+                        .long("value")
+                        .value_name("VALUE")
+                        .help("Wait for the attribute to have this value")
+                        .requires("attribute")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("gone")
+                        .long("gone")
+                        .help("Wait until no element matches the selector")
+                        .requires("selector")
+                        .conflicts_with("attribute"),
+                )
+                .arg(
+                    Arg::with_name("js")
+                        .long("js")
+                        .value_name("EXPRESSION")
+                        .help("Wait for a JavaScript expression to be truthy, with window and document those of the browser window")
+                        .conflicts_with("selector")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("timeout")
+                        .short("t")
+                        .long("timeout")
+                        .value_name("DURATION")
+                        .help("Give up after this long, e.g. 500ms, 5s or 2m")
+                        .default_value("5s"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("scenario")
+                .about("Run scripted interactions and capture the states they reach")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("run")
+                        .about("Run the steps of a scenario file (TOML, or JSON by extension)")
+                        .arg(
+                            Arg::with_name("file")
+                                .value_name("FILE")
+                                .required(true)
+                                .index(1),
+                        )
+                        .arg(
+                            Arg::with_name("output")
+                                .short("o")
+                                .long("output")
+                                .value_name("DIR")
+                                .help("Directory for screenshots")
+                                .default_value("scenario-screenshots"),
+                        )
+                        .arg(
+                            Arg::with_name("variant")
+                                .long("variant")
+                                .value_name("SETTING")
+                                .help("Run again with this accessibility setting on, saving to a subdirectory named after it")
+                                .possible_values(MediaSetting::NAMES)
+                                .multiple(true)
+                                .number_of_values(1)
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("locale")
+                                .long("locale")
+                                .value_name("LOCALE")
+                                .help("Run again with the UI in this locale, e.g. bidi or ar, saving to a subdirectory named after it")
+                                .multiple(true)
+                                .number_of_values(1)
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("matrix")
+                                .long("matrix")
+                                .help("Run once for each combination in the [matrix] of mus-uc.toml, and write a grid report")
+                                .conflicts_with_all(&["variant", "locale"]),
+                        )
+                        .arg(
+                            Arg::with_name("config")
+                                .short("c")
+                                .long("config")
+                                .value_name("FILE")
+                                .help("Project config with the [matrix] (default: nearest mus-uc.toml)")
+                                .requires("matrix")
+                                .takes_value(true),
+                        )
+                        .arg(member_arg().requires("matrix"))
+                        .args(&matrix_args())
+                        .args(&post_process_args()),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("macro")
+                .about("Record steps typed one at a time into a scenario file, and play them back")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("record")
+                        .about("Run steps as they are typed and save the ones that work")
+                        .arg(
+                            Arg::with_name("file")
+                                .value_name("FILE")
+                                .help("Scenario file to write (TOML, or JSON by extension)")
+                                .required(true)
+                                .index(1),
+                        )
+                        .arg(
+                            Arg::with_name("output")
+                                .short("o")
+                                .long("output")
+                                .value_name("DIR")
+                                .help("Directory for screenshots taken while recording")
+                                .default_value("scenario-screenshots"),
+                        )
+                        .arg(
+                            Arg::with_name("append")
+                                .long("append")
+                                .help("Add to the steps of an existing file instead of refusing to overwrite it"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("play")
+                        .about("Run the steps of a recorded macro, like scenario run")
+                        .arg(
+                            Arg::with_name("file")
+                                .value_name("FILE")
+                                .required(true)
+                                .index(1),
+                        )
+                        .arg(
+                            Arg::with_name("output")
+                                .short("o")
+                                .long("output")
+                                .value_name("DIR")
+                                .help("Directory for screenshots")
+                                .default_value("scenario-screenshots"),
+                        )
+                        .args(&post_process_args()),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("screenshot")
+                .about("Take a screenshot of the browser window")
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .value_name("FILE")
+                        .help("Output file path (default: screenshot.png)")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("selector")
+                        .short("s")
+                        .long("selector")
+                        .value_name("CSS_SELECTOR")
+                        .help("CSS selector to capture a specific element (default: full screen)")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("native")
+                        .long("native")
+                        .help("Capture with the OS, native titlebar included (macOS builds with the native-capture feature)"),
+                )
+                .arg(
+                    Arg::with_name("wait-for")
+                        .long("wait-for")
+                        .value_name("CSS_SELECTOR")
+                        .help("Wait for an element matching this before capturing")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("wait-timeout")
+                        .long("wait-timeout")
+                        .value_name("DURATION")
+                        .help("How long --wait-for waits, e.g. 500ms, 5s or 2m (default: 5s)")
+                        .requires("wait-for")
+                        .takes_value(true),
+                )
+                .args(&post_process_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("probe-color")
+                .about("Print the rendered color of one pixel of the browser window")
+                .arg(
+                    Arg::with_name("point")
+                        .value_name("X,Y")
+                        .help("Window coordinates of the pixel, in CSS pixels")
+                        .conflicts_with_all(&["selector", "at"])
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("selector")
+                        .short("s")
+                        .long("selector")
+                        .value_name("CSS_SELECTOR")
+                        .help("Read the pixel within the first element matching this selector")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("at")
+                        .long("at")
+                        .value_name("POINT")
+                        .help("Where in the element: center (default), top-left, top-right, bottom-left, bottom-right, or X,Y from its top left")
+                        .requires("selector")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("expect")
+                        .long("expect")
+                        .value_name("COLOR")
+                        .help("Fail unless the pixel is this hex color")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("tolerance")
+                        .long("tolerance")
+                        .value_name("N")
+                        .help("How far each channel may be from --expect, 0-255")
+                        .default_value("0")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .help("Print the position and channels as JSON"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("exec")
+                .about("Execute JavaScript in Firefox chrome context")
+                .arg(
+                    Arg::with_name("file")
+                        .short("f")
+                        .long("file")
+                        .value_name("FILE")
+                        .help("JavaScript file to execute")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("args")
+                        .short("a")
+                        .long("args")
+                        .value_name("JSON")
+                        .help("Arguments to pass to the script as JSON array")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("extract")
+                        .short("e")
+                        .long("extract")
+                        .value_name("PATH")
+                        .help("Print only this part of the result, as a JSON pointer (/tabs/0/title) or jq path (.tabs[].title); strings print unquoted")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("raw")
+                        .short("r")
+                        .long("raw")
+                        .help("Print a string result without JSON quotes"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("helper")
+                .about("Run built-in chrome scripts for common chores, such as opening the sidebar")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("list").about("List the helpers and their parameters"),
+                )
+                .subcommand(
+                    SubCommand::with_name("run")
+                        .about("Run a helper and print what it returns")
+                        .arg(
+                            Arg::with_name("name")
+                                .value_name("NAME")
+                                .help("Helper to run (see `helper list`)")
+                                .required(true)
+                                .index(1),
+                        )
+                        .arg(
+                            Arg::with_name("arg")
+                                .long("arg")
+                                .value_name("NAME=VALUE")
+                                .help("A parameter; the value is JSON if it parses, e.g. open=true, and text otherwise (repeatable)")
+                                .multiple(true)
+                                .number_of_values(1)
+                                .takes_value(true),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("config")
+                .about("Show the settings every command uses, from the config files, environment and options")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("show")
+                        .about("Print the effective connection and retry settings")
+                        .arg(
+                            Arg::with_name("origin")
+                                .long("origin")
+                                .help("Say where each value comes from, and which config files were read"),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("plugins")
+                .about("List the mus-uc-<COMMAND> executables on PATH that run as subcommands"),
+        )
+        .subcommand(
+            SubCommand::with_name("raw-command")
+                .about("Send any Marionette command and print the whole response, for debugging")
+                .arg(
+                    Arg::with_name("name")
+                        .value_name("NAME")
+                        .help("Command name, e.g. WebDriver:GetWindowHandles")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("params")
+                        .short("p")
+                        .long("params")
+                        .value_name("JSON")
+                        .help("Command parameters as a JSON object")
+                        .default_value("{}"),
+                )
+                .arg(
+                    Arg::with_name("content")
+                        .long("content")
+                        .help("Send it in the content context instead of the chrome context"),
+                ),
+        )
+        .get_matches();
+
+    if let Some(path) = matches.value_of("log-file") {
+        tracing_subscriber::registry()
+            .with(EventLogLayer::open(Path::new(path))?)
+            .init();
+    }
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let span = tracing::info_span!(
+        "command",
+        command = matches.subcommand_name().unwrap_or(""),
+        args = args.join(" "),
+        result = tracing::field::Empty,
+        error = tracing::field::Empty,
+    );
+    let result = span.in_scope(|| run_command(&matches));
+    match &result {
+        Ok(()) => span.record("result", "ok"),
+        Err(e) => span
+            .record("result", "error")
+            .record("error", e.to_string()),
+    };
+    result
+}
+
+fn run_command(matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let project = command_config(matches)?;
+    let config = project
+        .as_deref()
+        .and_then(|path| match ProjectConfig::load(path) {
+            Ok(config) => Some(config),
+            Err(e) => {
+                eprintln!("Warning: ignoring {}", e);
+                None
+            }
+        });
+    let layers = layers_from(matches, project.as_deref().zip(config.as_ref()))?;
+    let _ = SETTINGS.set(settings_from(&layers, matches)?);
+    let _ = RETRY.set(retry_from(&layers)?);
+    let _ = HOOKS.set(config.map(|config| config.hooks).unwrap_or_default());
+    if matches.is_present("ci") {
+        let _ = CI_PROFILE.set(CiProfile::default());
+    }
+    if let Some(name) = matches.subcommand_name() {
+        let _ = CALLER.set(format!("mus-uc {} (pid {})", name, std::process::id()));
+    }
+    if read_only(matches) {
+        if matches.is_present("ci") {
+            return Err("--ci sets prefs, which MUS_UC_READ_ONLY=1 does not allow".into());
+        }
+        if let (name, Some(sub_matches)) = matches.subcommand() {
+            if changes_browser(name, sub_matches) {
+                return Err(format!(
+                    "`{}` changes the browser, which --read-only does not allow",
+                    name
+                )
+                .into());
+            }
+        }
+    }
+
+    match matches.subcommand() {
+        ("register-manifest", Some(sub_matches)) => {
+            let mut manager = connect_manager()?;
+            let manifest_path = sub_matches.value_of("manifest").unwrap();
+            let path = Path::new(manifest_path);
+
+            if !path.exists() {
+                return Err(format!("chrome.manifest file not found: {}", manifest_path).into());
+            }
+
+            let manifest = ChromeManifest::parse_file(path)?;
+            report_manifest_issues(path, &manifest);
+            manager.register_chrome_manifest(path)?;
+            println!(
+                "chrome.manifest registered: {}",
+                manager.get_registered_manifest().unwrap_or("unknown")
+            );
+            let info = manager.connection().browser_info()?;
+            for (line, reason) in manifest.inactive_lines(&info) {
+                eprintln!(
+                    "Warning: {}:{}: '{}' is inactive in {} {} on {}: {}",
+                    path.display(),
+                    line.line,
+                    line.instruction,
+                    info.name,
+                    info.version,
+                    info.platform,
+                    reason
+                );
+            }
+        }
+
+        ("register-resource", Some(sub_matches)) => {
+            let alias = sub_matches.value_of("alias").unwrap();
+            let dir = Path::new(sub_matches.value_of("dir").unwrap());
+            if !dir.is_dir() {
+                return Err(format!("Directory not found: {}", dir.display()).into());
+            }
+            let mut manager = connect_manager()?;
+            let url = manager.register_resource(alias, dir)?;
+            println!("{} -> {}", url, dir.display());
+        }
+
+        ("unregister-resource", Some(sub_matches)) => {
+            let alias = sub_matches.value_of("alias").unwrap();
+            let mut manager = connect_manager()?;
+            if manager.unregister_resource(alias)? {
+                println!("resource://{}/ removed", alias);
+            } else {
+                return Err(format!("resource://{}/ was not registered with mus-uc", alias).into());
+            }
+        }
+
+        ("load", Some(sub_matches)) => {
+            let sheet_type = SheetType::parse(sub_matches.value_of("sheet-type").unwrap())?;
+            if sheet_type == SheetType::Author && sub_matches.is_present("target") {
+                return Err("--target needs a user or agent sheet".into());
+            }
+            let connect = |settings| {
+                let builder = ChromeCSSManager::builder()
+                    .settings(settings)
+                    .sheet_type(sheet_type)
+                    .caller(caller())
+                    .hooks(hooks());
+                with_wrappers(builder, sub_matches).build()
+            };
+            if sub_matches.is_present("all-profiles") && !sub_matches.is_present("file") {
+                return Err("--all-profiles needs --file; stdin can only be read once".into());
+            }
+            let Some(instances) = broadcast_instances(sub_matches)? else {
+                return run_load(&mut connect(marionette_settings())?, sub_matches);
+            };
+            let mut failed = 0;
+            for instance in &instances {
+                println!("== {} ==", instance);
+                if let Err(e) = connect(instance_settings(instance))
+                    .and_then(|mut manager| run_load(&mut manager, sub_matches))
+                {
+                    failed += 1;
+                    eprintln!("Failed on {}: {}", instance, e);
+                }
+            }
+            if failed > 0 {
+                return Err(format!("{} of {} browsers failed", failed, instances.len()).into());
+            }
+        }
+
+        ("export-session", Some(sub_matches)) => {
+            let output = Path::new(sub_matches.value_of("output").unwrap());
+            let mut manager = connect_manager()?;
+            let session = Session::capture(&mut manager)?;
+            let content = match output.extension().and_then(|e| e.to_str()) {
+                Some("json") => serde_json::to_string_pretty(&session)?,
+                _ => session.to_shell_script(),
+            };
+            fs::write(output, content)?;
+            println!(
+                "Saved {} sheet(s), {} manifest(s) and {} resource alias(es) to {}",
+                session.sheets.len(),
+                session.manifests.len(),
+                session.resources.len(),
+                output.display()
+            );
+        }
+
+        ("export", Some(sub_matches)) => {
+            run_export(sub_matches)?;
+        }
+
+        ("analyze", Some(sub_matches)) => {
+            run_analyze(sub_matches)?;
+        }
+
+        ("icons", Some(sub_matches)) => {
+            run_icons(sub_matches)?;
+        }
+
+        ("bench", Some(sub_matches)) => {
+            let iterations: u32 = match sub_matches.value_of("iterations").unwrap().parse() {
+                Ok(n) if n > 0 => n,
+                _ => return Err("--iterations must be a positive number".into()),
+            };
+            let file = Path::new(sub_matches.value_of("file").unwrap());
+            let mut manager = connect_manager()?;
+            let report = bench_file(&mut manager, file, iterations)?;
+            if sub_matches.is_present("json") {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                print!("{}", report.to_text());
+            }
+        }
+
+        ("compare", Some(sub_matches)) => {
+            run_compare(sub_matches)?;
+        }
+
+        ("bisect", Some(sub_matches)) => {
+            run_bisect(sub_matches)?;
+        }
+
+        ("gallery", Some(sub_matches)) => {
+            let out = Path::new(sub_matches.value_of("out").unwrap());
+            if sub_matches.is_present("grid") {
+                let dir = Path::new(sub_matches.value_of("dir").unwrap());
+                let columns = grid_columns(dir)?;
+                let rows = write_grid(dir, &columns, out)?;
+                println!(
+                    "{} images in {} columns, report written to {}",
+                    rows,
+                    columns.len(),
+                    out.display()
+                );
+                return Ok(());
+            }
+            let summary = write_gallery(
+                Path::new(sub_matches.value_of("dir").unwrap()),
+                sub_matches.value_of("baseline").map(Path::new),
+                out,
+                &post_process(sub_matches)?,
+            )?;
+            print!("{} images", summary.images);
+            if sub_matches.is_present("baseline") {
+                print!(
+                    ": {} changed, {} new, {} missing",
+                    summary.changed, summary.new, summary.missing
+                );
+            }
+            println!(", report written to {}", out.display());
+        }
+
+        ("package", Some(sub_matches)) => {
+            run_package(sub_matches)?;
+        }
+
+        ("watch", Some(sub_matches)) => {
+            let Some(instances) = broadcast_instances(sub_matches)? else {
+                return run_watch(marionette_settings(), sub_matches);
+            };
+            // One watcher per browser, each with its own connection
+            let failed = std::thread::scope(|scope| {
+                let watchers: Vec<_> = instances
+                    .iter()
+                    .map(|instance| {
+                        scope.spawn(move || {
+                            run_watch(instance_settings(instance), sub_matches)
+                                .map_err(|e| eprintln!("Stopped watching on {}: {}", instance, e))
+                                .is_err()
+                        })
+                    })
+                    .collect();
+                watchers
+                    .into_iter()
+                    .map(|watcher| watcher.join().unwrap_or(true))
+                    .filter(|&failed| failed)
+                    .count()
+            });
+            if failed > 0 {
+                return Err(format!("{} of {} browsers failed", failed, instances.len()).into());
+            }
+        }
+
+        ("unload", Some(sub_matches)) => {
+            let mut manager = connect_manager()?;
+            let ids = if let Some(tag) = sub_matches.value_of("tag") {
+                let ids = SheetState::load_in(&manager.state_dir())?.tagged(tag);
+                if ids.is_empty() {
+                    println!("No stylesheets tagged '{}'", tag);
+                }
+                ids
+            } else if let Some(group) = sub_matches.value_of("group") {
+                let (disabled, ids) = SheetState::update_in(&manager.state_dir(), |state| {
+                    let disabled = state.disabled_in(group);
+                    for id in &disabled {
+                        state.disabled.remove(id);
+                    }
+                    (disabled, state.grouped(group))
+                })?;
+                for id in &disabled {
+                    println!("Forgot switched-off CSS: {}", id);
+                }
+                if ids.is_empty() && disabled.is_empty() {
+                    println!("No stylesheets in group '{}'", group);
+                }
+                ids
+            } else {
+                vec![sub_matches.value_of("id").unwrap().to_string()]
+            };
+
+            let mut unloaded = Vec::new();
+            for id in ids {
+                if manager.unload_css(&id)? {
+                    println!("CSS unloaded: {}", id);
+                    unloaded.push(id);
+                } else {
+                    println!("Failed to unload CSS: {}", id);
+                }
+            }
+            forget_sheets(&manager.state_dir(), &unloaded);
+        }
+
+        ("reload", Some(sub_matches)) => {
+            let mut manager = connect_manager()?;
+            manager.set_cycle_policy(cycle_policy(sub_matches)?);
+            let id = sub_matches.value_of("id").unwrap();
+            let resolved = manager.reload(id)?;
+            report_unresolved(&resolved);
+            let root = resolved
+                .files
+                .first()
+                .and_then(|entry| entry.parent())
+                .map(Path::to_path_buf);
+            manager.report_css_errors(id, root.as_deref());
+            println!("CSS reloaded: {}", id);
+        }
+
+        ("show", Some(sub_matches)) => {
+            let mut manager = connect_manager()?;
+            let id = sub_matches.value_of("id").unwrap();
+            let css = manager
+                .get_css(id)?
+                .ok_or_else(|| format!("No stylesheet loaded with ID: {}", id))?;
+            print!("{}", css);
+            if !css.ends_with('\n') {
+                println!();
+            }
+        }
+
+        ("pull", Some(sub_matches)) => {
+            run_pull(sub_matches)?;
+        }
+
+        ("diff", Some(sub_matches)) => {
+            let mut manager = connect_manager()?;
+            manager.set_cycle_policy(cycle_policy(sub_matches)?);
+            let id = sub_matches.value_of("id").unwrap();
+            let sheet = manager
+                .fetch_sheet(id)?
+                .ok_or_else(|| format!("No stylesheet loaded with ID: {}", id))?;
+            let file = match sub_matches.value_of("file") {
+                Some(file) => file.to_string(),
+                None => sheet.source.clone().ok_or(
+                    "The sheet was not loaded from a file; pass --file to compare against one",
+                )?,
+            };
+
+            let resolved = manager.resolve_file(Path::new(&file))?;
+            if sheet.css == resolved.css {
+                println!("No differences between '{}' and {}", id, file);
+            } else {
+                let diff = TextDiff::from_lines(&sheet.css, &resolved.css);
+                print!(
+                    "{}",
+                    diff.unified_diff()
+                        .context_radius(3)
+                        .header(&format!("loaded:{}", id), &file)
+                );
+            }
+        }
+
+        ("panic-reset", Some(sub_matches)) => {
+            confirm(
+                sub_matches,
+                "Unregister every sheet any mus-uc-devtools process loaded in this browser?",
+            )?;
+            // Without the harness, which may be what is broken
+            let mut manager = ChromeCSSManager::builder()
+                .settings(marionette_settings())
+                .initialize(false)
+                .build()?;
+            let removed = manager.panic_reset(sub_matches.is_present("any-data-uri"))?;
+            for sheet in &removed {
+                println!(
+                    "Removed {} ({})",
+                    sheet.id.as_deref().unwrap_or("unmarked sheet"),
+                    sheet.sheet_type.as_str()
+                );
+            }
+            let ids: Vec<String> = removed.iter().filter_map(|s| s.id.clone()).collect();
+            forget_sheets(&manager.state_dir(), &ids);
+            println!("{} sheets removed", removed.len());
+        }
+
+        ("clear", Some(sub_matches)) => {
+            let mut manager = connect_manager()?;
+            let ids = manager.registered_ids()?;
+            let persisted = sub_matches.is_present("persisted");
+            if !ids.is_empty() || persisted {
+                confirm(
+                    sub_matches,
+                    &format!(
+                        "Unload {} stylesheet(s){}?",
+                        ids.len(),
+                        if persisted {
+                            " and remove the sheets saved to the profile"
+                        } else {
+                            ""
+                        }
+                    ),
+                )?;
+            }
+            manager.clear_all()?;
+            forget_sheets(&manager.state_dir(), &ids);
+            println!("All CSS cleared");
+            if sub_matches.is_present("persisted") {
+                manager.clear_persisted()?;
+                println!("Persisted sheets removed from the profile");
+            }
+        }
+
+        ("serve-assets", Some(sub_matches)) => {
+            let port = match sub_matches.value_of("port") {
+                Some(port) => port
+                    .parse()
+                    .map_err(|_| format!("Invalid --port: {}", port))?,
+                None => DEFAULT_PORT,
+            };
+            let dir = Path::new(sub_matches.value_of("DIR").unwrap());
+            let server = AssetServer::bind(dir, port)?;
+            println!(
+                "Serving {} at {} (Ctrl+C to stop)",
+                dir.display(),
+                server.url()?
+            );
+            server.run()?;
+        }
+
+        ("launch", Some(sub_matches)) => {
+            run_launch(sub_matches)?;
+        }
+
+        ("ci-image", Some(sub_matches)) => {
+            if let ("prepare", Some(prepare_matches)) = sub_matches.subcommand() {
+                run_ci_image_prepare(prepare_matches)?;
+            }
+        }
+
+        ("console", Some(sub_matches)) => {
+            let filter = console_filter(sub_matches)?;
+            let mut log = match sub_matches.value_of("out") {
+                Some(out) => Some(ConsoleLog::open(Path::new(out), filter)?),
+                None => None,
+            };
+            let mut manager = connect_manager()?;
+            manager.start_console_capture()?;
+            if let Some(out) = sub_matches.value_of("out") {
+                println!("Appending console messages to {} (Ctrl+C to stop)...", out);
+            }
+
+            loop {
+                let records = manager.drain_console()?;
+                match &mut log {
+                    Some(log) => {
+                        log.append(&records)?;
+                    }
+                    None => {
+                        for record in records.iter().filter(|r| filter.matches(r)) {
+                            println!("{}", serde_json::to_string(record)?);
+                        }
+                    }
+                }
+                std::thread::sleep(std::time::Duration::from_millis(500));
+            }
+        }
+
+        ("conflicts", Some(sub_matches)) => {
+            let mut manager = connect_manager()?;
+            let root = std::env::current_dir()?;
+            let report = manager.find_conflicts(Some(&root))?;
+            if sub_matches.is_present("json") {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                print!("{}", report.to_text());
+            }
+        }
+
+        ("compat-check", Some(sub_matches)) => {
+            let mut manager = connect_manager()?;
+            manager.set_cycle_policy(cycle_policy(sub_matches)?);
+            let path = Path::new(sub_matches.value_of("FILE").unwrap());
+            let resolved = manager.resolve_file(path)?;
+            report_unresolved(&resolved);
+            let root = canonicalize(path)?.parent().map(Path::to_path_buf);
+            let report = manager.check_compat(&resolved, root.as_deref())?;
+
+            if sub_matches.is_present("json") {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                report_compat(&report, true);
+                println!(
+                    "Firefox {}: {} warnings, {} notes",
+                    report.firefox_version,
+                    report.warnings(),
+                    report.issues.len() - report.warnings()
+                );
+            }
+            if sub_matches.is_present("strict") && report.warnings() > 0 {
+                return Err(format!("{} compatibility warnings", report.warnings()).into());
+            }
+        }
+
+        ("targets", Some(sub_matches)) => {
+            let manager = connect_manager()?;
+            let mut report = manager.connection().with(check_targets)?;
+            if let Some(area) = sub_matches.value_of("area") {
+                report.checks.retain(|check| check.target.area == area);
+            }
+            if sub_matches.is_present("missing") {
+                report
+                    .checks
+                    .retain(|check| check.status == TargetStatus::Missing);
+            }
+            if sub_matches.is_present("json") {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+                return Ok(());
+            }
+
+            let missing = report
+                .checks
+                .iter()
+                .filter(|check| check.status == TargetStatus::Missing)
+                .count();
+            println!(
+                "Firefox {}: {} of {} selectors found",
+                report.firefox_version,
+                report.checks.len() - missing,
+                report.checks.len()
+            );
+            let width = report
+                .checks
+                .iter()
+                .map(|check| check.target.selector.len())
+                .max()
+                .unwrap_or(0);
+            let mut area = "";
+            for check in &report.checks {
+                if check.target.area != area {
+                    area = check.target.area;
+                    println!("\n[{}]", area);
+                }
+                let status = match check.status {
+                    TargetStatus::Present => "ok",
+                    TargetStatus::Inactive => "inactive",
+                    TargetStatus::Missing => "MISSING",
+                };
+                println!(
+                    "  {:<8}  {:<width$}  {}",
+                    status,
+                    check.target.selector,
+                    check.target.description,
+                    width = width
+                );
+            }
+        }
+
+        ("status", Some(sub_matches)) => {
+            let mut manager = ChromeCSSManager::builder()
+                .settings(marionette_settings())
+                .initialize(false)
+                .build()?;
+            let info = manager.connection().browser_info()?;
+            let app = manager.connection().with(|c| c.app_profile())?;
+            let harness = manager.harness_version()?;
+            let sheets = match harness {
+                Some(_) => manager.registered_ids()?.len(),
+                None => 0,
+            };
+            if sub_matches.is_present("json") {
+                let status = serde_json::json!({
+                    "browser": info,
+                    "app": app.name,
+                    "harness_version": harness,
+                    "sheets": sheets,
+                });
+                println!("{}", serde_json::to_string_pretty(&status)?);
+                return Ok(());
+            }
+
+            println!(
+                "{} {} (build {}, {} channel)",
+                info.name, info.version, info.build_id, info.channel
+            );
+            println!(
+                "Platform: {}{}",
+                info.platform,
+                if info.headless { ", headless" } else { "" }
+            );
+            println!("App:      {} (window type {})", app.name, app.window_type);
+            match harness {
+                Some(version) => {
+                    println!("Harness:  version {}, {} sheet(s) loaded", version, sheets)
+                }
+                None => println!("Harness:  not installed"),
+            }
+        }
+
+        ("audit", Some(sub_matches)) => {
+            let mut manager = connect_manager()?;
+            let mut entries = manager.audit_log()?;
+            if let Some(last) = sub_matches.value_of("last") {
+                let last: usize = last
+                    .parse()
+                    .map_err(|_| format!("Invalid --last: {}", last))?;
+                entries.drain(..entries.len().saturating_sub(last));
+            }
+            if sub_matches.is_present("json") {
+                println!("{}", serde_json::to_string_pretty(&entries)?);
+                return Ok(());
+            }
+            if entries.is_empty() {
+                println!("No operations recorded since the harness was installed");
+            }
+            let now = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)?
+                .as_millis() as u64;
+            for entry in &entries {
+                let target = match (&entry.id, entry.count) {
+                    (Some(id), _) => id.clone(),
+                    (None, Some(count)) => format!("{} sheet(s)", count),
+                    (None, None) => String::new(),
+                };
+                println!(
+                    "{:>8}  {:<6}  {:<30}  {}",
+                    age(now.saturating_sub(entry.time)),
+                    entry.op,
+                    target,
+                    entry.caller.as_deref().unwrap_or("unknown")
+                );
+            }
+        }
+
+        ("colors", Some(sub_matches)) => {
+            let manager = connect_manager()?;
+            let mut colors = manager.connection().with(read_theme_colors)?;
+            if let Some(prefix) = sub_matches.value_of("prefix") {
+                colors.retain_prefix(prefix);
+            }
+            if sub_matches.is_present("no-system") {
+                colors.system.clear();
+            }
+            if sub_matches.is_present("json") {
+                println!("{}", serde_json::to_string_pretty(&colors)?);
+            } else {
+                print!("{}", colors.to_css());
+            }
+        }
+
+        ("vars", Some(sub_matches)) => {
+            let manager = connect_manager()?;
+            let values = manager.connection().with(read_variables)?;
+            if sub_matches.is_present("json") {
+                println!("{}", serde_json::to_string_pretty(&values)?);
+            } else {
+                let width = values.keys().map(|name| name.len() + 4).max().unwrap_or(0);
+                for (name, value) in &values {
+                    println!(
+                        "{:<width$}  {}",
+                        format!("{{{{{}}}}}", name),
+                        value,
+                        width = width
+                    );
+                }
+            }
+        }
+
+        ("a11y-contrast", Some(sub_matches)) => {
+            let min_ratio: f64 = sub_matches
+                .value_of("min")
+                .unwrap()
+                .parse()
+                .map_err(|_| "--min must be a ratio such as 4.5")?;
+            let mut targets = default_targets();
+            targets.extend(
+                sub_matches
+                    .values_of("element")
+                    .into_iter()
+                    .flatten()
+                    .map(ContrastTarget::element),
+            );
+            let manager = connect_manager()?;
+            let results = manager.connection().with(|c| check_contrast(c, &targets))?;
+            let low = results.iter().filter(|r| !r.passes(min_ratio)).count();
+
+            if sub_matches.is_present("json") {
+                println!("{}", serde_json::to_string_pretty(&results)?);
+            } else {
+                println!("Contrast ratios (minimum {}:1):", min_ratio);
+                for result in &results {
+                    match (result.ratio, &result.text, &result.background) {
+                        (Some(ratio), Some(text), Some(background)) => {
+                            println!(
+                                "  {:<4} {:>6.2}:1  {} ({} on {})",
+                                if result.passes(min_ratio) {
+                                    "ok"
+                                } else {
+                                    "LOW"
+                                },
+                                ratio,
+                                result.label,
+                                text,
+                                background
+                            );
+                            if let Some(note) = &result.note {
+                                println!("                 note: {}", note);
+                            }
+                        }
+                        _ => println!(
+                            "  skip           {}: {}",
+                            result.label,
+                            result.note.as_deref().unwrap_or("colors unavailable")
+                        ),
+                    }
+                }
+            }
+            if low > 0 {
+                let message = format!("{} element(s) below {}:1", low, min_ratio);
+                if sub_matches.is_present("strict") {
+                    return Err(message.into());
+                }
+                if !sub_matches.is_present("json") {
+                    println!("{}", message);
+                }
+            }
+        }
+
+        ("a11y-emulate", Some(sub_matches)) => {
+            let settings = sub_matches
+                .values_of("settings")
+                .into_iter()
+                .flatten()
+                .map(MediaSetting::parse)
+                .collect::<Result<Vec<_>, _>>()?;
+            let manager = connect_manager()?;
+            let state = if sub_matches.is_present("reset") {
+                manager.connection().with(reset_media)?
+            } else if settings.is_empty() {
+                manager.connection().with(media_state)?
+            } else {
+                let on = !sub_matches.is_present("off");
+                manager.connection().with(|c| emulate(c, &settings, on))?.1
+            };
+            if sub_matches.is_present("json") {
+                println!("{}", serde_json::to_string_pretty(&state)?);
+            } else {
+                print!("{}", state.to_text());
+            }
+        }
+
+        ("dump-symbols", Some(sub_matches)) => {
+            let manager = connect_manager()?;
+            let symbols = manager.connection().with(dump_symbols)?;
+            let output = match sub_matches.value_of("format") {
+                Some("vscode") => symbols.to_vscode_snippets(),
+                _ => serde_json::to_value(&symbols)?,
+            };
+            let text = serde_json::to_string_pretty(&output)? + "\n";
+            match sub_matches.value_of("output") {
+                Some(path) => {
+                    fs::write(path, text)?;
+                    eprintln!(
+                        "Wrote {} IDs, {} classes and {} attributes to {}",
+                        symbols.ids.len(),
+                        symbols.classes.len(),
+                        symbols.attributes.len(),
+                        path
+                    );
+                }
+                None => print!("{}", text),
+            }
+        }
+
+        ("list", Some(sub_matches)) => {
+            let mut manager = connect_manager()?;
+            let mut loaded = manager.registered_ids()?;
+            loaded.sort();
+
+            // Forget sheets that went away, e.g. with a browser restart
+            let state = SheetState::update_in(&manager.state_dir(), |state| {
+                state.retain_loaded(&loaded);
+                state.clone()
+            })?;
+
+            let mut disabled: Vec<&String> = state.disabled.keys().collect();
+            if let Some(tag) = sub_matches.value_of("tag") {
+                let tagged = state.tagged(tag);
+                loaded.retain(|id| tagged.contains(id));
+                disabled.retain(|id| state.disabled[*id].metadata.has_tag(tag));
+            }
+            if let Some(group) = sub_matches.value_of("group") {
+                let grouped = state.grouped(group);
+                loaded.retain(|id| grouped.contains(id));
+                disabled.retain(|id| state.disabled[*id].metadata.in_group(group));
+            }
+            if loaded.is_empty() {
+                println!("No stylesheets loaded");
+            } else {
+                println!("Loaded stylesheets:");
+                for id in &loaded {
+                    println!("  - {}", describe_sheet(id, state.sheets.get(id)));
+                }
+            }
+            if !disabled.is_empty() {
+                println!("Switched off:");
+                for id in disabled {
+                    println!(
+                        "  - {}",
+                        describe_sheet(id, Some(&state.disabled[id].metadata))
+                    );
+                }
+            }
+
+            if let Ok(resources) = manager.registered_resources() {
+                if !resources.is_empty() {
+                    println!("Resource aliases:");
+                    for (alias, dir) in resources {
+                        println!("  - resource://{}/ -> {}", alias, dir);
+                    }
+                }
+            }
+
+            if let Ok(persisted) = manager.persisted_sheets() {
+                if !persisted.is_empty() {
+                    println!("Persisted in the profile: {}", persisted.join(", "));
+                }
+            }
+
+            match manager.scan_sheets() {
+                Ok(scanned) => report_untracked(&scanned),
+                Err(e) => eprintln!("Could not look for other sheets: {}", e),
+            }
+
+            let watchers = active_watchers();
+            if !watchers.is_empty() {
+                println!("Running watchers:");
+                for watcher in watchers {
+                    println!(
+                        "  - {} (pid {}, {}): {}",
+                        watcher.id, watcher.pid, watcher.browser, watcher.file
+                    );
+                }
+            }
+        }
+
+        ("tag", Some(sub_matches)) => {
+            let mut manager = connect_manager()?;
+            let id = sub_matches.value_of("id").unwrap();
+            let sheet = manager
+                .fetch_sheet(id)?
+                .ok_or_else(|| format!("No stylesheet loaded with ID: {}", id))?;
+
+            let tags = SheetState::update_in(&manager.state_dir(), |state| {
+                let meta = state
+                    .sheets
+                    .entry(id.to_string())
+                    .or_insert_with(|| SheetMetadata::new(sheet.source, Vec::new(), None));
+                for tag in sub_matches.values_of("add").into_iter().flatten() {
+                    if !meta.has_tag(tag) {
+                        meta.tags.push(tag.to_string());
+                    }
+                }
+                for tag in sub_matches.values_of("remove").into_iter().flatten() {
+                    meta.tags.retain(|t| t != tag);
+                }
+                if let Some(description) = sub_matches.value_of("description") {
+                    meta.description = Some(description.to_string()).filter(|d| !d.is_empty());
+                }
+                meta.tags.join(", ")
+            })?;
+            println!("{}: [{}]", id, tags);
+        }
+
+        ("toggle", Some(sub_matches)) => run_toggle(sub_matches)?,
+
+        ("import-profile", Some(sub_matches)) => run_import_profile(sub_matches)?,
+
+        ("state", Some(sub_matches)) => match sub_matches.subcommand() {
+            ("export", Some(export_matches)) => {
+                let out = Path::new(export_matches.value_of("file").unwrap());
+                let config = if export_matches.is_present("no-config") {
+                    None
+                } else {
+                    project_config(export_matches)?
+                };
+                let project_dir = match &config {
+                    Some(config) => match config.parent() {
+                        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+                        _ => PathBuf::from("."),
+                    },
+                    None => std::env::current_dir()?,
+                };
+                let export = StateExport {
+                    state_dir: SheetState::dir(),
+                    project_dir,
+                    config: config.is_some(),
+                    baselines: export_matches
+                        .values_of("baseline")
+                        .into_iter()
+                        .flatten()
+                        .map(PathBuf::from)
+                        .collect(),
+                };
+                let files = export_state(out, &export)?;
+                for file in &files {
+                    println!("  {}", file);
+                }
+                println!("{} files written to {}", files.len(), out.display());
+            }
+            ("import", Some(import_matches)) => {
+                let import = StateImport {
+                    state_dir: SheetState::dir(),
+                    project_dir: match import_matches.value_of("project") {
+                        Some(dir) => PathBuf::from(dir),
+                        None => std::env::current_dir()?,
+                    },
+                    overwrite: import_matches.is_present("force"),
+                };
+                let report =
+                    import_state(Path::new(import_matches.value_of("file").unwrap()), &import)?;
+                for path in &report.written {
+                    println!("  {}", path.display());
+                }
+                println!(
+                    "{} files written, {} already up to date",
+                    report.written.len(),
+                    report.unchanged.len()
+                );
+            }
+            _ => {}
+        },
+
+        ("setup", Some(sub_matches)) => {
+            let manager = connect_manager()?;
+            let check = sub_matches.is_present("check");
+            let statuses = manager.setup_prefs(!check)?;
+            for status in &statuses {
+                let state = match (status.was, status.changed) {
+                    (true, _) => "on",
+                    (false, true) if status.needs_restart => "turned on (restart needed)",
+                    (false, true) => "turned on",
+                    (false, false) => "off",
+                };
+                match purpose(&status.name).filter(|_| !status.was && !status.changed) {
+                    Some(purpose) => println!("{}: {}, needed for {}", status.name, state, purpose),
+                    None => println!("{}: {}", status.name, state),
+                }
+            }
+            let off = statuses.iter().filter(|s| !s.was).count();
+            if check && off > 0 {
+                return Err(format!("{} prefs are off; run `setup` to turn them on", off).into());
+            }
+            if statuses.iter().any(|s| s.needs_restart) {
+                println!("Restart the browser for every change to take effect");
+            }
+        }
+
+        ("interactive", Some(_)) => {
+            let mut manager = connect_manager()?;
+            run_interactive_mode(&mut manager)?;
+        }
+
+        ("navigate", Some(sub_matches)) => {
+            let manager = connect_manager()?;
+            let url = sub_matches.value_of("url").unwrap();
+            manager.connection().with(|c| navigate(c, url))?;
+            println!("Loaded {}", url);
+        }
+
+        ("new-tab", Some(sub_matches)) => {
+            let manager = connect_manager()?;
+            let url = sub_matches.value_of("url");
+            let handle = manager.connection().with(|c| new_tab(c, url))?;
+            println!("Opened tab {}", handle);
+        }
+
+        ("switch-tab", Some(sub_matches)) => {
+            let manager = connect_manager()?;
+            let handles = manager.connection().with(tab_handles)?;
+            if sub_matches.is_present("list") {
+                for (i, handle) in handles.iter().enumerate() {
+                    println!("{}  {}", i + 1, handle);
+                }
+                return Ok(());
+            }
+
+            let handle = match sub_matches.value_of("handle") {
+                Some(handle) => handle.to_string(),
+                None => {
+                    let index = sub_matches.value_of("index").unwrap();
+                    index
+                        .parse::<usize>()
+                        .ok()
+                        .and_then(|i| i.checked_sub(1))
+                        .and_then(|i| handles.get(i))
+                        .cloned()
+                        .ok_or_else(|| format!("No tab {} (there are {})", index, handles.len()))?
+                }
+            };
+            manager.connection().with(|c| switch_tab(c, &handle))?;
+            println!("Switched to tab {}", handle);
+        }
+
+        ("window", Some(sub_matches)) => match sub_matches.subcommand() {
+            ("size", Some(size_matches)) => {
+                let size = size_matches.value_of("size").map(parse_size).transpose()?;
+                let manager = connect_manager()?;
+                let rect = match size {
+                    Some((width, height)) => manager
+                        .connection()
+                        .with(|c| set_window_size(c, width, height))?,
+                    None => manager.connection().with(window_rect)?,
+                };
+                println!("Window size: {}x{}", rect.width, rect.height);
+            }
+            ("maximize", _) => {
+                let manager = connect_manager()?;
+                let rect = manager.connection().with(maximize_window)?;
+                println!("Window maximized to {}x{}", rect.width, rect.height);
+            }
+            (name, density_matches) => {
+                let requested = match density_matches.and_then(|m| m.value_of("density")) {
+                    Some(density) => Some(Density::parse(density)?),
+                    None if name == "compact" => Some(Density::Compact),
+                    None => None,
+                };
+                let manager = connect_manager()?;
+                let density = match requested {
+                    Some(density) => {
+                        manager.connection().with(|c| set_density(c, density))?;
+                        density
+                    }
+                    None => manager.connection().with(density)?,
+                };
+                println!("Toolbar density: {}", density.as_str());
+            }
+        },
+
+        ("locale", Some(sub_matches)) => {
+            let mut manager = connect_manager()?;
+            let (status, restart) = match sub_matches.subcommand() {
+                ("set", Some(set_matches)) => {
+                    let locale = set_matches.value_of("locale").unwrap();
+                    let (_, status) = manager.connection().with(|c| set_locale(c, locale))?;
+                    (status, set_matches.is_present("restart"))
+                }
+                ("reset", Some(reset_matches)) => (
+                    manager.connection().with(reset_locale)?,
+                    reset_matches.is_present("restart"),
+                ),
+                _ => (manager.connection().with(locale_status)?, false),
+            };
+            let json = sub_matches.is_present("json");
+            let status = if restart {
+                eprintln!("Restarting the browser...");
+                manager.restart()?;
+                manager.connection().with(locale_status)?
+            } else {
+                status
+            };
+            if json {
+                println!("{}", serde_json::to_string_pretty(&status)?);
+            } else {
+                print!("{}", status.to_text());
+            }
+            if restart {
+                eprintln!("Warning: sheets loaded before the restart are gone unless loaded with --persist");
+            } else if sub_matches.subcommand_name().is_some() {
+                eprintln!("Warning: strings outside Fluent and the layout direction may only change after a restart (--restart)");
+            }
+        }
+
+        ("force-state", Some(sub_matches)) => {
+            let manager = connect_manager()?;
+            if sub_matches.is_present("clear") {
+                let released = manager.connection().with(release_states)?;
+                println!("Released forced states on {} element(s)", released);
+                return Ok(());
+            }
+
+            let selector = sub_matches.value_of("selector").unwrap();
+            let states = sub_matches
+                .values_of("states")
+                .unwrap()
+                .map(PseudoState::parse)
+                .collect::<Result<Vec<_>, _>>()?;
+            let element = manager.retry_policy().run(|| {
+                manager
+                    .connection()
+                    .with(|c| force_states(c, selector, &states))
+            })?;
+            let names: Vec<&str> = states.iter().map(|s| s.as_str()).collect();
+            println!("Forced {} on {}", names.join(""), element);
+            println!("States stay until `force-state --clear`");
+        }
+
+        ("simulate", Some(sub_matches)) => {
+            let manager = connect_manager()?;
+            if sub_matches.is_present("close") {
+                manager.connection().with(release)?;
+                println!("Closed simulated surfaces");
+                return Ok(());
+            }
+
+            let surface = Surface::parse(sub_matches.value_of("surface").unwrap())?;
+            if let Some(density) = sub_matches.value_of("density") {
+                let density = Density::parse(density)?;
+                manager.connection().with(|c| set_density(c, density))?;
+            }
+            let options = SimulateOptions {
+                query: sub_matches.value_of("query").map(String::from),
+                pin: !sub_matches.is_present("no-pin"),
+            };
+            manager.retry_policy().run(|| {
+                manager
+                    .connection()
+                    .with(|c| simulate(c, surface, &options))
+            })?;
+            println!("Opened {}", surface.as_str());
+            if options.pin {
+                println!("Popups stay open until `simulate --close`");
+            }
+        }
+
+        ("wait", Some(sub_matches)) => {
+            let timeout = parse_duration(sub_matches.value_of("timeout").unwrap())?;
+            let condition = match (sub_matches.value_of("js"), sub_matches.value_of("selector")) {
+                (Some(expression), _) => WaitCondition::Predicate(expression.to_string()),
+                (None, Some(selector)) if sub_matches.is_present("gone") => {
+                    WaitCondition::Gone(selector.to_string())
+                }
+                (None, Some(selector)) => match sub_matches.value_of("attribute") {
+                    Some(name) => WaitCondition::Attribute {
+                        selector: selector.to_string(),
+                        name: name.to_string(),
+                        value: sub_matches.value_of("value").map(String::from),
+                    },
+                    None => WaitCondition::Selector(selector.to_string()),
+                },
+                (None, None) => unreachable!(),
+            };
+            let manager = connect_manager()?;
+            let elapsed = manager.wait_for(&condition, timeout)?;
+            println!("Condition met after {}ms", elapsed.as_millis());
+        }
+
+        ("scenario", Some(sub_matches)) => {
+            if let ("run", Some(run_matches)) = sub_matches.subcommand() {
+                let scenario = Scenario::load(Path::new(run_matches.value_of("file").unwrap()))?;
+                let output = Path::new(run_matches.value_of("output").unwrap());
+                let variants = run_matches
+                    .values_of("variant")
+                    .into_iter()
+                    .flatten()
+                    .map(MediaSetting::parse)
+                    .collect::<Result<Vec<_>, _>>()?;
+                let cells = matrix_cells(run_matches)?;
+                let mut manager = connect_manager()?;
+                manager.set_post_process(post_process(run_matches)?);
+                prepare_capture(&manager)?;
+                let run = |output: &Path| run_steps(&manager, &scenario, output);
+                if let Some(cells) = cells {
+                    let mut columns = Vec::new();
+                    for (i, cell) in cells.iter().enumerate() {
+                        println!("{} ({}/{}):", cell.name(), i + 1, cells.len());
+                        with_cell(
+                            manager.connection(),
+                            cell,
+                            || run(&output.join(cell.name())),
+                        )?;
+                        columns.push(cell.name());
+                    }
+                    let report = output.join("matrix.html");
+                    write_grid(output, &columns, &report)?;
+                    println!("Grid report written to {}", report.display());
+                } else {
+                    run(output)?;
+                    for variant in variants {
+                        println!("With {}:", variant.as_str());
+                        with_emulation(manager.connection(), &[variant], || {
+                            run(&output.join(variant.as_str()))
+                        })?;
+                    }
+                    for locale in run_matches.values_of("locale").into_iter().flatten() {
+                        println!("In {}:", locale);
+                        with_locale(manager.connection(), locale, || run(&output.join(locale)))?;
+                    }
+                }
+            }
+        }
+
+        ("macro", Some(sub_matches)) => match sub_matches.subcommand() {
+            ("record", Some(record_matches)) => {
+                let path = Path::new(record_matches.value_of("file").unwrap());
+                let output = Path::new(record_matches.value_of("output").unwrap());
+                let scenario = if !path.exists() {
+                    Scenario::default()
+                } else if record_matches.is_present("append") {
+                    Scenario::load(path)?
+                } else {
+                    return Err(format!(
+                        "{} already exists (use --append to add to it)",
+                        path.display()
+                    )
+                    .into());
+                };
+                let manager = connect_manager()?;
+                prepare_capture(&manager)?;
+                record_macro(&manager, scenario, path, output)?;
+            }
+            ("play", Some(play_matches)) => {
+                let scenario = Scenario::load(Path::new(play_matches.value_of("file").unwrap()))?;
+                let output = Path::new(play_matches.value_of("output").unwrap());
+                let mut manager = connect_manager()?;
+                manager.set_post_process(post_process(play_matches)?);
+                prepare_capture(&manager)?;
+                run_steps(&manager, &scenario, output)?;
+            }
+            _ => {}
+        },
+
+        ("screenshot", Some(sub_matches)) => {
+            let output = sub_matches.value_of("output").unwrap_or("screenshot.png");
+            let selector = sub_matches.value_of("selector");
+
+            let mut manager = ChromeCSSManager::builder()
+                .settings(marionette_settings())
+                .initialize(false)
+                .build()?;
+            manager.set_retry_policy(retry_policy());
+            manager.set_post_process(post_process(sub_matches)?);
+            if sub_matches.is_present("native") {
+                manager.set_capture_mode(CaptureMode::Native);
+            }
+            prepare_capture(&manager)?;
+            if let Some(selector) = sub_matches.value_of("wait-for") {
+                let timeout = sub_matches
+                    .value_of("wait-timeout")
+                    .map(parse_duration)
+                    .transpose()?
+                    .unwrap_or(DEFAULT_TIMEOUT);
+                manager.wait_for(&WaitCondition::Selector(selector.to_string()), timeout)?;
+            }
+            manager.save_screenshot(Path::new(output), selector)?;
+
+            match selector {
+                Some(sel) => println!("Screenshot of element '{}' saved to: {}", sel, output),
+                None => println!("Full-screen screenshot saved to: {}", output),
+            }
+        }
+
+        ("probe-color", Some(sub_matches)) => {
+            let selector = sub_matches.value_of("selector");
+            let at = match (sub_matches.value_of("point"), sub_matches.value_of("at")) {
+                (Some(point), _) | (None, Some(point)) => ProbePoint::parse(point)?,
+                (None, None) if selector.is_some() => ProbePoint::Center,
+                (None, None) => return Err("Give a point X,Y or --selector".into()),
+            };
+            let expected = sub_matches
+                .value_of("expect")
+                .map(parse_hex_color)
+                .transpose()?;
+            let tolerance: u8 = sub_matches
+                .value_of("tolerance")
+                .unwrap()
+                .parse()
+                .map_err(|_| "--tolerance must be a number from 0 to 255")?;
+
+            let mut manager = ChromeCSSManager::builder()
+                .settings(marionette_settings())
+                .initialize(false)
+                .build()?;
+            manager.set_retry_policy(retry_policy());
+            prepare_capture(&manager)?;
+            let color = manager.probe_color(selector, at)?;
+            if sub_matches.is_present("json") {
+                println!("{}", serde_json::to_string_pretty(&color)?);
+            } else {
+                println!("{} at ({}, {})", color.hex(), color.x, color.y);
+            }
+            if let Some(expected) = expected {
+                let distance = color.distance(expected);
+                if distance > tolerance {
+                    return Err(format!(
+                        "Expected {} but found {} (channels differ by up to {})",
+                        sub_matches.value_of("expect").unwrap(),
+                        color.hex(),
+                        distance
+                    )
+                    .into());
+                }
+            }
+        }
+
+        ("exec", Some(sub_matches)) => {
+            let js = read_input(
+                sub_matches.value_of("file"),
+                "Enter JavaScript code (Ctrl+D to finish):",
+            )?;
+            if js.trim().is_empty() {
+                return Err("No JavaScript code provided".into());
+            }
+
+            let args = sub_matches
+                .value_of("args")
+                .map(
+                    |s| -> Result<Vec<serde_json::Value>, Box<dyn std::error::Error>> {
+                        match serde_json::from_str(s)? {
+                            serde_json::Value::Array(arr) => Ok(arr),
+                            _ => Err("Arguments must be a JSON array".into()),
+                        }
+                    },
+                )
+                .transpose()?;
+
+            let mut connection = MarionetteConnection::connect(&marionette_settings())?;
+            connection.set_context("chrome")?;
+            let source = Path::new(sub_matches.value_of("file").unwrap_or("stdin"));
+            let result = connection
+                .execute_script(&js, args)
+                .map_err(|e| locate_error(e, script_file_frame(source)))?;
+            match sub_matches.value_of("extract") {
+                Some(path) => {
+                    for value in extract(&result, path)? {
+                        print_value(&value, true)?;
+                    }
+                }
+                None => print_value(&result, sub_matches.is_present("raw"))?,
+            }
+        }
+
+        ("helper", Some(sub_matches)) => match sub_matches.subcommand() {
+            ("list", _) => {
+                for helper in HELPERS {
+                    println!("{}  {}", helper.name, helper.description);
+                    for param in helper.params {
+                        println!(
+                            "    {}={}  {}",
+                            param.name, param.default, param.description
+                        );
+                    }
+                }
+            }
+            ("run", Some(run_matches)) => {
+                let helper = Helper::find(run_matches.value_of("name").unwrap())?;
+                let args = run_matches
+                    .values_of("arg")
+                    .into_iter()
+                    .flatten()
+                    .map(parse_arg)
+                    .collect::<Result<BTreeMap<_, _>, _>>()?;
+                helper.check_args(&args)?;
+                let mut connection = MarionetteConnection::connect(&marionette_settings())?;
+                connection.set_context("chrome")?;
+                let result = helper.run(&mut connection, &args)?;
+                if !result.is_null() {
+                    print_value(&result, true)?;
+                }
+            }
+            _ => {}
+        },
+
+        ("raw-command", Some(sub_matches)) => {
+            let name = sub_matches.value_of("name").unwrap();
+            let params: serde_json::Value =
+                serde_json::from_str(sub_matches.value_of("params").unwrap())
+                    .map_err(|e| format!("Invalid --params: {}", e))?;
+            if !params.is_object() {
+                return Err("--params must be a JSON object".into());
+            }
+
+            let mut connection = MarionetteConnection::connect(&marionette_settings())?;
+            let context = if sub_matches.is_present("content") {
+                "content"
+            } else {
+                "chrome"
+            };
+            connection.set_context(context)?;
+            let response = connection.send_raw(name, params)?;
+            println!("{}", serde_json::to_string_pretty(&response)?);
+            if response.get("error").is_some_and(|error| !error.is_null()) {
+                return Err(format!("{} failed", name).into());
+            }
+        }
+
+        ("config", Some(sub_matches)) => {
+            if let ("show", Some(show_matches)) = sub_matches.subcommand() {
+                show_config(
+                    &layers,
+                    project.as_deref(),
+                    show_matches.is_present("origin"),
+                );
+            }
+        }
+
+        ("plugins", Some(_)) => {
+            let found = plugins::list();
+            if found.is_empty() {
+                println!("No {}<COMMAND> executables on PATH", plugins::PREFIX);
+            }
+            for (name, path) in found {
+                println!("{}  {}", name, path.display());
+            }
+        }
+
+        (name, Some(sub_matches)) => {
+            let path = plugins::find(name).ok_or_else(|| {
+                format!(
+                    "Unknown command '{}', and no {}{} on PATH",
+                    name,
+                    plugins::PREFIX,
+                    name
+                )
+            })?;
+            let args: Vec<OsString> = sub_matches
+                .values_of_os("")
+                .into_iter()
+                .flatten()
+                .map(OsString::from)
+                .collect();
+            let env = plugins::plugin_env(&marionette_settings(), read_only(matches));
+            let code = plugins::run(&path, &args, &env)?;
+            if code != 0 {
+                std::process::exit(code);
+            }
+        }
+
+        _ => {
+            println!("Use --help for usage information");
+        }
+    }
+
+    Ok(())
+}
+
+fn run_export(matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let file = Path::new(matches.value_of("file").unwrap());
+    let output = matches.value_of("output").map(Path::new);
+
+    let mut mode = AssetMode::parse(matches.value_of("assets").unwrap_or("rebase"))?;
+    if let (AssetMode::Inline { limit }, Some(value)) =
+        (&mut mode, matches.value_of("inline-limit"))
+    {
+        *limit = value
+            .parse()
+            .map_err(|_| format!("Invalid --inline-limit: {}", value))?;
+    }
+
+    let output_dir = match output {
+        Some(path) => {
+            let parent = path.parent().filter(|p| !p.as_os_str().is_empty());
+            std::env::current_dir()?.join(parent.unwrap_or(Path::new(".")))
+        }
+        None => std::env::current_dir()?,
+    };
+    let mut assets = AssetRewriter::new(mode.clone()).with_output_dir(&output_dir);
+    match matches.value_of("manifest") {
+        Some(manifest) => {
+            let parsed = ChromeManifest::parse_file(Path::new(manifest))?;
+            report_manifest_issues(Path::new(manifest), &parsed);
+            assets = assets.with_manifest(parsed)
+        }
+        None if mode == AssetMode::Chrome => {
+            return Err("--assets chrome requires --manifest".into());
+        }
+        None => {}
+    }
+    if let Some(dir) = matches.value_of("served-dir") {
+        let url = match matches.value_of("served-url") {
+            Some(url) if url.ends_with('/') => url.to_string(),
+            Some(url) => format!("{}/", url),
+            None => served_url(DEFAULT_PORT),
+        };
+        assets = assets.with_served(&url, &canonicalize(Path::new(dir))?);
+    }
+
+    let resolved = ImportResolver::new()
+        .with_assets(assets)
+        .with_cycle_policy(cycle_policy(matches)?)
+        .resolve(file)?;
+    report_unresolved(&resolved);
+
+    let format = CssFormat::parse(matches.value_of("format").unwrap_or("preserve"))?;
+    if matches.is_present("source-map") {
+        if output.is_none() {
+            return Err("--source-map requires --output".into());
+        }
+        if format != CssFormat::Preserve {
+            return Err("--source-map cannot be combined with --format".into());
+        }
+    }
+
+    match output {
+        Some(path) => {
+            let mut css = format.apply(&resolved.css);
+            if matches.is_present("source-map") {
+                let file_name = path
+                    .file_name()
+                    .and_then(|n| n.to_str())
+                    .ok_or("Invalid output path")?;
+                let map_name = format!("{}.map", file_name);
+                let map_json = resolved.source_map.to_json(file_name, Some(&output_dir));
+                fs::write(path.with_file_name(&map_name), map_json)?;
+                css.push_str(&format!("\n/*# sourceMappingURL={} */\n", map_name));
+            }
+            fs::write(path, css)?;
+            eprintln!(
+                "Exported {} file(s) to {}",
+                resolved.files.len(),
+                path.display()
+            );
+        }
+        None => print!("{}", format.apply(&resolved.css)),
+    }
+    Ok(())
+}
+
+/// Analyze a stylesheet offline and check it against the limits from the
+/// project config and the command line.
+fn run_analyze(matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let path = Path::new(matches.value_of("FILE").unwrap());
+    let resolved = ImportResolver::new()
+        .with_cycle_policy(cycle_policy(matches)?)
+        .resolve(path)?;
+    report_unresolved(&resolved);
+    let root = canonicalize(path)?.parent().map(Path::to_path_buf);
+    let report = analyze(&resolved, root.as_deref());
+
+    let mut thresholds = match project_config(matches)? {
+        Some(config) => ProjectConfig::load(&config)?.analyze,
+        None => AnalyzeThresholds::default(),
+    };
+    let limit = |name: &str| -> Result<Option<usize>, Box<dyn std::error::Error>> {
+        matches
+            .value_of(name)
+            .map(|n| {
+                n.parse()
+                    .map_err(|_| format!("--{} must be a number", name))
+            })
+            .transpose()
+            .map_err(Into::into)
+    };
+    thresholds.merge(AnalyzeThresholds {
+        max_bytes: limit("max-bytes")?,
+        max_selectors: limit("max-selectors")?,
+        max_important: limit("max-important")?,
+        max_specificity: matches
+            .value_of("max-specificity")
+            .map(Specificity::parse)
+            .transpose()?,
+        max_expensive: limit("max-expensive")?,
+    });
+
+    if matches.is_present("json") {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print!("{}", report.to_text());
+    }
+    let violations = thresholds.violations(&report);
+    for violation in &violations {
+        eprintln!("error: {}", violation);
+    }
+    if !violations.is_empty() {
+        return Err(format!("{} is over its analyze limits", path.display()).into());
+    }
+    Ok(())
+}
+
+fn run_icons(matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let paths: Vec<PathBuf> = matches
+        .values_of("PATH")
+        .unwrap()
+        .map(PathBuf::from)
+        .collect();
+    let (icons, sets_properties) = collect_icons(&paths)?;
+    if icons.is_empty() {
+        println!("No SVG icons found");
+        return Ok(());
+    }
+
+    let mut failing = 0;
+    let mut fixed = 0;
+    let mut uses_context = false;
+    for icon in &icons {
+        let report = check_icon(icon)?;
+        let fix = report.fixed.filter(|_| matches.is_present("fix"));
+        if let Some(svg) = &fix {
+            fs::write(icon, svg)?;
+            fixed += 1;
+        }
+        uses_context |= report.uses_context || fix.is_some();
+        if report.issues.is_empty() && report.notes.is_empty() {
+            continue;
+        }
+        println!("{}", icon.display());
+        for issue in &report.issues {
+            println!("  - {}", issue);
+        }
+        for note in &report.notes {
+            println!("  note: {}", note);
+        }
+        if fix.is_some() {
+            println!("  fixed");
+        }
+        // Fixing covers every issue but a missing viewBox
+        if !report.issues.is_empty() && (fix.is_none() || report.issues.len() > 1) {
+            failing += 1;
+        }
+    }
+    println!(
+        "Checked {} icon(s): {} fixed, {} need attention",
+        icons.len(),
+        fixed,
+        failing
+    );
+
+    if uses_context {
+        if sets_properties == Some(false) {
+            eprintln!(
+                "Warning: no rule sets -moz-context-properties, so context-fill icons are drawn black; add `-moz-context-properties: fill; fill: currentColor;` where they are shown"
+            );
+        }
+        // Sheets from `load` and `watch` refer to icons by file:// URL
+        let pref = ChromeCSSManager::builder()
+            .settings(marionette_settings())
+            .initialize(false)
+            .build()
+            .and_then(|manager| manager.setup_prefs(false));
+        match pref {
+            Ok(prefs) => {
+                if prefs
+                    .iter()
+                    .any(|p| p.name == CONTEXT_PROPERTIES_PREF && !p.was)
+                {
+                    eprintln!(
+                        "Warning: {} is off, so icons loaded from file:// ignore context-fill; run `setup` to turn it on",
+                        CONTEXT_PROPERTIES_PREF
+                    );
+                }
+            }
+            Err(e) => eprintln!(
+                "Could not check {} in Firefox: {}",
+                CONTEXT_PROPERTIES_PREF, e
+            ),
+        }
+    }
+
+    if failing > 0 {
+        return Err(format!("{} icon(s) need attention", failing).into());
+    }
+    Ok(())
+}
+
+/// Watch the file or project given to `watch` in one browser, until stopped.
+fn run_watch(
+    settings: MarionetteSettings,
+    sub_matches: &ArgMatches,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let builder = ChromeCSSManager::builder()
+        .settings(settings)
+        .caller(caller())
+        .hooks(hooks());
+    let mut manager = with_wrappers(builder, sub_matches).build()?;
+    manager.set_cycle_policy(cycle_policy(sub_matches)?);
+    manager.set_notify_level(NotifyLevel::parse(
+        sub_matches.value_of("notify").unwrap_or("off"),
+    )?);
+    manager.set_term_status(sub_matches.is_present("term-status"));
+    if let Some(interval) = sub_matches.value_of("heartbeat") {
+        let interval = parse_duration(interval)?;
+        manager.set_heartbeat(Some(interval).filter(|i| !i.is_zero()));
+    }
+    if let Some(interval) = sub_matches.value_of("poll") {
+        let interval = parse_duration(interval)?;
+        if interval.is_zero() {
+            return Err("--poll needs an interval above 0, e.g. 1s".into());
+        }
+        manager.set_poll_interval(Some(interval));
+    }
+    if let Some(log) = sub_matches.value_of("console-log") {
+        manager.set_console_log(ConsoleLog::open(
+            Path::new(log),
+            console_filter(sub_matches)?,
+        )?);
+    }
+    if sub_matches.is_present("profile-reload") {
+        manager.set_profile_dir(Path::new(
+            sub_matches
+                .value_of("profile-dir")
+                .unwrap_or("mus-uc-profiles"),
+        ));
+    }
+    manager.set_rollback_policy(RollbackPolicy {
+        max_errors: sub_matches
+            .value_of("rollback-errors")
+            .map(|n| n.parse().map_err(|_| "--rollback-errors must be a number"))
+            .transpose()?,
+        verify: sub_matches.value_of("verify").map(PathBuf::from),
+    });
+    set_persist(&mut manager, sub_matches);
+    if let Some(path) = sub_matches.value_of("metrics-json") {
+        manager.set_metrics_file(Path::new(path));
+    }
+    let file_path = match sub_matches.value_of("file") {
+        Some(file_path) => file_path,
+        None => return watch_project(&mut manager, sub_matches),
+    };
+    let dir = sub_matches.value_of("dir");
+    let mut target = WatchTarget::css(
+        Path::new(file_path),
+        dir.map(Path::new),
+        Some(sub_matches.value_of("id").unwrap_or("watched-sheet")),
+    );
+    target.ignore = sub_matches
+        .values_of("ignore")
+        .into_iter()
+        .flatten()
+        .map(String::from)
+        .collect();
+    // Recorded in the state file once the watcher holds the sheet's ID
+    target.tags = sub_matches
+        .values_of("tag")
+        .map(|values| values.map(String::from).collect())
+        .unwrap_or_default();
+    target.description = sub_matches.value_of("description").map(String::from);
+    target.group = sub_matches.value_of("group").map(String::from);
+
+    match dir {
+        Some(dir) => println!(
+            "Watching {} and its imports under {} (Ctrl+C to stop)...",
+            file_path, dir
+        ),
+        None => println!("Watching {} for changes (Ctrl+C to stop)...", file_path),
+    }
+    manager.watch_targets(&[target])
+}
 
-    let mut manager = ChromeCSSManager::new()?;
-    manager.initialize_chrome_context()?;
+/// One line of `list`: the ID followed by what the state file knows.
+fn describe_sheet(id: &str, meta: Option<&SheetMetadata>) -> String {
+    let mut line = id.to_string();
+    if let Some(meta) = meta {
+        if let Some(group) = &meta.group {
+            line.push_str(&format!(" ({})", group));
+        }
+        if !meta.tags.is_empty() {
+            line.push_str(&format!(" [{}]", meta.tags.join(", ")));
+        }
+        if let Some(source) = &meta.source {
+            line.push_str(&format!(" {}", source));
+        }
+        if let Some(description) = &meta.description {
+            line.push_str(&format!(" - {}", description));
+        }
+    }
+    line
+}
 
-    match matches.subcommand() {
-        ("register-manifest", Some(sub_matches)) => {
-            let manifest_path = sub_matches.value_of("manifest").unwrap();
-            let path = Path::new(manifest_path);
+/// Switch the sheets of a group off, keeping them in the state file, or
+/// register them again. A group that was never loaded is loaded from the
+/// project config.
+fn run_toggle(matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let group = matches.value_of("group").unwrap();
+    let mut manager = connect_manager()?;
+    let state_dir = manager.state_dir();
+    let mut state = SheetState::load_in(&state_dir)?;
+    state.retain_loaded(&manager.registered_ids()?);
 
-            if !path.exists() {
-                return Err(format!("chrome.manifest file not found: {}", manifest_path).into());
+    let loaded = state.grouped(group);
+    let switch_off = if matches.is_present("on") {
+        false
+    } else if matches.is_present("off") {
+        true
+    } else {
+        !loaded.is_empty()
+    };
+
+    if switch_off {
+        if loaded.is_empty() {
+            println!("No stylesheets in group '{}' are loaded", group);
+        }
+        for id in loaded {
+            // Gone since the list was taken
+            let Some(sheet) = manager.fetch_sheet(&id)? else {
+                state.sheets.remove(&id);
+                continue;
+            };
+            if !manager.unload_css(&id)? {
+                eprintln!("Failed to unload CSS: {}", id);
+                continue;
             }
+            let metadata = state.sheets.remove(&id).unwrap_or_default();
+            state
+                .disabled
+                .insert(id.clone(), DisabledSheet { metadata, sheet });
+            println!("Switched off: {}", id);
+        }
+        state.save_in(&state_dir)?;
+        return Ok(());
+    }
 
-            manager.register_chrome_manifest(path)?;
-            println!(
-                "chrome.manifest registered: {}",
-                manager.get_registered_manifest().unwrap_or("unknown")
-            );
+    let disabled = state.disabled_in(group);
+    if disabled.is_empty() && !loaded.is_empty() {
+        println!("Group '{}' is already on", group);
+        return state.save_in(&state_dir);
+    }
+    if disabled.is_empty() {
+        state.save_in(&state_dir)?;
+        return load_config_group(&mut manager, group, project_config(matches)?);
+    }
+    let sheets: Vec<(String, RegisteredSheet)> = disabled
+        .iter()
+        .map(|id| (id.clone(), state.disabled[id].sheet.clone()))
+        .collect();
+    let mut failed = 0;
+    for ((id, _), error) in sheets.iter().zip(manager.restore_sheets(&sheets)?) {
+        match error {
+            Some(e) => {
+                failed += 1;
+                eprintln!("Failed to switch on {}: {}", id, e);
+            }
+            None => {
+                let disabled = state.disabled.remove(id).unwrap();
+                state.sheets.insert(id.clone(), disabled.metadata);
+                println!("Switched on: {}", id);
+            }
         }
+    }
+    state.save_in(&state_dir)?;
+    if failed > 0 {
+        return Err(format!("{} of {} sheets failed to load", failed, sheets.len()).into());
+    }
+    Ok(())
+}
 
-        ("load", Some(sub_matches)) => {
-            let css = read_input(sub_matches.value_of("file"), "Enter CSS content (Ctrl+D to finish):")?;
-            let sheet_id = manager.load_css(&css, sub_matches.value_of("id"))?;
-            println!("CSS loaded with ID: {}", sheet_id);
+/// Load the `[[watch]]` targets of `group` from the project config.
+fn load_config_group(
+    manager: &mut ChromeCSSManager,
+    group: &str,
+    path: Option<PathBuf>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let config = path.as_deref().map(ProjectConfig::load).transpose()?;
+    let targets = config
+        .as_ref()
+        .map(|config| config.group(group))
+        .unwrap_or_default();
+    if targets.is_empty() {
+        return Err(format!("No stylesheets in group '{}'", group).into());
+    }
+    for target in targets {
+        let (id, resolved) = manager.load_css_file(&target.file, target.id.as_deref())?;
+        report_unresolved(&resolved);
+        save_sheet_metadata(
+            &manager.state_dir(),
+            &id,
+            Some(&target.file),
+            target.tags.clone(),
+            target.description.clone(),
+            target.group.clone(),
+        );
+        println!("Switched on: {} ({})", id, target.file.display());
+    }
+    Ok(())
+}
+
+fn run_import_profile(matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let dry_run = matches.is_present("dry-run");
+    let mut manager = None;
+    let user_chrome = match matches.value_of("profile") {
+        Some(name) if Path::new(name).is_dir() => user_chrome_path(Path::new(name))?,
+        Some(name) => user_chrome_path(
+            &find_profile(name).ok_or_else(|| format!("No profile named '{}'", name))?,
+        )?,
+        None => {
+            let connected = manager.insert(connect_manager()?);
+            let profile = connected.browser_profile_dir()?;
+            match user_chrome_path(&profile) {
+                Ok(path) => path,
+                // Not reachable from here, e.g. in a WASI build
+                Err(_) if !profile.is_dir() && !dry_run => {
+                    let dir = PathBuf::from(matches.value_of("copy-to").unwrap());
+                    if dir.join("userChrome.css").exists() {
+                        return Err(format!(
+                            "{} already has a userChrome.css; pick another --copy-to",
+                            dir.display()
+                        )
+                        .into());
+                    }
+                    let copy = copy_through_browser(
+                        connected.connection(),
+                        &profile.to_string_lossy(),
+                        &dir,
+                    )?;
+                    println!("Copied the profile's chrome files to {}", dir.display());
+                    copy
+                }
+                Err(e) => return Err(e),
+            }
         }
+    };
 
-        ("watch", Some(sub_matches)) => {
-            let file_path = sub_matches.value_of("file").unwrap();
-            let id = sub_matches.value_of("id");
+    if dry_run {
+        let plan = plan_import(&user_chrome)?;
+        println!("{}", plan.user_chrome.display());
+        for part in &plan.parts {
+            println!("  - {}", part.display());
+        }
+        if plan.has_rest {
+            println!("  - the rules in userChrome.css itself");
+        }
+        return Ok(());
+    }
+
+    let mut manager = match manager {
+        Some(manager) => manager,
+        None => connect_manager()?,
+    };
+    let sheets = import_user_chrome(&mut manager, &user_chrome)?;
+    let group = matches.value_of("group").map(String::from);
+    for sheet in &sheets {
+        report_unresolved(&sheet.resolved);
+        let description = if sheet.rest {
+            "Rules of userChrome.css itself"
+        } else {
+            "Imported by userChrome.css"
+        };
+        save_sheet_metadata(
+            &manager.state_dir(),
+            &sheet.id,
+            Some(&sheet.file),
+            vec!["imported".to_string()],
+            Some(description.to_string()),
+            group.clone(),
+        );
+        println!("{} loaded with ID: {}", sheet.file.display(), sheet.id);
+    }
+    println!(
+        "{} sheets loaded from {}",
+        sheets.len(),
+        user_chrome.display()
+    );
+    Ok(())
+}
 
-            println!("Watching {} for changes (Ctrl+C to stop)...", file_path);
-            manager.watch_and_reload(file_path, id)?;
+/// Run the steps of a scenario in order, stopping at the first that fails.
+fn run_steps(
+    manager: &ChromeCSSManager,
+    scenario: &Scenario,
+    output: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    for (i, step) in scenario.steps.iter().enumerate() {
+        println!("[{}/{}] {}", i + 1, scenario.steps.len(), step);
+        let saved = step
+            .run(manager, output)
+            .map_err(|e| format!("Step {} ({}) failed: {}", i + 1, step, e))?;
+        if let Some(path) = saved {
+            println!("Saved {}", path.display());
         }
+    }
+    Ok(())
+}
 
-        ("unload", Some(sub_matches)) => {
-            let id = sub_matches.value_of("id").unwrap();
-            let msg = if manager.unload_css(id)? {
-                format!("CSS unloaded: {}", id)
-            } else {
-                format!("Failed to unload CSS: {}", id)
-            };
-            println!("{}", msg);
+/// The matrix axes of `scenario run`, each replacing the one in `mus-uc.toml`.
+fn matrix_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    let axis = |name: &'a str, value_name: &'a str, help: &'a str| {
+        Arg::with_name(name)
+            .long(name)
+            .value_name(value_name)
+            .help(help)
+            .multiple(true)
+            .number_of_values(1)
+            .conflicts_with_all(&["variant", "locale"])
+            .takes_value(true)
+    };
+    vec![
+        axis(
+            "color-scheme",
+            "SCHEME",
+            "Matrix axis: light or dark, as the system's setting",
+        )
+        .possible_values(ColorScheme::NAMES),
+        axis(
+            "accent",
+            "COLOR",
+            "Matrix axis: the system's accent color, e.g. #0061e0",
+        ),
+        axis("density", "DENSITY", "Matrix axis: the toolbar density")
+            .possible_values(&["normal", "compact", "touch"]),
+        axis("size", "WIDTHxHEIGHT", "Matrix axis: the outer window size"),
+    ]
+}
+
+/// The matrix of a `scenario run`, if it has one: the `[matrix]` of the
+/// project config with `--matrix`, with axes given on the command line
+/// replacing its own.
+fn matrix_cells(
+    matches: &ArgMatches,
+) -> Result<Option<Vec<MatrixCell>>, Box<dyn std::error::Error>> {
+    let mut settings = if matches.is_present("matrix") {
+        let path =
+            project_config(matches)?.ok_or("--matrix needs a mus-uc.toml with a [matrix] table")?;
+        ProjectConfig::load(&path)?.matrix
+    } else {
+        MatrixSettings::default()
+    };
+    let axes = [
+        ("color-scheme", &mut settings.color_scheme),
+        ("accent", &mut settings.accent),
+        ("density", &mut settings.density),
+        ("size", &mut settings.size),
+    ];
+    for (name, axis) in axes {
+        if let Some(values) = matches.values_of(name) {
+            *axis = values.map(String::from).collect();
+        }
+    }
+    if settings.is_empty() {
+        if matches.is_present("matrix") {
+            return Err("The [matrix] table of mus-uc.toml has no axes".into());
         }
+        return Ok(None);
+    }
+    settings.cells().map(Some)
+}
 
-        ("clear", Some(_)) => {
-            manager.clear_all()?;
-            println!("All CSS cleared");
+fn persist_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("persist")
+        .long("persist")
+        .help("Also save the sheet to the profile, so it is back right after a browser restart")
+}
+
+/// Turn on `--persist`, warning if the browser will not load the saved
+/// sheets at startup.
+fn set_persist(manager: &mut ChromeCSSManager, matches: &ArgMatches) {
+    if !matches.is_present("persist") {
+        return;
+    }
+    manager.set_persist(true);
+    let startup_sheets = manager.setup_prefs(false).map(|prefs| {
+        prefs
+            .iter()
+            .any(|p| p.name == "toolkit.legacyUserProfileCustomizations.stylesheets" && p.was)
+    });
+    if let Ok(false) = startup_sheets {
+        eprintln!(
+            "Warning: toolkit.legacyUserProfileCustomizations.stylesheets is off, so persisted sheets are not loaded at startup; run `setup` to turn it on"
+        );
+    }
+}
+
+/// Load the files or stdin given to `load` into one browser.
+fn run_load(
+    manager: &mut ChromeCSSManager,
+    sub_matches: &ArgMatches,
+) -> Result<(), Box<dyn std::error::Error>> {
+    manager.set_cycle_policy(cycle_policy(sub_matches)?);
+    set_persist(manager, sub_matches);
+    let id = sub_matches.value_of("id");
+    let files: Vec<&str> = sub_matches
+        .values_of("file")
+        .map(|v| v.collect())
+        .unwrap_or_default();
+
+    if let Some(rev) = sub_matches.value_of("git-ref") {
+        if files.len() > 1 {
+            return Err("--git-ref can only be used with a single file".into());
         }
+        let (sheet_id, commit) =
+            load_git_revision(manager, files[0], rev, id, cycle_policy(sub_matches)?)?;
+        record_sheet(manager, &sheet_id, Some(Path::new(files[0])), sub_matches);
+        println!("CSS loaded from {} with ID: {}", commit, sheet_id);
+        run_load_hooks(manager, &sheet_id, Some(Path::new(files[0])));
+        return Ok(());
+    }
 
-        ("list", Some(_)) => {
-            let loaded = manager.list_loaded();
-            if loaded.is_empty() {
-                println!("No stylesheets loaded");
-            } else {
-                println!("Loaded stylesheets:");
-                for id in loaded {
-                    println!("  - {}", id);
+    let strict = sub_matches.is_present("strict");
+
+    if files.len() > 1 {
+        if id.is_some() {
+            return Err("--id cannot be used when loading several files".into());
+        }
+        for file in &files {
+            let path = Path::new(file);
+            let resolved = manager.resolve_file(path)?;
+            check_before_load(manager, path, &resolved, strict)?;
+        }
+        let mut failed = 0;
+        for entry in manager.load_many(&files)? {
+            if let Some(resolved) = &entry.resolved {
+                report_unresolved(resolved);
+            }
+            match entry.result {
+                Ok(id) => {
+                    record_sheet(manager, &id, Some(&entry.path), sub_matches);
+                    println!("{} loaded with ID: {}", entry.path.display(), id);
+                    report_pages(manager, &id, sub_matches);
+                    run_load_hooks(manager, &id, Some(&entry.path));
+                }
+                Err(e) => {
+                    failed += 1;
+                    eprintln!("Failed to load {}: {}", entry.path.display(), e);
                 }
             }
         }
+        if failed > 0 {
+            return Err(format!("{} of {} files failed to load", failed, files.len()).into());
+        }
+        return Ok(());
+    }
 
-        ("interactive", Some(_)) => {
-            run_interactive_mode(&mut manager)?;
+    let (sheet_id, source) = match files.first() {
+        Some(file) => {
+            let path = Path::new(file);
+            let resolved = manager.resolve_file(path)?;
+            report_unresolved(&resolved);
+            check_before_load(manager, path, &resolved, strict)?;
+            let sheet_id = manager.load_resolved(path, &resolved, id)?;
+            record_sheet(manager, &sheet_id, Some(path), sub_matches);
+            (sheet_id, Some(path))
+        }
+        None => {
+            let css = read_input(None, "Enter CSS content (Ctrl+D to finish):")?;
+            let sheet_id = manager.load_css(&css, id)?;
+            record_sheet(manager, &sheet_id, None, sub_matches);
+            (sheet_id, None)
         }
+    };
+    println!("CSS loaded with ID: {}", sheet_id);
+    report_pages(manager, &sheet_id, sub_matches);
+    run_load_hooks(manager, &sheet_id, source);
+    Ok(())
+}
 
-        ("screenshot", Some(sub_matches)) => {
-            let output = sub_matches.value_of("output").unwrap_or("screenshot.png");
-            let selector = sub_matches.value_of("selector");
+/// Check the pages given with `load --target`.
+fn report_pages(manager: &mut ChromeCSSManager, id: &str, matches: &ArgMatches) {
+    let Some(pages) = matches.values_of("target") else {
+        return;
+    };
+    match manager.check_pages(id, &pages.collect::<Vec<_>>()) {
+        Ok(checks) => {
+            for check in checks {
+                println!("  {}", check);
+            }
+        }
+        Err(e) => eprintln!("Could not check the target pages: {}", e),
+    }
+}
 
-            let connection = MarionetteConnection::connect(&MarionetteSettings::new())?;
-            let mut screenshot_manager = ScreenshotManager::new(connection)?;
-            screenshot_manager.screenshot_to_file(Path::new(output), selector)?;
+/// Capture the UI with each of two stylesheets and save the comparison.
+fn run_compare(matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let a = Path::new(matches.value_of("a").unwrap());
+    let b = matches.value_of("b").map(Path::new);
+    let variant = matches
+        .value_of("variant")
+        .map(MediaSetting::parse)
+        .transpose()?;
+    let output = Path::new(matches.value_of("output").unwrap());
+    let delay: u64 = matches
+        .value_of("delay")
+        .unwrap()
+        .parse()
+        .map_err(|_| "--delay must be a number of milliseconds")?;
+    let blink = output
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("gif"));
 
-            match selector {
-                Some(sel) => println!("Screenshot of element '{}' saved to: {}", sel, output),
-                None => println!("Full-screen screenshot saved to: {}", output),
+    let mut manager = connect_manager()?;
+    prepare_capture(&manager)?;
+    let selector = matches.value_of("selector");
+    let (a_png, b_png) = match (b, variant) {
+        (Some(b), None) => capture_pair(&mut manager, a, b, selector)?,
+        (Some(b), Some(variant)) => {
+            let connection = manager.connection().clone();
+            with_emulation(&connection, &[variant], || {
+                capture_pair(&mut manager, a, b, selector)
+            })?
+        }
+        (None, Some(variant)) => capture_variant(&mut manager, a, variant, selector)?,
+        (None, None) => unreachable!(),
+    };
+    let (a_label, b_label) = match (b, variant) {
+        (Some(b), None) => (a.display().to_string(), b.display().to_string()),
+        (Some(b), Some(variant)) => (
+            format!("{} with {}", a.display(), variant.as_str()),
+            format!("{} with {}", b.display(), variant.as_str()),
+        ),
+        (None, Some(variant)) => (
+            a.display().to_string(),
+            format!("{} with {}", a.display(), variant.as_str()),
+        ),
+        (None, None) => unreachable!(),
+    };
+    let (a_capture, b_capture) = (Capture::from_png(&a_png)?, Capture::from_png(&b_png)?);
+    let image = if blink {
+        blink_gif(
+            &a_capture,
+            &b_capture,
+            std::time::Duration::from_millis(delay),
+        )?
+    } else {
+        side_by_side(&a_capture, &b_capture)?
+    };
+    fs::write(output, image)?;
+    println!(
+        "{} ({}) and {} ({}) compared in {}",
+        a_label,
+        if blink { "first" } else { "left" },
+        b_label,
+        if blink { "second" } else { "right" },
+        output.display()
+    );
+    Ok(())
+}
+
+/// Read a sheet back from the browser, once or each time it changes.
+fn run_pull(matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let id = matches.value_of("id").unwrap();
+    let out = matches.value_of("out").map(Path::new);
+    let interval: u64 = matches
+        .value_of("interval")
+        .unwrap()
+        .parse()
+        .map_err(|_| "--interval must be a number of milliseconds")?;
+    let mut manager = connect_manager()?;
+
+    let mut last: Option<String> = None;
+    loop {
+        let sheet = manager
+            .pull_sheet(id)?
+            .ok_or_else(|| format!("No stylesheet loaded with ID: {}", id))?;
+        if last.is_none() && !sheet.live {
+            eprintln!(
+                "Warning: {} is not applied to the browser window; pulling the CSS as registered",
+                id
+            );
+        }
+        if last.as_deref() != Some(sheet.css.as_str()) {
+            match out {
+                Some(out) => {
+                    fs::write(out, &sheet.css)?;
+                    println!("Pulled {} to {}", id, out.display());
+                }
+                None => print!("{}", sheet.css),
             }
+            last = Some(sheet.css);
         }
+        if !matches.is_present("watch-browser") {
+            return Ok(());
+        }
+        std::thread::sleep(std::time::Duration::from_millis(interval));
+    }
+}
 
-        ("exec", Some(sub_matches)) => {
-            let js = read_input(sub_matches.value_of("file"), "Enter JavaScript code (Ctrl+D to finish):")?;
-            if js.trim().is_empty() {
-                return Err("No JavaScript code provided".into());
+/// Mention registered sheets `list` cannot show: ones from the tool that the
+/// harness lost track of, and unmarked ones from older versions or other tools.
+fn report_manifest_issues(path: &Path, manifest: &ChromeManifest) {
+    for issue in &manifest.issues {
+        eprintln!(
+            "Warning: {}:{}: {}",
+            path.display(),
+            issue.line,
+            issue.message
+        );
+    }
+}
+
+fn report_untracked(scanned: &[ScannedSheet]) {
+    let untracked: Vec<&str> = scanned
+        .iter()
+        .filter(|sheet| !sheet.tracked)
+        .filter_map(|sheet| sheet.id.as_deref())
+        .collect();
+    if !untracked.is_empty() {
+        println!("Registered but not tracked (remove with panic-reset):");
+        for id in untracked {
+            println!("  - {}", id);
+        }
+    }
+    let foreign = scanned
+        .iter()
+        .filter(|sheet| sheet.id.is_none() && !sheet.tracked)
+        .count();
+    if foreign > 0 {
+        println!(
+            "{} other data: sheets registered, from older versions or other tools",
+            foreign
+        );
+    }
+}
+
+/// Print compatibility warnings for a file about to be loaded. With `strict`,
+/// warnings (or a failed check) stop the load.
+fn check_before_load(
+    manager: &mut ChromeCSSManager,
+    path: &Path,
+    resolved: &ResolvedStylesheet,
+    strict: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let root = canonicalize(path)?.parent().map(Path::to_path_buf);
+    let report = match manager.check_compat(resolved, root.as_deref()) {
+        Ok(report) => report,
+        Err(e) if strict => return Err(e),
+        Err(e) => {
+            eprintln!("Could not check compatibility: {}", e);
+            return Ok(());
+        }
+    };
+    report_compat(&report, false);
+    if strict && report.warnings() > 0 {
+        return Err(format!(
+            "{}: {} compatibility warnings (loaded nothing because of --strict)",
+            path.display(),
+            report.warnings()
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Load `file` as it was at `rev`; returns the sheet ID and a description of
+/// the commit. The checkout is removed on return, so its assets are inlined
+/// as `data:` URIs instead of pointing into it.
+#[cfg(not(target_family = "wasm"))]
+fn load_git_revision(
+    manager: &mut ChromeCSSManager,
+    file: &str,
+    rev: &str,
+    id: Option<&str>,
+    policy: CyclePolicy,
+) -> Result<(String, String), Box<dyn std::error::Error>> {
+    let snapshot = GitSnapshot::checkout(Path::new(file), rev)?;
+    let path = snapshot.path_for(Path::new(file))?;
+    let resolved = ImportResolver::new()
+        .with_cycle_policy(policy)
+        .with_assets(AssetRewriter::new(AssetMode::Inline { limit: u64::MAX }))
+        .resolve(&path)?;
+    report_unresolved(&resolved);
+    let sheet_id = manager.load_resolved(&path, &resolved, id)?;
+    let commit = snapshot.commit();
+    Ok((sheet_id, format!("{} {}", commit.short_id, commit.summary)))
+}
+
+#[cfg(target_family = "wasm")]
+fn load_git_revision(
+    _manager: &mut ChromeCSSManager,
+    _file: &str,
+    _rev: &str,
+    _id: Option<&str>,
+    _policy: CyclePolicy,
+) -> Result<(String, String), Box<dyn std::error::Error>> {
+    Err("--git-ref is not available in WASI builds".into())
+}
+
+/// Start Firefox, report where Marionette listens, and wait for it to exit.
+#[cfg(not(target_family = "wasm"))]
+fn run_launch(matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let virtual_display = matches
+        .is_present("virtual-display")
+        .then(|| VirtualDisplay::parse(matches.value_of("virtual-display").unwrap_or("auto")))
+        .transpose()?;
+    let mut options = LaunchOptions {
+        firefox: matches.value_of("firefox").map(PathBuf::from),
+        profile: matches.value_of("profile").map(PathBuf::from),
+        port: marionette_settings().port,
+        virtual_display,
+        ..LaunchOptions::default()
+    };
+    if let Some(size) = matches.value_of("size") {
+        options.size = parse_size(size)?;
+    }
+    if let Some(timeout) = matches.value_of("timeout") {
+        options.timeout = parse_duration(timeout)?;
+    }
+
+    let launched = launch(&options)?;
+    let display = match launched.xvfb_display() {
+        Some(display) => format!("Xvfb on {}", display),
+        None => launched.display().describe(),
+    };
+    println!(
+        "Firefox {} is listening for Marionette on port {} ({}, profile {})",
+        launched.pid(),
+        options.port,
+        display,
+        launched.profile().display()
+    );
+    let code = launched.wait()?;
+    if code != 0 {
+        return Err(format!("Firefox exited with code {}", code).into());
+    }
+    Ok(())
+}
+
+/// Write the files of a CI image and say how to use them.
+#[cfg(not(target_family = "wasm"))]
+fn run_ci_image_prepare(matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let binary = match matches.value_of("binary") {
+        Some(binary) => PathBuf::from(binary),
+        None if cfg!(target_os = "linux") => std::env::current_exe()?,
+        None => {
+            return Err("The image needs a Linux build of mus-uc; give it with --binary".into())
+        }
+    };
+    let options = ImageOptions {
+        name: matches.value_of("name").unwrap().to_string(),
+        binary,
+        firefox: FirefoxChannel::parse(matches.value_of("firefox").unwrap())?,
+        base: matches.value_of("base").unwrap().to_string(),
+        size: parse_size(matches.value_of("size").unwrap())?,
+    };
+    let out = Path::new(matches.value_of("out").unwrap());
+    for file in prepare(&options, out)? {
+        println!("Wrote {}", file.display());
+    }
+    println!(
+        "\nStart it with:\n  docker compose -f {} up -d --build\nThen connect with:\n  mus-uc --container {} list",
+        out.join("compose.yaml").display(),
+        options.name
+    );
+    Ok(())
+}
+
+#[cfg(target_family = "wasm")]
+fn run_ci_image_prepare(_matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    Err("ci-image is not available in WASI builds".into())
+}
+
+#[cfg(target_family = "wasm")]
+fn run_launch(_matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    Err("launch is not available in WASI builds".into())
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn run_bisect(matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    const SHEET_ID: &str = "bisect-sheet";
+
+    let file = matches.value_of("file").unwrap();
+    let good = matches.value_of("good").unwrap();
+    let bad = matches.value_of("bad").unwrap_or("HEAD");
+    let selector = matches.value_of("selector");
+    let output = Path::new(matches.value_of("output").unwrap_or("bisect-screenshots"));
+
+    let mut commits = commit_range(Path::new(file), good, bad)?;
+    if commits.is_empty() {
+        return Err(format!("No commits between {} and {}", good, bad).into());
+    }
+    fs::create_dir_all(output)?;
+
+    let mut manager = connect_manager()?;
+    manager.set_cycle_policy(cycle_policy(matches)?);
+    manager.set_post_process(post_process(matches)?);
+    prepare_capture(&manager)?;
+
+    // Loads one revision and saves a screenshot named after its position and commit
+    let capture = |manager: &mut ChromeCSSManager,
+                   step: usize,
+                   commit: &CommitInfo|
+     -> Result<PathBuf, Box<dyn std::error::Error>> {
+        manager.unload_css(SHEET_ID)?;
+        let snapshot = GitSnapshot::checkout(Path::new(file), &commit.id)?;
+        let (_, resolved) =
+            manager.load_css_file(&snapshot.path_for(Path::new(file))?, Some(SHEET_ID))?;
+        report_unresolved(&resolved);
+        // Give Firefox a moment to restyle before capturing
+        std::thread::sleep(std::time::Duration::from_millis(300));
+        let path = output.join(format!("{:03}-{}.png", step, commit.short_id));
+        manager.save_screenshot(&path, selector)?;
+        Ok(path)
+    };
+
+    if matches.is_present("all") {
+        for (step, commit) in commits.iter().enumerate() {
+            let path = capture(&mut manager, step + 1, commit)?;
+            println!(
+                "{} {} -> {}",
+                commit.short_id,
+                commit.summary,
+                path.display()
+            );
+        }
+        manager.unload_css(SHEET_ID)?;
+        return Ok(());
+    }
+
+    // `commits[hi]` is known bad; everything before `lo` is known good
+    let mut lo = 0;
+    let mut hi = commits.len() - 1;
+    let mut step = 0;
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        let commit = commits[mid].clone();
+        step += 1;
+        let path = capture(&mut manager, step, &commit)?;
+        println!(
+            "[{} left] {} {} -> {}",
+            hi - lo,
+            commit.short_id,
+            commit.summary,
+            path.display()
+        );
+
+        match prompt_verdict()? {
+            Verdict::Good => lo = mid + 1,
+            Verdict::Bad => hi = mid,
+            Verdict::Skip => {
+                commits.remove(mid);
+                hi -= 1;
             }
+            Verdict::Quit => {
+                manager.unload_css(SHEET_ID)?;
+                println!("Bisect stopped; screenshots are in {}", output.display());
+                return Ok(());
+            }
+        }
+    }
 
-            let args = sub_matches
-                .value_of("args")
-                .map(|s| -> Result<Vec<serde_json::Value>, Box<dyn std::error::Error>> {
-                    match serde_json::from_str(s)? {
-                        serde_json::Value::Array(arr) => Ok(arr),
-                        _ => Err("Arguments must be a JSON array".into()),
-                    }
-                })
-                .transpose()?;
+    manager.unload_css(SHEET_ID)?;
+    let first_bad = &commits[hi];
+    println!(
+        "First bad commit: {} {}",
+        first_bad.short_id, first_bad.summary
+    );
+    println!("Screenshots are in {}", output.display());
+    Ok(())
+}
 
-            let mut connection = MarionetteConnection::connect(&MarionetteSettings::new())?;
-            connection.set_context("chrome")?;
-            let result = connection.execute_script(&js, args)?;
-            println!("{}", serde_json::to_string_pretty(&result)?);
+#[cfg(target_family = "wasm")]
+fn run_bisect(_matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    Err("bisect is not available in WASI builds".into())
+}
+
+#[cfg(not(target_family = "wasm"))]
+enum Verdict {
+    Good,
+    Bad,
+    Skip,
+    Quit,
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn prompt_verdict() -> Result<Verdict, Box<dyn std::error::Error>> {
+    loop {
+        print!("Does this revision look right? [g]ood/[b]ad/[s]kip/[q]uit: ");
+        io::stdout().flush()?;
+        let mut line = String::new();
+        if io::stdin().read_line(&mut line)? == 0 {
+            return Ok(Verdict::Quit);
+        }
+        match line.trim() {
+            "g" | "good" => return Ok(Verdict::Good),
+            "b" | "bad" => return Ok(Verdict::Bad),
+            "s" | "skip" => return Ok(Verdict::Skip),
+            "q" | "quit" => return Ok(Verdict::Quit),
+            _ => {}
         }
+    }
+}
 
-        _ => {
-            println!("Use --help for usage information");
+fn run_package(matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let entry = PathBuf::from(matches.value_of("file").unwrap());
+    let name = match matches.value_of("name") {
+        Some(name) => name.to_string(),
+        None => canonicalize(&entry)?
+            .parent()
+            .and_then(|dir| dir.file_name())
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "theme".to_string()),
+    };
+    let version = matches.value_of("version").unwrap_or("0.1.0").to_string();
+
+    let options = PackageOptions {
+        entry,
+        name: name.clone(),
+        version: version.clone(),
+        description: matches.value_of("description").map(String::from),
+        firefox: FirefoxRange {
+            min: matches.value_of("min-firefox").map(String::from),
+            max: matches.value_of("max-firefox").map(String::from),
+        },
+        manifest: matches.value_of("manifest").map(PathBuf::from),
+        format: CssFormat::parse(matches.value_of("format").unwrap_or("preserve"))?,
+        cycle_policy: cycle_policy(matches)?,
+    };
+    let out = matches
+        .value_of("out")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(format!("{}-{}.zip", name, version)));
+
+    let summary = build_package(&options, &out)?;
+    for warning in &summary.warnings {
+        eprintln!("Warning: {}", warning);
+    }
+    println!("Packaged {} {} into {}", name, version, out.display());
+    for file in &summary.metadata.files {
+        println!("  {}", file);
+    }
+    Ok(())
+}
+
+/// `millis` as a short age such as `42s ago` or `3h ago`.
+fn age(millis: u64) -> String {
+    let seconds = millis / 1000;
+    match seconds {
+        0..=59 => format!("{}s ago", seconds),
+        60..=3599 => format!("{}m ago", seconds / 60),
+        3600..=86399 => format!("{}h ago", seconds / 3600),
+        _ => format!("{}d ago", seconds / 86400),
+    }
+}
+
+/// Print `value` as pretty JSON, or a string as it is with `raw`.
+/// Print the effective layered settings, with `origin` where each comes
+/// from.
+fn show_config(layers: &Layers, project: Option<&Path>, origin: bool) {
+    if origin {
+        let files = [
+            ("system", UserConfig::system_path()),
+            ("user", UserConfig::user_path()),
+            ("project", project.map(Path::to_path_buf)),
+        ];
+        for (name, path) in files {
+            match path {
+                Some(path) if path.is_file() => println!("# {} config: {}", name, path.display()),
+                Some(path) => println!("# {} config: {} (not found)", name, path.display()),
+                None => println!("# {} config: none", name),
+            }
+        }
+    }
+    let settings = marionette_settings();
+    let retry = retry_policy();
+    let mut lines = Vec::new();
+    for key in KEYS {
+        let value = match *key {
+            "host" => Some(format!("{:?}", settings.host)),
+            "port" => Some(settings.port.to_string()),
+            "socket" => settings
+                .socket
+                .as_ref()
+                .map(|path| format!("{:?}", path.display().to_string())),
+            "app" => settings.app.as_ref().map(|app| format!("{:?}", app.name)),
+            "backend" => Some(format!("{:?}", settings.backend.as_str())),
+            "retry.attempts" => Some(retry.attempts.to_string()),
+            "retry.interval" => Some(retry.interval.as_millis().to_string()),
+            "retry.timeout" => retry.timeout.map(|t| t.as_millis().to_string()),
+            _ => None,
+        };
+        let source = match (layers.origin(key), *key) {
+            (Some(origin), "socket") if layers.socket().is_none() => {
+                format!("{} set it, but a later host or port replaces it", origin)
+            }
+            (Some(origin), _) => origin.to_string(),
+            (None, "port") if settings.app.is_some() || layers.get("backend").is_some() => {
+                "default for the app or backend".to_string()
+            }
+            (None, _) => "default".to_string(),
+        };
+        match value {
+            Some(value) => lines.push((format!("{} = {}", key, value), source)),
+            None if origin => lines.push((format!("# {} is not set", key), source)),
+            None => {}
         }
     }
+    let width = lines.iter().map(|(line, _)| line.len()).max().unwrap_or(0);
+    for (line, source) in lines {
+        if origin {
+            println!("{:width$}  # {}", line, source, width = width);
+        } else {
+            println!("{}", line);
+        }
+    }
+}
 
+fn print_value(value: &serde_json::Value, raw: bool) -> Result<(), Box<dyn std::error::Error>> {
+    match value {
+        serde_json::Value::String(text) if raw => println!("{}", text),
+        _ => println!("{}", serde_json::to_string_pretty(value)?),
+    }
     Ok(())
 }
 
+fn yes_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("yes")
+        .short("y")
+        .long("yes")
+        .help("Do not ask for confirmation")
+}
+
+/// Ask `question` on the terminal unless `--yes` was given. Without a
+/// terminal to ask on, `--yes` is required.
+fn confirm(matches: &ArgMatches, question: &str) -> Result<(), Box<dyn std::error::Error>> {
+    if matches.is_present("yes") {
+        return Ok(());
+    }
+    if !io::stdin().is_terminal() {
+        return Err(format!(
+            "{} Pass --yes to confirm when not run from a terminal",
+            question
+        )
+        .into());
+    }
+    print!("{} [y/N] ", question);
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    match answer.trim().to_ascii_lowercase().as_str() {
+        "y" | "yes" => Ok(()),
+        _ => Err("Cancelled".into()),
+    }
+}
+
+/// Whether `--read-only` is given, or `MUS_UC_READ_ONLY=1` is set, as it
+/// is for plugins run with `--read-only` and the commands they run in turn.
+fn read_only(matches: &ArgMatches) -> bool {
+    matches.is_present("read-only")
+        || std::env::var_os("MUS_UC_READ_ONLY").is_some_and(|v| v == "1")
+}
+
+/// Whether the command `name` changes the browser it connects to, which
+/// `--read-only` refuses. Commands that only write local files are allowed.
+fn changes_browser(name: &str, matches: &ArgMatches) -> bool {
+    match name {
+        "import-profile" => !matches.is_present("dry-run"),
+        "setup" => !matches.is_present("check"),
+        "switch-tab" => !matches.is_present("list"),
+        "locale" => matches.subcommand_name().is_some(),
+        "a11y-emulate" => matches.is_present("settings") || matches.is_present("reset"),
+        "window" => !matches!(
+            matches.subcommand(),
+            ("size", Some(window)) | ("density", Some(window))
+                if !window.is_present("size") && !window.is_present("density")
+        ),
+        "helper" => matches!(
+            matches.subcommand(),
+            ("run", Some(run)) if Helper::find(run.value_of("name").unwrap())
+                .map_or(true, |helper| helper.changes_browser)
+        ),
+        "register-manifest"
+        | "register-resource"
+        | "unregister-resource"
+        | "load"
+        | "watch"
+        | "unload"
+        | "reload"
+        | "panic-reset"
+        | "clear"
+        | "toggle"
+        | "interactive"
+        | "navigate"
+        | "new-tab"
+        | "force-state"
+        | "simulate"
+        | "scenario"
+        | "macro"
+        | "exec"
+        | "raw-command"
+        | "bench"
+        | "compare"
+        | "bisect" => true,
+        _ => false,
+    }
+}
+
 fn read_css_lines() -> Result<String, Box<dyn std::error::Error>> {
     println!("Enter CSS content (empty line to finish):");
     let mut lines = Vec::new();
@@ -252,6 +5297,76 @@ fn read_css_lines() -> Result<String, Box<dyn std::error::Error>> {
     Ok(lines.join("\n"))
 }
 
+fn print_macro_help() {
+    println!("Steps: exec <js>, wait <ms>, wait-for <selector>, wait-until <js>, hover <selector>, click <selector>, simulate <surface> [query], screenshot <name> [selector], helper <name> [k=v ...]");
+    println!("Commands: steps, undo, save, discard, help");
+}
+
+/// The prompt of `macro record`: each step typed is run at once and kept if
+/// it works, and the steps kept are written to `path` at the end.
+fn record_macro(
+    manager: &ChromeCSSManager,
+    mut scenario: Scenario,
+    path: &Path,
+    output: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!("Recording to {}", path.display());
+    print_macro_help();
+
+    loop {
+        print!("> ");
+        io::stdout().flush()?;
+
+        let mut input = String::new();
+        // End of input saves, so a macro can be piped in
+        if io::stdin().read_line(&mut input)? == 0 {
+            println!();
+            break;
+        }
+        match input.trim() {
+            "" => continue,
+            "steps" | "list" => {
+                if scenario.steps.is_empty() {
+                    println!("No steps recorded");
+                }
+                for (i, step) in scenario.steps.iter().enumerate() {
+                    println!("{}. {}", i + 1, step);
+                }
+            }
+            "undo" => match scenario.steps.pop() {
+                Some(step) => println!("Removed: {}", step),
+                None => println!("No steps recorded"),
+            },
+            "help" => print_macro_help(),
+            "save" | "done" | "quit" | "exit" => break,
+            "discard" => {
+                println!("Discarded, nothing written");
+                return Ok(());
+            }
+            line => match Step::parse_line(line) {
+                Ok(step) => match step.run(manager, output) {
+                    Ok(saved) => {
+                        if let Some(path) = saved {
+                            println!("Saved {}", path.display());
+                        }
+                        scenario.steps.push(step);
+                    }
+                    Err(e) => println!("Error (not recorded): {}", e),
+                },
+                Err(e) => println!("Error: {}", e),
+            },
+        }
+    }
+
+    if scenario.steps.is_empty() {
+        println!("No steps recorded, nothing written");
+        return Ok(());
+    }
+    scenario.save(path)?;
+    println!("Steps written to {}", path.display());
+    Ok(())
+}
+
 pub fn run_interactive_mode(
     manager: &mut ChromeCSSManager,
 ) -> Result<(), Box<dyn std::error::Error>> {
@@ -264,25 +5379,30 @@ pub fn run_interactive_mode(
 
         let mut input = String::new();
         io::stdin().read_line(&mut input)?;
-        let parts: Vec<&str> = input.trim().split_whitespace().collect();
+        let parts: Vec<&str> = input.split_whitespace().collect();
         if parts.is_empty() {
             continue;
         }
 
         match parts[0] {
             "load" => {
-                let css = if parts.len() >= 2 && Path::new(parts[1]).exists() {
-                    fs::read_to_string(parts[1])?
-                } else {
-                    read_css_lines()?
-                };
-
-                if !css.is_empty() {
-                    let id = parts.get(2).copied();
-                    match manager.load_css(&css, id) {
-                        Ok(id) => println!("CSS loaded with ID: {}", id),
+                let id = parts.get(2).copied();
+                if parts.len() >= 2 && Path::new(parts[1]).exists() {
+                    match manager.load_css_file(Path::new(parts[1]), id) {
+                        Ok((id, resolved)) => {
+                            report_unresolved(&resolved);
+                            println!("CSS loaded with ID: {}", id);
+                        }
                         Err(e) => println!("Error loading CSS: {}", e),
                     }
+                } else {
+                    let css = read_css_lines()?;
+                    if !css.is_empty() {
+                        match manager.load_css(&css, id) {
+                            Ok(id) => println!("CSS loaded with ID: {}", id),
+                            Err(e) => println!("Error loading CSS: {}", e),
+                        }
+                    }
                 }
             }
 
@@ -322,7 +5442,9 @@ pub fn run_interactive_mode(
 
             _ => {
                 println!("Unknown command: {}", parts[0]);
-                println!("Available commands: load [filepath] [id], unload <id>, clear, list, quit");
+                println!(
+                    "Available commands: load [filepath] [id], unload <id>, clear, list, quit"
+                );
             }
         }
     }
@@ -0,0 +1,255 @@
+//! Screenshots of the same part of the UI with two stylesheets, or with one
+//! stylesheet with and without an accessibility setting, put next to each
+//! other or alternated in an animated GIF.
+//!
+//! Both files are loaded in turn under one sheet ID, so the second replaces
+//! the first and nothing else loaded is disturbed.
+
+use crate::chrome_css_manager::ChromeCSSManager;
+use crate::media_emulation::{with_emulation, MediaSetting};
+use std::io::Cursor;
+use std::path::Path;
+use std::time::Duration;
+
+const COMPARE_SHEET_ID: &str = "mus-uc-compare";
+
+/// Pixels between the two captures side by side.
+const GAP: u32 = 16;
+const BACKGROUND: [u8; 4] = [128, 128, 128, 255];
+const DIFF_COLOR: [u8; 4] = [255, 0, 255, 255];
+
+/// A decoded screenshot.
+#[derive(Debug, Clone)]
+pub struct Capture {
+    pub width: u32,
+    pub height: u32,
+    /// 8-bit RGBA, row by row.
+    pub rgba: Vec<u8>,
+}
+
+impl Capture {
+    pub fn from_png(png: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut decoder = png::Decoder::new(Cursor::new(png));
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info()?;
+        let size = reader
+            .output_buffer_size()
+            .ok_or("Screenshot is too large")?;
+        let mut buf = vec![0; size];
+        let info = reader.next_frame(&mut buf)?;
+        buf.truncate(info.buffer_size());
+
+        let rgba = match info.color_type {
+            png::ColorType::Rgba => buf,
+            png::ColorType::Rgb => buf
+                .chunks_exact(3)
+                .flat_map(|p| [p[0], p[1], p[2], 255])
+                .collect(),
+            png::ColorType::GrayscaleAlpha => buf
+                .chunks_exact(2)
+                .flat_map(|p| [p[0], p[0], p[0], p[1]])
+                .collect(),
+            png::ColorType::Grayscale => buf.iter().flat_map(|&v| [v, v, v, 255]).collect(),
+            png::ColorType::Indexed => return Err("Unexpected indexed screenshot".into()),
+        };
+        Ok(Capture {
+            width: info.width,
+            height: info.height,
+            rgba,
+        })
+    }
+
+    pub fn to_png(&self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let mut png = Vec::new();
+        let mut encoder = png::Encoder::new(&mut png, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header()?.write_image_data(&self.rgba)?;
+        Ok(png)
+    }
+
+    fn blank(width: u32, height: u32) -> Self {
+        Capture {
+            width,
+            height,
+            rgba: BACKGROUND.repeat((width * height) as usize),
+        }
+    }
+
+    /// Copy `other` in with its top left corner at `x`, `y`.
+    fn paste(&mut self, other: &Capture, x: u32, y: u32) {
+        let row = (other.width * 4) as usize;
+        for (i, line) in other.rgba.chunks_exact(row).enumerate() {
+            let start = (((y + i as u32) * self.width + x) * 4) as usize;
+            self.rgba[start..start + row].copy_from_slice(line);
+        }
+    }
+
+    /// This capture on a background of `width` by `height`, so frames of
+    /// different sizes line up.
+    fn padded(&self, width: u32, height: u32) -> Self {
+        let mut padded = Capture::blank(width, height);
+        padded.paste(self, 0, 0);
+        padded
+    }
+}
+
+/// Screenshot `selector`, or the whole window, with `a` loaded and then with
+/// `b` in its place, and unload it again. Returns the two PNGs.
+pub fn capture_pair(
+    manager: &mut ChromeCSSManager,
+    a: &Path,
+    b: &Path,
+    selector: Option<&str>,
+) -> Result<(Vec<u8>, Vec<u8>), Box<dyn std::error::Error>> {
+    let mut capture = |path: &Path| -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        manager.load_css_file(path, Some(COMPARE_SHEET_ID))?;
+        manager.screenshot(selector)
+    };
+    let captures = capture(a).and_then(|a| Ok((a, capture(b)?)));
+    manager.unload_css(COMPARE_SHEET_ID)?;
+    captures
+}
+
+/// Screenshot `selector`, or the whole window, with `css` loaded, first as
+/// it is and then with `variant` turned on. Returns the two PNGs.
+pub fn capture_variant(
+    manager: &mut ChromeCSSManager,
+    css: &Path,
+    variant: MediaSetting,
+    selector: Option<&str>,
+) -> Result<(Vec<u8>, Vec<u8>), Box<dyn std::error::Error>> {
+    manager.load_css_file(css, Some(COMPARE_SHEET_ID))?;
+    let connection = manager.connection().clone();
+    let captures = manager.screenshot(selector).and_then(|plain| {
+        let emulated = with_emulation(&connection, &[variant], || manager.screenshot(selector))?;
+        Ok((plain, emulated))
+    });
+    manager.unload_css(COMPARE_SHEET_ID)?;
+    captures
+}
+
+/// `a` on the left and `b` on the right, as a PNG.
+pub fn side_by_side(a: &Capture, b: &Capture) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut image = Capture::blank(a.width + GAP + b.width, a.height.max(b.height));
+    image.paste(a, 0, 0);
+    image.paste(b, a.width + GAP, 0);
+    image.to_png()
+}
+
+/// Where `b` differs from `a`: changed pixels in magenta over a faded copy
+/// of `a`. Also returns how many pixels changed, counting those only one of
+/// them covers.
+pub fn diff(a: &Capture, b: &Capture) -> (Capture, u64) {
+    let width = a.width.max(b.width);
+    let height = a.height.max(b.height);
+    fn pixel(capture: &Capture, x: u32, y: u32) -> Option<&[u8]> {
+        (x < capture.width && y < capture.height).then(|| {
+            let i = ((y * capture.width + x) * 4) as usize;
+            &capture.rgba[i..i + 4]
+        })
+    }
+
+    let mut image = Capture::blank(width, height);
+    let mut changed = 0;
+    for y in 0..height {
+        for x in 0..width {
+            let (pa, pb) = (pixel(a, x, y), pixel(b, x, y));
+            let out = match (pa, pb) {
+                (Some(p), Some(q)) if p == q => {
+                    // A quarter of the original over white
+                    let fade = |v: u8| 255 - (255 - v) / 4;
+                    [fade(p[0]), fade(p[1]), fade(p[2]), 255]
+                }
+                // Outside both captures when their shapes differ
+                (None, None) => BACKGROUND,
+                _ => {
+                    changed += 1;
+                    DIFF_COLOR
+                }
+            };
+            let i = ((y * width + x) * 4) as usize;
+            image.rgba[i..i + 4].copy_from_slice(&out);
+        }
+    }
+    (image, changed)
+}
+
+/// A GIF that switches between `a` and `b` every `delay`, looping forever.
+pub fn blink_gif(
+    a: &Capture,
+    b: &Capture,
+    delay: Duration,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let width = a.width.max(b.width);
+    let height = a.height.max(b.height);
+    let (Ok(gif_width), Ok(gif_height)) = (u16::try_from(width), u16::try_from(height)) else {
+        return Err(format!("{}x{} is too large for a GIF", width, height).into());
+    };
+    // GIF delays are in hundredths of a second
+    let delay = (delay.as_millis() / 10).clamp(1, u16::MAX as u128) as u16;
+
+    let mut gif = Vec::new();
+    {
+        let mut encoder = gif::Encoder::new(&mut gif, gif_width, gif_height, &[])?;
+        encoder.set_repeat(gif::Repeat::Infinite)?;
+        for capture in [a, b] {
+            let mut rgba = capture.padded(width, height).rgba;
+            let mut frame = gif::Frame::from_rgba_speed(gif_width, gif_height, &mut rgba, 10);
+            frame.delay = delay;
+            encoder.write_frame(&frame)?;
+        }
+    }
+    Ok(gif)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{diff, Capture, BACKGROUND, DIFF_COLOR};
+
+    fn solid(width: u32, height: u32, rgba: [u8; 4]) -> Capture {
+        Capture {
+            width,
+            height,
+            rgba: rgba.repeat((width * height) as usize),
+        }
+    }
+
+    fn at(capture: &Capture, x: u32, y: u32) -> [u8; 4] {
+        let i = ((y * capture.width + x) * 4) as usize;
+        capture.rgba[i..i + 4].try_into().unwrap()
+    }
+
+    #[test]
+    fn identical_captures_are_faded() {
+        let a = solid(4, 3, [0, 0, 0, 255]);
+        let (image, changed) = diff(&a, &a.clone());
+        assert_eq!(changed, 0);
+        assert_eq!((image.width, image.height), (4, 3));
+        assert_eq!(at(&image, 2, 1), [192, 192, 192, 255]);
+    }
+
+    #[test]
+    fn differing_pixels_are_counted() {
+        let a = solid(4, 3, [0, 0, 0, 255]);
+        let mut b = a.clone();
+        b.rgba[0..4].copy_from_slice(&[255, 255, 255, 255]);
+        let (image, changed) = diff(&a, &b);
+        assert_eq!(changed, 1);
+        assert_eq!(at(&image, 0, 0), DIFF_COLOR);
+        assert_eq!(at(&image, 1, 0), [192, 192, 192, 255]);
+    }
+
+    #[test]
+    fn mismatched_sizes_count_only_what_one_covers() {
+        let a = solid(4, 2, [0, 0, 0, 255]);
+        let b = solid(2, 4, [0, 0, 0, 255]);
+        let (image, changed) = diff(&a, &b);
+        assert_eq!((image.width, image.height), (4, 4));
+        // 2x2 only in `a` and 2x2 only in `b`; the corner is in neither
+        assert_eq!(changed, 8);
+        assert_eq!(at(&image, 3, 0), DIFF_COLOR);
+        assert_eq!(at(&image, 0, 3), DIFF_COLOR);
+        assert_eq!(at(&image, 3, 3), BACKGROUND);
+    }
+}
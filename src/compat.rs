@@ -0,0 +1,547 @@
+//! Compatibility checks between a stylesheet and the connected Firefox.
+//!
+//! Three heuristics are combined:
+//!
+//! - selectors are parsed by the browser (`CSS.supports(selector(...))`) and
+//!   IDs they mention are looked up in the browser window;
+//! - selectors are matched against a table of hooks known to have been renamed
+//!   or removed;
+//! - declarations using `-moz-` properties or values are checked with
+//!   `CSS.supports()`.
+//!
+//! None of these is proof that a rule is broken, so issues are reported with
+//! the file and line they came from and left for the author to judge.
+
+use crate::css_format::split_top_level;
+use crate::css_imports::{
+    is_ident_byte, skip_comment, skip_string, strip_comments, ResolvedStylesheet,
+};
+use crate::marionette_client::MarionetteConnection;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CompatSeverity {
+    /// The rule is very likely broken on this version.
+    Warning,
+    /// The rule may be fine, e.g. a selector for a state that is not active.
+    Info,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CompatIssueKind {
+    InvalidSelector,
+    MissingId,
+    RenamedHook,
+    UnmatchedSelector,
+    UnsupportedDeclaration,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CompatIssue {
+    pub severity: CompatSeverity,
+    pub kind: CompatIssueKind,
+    /// 1-based line in the bundled stylesheet.
+    pub line: usize,
+    /// `file:line` in the original sources.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CompatReport {
+    pub firefox_version: String,
+    pub issues: Vec<CompatIssue>,
+}
+
+impl CompatReport {
+    pub fn warnings(&self) -> usize {
+        self.issues
+            .iter()
+            .filter(|issue| issue.severity == CompatSeverity::Warning)
+            .count()
+    }
+}
+
+/// A hook that was renamed or removed in some Firefox release.
+struct RenamedHook {
+    old: &'static str,
+    replacement: Option<&'static str>,
+    since: &'static str,
+}
+
+const RENAMED_HOOKS: &[RenamedHook] = &[
+    RenamedHook {
+        old: "[tabsintitlebar]",
+        replacement: Some("[customtitlebar]"),
+        since: "Firefox 133",
+    },
+    RenamedHook {
+        old: "#tracking-protection-icon-box",
+        replacement: Some("#tracking-protection-icon-container"),
+        since: "Firefox 70",
+    },
+    RenamedHook {
+        old: ".tabbrowser-arrowscrollbox",
+        replacement: Some("#tabbrowser-arrowscrollbox"),
+        since: "Firefox 71",
+    },
+    RenamedHook {
+        old: ".tab-line",
+        replacement: None,
+        since: "Firefox 89",
+    },
+    RenamedHook {
+        old: "#pageActionButton",
+        replacement: None,
+        since: "Firefox 89",
+    },
+    RenamedHook {
+        old: "#urlbar-display-box",
+        replacement: None,
+        since: "Firefox 89",
+    },
+    RenamedHook {
+        old: ".toolbarbutton-menubutton-dropmarker",
+        replacement: None,
+        since: "Firefox 68",
+    },
+];
+
+/// IDs of panels and popups Firefox only creates when they first open, so a
+/// window that has not opened them yet lacks them. Those kept in a
+/// `<template>` are found there; these are built by script.
+const LAZY_IDS: &[&str] = &[
+    "appMenu-popup",
+    "appMenu-mainView",
+    "appMenu-protonMainView",
+    "customizationui-widget-panel",
+    "downloadsPanel",
+    "downloadsListBox",
+    "editBookmarkPanel",
+    "identity-popup",
+    "permission-popup",
+    "protections-popup",
+    "unified-extensions-panel",
+    "unified-extensions-view",
+    "widget-overflow",
+    "widget-overflow-mainView",
+];
+
+/// Pseudo-classes that depend on user interaction or transient state; they are
+/// dropped before checking whether a selector matches anything.
+const DYNAMIC_PSEUDO_CLASSES: &[&str] = &[
+    ":hover",
+    ":active",
+    ":focus-visible",
+    ":focus-within",
+    ":focus",
+    ":checked",
+    ":open",
+    ":popover-open",
+    ":-moz-window-inactive",
+    ":-moz-lwtheme",
+];
+
+/// Check `resolved` against the browser behind `connection`, which must be in
+/// the chrome context. Locations are reported relative to `root` when given.
+pub fn check_compat(
+    connection: &mut MarionetteConnection,
+    resolved: &ResolvedStylesheet,
+    root: Option<&Path>,
+) -> Result<CompatReport, Box<dyn std::error::Error>> {
+    let outline = outline(&resolved.css);
+
+    // Deduplicate what is sent to the browser; keep the first line of each
+    let mut selectors: BTreeMap<String, usize> = BTreeMap::new();
+    let mut ids: BTreeMap<String, usize> = BTreeMap::new();
+    let mut declarations: BTreeMap<(String, String), usize> = BTreeMap::new();
+    let mut issues = Vec::new();
+
+    for (selector, line) in &outline.selectors {
+        for hook in RENAMED_HOOKS {
+            if contains_hook(selector, hook.old) {
+                let message = match hook.replacement {
+                    Some(new) => format!(
+                        "'{}' was renamed to '{}' in {} (in '{}')",
+                        hook.old, new, hook.since, selector
+                    ),
+                    None => format!(
+                        "'{}' was removed in {} (in '{}')",
+                        hook.old, hook.since, selector
+                    ),
+                };
+                issues.push((
+                    CompatSeverity::Warning,
+                    CompatIssueKind::RenamedHook,
+                    *line,
+                    message,
+                ));
+            }
+        }
+        for id in selector_ids(selector) {
+            ids.entry(id).or_insert(*line);
+        }
+        selectors.entry(selector.clone()).or_insert(*line);
+    }
+    for (property, value, line) in &outline.declarations {
+        if property.starts_with("-moz-") || value.contains("-moz-") {
+            declarations
+                .entry((property.clone(), value.clone()))
+                .or_insert(*line);
+        }
+    }
+
+    let script = r#"
+        const [selectors, ids, declarations, dynamic, windowType] = arguments;
+        const window = Services.wm.getMostRecentWindow(windowType);
+        if (!window) throw new Error("No browser window found");
+        const document = window.document;
+        const stripDynamic = (selector) => {
+            let stripped = selector.replace(/::?(before|after|first-line|first-letter|marker|placeholder|selection|backdrop|part\([^)]*\)|slotted\([^)]*\)|-moz-[\w-]+(\([^)]*\))?)(?![\w-])/g, "");
+            for (const pseudo of dynamic) {
+                stripped = stripped.split(pseudo).join("");
+            }
+            return stripped.trim() || "*";
+        };
+        // Lazily inserted panels wait in <template>s, possibly nested
+        const inTemplate = (root, id) =>
+            [...root.querySelectorAll("template")].some(
+                (template) => template.content.getElementById(id) !== null || inTemplate(template.content, id)
+            );
+        return {
+            selectors: selectors.map((selector) => {
+                if (!window.CSS.supports(`selector(${selector})`)) return "invalid";
+                try {
+                    return document.querySelector(stripDynamic(selector)) ? "matched" : "unmatched";
+                } catch (e) {
+                    return "unknown";
+                }
+            }),
+            ids: ids.map((id) => document.getElementById(id) !== null || inTemplate(document, id)),
+            declarations: declarations.map(([property, value]) => window.CSS.supports(property, value)),
+        };
+    "#;
+    let window_type = connection.app_profile()?.window_type;
+    let result = connection.execute_script(
+        script,
+        Some(vec![
+            json!(selectors.keys().collect::<Vec<_>>()),
+            json!(ids.keys().collect::<Vec<_>>()),
+            json!(declarations.keys().map(|(p, v)| [p, v]).collect::<Vec<_>>()),
+            json!(DYNAMIC_PSEUDO_CLASSES),
+            json!(window_type),
+        ]),
+    )?;
+
+    let empty = Vec::new();
+    let results = |key: &str| -> &Vec<Value> { result[key].as_array().unwrap_or(&empty) };
+
+    for ((selector, line), status) in selectors.iter().zip(results("selectors")) {
+        match status.as_str() {
+            Some("invalid") => issues.push((
+                CompatSeverity::Warning,
+                CompatIssueKind::InvalidSelector,
+                *line,
+                format!("'{}' is not a valid selector in this version", selector),
+            )),
+            Some("unmatched") => issues.push((
+                CompatSeverity::Info,
+                CompatIssueKind::UnmatchedSelector,
+                *line,
+                format!("'{}' matches nothing in the current window", selector),
+            )),
+            _ => {}
+        }
+    }
+    for ((id, line), found) in ids.iter().zip(results("ids")) {
+        if found.as_bool() == Some(false) {
+            issues.push(if LAZY_IDS.contains(&id.as_str()) {
+                (
+                    CompatSeverity::Info,
+                    CompatIssueKind::MissingId,
+                    *line,
+                    format!(
+                        "No element with id '{}' yet; it is created when its panel first opens",
+                        id
+                    ),
+                )
+            } else {
+                (
+                    CompatSeverity::Warning,
+                    CompatIssueKind::MissingId,
+                    *line,
+                    format!("No element with id '{}' exists in this version", id),
+                )
+            });
+        }
+    }
+    for (((property, value), line), supported) in declarations.iter().zip(results("declarations")) {
+        if supported.as_bool() == Some(false) {
+            issues.push((
+                CompatSeverity::Warning,
+                CompatIssueKind::UnsupportedDeclaration,
+                *line,
+                format!("'{}: {}' is not supported", property, value),
+            ));
+        }
+    }
+
+    issues.sort_by_key(|(_, _, line, _)| *line);
+    let issues = issues
+        .into_iter()
+        .map(|(severity, kind, line, message)| CompatIssue {
+            severity,
+            kind,
+            line,
+            location: resolved.source_map.describe(line, root),
+            message,
+        })
+        .collect();
+
+    Ok(CompatReport {
+        firefox_version: connection.browser_info()?.version,
+        issues,
+    })
+}
+
+/// Print the issues of a report to stderr; infos only with `include_info`.
+pub fn report_compat(report: &CompatReport, include_info: bool) {
+    for issue in &report.issues {
+        if issue.severity == CompatSeverity::Info && !include_info {
+            continue;
+        }
+        let severity = match issue.severity {
+            CompatSeverity::Warning => "warning",
+            CompatSeverity::Info => "info",
+        };
+        let location = issue
+            .location
+            .clone()
+            .unwrap_or_else(|| format!("line {}", issue.line));
+        eprintln!("{}: {}: {}", severity, location, issue.message);
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Outline {
+    /// Style rules, nested ones included.
+    pub(crate) rules: usize,
+    /// Every selector of every style rule, with the line its rule starts on.
+    pub(crate) selectors: Vec<(String, usize)>,
+    /// `(property, value, line)`, with `!important` removed from the value.
+    pub(crate) declarations: Vec<(String, String, usize)>,
+    /// Lines of the declarations in `declarations` marked `!important`.
+    pub(crate) important: Vec<usize>,
+    /// Top-level style rules, with their declarations.
+    pub(crate) style_rules: Vec<StyleRule>,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct StyleRule {
+    /// Preludes of the `@media`, `@supports` and other blocks around the rule,
+    /// outermost first.
+    pub(crate) conditions: Vec<String>,
+    pub(crate) selectors: Vec<String>,
+    /// `(property, value, important, line)`, in order.
+    pub(crate) declarations: Vec<(String, String, bool, usize)>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Block {
+    /// `@media`, `@supports` and other rules holding style rules.
+    Group,
+    /// Style rules and at-rules holding declarations.
+    Declarations,
+    /// `@keyframes`, whose preludes are not selectors.
+    Keyframes,
+}
+
+pub(crate) fn outline(css: &str) -> Outline {
+    let bytes = css.as_bytes();
+    // Offsets of the newlines, so lines are found by binary search
+    let newlines: Vec<usize> = (0..bytes.len()).filter(|&i| bytes[i] == b'\n').collect();
+    let line_at = |offset: usize| newlines.partition_point(|&newline| newline < offset) + 1;
+    let mut outline = Outline::default();
+    // Each block, with the index of its rule in `style_rules`
+    let mut stack: Vec<(Block, Option<usize>)> = Vec::new();
+    let mut conditions: Vec<String> = Vec::new();
+    let mut start = 0;
+    let mut parens = 0usize;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                let end = skip_comment(bytes, i);
+                if css[start..i].trim().is_empty() {
+                    start = end;
+                }
+                i = end;
+                continue;
+            }
+            b'"' | b'\'' => {
+                i = skip_string(bytes, i);
+                continue;
+            }
+            b'\\' => i += 1,
+            b'(' => parens += 1,
+            b')' => parens = parens.saturating_sub(1),
+            b'{' if parens == 0 => {
+                let prelude = strip_comments(&css[start..i]);
+                let prelude = prelude.trim();
+                let offset = start + (css[start..i].len() - css[start..i].trim_start().len());
+                let parent = stack.last().map(|(block, _)| *block);
+                let mut rule = None;
+                let block = if let Some(at_rule) = prelude.strip_prefix('@') {
+                    let name = at_rule
+                        .split(|c: char| !(c.is_alphanumeric() || c == '-'))
+                        .next()
+                        .unwrap_or("")
+                        .to_ascii_lowercase();
+                    match name.as_str() {
+                        "media" | "supports" | "layer" | "container" | "document"
+                        | "-moz-document" | "scope" | "starting-style" => Block::Group,
+                        "keyframes" | "-moz-keyframes" => Block::Keyframes,
+                        _ => Block::Declarations,
+                    }
+                } else if parent == Some(Block::Keyframes) {
+                    Block::Declarations
+                } else if parent == Some(Block::Declarations) {
+                    // Nested rules are relative to their parent rule
+                    outline.rules += 1;
+                    Block::Declarations
+                } else {
+                    outline.rules += 1;
+                    let line = line_at(offset);
+                    let mut selectors = Vec::new();
+                    for selector in split_top_level(prelude, ',') {
+                        let selector = selector.trim();
+                        if !selector.is_empty() {
+                            outline.selectors.push((selector.to_string(), line));
+                            selectors.push(selector.to_string());
+                        }
+                    }
+                    rule = Some(outline.style_rules.len());
+                    outline.style_rules.push(StyleRule {
+                        conditions: conditions.clone(),
+                        selectors,
+                        declarations: Vec::new(),
+                    });
+                    Block::Declarations
+                };
+                if block == Block::Group {
+                    conditions.push(prelude.split_whitespace().collect::<Vec<_>>().join(" "));
+                }
+                stack.push((block, rule));
+                start = i + 1;
+            }
+            b';' | b'}' if parens == 0 => {
+                if let Some(&(Block::Declarations, rule)) = stack.last() {
+                    let text = &css[start..i];
+                    if let Some((property, value)) = strip_comments(text).split_once(':') {
+                        let property = property.trim();
+                        if !property.is_empty()
+                            && !property.starts_with("--")
+                            && property.bytes().all(is_ident_byte)
+                        {
+                            let value = value.trim();
+                            let important = value.strip_suffix("!important").map(str::trim_end);
+                            let offset = start + (text.len() - text.trim_start().len());
+                            let line = line_at(offset);
+                            let property = property.to_ascii_lowercase();
+                            let value = important.unwrap_or(value).to_string();
+                            if important.is_some() {
+                                outline.important.push(line);
+                            }
+                            if let Some(rule) = rule {
+                                outline.style_rules[rule].declarations.push((
+                                    property.clone(),
+                                    value.clone(),
+                                    important.is_some(),
+                                    line,
+                                ));
+                            }
+                            outline.declarations.push((property, value, line));
+                        }
+                    }
+                }
+                if bytes[i] == b'}' && stack.pop().is_some_and(|(block, _)| block == Block::Group) {
+                    conditions.pop();
+                }
+                start = i + 1;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    outline
+}
+
+/// Whether `selector` mentions `hook` as a whole token.
+fn contains_hook(selector: &str, hook: &str) -> bool {
+    // `[attr]` also covers `[attr="value"]`
+    let needle = hook.strip_suffix(']').unwrap_or(hook);
+    selector.match_indices(needle).any(|(at, _)| {
+        let after = selector.as_bytes().get(at + needle.len()).copied();
+        !after.is_some_and(is_ident_byte)
+    })
+}
+
+/// The `#id`s a selector refers to.
+fn selector_ids(selector: &str) -> Vec<String> {
+    let bytes = selector.as_bytes();
+    let mut ids = Vec::new();
+    let mut brackets = 0usize;
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'"' | b'\'' => {
+                i = skip_string(bytes, i);
+                continue;
+            }
+            b'[' => brackets += 1,
+            b']' => brackets = brackets.saturating_sub(1),
+            b'#' if brackets == 0 => {
+                let end = (i + 1..bytes.len())
+                    .find(|&j| !is_ident_byte(bytes[j]))
+                    .unwrap_or(bytes.len());
+                if end > i + 1 {
+                    ids.push(selector[i + 1..end].to_string());
+                }
+                i = end;
+                continue;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    ids
+}
+
+#[cfg(test)]
+mod tests {
+    use super::outline;
+
+    #[test]
+    fn lines_of_rules_and_declarations() {
+        let css = "#a { color: red; }\n\n/* x */\n#b,\n#c {\n  color: blue;\n  margin: 0 !important;\n}\n";
+        let outline = outline(css);
+        assert_eq!(
+            outline.selectors,
+            vec![
+                ("#a".to_string(), 1),
+                ("#b".to_string(), 4),
+                ("#c".to_string(), 4)
+            ]
+        );
+        let lines: Vec<usize> = outline.declarations.iter().map(|d| d.2).collect();
+        assert_eq!(lines, vec![1, 6, 7]);
+        assert_eq!(outline.important, vec![7]);
+    }
+}
@@ -3,10 +3,14 @@
 //! This module provides WebAssembly Component Model exports for use with jco.
 
 #[cfg(feature = "component")]
-use crate::{ChromeCSSManager, MarionetteConnection, MarionetteSettings};
+use crate::op_queue::QueueSettings;
+#[cfg(feature = "component")]
+use crate::ChromeCSSManager;
 
 #[cfg(feature = "component")]
 use std::sync::Mutex;
+#[cfg(feature = "component")]
+use std::time::Duration;
 
 #[cfg(feature = "component")]
 wit_bindgen::generate!({
@@ -39,17 +43,17 @@ impl Guest for Component {
     type Connection = client::Connection;
 
     fn connect(host: String, port: u16) -> Result<Self::Connection, String> {
-        MarionetteConnection::connect(&MarionetteSettings { host, port })
-            .and_then(|mut conn| {
-                conn.set_context("chrome")?;
-                Ok(conn)
-            })
-            .map(|conn| {
+        ChromeCSSManager::builder()
+            .host(&host)
+            .port(port)
+            .initialize(false)
+            .build()
+            .map(|manager| {
                 // construct our internal Connection type and wrap it with the
                 // WIT-generated resource type so it matches the expected return
                 // signature.
                 client::Connection::new(Connection {
-                    manager: Mutex::new(ChromeCSSManager::new_with_connection(conn)),
+                    manager: Mutex::new(manager),
                 })
             })
             .map_err(|e| e.to_string())
@@ -101,10 +105,21 @@ impl GuestConnection for Connection {
         ResultList::Ok(mgr.list_loaded())
     }
 
+    fn css_set_queue(&self, min_interval_ms: u32, max_pending: Option<u32>) -> ResultString {
+        self.manager
+            .lock()
+            .unwrap()
+            .set_queue_settings(QueueSettings {
+                min_interval: Duration::from_millis(min_interval_ms.into()),
+                max_pending: max_pending.map(|max| max as usize),
+            });
+        ResultString::Ok("queue set".to_string())
+    }
+
     fn execute(&self, script: String, args: Option<String>) -> ResultString {
-        let mut mgr = self.manager.lock().unwrap();
+        let mgr = self.manager.lock().unwrap();
         let parsed_args = args.and_then(|a| serde_json::from_str(&a).ok());
-        mgr.connection_mut()
+        mgr.connection()
             .execute_script(&script, parsed_args)
             .map(|r| r.to_string())
             .map(ResultString::Ok)
@@ -112,8 +127,8 @@ impl GuestConnection for Connection {
     }
 
     fn screenshot(&self, selector: Option<String>) -> ResultBytes {
-        let mut mgr = self.manager.lock().unwrap();
-        crate::screenshot::take_screenshot(mgr.connection_mut(), selector.as_deref())
+        let mgr = self.manager.lock().unwrap();
+        mgr.screenshot(selector.as_deref())
             .map(ResultBytes::Ok)
             .unwrap_or_else(|e| ResultBytes::Err(e.to_string()))
     }
@@ -146,6 +161,11 @@ impl client::GuestConnection for client::Connection {
         self.get::<Connection>().css_list()
     }
 
+    fn css_set_queue(&self, min_interval_ms: u32, max_pending: Option<u32>) -> ResultString {
+        self.get::<Connection>()
+            .css_set_queue(min_interval_ms, max_pending)
+    }
+
     fn execute(&self, script: String, args: Option<String>) -> ResultString {
         self.get::<Connection>().execute(script, args)
     }
@@ -0,0 +1,246 @@
+//! Settings every command shares, such as where the browser listens and how
+//! long to wait for the UI, read from several places. Each one overrides
+//! the ones before it:
+//!
+//! 1. the system config, `/etc/mus-uc-devtools/config.toml`
+//!    (`%PROGRAMDATA%\mus-uc-devtools\config.toml` on Windows)
+//! 2. the user config, `$XDG_CONFIG_HOME/mus-uc-devtools/config.toml`
+//!    (`~/.config/...`, or `%APPDATA%\...` on Windows)
+//! 3. the `[connection]` and `[retry]` tables of the project's `mus-uc.toml`
+//! 4. `MUS_UC_*` environment variables, e.g. `MUS_UC_PORT` and
+//!    `MUS_UC_RETRY_ATTEMPTS`
+//! 5. command-line options
+//!
+//! The system and user configs take the same two tables:
+//!
+//! ```toml
+//! [connection]
+//! host = "localhost"
+//! port = 2828
+//!
+//! [retry]
+//! attempts = 10
+//! ```
+//!
+//! None of them holds secrets: SSH logins use the agent and keys in
+//! `~/.ssh`, and there is nothing else to authenticate.
+
+use crate::retry::RetrySettings;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// The settings that are layered, as named in `config show`.
+pub const KEYS: &[&str] = &[
+    "host",
+    "port",
+    "socket",
+    "app",
+    "backend",
+    "retry.attempts",
+    "retry.interval",
+    "retry.timeout",
+];
+
+/// Where a setting came from, in order of precedence.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Origin {
+    System(PathBuf),
+    User(PathBuf),
+    Project(PathBuf),
+    /// The name of the variable.
+    Env(String),
+    /// The option, e.g. `--port`.
+    Flag(String),
+}
+
+impl Origin {
+    fn rank(&self) -> u8 {
+        match self {
+            Origin::System(_) => 0,
+            Origin::User(_) => 1,
+            Origin::Project(_) => 2,
+            Origin::Env(_) => 3,
+            Origin::Flag(_) => 4,
+        }
+    }
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Origin::System(path) => write!(f, "system config {}", path.display()),
+            Origin::User(path) => write!(f, "user config {}", path.display()),
+            Origin::Project(path) => write!(f, "project config {}", path.display()),
+            Origin::Env(name) => write!(f, "environment variable {}", name),
+            Origin::Flag(name) => write!(f, "option {}", name),
+        }
+    }
+}
+
+/// The `[connection]` table: where to reach the browser.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConnectionSettings {
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub socket: Option<PathBuf>,
+    /// A name from [`crate::app_profile::AppProfile::names`].
+    pub app: Option<String>,
+    /// `marionette`, `rdp` or `bidi`.
+    pub backend: Option<String>,
+}
+
+/// The system and user config files.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UserConfig {
+    #[serde(default)]
+    pub connection: ConnectionSettings,
+    #[serde(default)]
+    pub retry: RetrySettings,
+}
+
+impl UserConfig {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+        Ok(toml::from_str(&content).map_err(|e| format!("{}: {}", path.display(), e))?)
+    }
+
+    /// The system-wide config file.
+    pub fn system_path() -> Option<PathBuf> {
+        if cfg!(windows) {
+            env("PROGRAMDATA").map(|dir| {
+                PathBuf::from(dir)
+                    .join("mus-uc-devtools")
+                    .join("config.toml")
+            })
+        } else {
+            Some(PathBuf::from("/etc/mus-uc-devtools/config.toml"))
+        }
+    }
+
+    /// The config file of the current user.
+    pub fn user_path() -> Option<PathBuf> {
+        let base = if let Some(dir) = env("XDG_CONFIG_HOME") {
+            PathBuf::from(dir)
+        } else if let Some(dir) = env("APPDATA") {
+            PathBuf::from(dir)
+        } else {
+            PathBuf::from(env("HOME")?).join(".config")
+        };
+        Some(base.join("mus-uc-devtools").join("config.toml"))
+    }
+}
+
+fn env(name: &str) -> Option<std::ffi::OsString> {
+    std::env::var_os(name).filter(|v| !v.is_empty())
+}
+
+/// The value of each setting that is set anywhere, with where it came from.
+#[derive(Debug, Clone, Default)]
+pub struct Layers {
+    values: BTreeMap<&'static str, (String, Origin)>,
+}
+
+impl Layers {
+    /// The system, user and `project` configs and the environment. Configs
+    /// that cannot be read are reported and skipped.
+    pub fn load(project: Option<(&Path, &ConnectionSettings, &RetrySettings)>) -> Self {
+        let mut layers = Layers::default();
+        let mut add_config = |path: Option<PathBuf>, origin: fn(PathBuf) -> Origin| {
+            let Some(path) = path.filter(|path| path.is_file()) else {
+                return;
+            };
+            match UserConfig::load(&path) {
+                Ok(config) => layers.add_file(&config.connection, &config.retry, &origin(path)),
+                Err(e) => eprintln!("Warning: ignoring {}", e),
+            }
+        };
+        add_config(UserConfig::system_path(), Origin::System);
+        add_config(UserConfig::user_path(), Origin::User);
+        if let Some((path, connection, retry)) = project {
+            layers.add_file(connection, retry, &Origin::Project(path.to_path_buf()));
+        }
+        for key in KEYS {
+            let name = env_name(key);
+            if let Ok(value) = std::env::var(&name) {
+                if !value.is_empty() {
+                    layers.set(key, value, Origin::Env(name));
+                }
+            }
+        }
+        layers
+    }
+
+    fn add_file(
+        &mut self,
+        connection: &ConnectionSettings,
+        retry: &RetrySettings,
+        origin: &Origin,
+    ) {
+        let values = [
+            ("host", connection.host.clone()),
+            ("port", connection.port.map(|v| v.to_string())),
+            (
+                "socket",
+                connection.socket.as_ref().map(|v| v.display().to_string()),
+            ),
+            ("app", connection.app.clone()),
+            ("backend", connection.backend.clone()),
+            ("retry.attempts", retry.attempts.map(|v| v.to_string())),
+            ("retry.interval", retry.interval.map(|v| v.to_string())),
+            ("retry.timeout", retry.timeout.map(|v| v.to_string())),
+        ];
+        for (key, value) in values {
+            if let Some(value) = value {
+                self.set(key, value, origin.clone());
+            }
+        }
+    }
+
+    /// Set `key`, overriding what earlier layers set.
+    pub fn set(&mut self, key: &'static str, value: String, origin: Origin) {
+        self.values.insert(key, (value, origin));
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(|(value, _)| value.as_str())
+    }
+
+    pub fn origin(&self, key: &str) -> Option<&Origin> {
+        self.values.get(key).map(|(_, origin)| origin)
+    }
+
+    /// `key` parsed as a `T`, with an error naming where a bad value came
+    /// from.
+    pub fn parse<T: FromStr>(&self, key: &str) -> Result<Option<T>, Box<dyn std::error::Error>> {
+        let Some((value, origin)) = self.values.get(key) else {
+            return Ok(None);
+        };
+        value
+            .parse()
+            .map(Some)
+            .map_err(|_| format!("Invalid {} '{}' from {}", key, value, origin).into())
+    }
+
+    /// The socket, unless a later layer gave a host or port instead, since a
+    /// socket is used in place of both.
+    pub fn socket(&self) -> Option<&str> {
+        let (socket, origin) = self.values.get("socket")?;
+        let overridden = ["host", "port"]
+            .iter()
+            .filter_map(|key| self.origin(key))
+            .any(|other| other.rank() > origin.rank());
+        (!overridden).then_some(socket.as_str())
+    }
+}
+
+/// The environment variable for `key`, e.g. `MUS_UC_RETRY_ATTEMPTS`.
+pub fn env_name(key: &str) -> String {
+    format!("MUS_UC_{}", key.replace('.', "_").to_uppercase())
+}
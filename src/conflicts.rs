@@ -0,0 +1,201 @@
+//! Declarations in different loaded sheets that fight over the same selector
+//! and property.
+//!
+//! Rules are compared by selector text, under the same `@media`/`@supports`
+//! conditions. The winner is picked the way the cascade does for equal
+//! specificity: by origin and importance, then by the order the sheets were
+//! registered in, then by position in the sheet. Shorthands are not expanded,
+//! so `margin` and `margin-top` are not compared with each other.
+
+use crate::chrome_css_manager::{RegisteredSheet, SheetType};
+use crate::compat::outline;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SheetDeclaration {
+    pub sheet: String,
+    pub sheet_type: SheetType,
+    pub value: String,
+    pub important: bool,
+    /// 1-based line in the registered sheet.
+    pub line: usize,
+    /// `file:line` in the original sources, when known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+    /// Position of the sheet in registration order.
+    #[serde(skip)]
+    order: usize,
+}
+
+impl SheetDeclaration {
+    fn describe(&self) -> String {
+        format!(
+            "{}{}",
+            self.value,
+            if self.important { " !important" } else { "" }
+        )
+    }
+
+    /// Where the declaration comes in the cascade; higher wins.
+    fn precedence(&self) -> (u8, usize, usize) {
+        let origin = match (self.important, self.sheet_type) {
+            (false, SheetType::Agent) => 0,
+            (false, SheetType::User) => 1,
+            (false, SheetType::Author) => 2,
+            (true, SheetType::Author) => 3,
+            (true, SheetType::User) => 4,
+            (true, SheetType::Agent) => 5,
+        };
+        (origin, self.order, self.line)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Conflict {
+    pub selector: String,
+    pub property: String,
+    /// `@media` and similar blocks the rules are in, outermost first.
+    pub conditions: Vec<String>,
+    pub winner: SheetDeclaration,
+    pub overridden: Vec<SheetDeclaration>,
+}
+
+impl Conflict {
+    /// Whether every sheet sets the same value, so nothing is lost.
+    pub fn is_duplicate(&self) -> bool {
+        self.overridden
+            .iter()
+            .all(|d| d.value == self.winner.value && d.important == self.winner.important)
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ConflictReport {
+    pub sheets: usize,
+    pub conflicts: Vec<Conflict>,
+}
+
+impl ConflictReport {
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for conflict in &self.conflicts {
+            let mut heading = format!("{} {{ {} }}", conflict.selector, conflict.property);
+            for condition in &conflict.conditions {
+                heading = format!("{} {{ {} }}", condition, heading);
+            }
+            if conflict.is_duplicate() {
+                heading.push_str(" (same value)");
+            }
+            let _ = writeln!(text, "{}", heading);
+            let location = |d: &SheetDeclaration| {
+                d.location
+                    .clone()
+                    .unwrap_or_else(|| format!("line {}", d.line))
+            };
+            let winner = &conflict.winner;
+            let _ = writeln!(
+                text,
+                "  wins        {} ({}): {}",
+                winner.sheet,
+                location(winner),
+                winner.describe()
+            );
+            for overridden in &conflict.overridden {
+                let _ = writeln!(
+                    text,
+                    "  overridden  {} ({}): {}",
+                    overridden.sheet,
+                    location(overridden),
+                    overridden.describe()
+                );
+            }
+        }
+        let duplicates = self.conflicts.iter().filter(|c| c.is_duplicate()).count();
+        let _ = writeln!(
+            text,
+            "{} conflicts, {} duplicates across {} sheets",
+            self.conflicts.len() - duplicates,
+            duplicates,
+            self.sheets
+        );
+        text
+    }
+}
+
+/// Compare `sheets`, given in registration order. `locate` maps a sheet ID
+/// and line to a location in the original sources.
+pub fn find_conflicts(
+    sheets: &[(String, RegisteredSheet)],
+    locate: impl Fn(&str, usize) -> Option<String>,
+) -> ConflictReport {
+    let mut declarations: BTreeMap<(Vec<String>, String, String), Vec<SheetDeclaration>> =
+        BTreeMap::new();
+
+    for (order, (id, sheet)) in sheets.iter().enumerate() {
+        for rule in outline(&sheet.css).style_rules {
+            for selector in &rule.selectors {
+                for (property, value, important, line) in &rule.declarations {
+                    let key = (
+                        rule.conditions.clone(),
+                        normalize_selector(selector),
+                        property.clone(),
+                    );
+                    declarations.entry(key).or_default().push(SheetDeclaration {
+                        sheet: id.clone(),
+                        sheet_type: sheet.sheet_type,
+                        value: value.clone(),
+                        important: *important,
+                        line: *line,
+                        location: locate(id, *line),
+                        order,
+                    });
+                }
+            }
+        }
+    }
+
+    let mut conflicts = Vec::new();
+    for ((conditions, selector, property), mut found) in declarations {
+        // Repeats within one sheet are that sheet's own business
+        if found.iter().all(|d| d.sheet == found[0].sheet) {
+            continue;
+        }
+        found.sort_by_key(SheetDeclaration::precedence);
+        let winner = found.pop().unwrap();
+        found.reverse();
+        conflicts.push(Conflict {
+            selector,
+            property,
+            conditions,
+            winner,
+            overridden: found,
+        });
+    }
+    // Sheets first, then the order of the winning rule
+    conflicts.sort_by_key(|c| (c.winner.order, c.winner.line));
+
+    ConflictReport {
+        sheets: sheets.len(),
+        conflicts,
+    }
+}
+
+/// Collapse whitespace, and drop it around combinators, so `a>b` and
+/// `a > b` compare equal.
+fn normalize_selector(selector: &str) -> String {
+    let collapsed = selector.split_whitespace().collect::<Vec<_>>().join(" ");
+    let mut normalized = String::with_capacity(collapsed.len());
+    let mut chars = collapsed.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == ' ' && matches!(chars.peek(), Some('>' | '+' | '~')) {
+            continue;
+        }
+        normalized.push(c);
+        if matches!(c, '>' | '+' | '~') && chars.peek() == Some(&' ') {
+            chars.next();
+        }
+    }
+    normalized
+}
@@ -0,0 +1,173 @@
+//! The backends that run scripts in the browser.
+//!
+//! [`MarionetteConnection`](crate::MarionetteConnection) talks to the browser
+//! through a [`Connection`]: Marionette by default, the DevTools remote
+//! debugging protocol for builds started without `--marionette`, or WebDriver
+//! BiDi.
+
+use serde_json::Value;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::time::Duration;
+
+pub trait Connection: Send {
+    fn set_context(&mut self, context: &str) -> Result<(), Box<dyn std::error::Error>>;
+
+    /// Run `script` as the body of a function, with `args` as `arguments`,
+    /// and return what it returns.
+    fn execute_script(
+        &mut self,
+        script: &str,
+        args: Option<Vec<Value>>,
+    ) -> Result<Value, Box<dyn std::error::Error>>;
+
+    /// Send a raw Marionette command such as `WebDriver:Navigate`.
+    fn send_command(
+        &mut self,
+        name: &str,
+        params: Value,
+    ) -> Result<Value, Box<dyn std::error::Error>>;
+
+    /// The capabilities the browser answered the new session with, for
+    /// backends that start one.
+    fn capabilities(&self) -> Option<&Value> {
+        None
+    }
+
+    /// Send a Marionette command and return the whole response envelope,
+    /// errors included. Only Marionette has one.
+    fn send_raw(
+        &mut self,
+        name: &str,
+        _params: Value,
+    ) -> Result<Value, Box<dyn std::error::Error>> {
+        Err(format!(
+            "{} needs Marionette; raw commands are only sent over Marionette",
+            name
+        )
+        .into())
+    }
+}
+
+/// Which protocol to reach the browser with.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Backend {
+    /// Marionette, enabled with `--marionette` or `marionette.enabled`.
+    #[default]
+    Marionette,
+    /// The DevTools remote debugging protocol, enabled with
+    /// `--start-debugger-server`.
+    Rdp,
+    /// WebDriver BiDi, enabled with `--remote-debugging-port`.
+    Bidi,
+}
+
+impl Backend {
+    pub fn parse(name: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        match name {
+            "marionette" => Ok(Backend::Marionette),
+            "rdp" => Ok(Backend::Rdp),
+            "bidi" => Ok(Backend::Bidi),
+            other => Err(format!(
+                "Unknown backend '{}' (expected marionette, rdp or bidi)",
+                other
+            )
+            .into()),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Backend::Marionette => "marionette",
+            Backend::Rdp => "rdp",
+            Backend::Bidi => "bidi",
+        }
+    }
+
+    pub fn default_port(self) -> u16 {
+        match self {
+            Backend::Marionette => 2828,
+            Backend::Rdp => 6000,
+            Backend::Bidi => 9222,
+        }
+    }
+}
+
+/// The socket to the browser: TCP, or a Unix socket forwarded to it.
+#[derive(Debug)]
+pub(crate) enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Stream {
+    pub(crate) fn connect_tcp(host: &str, port: u16) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Stream::Tcp(TcpStream::connect((host, port))?))
+    }
+
+    #[cfg(unix)]
+    pub(crate) fn connect_unix(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let stream = UnixStream::connect(path)
+            .map_err(|e| format!("Cannot connect to {}: {}", path.display(), e))?;
+        Ok(Stream::Unix(stream))
+    }
+
+    #[cfg(not(unix))]
+    pub(crate) fn connect_unix(_path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        Err("Unix sockets are not supported on this platform".into())
+    }
+
+    pub(crate) fn try_clone(&self) -> std::io::Result<Self> {
+        match self {
+            Stream::Tcp(s) => s.try_clone().map(Stream::Tcp),
+            #[cfg(unix)]
+            Stream::Unix(s) => s.try_clone().map(Stream::Unix),
+        }
+    }
+
+    pub(crate) fn set_timeouts(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        match self {
+            Stream::Tcp(s) => {
+                s.set_read_timeout(timeout)?;
+                s.set_write_timeout(timeout)
+            }
+            #[cfg(unix)]
+            Stream::Unix(s) => {
+                s.set_read_timeout(timeout)?;
+                s.set_write_timeout(timeout)
+            }
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Stream::Tcp(s) => s.read(buf),
+            #[cfg(unix)]
+            Stream::Unix(s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Stream::Tcp(s) => s.write(buf),
+            #[cfg(unix)]
+            Stream::Unix(s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Stream::Tcp(s) => s.flush(),
+            #[cfg(unix)]
+            Stream::Unix(s) => s.flush(),
+        }
+    }
+}
@@ -0,0 +1,92 @@
+//! Structured records from the browser console, written as JSON lines.
+
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+/// One message from the browser console.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConsoleRecord {
+    /// Milliseconds since the Unix epoch.
+    pub time: u64,
+    /// `error`, `warning` or `info`.
+    pub level: String,
+    /// Gecko's message category, such as `CSS Parser` or `chrome javascript`.
+    pub category: String,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub column: Option<usize>,
+    /// The ID of the loaded sheet the message refers to.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sheet: Option<String>,
+    /// `file:line` in the original sources, for messages about a loaded sheet.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub origin: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ConsoleFilter {
+    #[default]
+    All,
+    /// CSS parser and loader messages.
+    Css,
+    /// JavaScript errors and warnings.
+    Js,
+}
+
+impl ConsoleFilter {
+    pub fn parse(name: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        match name {
+            "all" => Ok(ConsoleFilter::All),
+            "css" => Ok(ConsoleFilter::Css),
+            "js" => Ok(ConsoleFilter::Js),
+            other => Err(format!(
+                "Unknown console filter '{}' (expected all, css or js)",
+                other
+            )
+            .into()),
+        }
+    }
+
+    pub fn matches(self, record: &ConsoleRecord) -> bool {
+        let category = record.category.to_ascii_lowercase();
+        match self {
+            ConsoleFilter::All => true,
+            ConsoleFilter::Css => category.starts_with("css"),
+            ConsoleFilter::Js => category.contains("javascript"),
+        }
+    }
+}
+
+/// Appends filtered console records to a file, one JSON object per line.
+#[derive(Debug)]
+pub struct ConsoleLog {
+    file: File,
+    filter: ConsoleFilter,
+}
+
+impl ConsoleLog {
+    pub fn open(path: &Path, filter: ConsoleFilter) -> Result<Self, Box<dyn std::error::Error>> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(ConsoleLog { file, filter })
+    }
+
+    /// Write the records that pass the filter; returns how many were written.
+    pub fn append(
+        &mut self,
+        records: &[ConsoleRecord],
+    ) -> Result<usize, Box<dyn std::error::Error>> {
+        let mut written = 0;
+        for record in records.iter().filter(|r| self.filter.matches(r)) {
+            writeln!(self.file, "{}", serde_json::to_string(record)?)?;
+            written += 1;
+        }
+        self.file.flush()?;
+        Ok(written)
+    }
+}
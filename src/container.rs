@@ -0,0 +1,219 @@
+//! Firefox in a Docker container, for CI: `ci-image prepare` writes the
+//! files for an image that runs it, and `--container` connects to one.
+//!
+//! The image runs `launch` as its entrypoint, on Xvfb, so the container lives
+//! as long as Firefox does. Marionette only listens on the container's
+//! loopback, so `socat` publishes it on [`CONTAINER_PORT`]. Compose maps that
+//! to a free port on the host's loopback; [`mapped_address`] asks Docker
+//! which one.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// The port the image publishes Marionette on.
+pub const CONTAINER_PORT: u16 = 2828;
+
+/// The port Firefox listens on inside the container, behind `socat`.
+const INNER_PORT: u16 = 2827;
+
+/// Where the host reaches `container_port` of the running container `name`,
+/// as `docker port` reports it.
+pub fn mapped_address(
+    name: &str,
+    container_port: u16,
+) -> Result<(String, u16), Box<dyn std::error::Error>> {
+    let output = Command::new("docker")
+        .args(["port", name, &format!("{}/tcp", container_port)])
+        .output()
+        .map_err(|e| format!("Failed to run docker: {}", e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "docker port {}: {}",
+            name,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .into());
+    }
+    parse_mapping(&String::from_utf8_lossy(&output.stdout)).ok_or_else(|| {
+        format!(
+            "Container {} does not publish port {}",
+            name, container_port
+        )
+        .into()
+    })
+}
+
+/// The first `HOST:PORT` line of `docker port` output. A wildcard address
+/// becomes `127.0.0.1`.
+fn parse_mapping(output: &str) -> Option<(String, u16)> {
+    output.lines().find_map(|line| {
+        let (host, port) = line.trim().rsplit_once(':')?;
+        let port = port.parse().ok()?;
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let host = match host {
+            "0.0.0.0" | "::" | "" => "127.0.0.1",
+            host => host,
+        };
+        Some((host.to_string(), port))
+    })
+}
+
+/// The Firefox the image installs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FirefoxChannel {
+    /// The current release, from Mozilla's APT repository.
+    #[default]
+    Release,
+    /// Debian's own Firefox ESR.
+    Esr,
+}
+
+impl FirefoxChannel {
+    pub fn parse(name: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        match name {
+            "release" => Ok(FirefoxChannel::Release),
+            "esr" => Ok(FirefoxChannel::Esr),
+            other => Err(format!(
+                "Unknown Firefox channel '{}' (expected release or esr)",
+                other
+            )
+            .into()),
+        }
+    }
+
+    fn executable(self) -> &'static str {
+        match self {
+            FirefoxChannel::Release => "/usr/bin/firefox",
+            FirefoxChannel::Esr => "/usr/bin/firefox-esr",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ImageOptions {
+    /// Name of the compose service and its container.
+    pub name: String,
+    /// The Linux `mus-uc` binary copied into the image.
+    pub binary: PathBuf,
+    pub firefox: FirefoxChannel,
+    /// Base image, which needs `apt-get`.
+    pub base: String,
+    /// Screen size of the Xvfb display.
+    pub size: (u32, u32),
+}
+
+/// Write the `Dockerfile`, `entrypoint.sh`, `compose.yaml` and a copy of the
+/// binary to `out`. Returns the files written.
+pub fn prepare(
+    options: &ImageOptions,
+    out: &Path,
+) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+    if !options.binary.is_file() {
+        return Err(format!("No mus-uc binary at {}", options.binary.display()).into());
+    }
+    fs::create_dir_all(out)?;
+
+    let binary = out.join("mus-uc");
+    fs::copy(&options.binary, &binary)?;
+    let dockerfile = out.join("Dockerfile");
+    fs::write(&dockerfile, dockerfile_text(options))?;
+    let entrypoint = out.join("entrypoint.sh");
+    fs::write(&entrypoint, entrypoint_text(options))?;
+    make_executable(&entrypoint)?;
+    make_executable(&binary)?;
+    let compose = out.join("compose.yaml");
+    fs::write(&compose, compose_text(options))?;
+    Ok(vec![dockerfile, entrypoint, compose, binary])
+}
+
+fn dockerfile_text(options: &ImageOptions) -> String {
+    let install = match options.firefox {
+        FirefoxChannel::Release => "\
+RUN apt-get update \\
+ && apt-get install -y --no-install-recommends ca-certificates wget \\
+ && install -d -m 0755 /etc/apt/keyrings \\
+ && wget -q https://packages.mozilla.org/apt/repo-signing-key.gpg -O /etc/apt/keyrings/packages.mozilla.org.asc \\
+ && echo \"deb [signed-by=/etc/apt/keyrings/packages.mozilla.org.asc] https://packages.mozilla.org/apt mozilla main\" > /etc/apt/sources.list.d/mozilla.list \\
+ && apt-get update \\
+ && apt-get install -y --no-install-recommends firefox xvfb socat fonts-dejavu-core \\
+ && rm -rf /var/lib/apt/lists/*",
+        FirefoxChannel::Esr => "\
+RUN apt-get update \\
+ && apt-get install -y --no-install-recommends firefox-esr xvfb socat fonts-dejavu-core \\
+ && rm -rf /var/lib/apt/lists/*",
+    };
+    format!(
+        "\
+# Generated by mus-uc ci-image prepare: Firefox on Xvfb with Marionette
+# published on port {port}.
+FROM {base}
+
+{install}
+
+COPY mus-uc /usr/local/bin/mus-uc
+COPY entrypoint.sh /usr/local/bin/mus-uc-entrypoint
+
+EXPOSE {port}
+ENTRYPOINT [\"/usr/local/bin/mus-uc-entrypoint\"]
+",
+        base = options.base,
+        install = install,
+        port = CONTAINER_PORT,
+    )
+}
+
+fn entrypoint_text(options: &ImageOptions) -> String {
+    let (width, height) = options.size;
+    format!(
+        "\
+#!/bin/sh
+# Generated by mus-uc ci-image prepare.
+set -e
+
+# Marionette only listens on the container's loopback; publish it
+socat TCP-LISTEN:{port},fork,reuseaddr TCP:127.0.0.1:{inner} &
+
+# Runs until Firefox exits, which stops the container
+exec mus-uc --port {inner} launch --firefox {firefox} --virtual-display=xvfb --size \"${{MUS_UC_SIZE:-{width}x{height}}}\"
+",
+        port = CONTAINER_PORT,
+        inner = INNER_PORT,
+        firefox = options.firefox.executable(),
+        width = width,
+        height = height,
+    )
+}
+
+fn compose_text(options: &ImageOptions) -> String {
+    format!(
+        "\
+# Generated by mus-uc ci-image prepare. Start with
+#   docker compose -f compose.yaml up -d --build
+# then connect with
+#   mus-uc --container {name} list
+services:
+  {name}:
+    build: .
+    container_name: {name}
+    init: true
+    shm_size: 2gb
+    ports:
+      # A free port on the host's loopback; --container looks it up
+      - \"127.0.0.1::{port}\"
+",
+        name = options.name,
+        port = CONTAINER_PORT,
+    )
+}
+
+#[cfg(unix)]
+fn make_executable(path: &Path) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o755))
+}
+
+#[cfg(not(unix))]
+fn make_executable(_path: &Path) -> std::io::Result<()> {
+    Ok(())
+}
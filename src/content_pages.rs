@@ -0,0 +1,59 @@
+//! Styling in-content pages such as `about:preferences` and `about:addons`.
+//!
+//! Registered sheets apply to every document, in the parent process and in
+//! content processes alike, so a sheet meant for one page is scoped to it with
+//! `@-moz-document url-prefix(...)` (see [`DocumentFilter`]). Whether it took
+//! effect can only be checked for pages in the parent process; the new tab
+//! page and other remote pages live in a content process out of reach of the
+//! chrome context.
+
+use crate::preprocess::DocumentFilter;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PageStatus {
+    /// A tab shows the page and its document has the sheet.
+    Applied,
+    /// A tab shows the page, but its document does not have the sheet.
+    NotApplied,
+    /// The page is open in a content process, where it cannot be checked.
+    ContentProcess,
+    /// No tab shows the page.
+    NotOpen,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PageCheck {
+    pub page: String,
+    pub status: PageStatus,
+}
+
+impl fmt::Display for PageCheck {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.status {
+            PageStatus::Applied => write!(f, "{}: applied", self.page),
+            PageStatus::NotApplied => {
+                write!(f, "{}: open, but the sheet does not apply", self.page)
+            }
+            PageStatus::ContentProcess => write!(
+                f,
+                "{}: open in a content process, where it cannot be checked",
+                self.page
+            ),
+            PageStatus::NotOpen => write!(f, "{}: not open; applies once opened", self.page),
+        }
+    }
+}
+
+/// Scope a sheet to `pages`, each a URL or the start of one, e.g.
+/// `about:preferences` for every pane of the settings.
+pub fn page_filter(pages: &[&str]) -> DocumentFilter {
+    DocumentFilter {
+        documents: pages
+            .iter()
+            .map(|page| format!("url-prefix(\"{}\")", page.replace('"', "\\\"")))
+            .collect(),
+    }
+}
@@ -0,0 +1,191 @@
+//! WCAG contrast ratios of text in the browser UI, for `a11y-contrast`.
+//!
+//! Colors are read from computed styles. A background is the element's own
+//! background color layered over those of its ancestors, down to the first
+//! opaque one; background images, such as a theme's header image, are not
+//! counted, and are pointed out instead. Translucent text is blended over its
+//! background before the ratio is taken.
+
+use crate::chrome_js;
+use crate::marionette_client::MarionetteConnection;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+/// Where a color comes from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ColorSource {
+    /// The first element matching a selector: its `color`, or its background
+    /// layered over its ancestors'.
+    Element(String),
+    /// A custom property on the window's root, e.g. `--menu-color`.
+    Variable(String),
+}
+
+impl ColorSource {
+    fn to_value(&self) -> Value {
+        match self {
+            ColorSource::Element(selector) => json!({ "selector": selector }),
+            ColorSource::Variable(name) => json!({ "variable": name }),
+        }
+    }
+}
+
+/// A pair of colors to check.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContrastTarget {
+    pub label: String,
+    pub text: ColorSource,
+    pub background: ColorSource,
+}
+
+impl ContrastTarget {
+    /// Text and background of the first element matching `selector`.
+    pub fn element(selector: &str) -> Self {
+        ContrastTarget {
+            label: selector.to_string(),
+            text: ColorSource::Element(selector.to_string()),
+            background: ColorSource::Element(selector.to_string()),
+        }
+    }
+}
+
+/// The parts of the Firefox UI themes most often make hard to read.
+pub fn default_targets() -> Vec<ContrastTarget> {
+    let element = |label: &str, text: &str, background: &str| ContrastTarget {
+        label: label.to_string(),
+        text: ColorSource::Element(text.to_string()),
+        background: ColorSource::Element(background.to_string()),
+    };
+    let variables = |label: &str, text: &str, background: &str| ContrastTarget {
+        label: label.to_string(),
+        text: ColorSource::Variable(text.to_string()),
+        background: ColorSource::Variable(background.to_string()),
+    };
+    vec![
+        element(
+            "Selected tab",
+            ".tabbrowser-tab[selected] .tab-label",
+            ".tabbrowser-tab[selected] .tab-background",
+        ),
+        element(
+            "Background tab",
+            ".tabbrowser-tab:not([selected]) .tab-label",
+            ".tabbrowser-tab:not([selected]) .tab-background",
+        ),
+        element("Navigation toolbar", "#nav-bar", "#nav-bar"),
+        element("Address bar", "#urlbar-input", "#urlbar-background"),
+        element("Bookmarks toolbar", "#PersonalToolbar", "#PersonalToolbar"),
+        element("Sidebar", "#sidebar-box", "#sidebar-box"),
+        variables("Menus", "--menu-color", "--menu-background-color"),
+        variables("Panels", "--panel-color", "--panel-background"),
+    ]
+}
+
+/// The outcome of checking one [`ContrastTarget`].
+#[derive(Debug, Clone, Serialize)]
+pub struct ContrastResult {
+    pub label: String,
+    /// `#rrggbb`, after blending translucent text over the background.
+    pub text: Option<String>,
+    pub background: Option<String>,
+    /// `None` when either color could not be read.
+    pub ratio: Option<f64>,
+    /// Why the check was skipped, or what the ratio leaves out.
+    pub note: Option<String>,
+}
+
+impl ContrastResult {
+    pub fn passes(&self, min_ratio: f64) -> bool {
+        self.ratio.is_none_or(|ratio| ratio >= min_ratio)
+    }
+}
+
+/// Check each target in the most recent browser window. The connection must
+/// be in the chrome context.
+pub fn check_contrast(
+    connection: &mut MarionetteConnection,
+    targets: &[ContrastTarget],
+) -> Result<Vec<ContrastResult>, Box<dyn std::error::Error>> {
+    #[derive(Deserialize)]
+    struct Sample {
+        label: String,
+        text: Option<[f64; 4]>,
+        background: Option<[f64; 4]>,
+        note: Option<String>,
+    }
+
+    let checks: Vec<Value> = targets
+        .iter()
+        .map(|target| {
+            json!({
+                "label": target.label,
+                "text": target.text.to_value(),
+                "background": target.background.to_value(),
+            })
+        })
+        .collect();
+    let window_type = connection.app_profile()?.window_type;
+    let script = chrome_js::render(
+        chrome_js::CONTRAST,
+        &[
+            ("windowType", Value::from(window_type)),
+            ("checks", Value::from(checks)),
+        ],
+    );
+    let samples: Vec<Sample> = serde_json::from_value(connection.execute_script(&script, None)?)?;
+
+    Ok(samples
+        .into_iter()
+        .map(|sample| {
+            let colors = sample
+                .text
+                .zip(sample.background)
+                .map(|(text, background)| {
+                    let background = over(background, [255.0, 255.0, 255.0, 1.0]);
+                    (over(text, background), background)
+                });
+            ContrastResult {
+                label: sample.label,
+                text: colors.map(|(text, _)| hex(text)),
+                background: colors.map(|(_, background)| hex(background)),
+                ratio: colors.map(|(text, background)| contrast_ratio(text, background)),
+                note: sample.note,
+            }
+        })
+        .collect())
+}
+
+/// `top` drawn over the opaque `bottom`; channels 0-255, alpha 0-1.
+fn over(top: [f64; 4], bottom: [f64; 4]) -> [f64; 4] {
+    let mix = |i: usize| top[i] * top[3] + bottom[i] * (1.0 - top[3]);
+    [mix(0), mix(1), mix(2), 1.0]
+}
+
+fn hex(color: [f64; 4]) -> String {
+    let channel = |v: f64| v.round().clamp(0.0, 255.0) as u8;
+    format!(
+        "#{:02x}{:02x}{:02x}",
+        channel(color[0]),
+        channel(color[1]),
+        channel(color[2])
+    )
+}
+
+/// WCAG relative luminance of an sRGB color with 0-255 channels.
+fn luminance(color: [f64; 4]) -> f64 {
+    let linear = |v: f64| {
+        let v = v / 255.0;
+        if v <= 0.04045 {
+            v / 12.92
+        } else {
+            ((v + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    0.2126 * linear(color[0]) + 0.7152 * linear(color[1]) + 0.0722 * linear(color[2])
+}
+
+/// The WCAG contrast ratio of two opaque colors, from 1 to 21.
+pub fn contrast_ratio(a: [f64; 4], b: [f64; 4]) -> f64 {
+    let (a, b) = (luminance(a), luminance(b));
+    (a.max(b) + 0.05) / (a.min(b) + 0.05)
+}
@@ -0,0 +1,466 @@
+//! Rewriting of relative `url(...)` references while bundling stylesheets.
+//!
+//! Once imports are inlined, a relative `url()` would resolve against the bundle
+//! instead of the file it was written in. Every relative reference is therefore
+//! resolved against its own file and then rewritten according to an
+//! [`AssetMode`].
+
+use crate::chrome_manifest::{normalize_path, ChromeManifest};
+use crate::css_imports::{is_external_url, skip_comment, skip_string};
+pub use crate::paths::file_url;
+use crate::paths::{encode, local_url_path};
+use base64::{engine::general_purpose, Engine as _};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// Assets at or below this size are inlined by default in [`AssetMode::Inline`].
+pub const DEFAULT_INLINE_LIMIT: u64 = 8 * 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AssetMode {
+    /// Leave every `url()` untouched.
+    Keep,
+    /// Point at the original file, relative to the output directory or as an
+    /// absolute `file://` URL when there is none.
+    Rebase,
+    /// Embed assets up to `limit` bytes as `data:` URIs, rebasing larger ones.
+    Inline { limit: u64 },
+    /// Map assets through a chrome.manifest to `chrome://` URLs, rebasing files
+    /// the manifest does not cover.
+    Chrome,
+}
+
+impl AssetMode {
+    pub fn parse(name: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        match name {
+            "keep" => Ok(AssetMode::Keep),
+            "rebase" => Ok(AssetMode::Rebase),
+            "inline" => Ok(AssetMode::Inline {
+                limit: DEFAULT_INLINE_LIMIT,
+            }),
+            "chrome" => Ok(AssetMode::Chrome),
+            other => Err(format!(
+                "Unknown asset mode '{}' (expected keep, rebase, inline or chrome)",
+                other
+            )
+            .into()),
+        }
+    }
+}
+
+/// What a rewrite pass found along the way.
+#[derive(Debug, Clone, Default)]
+pub struct RewriteLog {
+    pub warnings: Vec<String>,
+    /// Local files referenced through `url()`.
+    pub assets: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone)]
+pub struct AssetRewriter {
+    pub mode: AssetMode,
+    /// Directory the bundle will be written to. `None` rebases to absolute
+    /// `file://` URLs, which is what sheets loaded through `data:` URIs need.
+    pub output_dir: Option<PathBuf>,
+    pub manifest: Option<ChromeManifest>,
+    /// URL prefix of a `serve-assets` server and the directory it serves, so
+    /// its URLs are rewritten like the local files they are.
+    pub served: Option<(String, PathBuf)>,
+}
+
+impl Default for AssetRewriter {
+    fn default() -> Self {
+        AssetRewriter {
+            mode: AssetMode::Rebase,
+            output_dir: None,
+            manifest: None,
+            served: None,
+        }
+    }
+}
+
+impl AssetRewriter {
+    pub fn new(mode: AssetMode) -> Self {
+        AssetRewriter {
+            mode,
+            ..Self::default()
+        }
+    }
+
+    pub fn with_output_dir(mut self, dir: &Path) -> Self {
+        self.output_dir = Some(dir.to_path_buf());
+        self
+    }
+
+    pub fn with_manifest(mut self, manifest: ChromeManifest) -> Self {
+        self.manifest = Some(manifest);
+        self
+    }
+
+    pub fn with_served(mut self, url: &str, dir: &Path) -> Self {
+        self.served = Some((url.to_string(), dir.to_path_buf()));
+        self
+    }
+
+    /// Rewrite the relative `url()` references in `css`, which was read from a
+    /// file in `source_dir`, and those to the `serve-assets` server. Problems
+    /// and referenced files are recorded in `log`.
+    pub fn rewrite(&self, css: &str, source_dir: &Path, log: &mut RewriteLog) -> String {
+        if self.mode == AssetMode::Keep {
+            return css.to_string();
+        }
+
+        map_urls(css, |url| self.rewrite_url(url, source_dir, log))
+    }
+
+    fn rewrite_url(&self, url: &str, source_dir: &Path, log: &mut RewriteLog) -> Option<String> {
+        if url.is_empty() || url.starts_with('#') {
+            return None;
+        }
+        let served = self
+            .served
+            .as_ref()
+            .and_then(|(prefix, dir)| Some((dir.as_path(), url.strip_prefix(prefix.as_str())?)));
+        let (source_dir, url) = match served {
+            Some(served) => served,
+            None if is_external_url(url) => return None,
+            None => (source_dir, url),
+        };
+
+        let split = url.find(['?', '#']).unwrap_or(url.len());
+        let (path_part, suffix) = url.split_at(split);
+        let target = normalize_path(&local_url_path(source_dir, path_part));
+
+        if !target.is_file() {
+            log.warnings
+                .push(format!("asset not found: {}", target.display()));
+            return None;
+        }
+        if !log.assets.contains(&target) {
+            log.assets.push(target.clone());
+        }
+
+        let rewritten = match &self.mode {
+            AssetMode::Keep => return None,
+            AssetMode::Rebase => self.rebase(&target),
+            AssetMode::Inline { limit } => match fs::metadata(&target) {
+                Ok(meta) if meta.len() <= *limit => match fs::read(&target) {
+                    Ok(data) => format!(
+                        "data:{};base64,{}",
+                        mime_type(&target),
+                        general_purpose::STANDARD.encode(data)
+                    ),
+                    Err(e) => {
+                        log.warnings
+                            .push(format!("failed to read {}: {}", target.display(), e));
+                        self.rebase(&target)
+                    }
+                },
+                _ => self.rebase(&target),
+            },
+            AssetMode::Chrome => match self.manifest.as_ref().and_then(|m| m.url_for(&target)) {
+                Some(url) => url,
+                None => {
+                    log.warnings.push(format!(
+                        "asset not covered by chrome.manifest: {}",
+                        target.display()
+                    ));
+                    self.rebase(&target)
+                }
+            },
+        };
+        Some(format!("{}{}", rewritten, suffix))
+    }
+
+    fn rebase(&self, target: &Path) -> String {
+        match &self.output_dir {
+            Some(dir) => relative_path(&normalize_path(dir), target),
+            None => file_url(target),
+        }
+    }
+}
+
+/// Replace the `url()` values in `css` for which `f` returns a new value,
+/// leaving comments, strings and `@import`s alone.
+pub fn map_urls(css: &str, mut f: impl FnMut(&str) -> Option<String>) -> String {
+    let bytes = css.as_bytes();
+    let mut out = String::with_capacity(css.len());
+    let mut last = 0;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'/' if bytes.get(i + 1) == Some(&b'*') => i = skip_comment(bytes, i),
+            b'"' | b'\'' => i = skip_string(bytes, i),
+            b'\\' => i += 2,
+            b'@' if crate::css_imports::is_at_keyword(bytes, i, b"import") => {
+                // Imports are handled by the resolver
+                while i < bytes.len() && bytes[i] != b';' {
+                    i = match bytes[i] {
+                        b'"' | b'\'' => skip_string(bytes, i),
+                        _ => i + 1,
+                    };
+                }
+            }
+            b'u' | b'U' if is_url_function(bytes, i) => {
+                let Some((value, end)) = read_url(css, i + 4) else {
+                    i += 1;
+                    continue;
+                };
+                if let Some(rewritten) = f(&value) {
+                    out.push_str(&css[last..i]);
+                    out.push_str(&format!("url(\"{}\")", escape_url(&rewritten)));
+                    last = end;
+                }
+                i = end;
+            }
+            _ => i += 1,
+        }
+    }
+    out.push_str(&css[last.min(css.len())..]);
+    out
+}
+
+/// `url` with a `mus-uc=<version>` query added, so the browser fetches it
+/// again instead of reusing the copy it cached.
+pub fn cache_busted(url: &str, version: u32) -> String {
+    let (base, fragment) = url.split_at(url.find('#').unwrap_or(url.len()));
+    let separator = if base.contains('?') { '&' } else { '?' };
+    format!("{}{}mus-uc={}{}", base, separator, version, fragment)
+}
+
+fn is_url_function(bytes: &[u8], i: usize) -> bool {
+    i + 4 <= bytes.len()
+        && bytes[i..i + 3].eq_ignore_ascii_case(b"url")
+        && bytes[i + 3] == b'('
+        && (i == 0 || !(bytes[i - 1].is_ascii_alphanumeric() || bytes[i - 1] == b'-'))
+}
+
+/// Read the argument of a `url(` whose contents start at `start`. Returns the
+/// unquoted value and the index just past the closing `)`.
+fn read_url(css: &str, start: usize) -> Option<(String, usize)> {
+    let bytes = css.as_bytes();
+    let mut i = start;
+    while i < bytes.len() && bytes[i].is_ascii_whitespace() {
+        i += 1;
+    }
+
+    let value = if matches!(bytes.get(i), Some(b'"') | Some(b'\'')) {
+        let end = skip_string(bytes, i);
+        // An unterminated string runs to the end of the line or file
+        let closed =
+            end >= i + 2 && bytes[end - 1] == bytes[i] && !is_escaped(bytes, i + 1, end - 1);
+        let value_end = if closed { end - 1 } else { end };
+        let value = unescape(&css[i + 1..value_end]);
+        i = end;
+        value
+    } else {
+        let value_start = i;
+        while i < bytes.len() && bytes[i] != b')' {
+            i += if bytes[i] == b'\\' { 2 } else { 1 };
+        }
+        i = i.min(bytes.len());
+        unescape(css[value_start..i].trim_end())
+    };
+
+    while i < bytes.len() && bytes[i].is_ascii_whitespace() {
+        i += 1;
+    }
+    (bytes.get(i) == Some(&b')')).then(|| (value, i + 1))
+}
+
+/// Whether the byte at `at` follows an odd number of backslashes, none of
+/// them before `from`.
+fn is_escaped(bytes: &[u8], from: usize, at: usize) -> bool {
+    bytes[from..at]
+        .iter()
+        .rev()
+        .take_while(|b| **b == b'\\')
+        .count()
+        % 2
+        == 1
+}
+
+/// `text` with CSS escapes decoded: `\` and up to six hex digits (and one
+/// whitespace after them) for a code point, `\` and a newline for nothing,
+/// and `\` and any other character for that character.
+fn unescape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        let mut hex = String::new();
+        while hex.len() < 6 {
+            match chars.peek() {
+                Some(d) if d.is_ascii_hexdigit() => {
+                    hex.push(*d);
+                    chars.next();
+                }
+                _ => break,
+            }
+        }
+        if hex.is_empty() {
+            match chars.next() {
+                Some('\n') | None => {}
+                Some(other) => out.push(other),
+            }
+            continue;
+        }
+        let code = u32::from_str_radix(&hex, 16).unwrap_or(0);
+        out.push(
+            char::from_u32(code)
+                .filter(|c| *c != '\0')
+                .unwrap_or('\u{FFFD}'),
+        );
+        if chars.peek().is_some_and(|c| c.is_ascii_whitespace()) {
+            chars.next();
+        }
+    }
+    out
+}
+
+fn escape_url(url: &str) -> String {
+    url.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// A `/`-separated path to `target` relative to the directory `from`.
+pub fn relative_path(from: &Path, target: &Path) -> String {
+    let from: Vec<Component> = from.components().collect();
+    let to: Vec<Component> = target.components().collect();
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+
+    if common == 0 {
+        return file_url(target);
+    }
+
+    let mut parts: Vec<String> =
+        std::iter::repeat_n("..".to_string(), from.len() - common).collect();
+    parts.extend(
+        to[common..]
+            .iter()
+            .map(|c| encode(&c.as_os_str().to_string_lossy())),
+    );
+    parts.join("/")
+}
+
+pub fn mime_type(path: &Path) -> &'static str {
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+    match ext.as_deref() {
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("svg") => "image/svg+xml",
+        Some("webp") => "image/webp",
+        Some("avif") => "image/avif",
+        Some("ico") => "image/x-icon",
+        Some("woff") => "font/woff",
+        Some("woff2") => "font/woff2",
+        Some("ttf") => "font/ttf",
+        Some("otf") => "font/otf",
+        Some("css") => "text/css",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn urls(css: &str) -> Vec<String> {
+        let mut found = Vec::new();
+        map_urls(css, |url| {
+            found.push(url.to_string());
+            None
+        });
+        found
+    }
+
+    #[test]
+    fn reads_quoted_and_unquoted_urls() {
+        assert_eq!(
+            urls(r#"a { background: url("a.png"), url('b.png'), url( c.png ) }"#),
+            ["a.png", "b.png", "c.png"]
+        );
+    }
+
+    #[test]
+    fn unterminated_url_keeps_its_last_character() {
+        assert_eq!(read_url("\"icon.png", 0), None);
+        assert_eq!(
+            read_url("\"ico\u{e9}\n)", 0),
+            Some(("ico\u{e9}".to_string(), 8))
+        );
+        // Used to slice inside the last character and panic
+        assert_eq!(
+            urls("a { background: url(\"\u{1f600}"),
+            Vec::<String>::new()
+        );
+        assert_eq!(urls("a { background: url('\u{e9}\n) }"), ["\u{e9}"]);
+    }
+
+    #[test]
+    fn decodes_escapes() {
+        assert_eq!(urls(r#"a { b: url("a\"b.png") }"#), ["a\"b.png"]);
+        assert_eq!(urls(r#"a { b: url("c:\\x.png") }"#), ["c:\\x.png"]);
+        assert_eq!(urls(r#"a { b: url(my\ icon.png) }"#), ["my icon.png"]);
+        assert_eq!(urls(r#"a { b: url("caf\e9 .png") }"#), ["caf\u{e9}.png"]);
+        // An escaped quote does not close the string
+        assert_eq!(urls(r#"a { b: url("x\") }"#), Vec::<String>::new());
+    }
+
+    #[test]
+    fn keeps_non_ascii_urls() {
+        assert_eq!(
+            urls("a { b: url(\"\u{30a2}\u{30a4}\u{30b3}\u{30f3}.svg\") }"),
+            ["\u{30a2}\u{30a4}\u{30b3}\u{30f3}.svg"]
+        );
+    }
+
+    #[test]
+    fn skips_strings_comments_and_imports() {
+        assert_eq!(
+            urls(
+                r#"@import url("x.css"); /* url(c.png) */ a::after { content: "url(s.png)"; b: url(u.png) }"#
+            ),
+            ["u.png"]
+        );
+    }
+
+    #[test]
+    fn rewrite_escapes_what_it_writes() {
+        let css = map_urls(r#"a { b: url("x.png") }"#, |_| {
+            Some("a\"b\\c.png".to_string())
+        });
+        assert_eq!(css, r#"a { b: url("a\"b\\c.png") }"#);
+        assert_eq!(urls(&css), ["a\"b\\c.png"]);
+    }
+
+    #[test]
+    fn rebases_relative_urls_to_the_output_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = crate::paths::canonicalize(dir.path()).unwrap();
+        fs::create_dir_all(root.join("theme/icons")).unwrap();
+        fs::write(root.join("theme/icons/b\u{e4}r one.svg"), "<svg/>").unwrap();
+
+        let mut log = RewriteLog::default();
+        let css = AssetRewriter::new(AssetMode::Rebase)
+            .with_output_dir(&root.join("out"))
+            .rewrite(
+                "a { b: url(\"icons/b\u{e4}r one.svg#i\") } c { d: url(missing.png) }",
+                &root.join("theme"),
+                &mut log,
+            );
+        assert_eq!(
+            css,
+            "a { b: url(\"../theme/icons/b\u{e4}r%20one.svg#i\") } c { d: url(missing.png) }"
+        );
+        assert_eq!(log.assets, [root.join("theme/icons/b\u{e4}r one.svg")]);
+        assert_eq!(log.warnings.len(), 1);
+    }
+}
@@ -0,0 +1,261 @@
+//! Whitespace formatting for exported stylesheets.
+//!
+//! The formatter only touches whitespace, statement separators and comments;
+//! selectors, values and the order of declarations are left as written.
+
+use crate::css_imports::{skip_comment, skip_string};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CssFormat {
+    /// Keep the bundle exactly as resolved.
+    Preserve,
+    /// One declaration per line, two-space indentation, one selector per line.
+    Pretty,
+    /// Everything on as few bytes as possible. `/*! ... */` comments are kept.
+    Compact,
+}
+
+impl CssFormat {
+    pub fn parse(name: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        match name {
+            "preserve" => Ok(CssFormat::Preserve),
+            "pretty" => Ok(CssFormat::Pretty),
+            "compact" => Ok(CssFormat::Compact),
+            other => Err(format!(
+                "Unknown format '{}' (expected preserve, pretty or compact)",
+                other
+            )
+            .into()),
+        }
+    }
+
+    pub fn apply(self, css: &str) -> String {
+        match self {
+            CssFormat::Preserve => css.to_string(),
+            CssFormat::Pretty => Formatter::new(false).run(css),
+            CssFormat::Compact => Formatter::new(true).run(css),
+        }
+    }
+}
+
+struct Formatter {
+    compact: bool,
+    out: String,
+    buf: String,
+    depth: usize,
+}
+
+impl Formatter {
+    fn new(compact: bool) -> Self {
+        Formatter {
+            compact,
+            out: String::new(),
+            buf: String::new(),
+            depth: 0,
+        }
+    }
+
+    fn run(mut self, css: &str) -> String {
+        let bytes = css.as_bytes();
+        let mut parens = 0usize;
+        let mut i = 0;
+
+        while i < bytes.len() {
+            match bytes[i] {
+                b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                    let end = skip_comment(bytes, i);
+                    self.comment(&css[i..end]);
+                    i = end;
+                }
+                b'"' | b'\'' => {
+                    let end = skip_string(bytes, i);
+                    self.buf.push_str(&css[i..end]);
+                    i = end;
+                }
+                b'\\' => {
+                    let end = (i + 2).min(bytes.len());
+                    // Escapes may be followed by a multi-byte character
+                    let end = (end..=bytes.len())
+                        .find(|&e| css.is_char_boundary(e))
+                        .unwrap_or(bytes.len());
+                    self.buf.push_str(&css[i..end]);
+                    i = end;
+                }
+                b'(' => {
+                    parens += 1;
+                    self.buf.push('(');
+                    i += 1;
+                }
+                b')' => {
+                    parens = parens.saturating_sub(1);
+                    self.buf.push(')');
+                    i += 1;
+                }
+                b'{' if parens == 0 => {
+                    self.open_block();
+                    i += 1;
+                }
+                b';' if parens == 0 => {
+                    self.statement();
+                    i += 1;
+                }
+                b'}' if parens == 0 => {
+                    self.close_block();
+                    i += 1;
+                }
+                b if b.is_ascii_whitespace() => {
+                    if !self.buf.is_empty() && !self.buf.ends_with(' ') {
+                        self.buf.push(' ');
+                    }
+                    i += 1;
+                }
+                _ => {
+                    let len = css[i..].chars().next().map_or(1, char::len_utf8);
+                    self.buf.push_str(&css[i..i + len]);
+                    i += len;
+                }
+            }
+        }
+
+        if !self.buf.trim().is_empty() {
+            self.statement();
+        }
+        while self.depth > 0 {
+            self.close_block();
+        }
+
+        let out = self.out.trim().to_string();
+        if self.compact {
+            out
+        } else {
+            out + "\n"
+        }
+    }
+
+    fn indent(&mut self) {
+        if !self.compact {
+            for _ in 0..self.depth {
+                self.out.push_str("  ");
+            }
+        }
+    }
+
+    fn newline(&mut self) {
+        if !self.compact {
+            self.out.push('\n');
+        }
+    }
+
+    fn take_buf(&mut self) -> String {
+        let text = self.buf.trim().to_string();
+        self.buf.clear();
+        text
+    }
+
+    fn comment(&mut self, comment: &str) {
+        let keep =
+            !self.compact || comment.starts_with("/*!") || comment.contains("sourceMappingURL");
+        if !keep {
+            if !self.buf.is_empty() && !self.buf.ends_with(' ') {
+                self.buf.push(' ');
+            }
+            return;
+        }
+        if self.buf.trim().is_empty() {
+            self.indent();
+            self.out.push_str(comment);
+            self.newline();
+        } else {
+            self.buf.push_str(comment);
+        }
+    }
+
+    fn open_block(&mut self) {
+        let prelude = self.take_buf();
+        self.indent();
+        if prelude.starts_with('@') {
+            self.out.push_str(&prelude);
+        } else {
+            let separator = if self.compact { "," } else { ",\n" };
+            for (n, selector) in split_top_level(&prelude, ',').iter().enumerate() {
+                if n > 0 {
+                    self.out.push_str(separator);
+                    self.indent();
+                }
+                self.out.push_str(selector.trim());
+            }
+        }
+        self.out.push_str(if self.compact { "{" } else { " {" });
+        self.newline();
+        self.depth += 1;
+    }
+
+    fn statement(&mut self) {
+        let text = self.take_buf();
+        if text.is_empty() {
+            return;
+        }
+        self.indent();
+        if self.depth > 0 && !text.starts_with('@') {
+            self.out.push_str(&format_declaration(&text, self.compact));
+        } else {
+            self.out.push_str(&text);
+        }
+        self.out.push(';');
+        self.newline();
+    }
+
+    fn close_block(&mut self) {
+        if !self.buf.trim().is_empty() {
+            self.statement();
+        }
+        if self.compact && self.out.ends_with(';') {
+            self.out.pop();
+        }
+        self.depth = self.depth.saturating_sub(1);
+        self.indent();
+        self.out.push('}');
+        self.newline();
+        if self.depth == 0 {
+            self.newline();
+        }
+    }
+}
+
+/// `prop:value` -> `prop: value` (or `prop:value` when compact).
+fn format_declaration(text: &str, compact: bool) -> String {
+    match text.split_once(':') {
+        Some((property, value)) if !property.contains(['(', '"', '\'']) => {
+            let separator = if compact { ":" } else { ": " };
+            format!("{}{}{}", property.trim(), separator, value.trim())
+        }
+        _ => text.to_string(),
+    }
+}
+
+/// Split on `separator` outside strings and parentheses.
+pub(crate) fn split_top_level(text: &str, separator: char) -> Vec<&str> {
+    let bytes = text.as_bytes();
+    let mut parts = Vec::new();
+    let mut parens = 0usize;
+    let mut start = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'"' | b'\'' => {
+                i = skip_string(bytes, i);
+                continue;
+            }
+            b'(' | b'[' => parens += 1,
+            b')' | b']' => parens = parens.saturating_sub(1),
+            b if b == separator as u8 && parens == 0 => {
+                parts.push(&text[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    parts.push(&text[start..]);
+    parts
+}
@@ -0,0 +1,686 @@
+//! `@import` parsing and inlining for stylesheets loaded from disk.
+//!
+//! Stylesheets are registered through `data:` URIs, so relative `@import`s have
+//! no base to resolve against. The resolver reads local imports and inlines them
+//! in place, wrapping them in the `@layer`/`@supports`/`@media` blocks their
+//! import conditions describe. Imports that point elsewhere (`chrome://`,
+//! `resource://`, remote URLs) are kept as `@import` rules and hoisted to the top
+//! of the bundle so they stay valid. Relative `url()` references are rewritten
+//! on the way through (see [`crate::css_assets`]).
+
+use crate::chrome_manifest::normalize_path;
+use crate::css_assets::{AssetRewriter, RewriteLog};
+use crate::paths::{canonicalize, local_url_path};
+use crate::source_map::{MappedCss, SourceLocation, SourceMap};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+/// A single top-level `@import` rule.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportRule {
+    pub url: String,
+    /// `Some("")` for an anonymous `layer`, `Some(name)` for `layer(name)`.
+    pub layer: Option<String>,
+    pub supports: Option<String>,
+    pub media: Option<String>,
+    /// Byte range of the whole rule, including the trailing `;`.
+    pub span: Range<usize>,
+}
+
+impl ImportRule {
+    pub fn is_external(&self) -> bool {
+        is_external_url(&self.url)
+    }
+
+    /// The opening and closing text of the blocks described by the import
+    /// conditions, outermost first.
+    pub fn wrappers(&self) -> (String, String) {
+        let mut open = String::new();
+        let mut close = String::new();
+        if let Some(layer) = &self.layer {
+            if layer.is_empty() {
+                open.push_str("@layer {\n");
+            } else {
+                open.push_str(&format!("@layer {} {{\n", layer));
+            }
+            close.push_str("\n}");
+        }
+        if let Some(supports) = &self.supports {
+            open.push_str(&format!("@supports ({}) {{\n", supports));
+            close.push_str("\n}");
+        }
+        if let Some(media) = &self.media {
+            open.push_str(&format!("@media {} {{\n", media));
+            close.push_str("\n}");
+        }
+        (open, close)
+    }
+
+    /// Wrap inlined content in the blocks described by the import conditions.
+    pub fn wrap(&self, content: &str) -> String {
+        let (open, close) = self.wrappers();
+        format!("{}{}{}", open, content.trim_end(), close)
+    }
+}
+
+/// An import that could not be inlined.
+#[derive(Debug, Clone)]
+pub struct UnresolvedImport {
+    pub url: String,
+    pub from: PathBuf,
+    pub reason: String,
+}
+
+/// The result of resolving a stylesheet and its imports.
+#[derive(Debug, Clone, Default)]
+pub struct ResolvedStylesheet {
+    pub css: String,
+    /// Every local file that contributed to `css`, in inclusion order.
+    pub files: Vec<PathBuf>,
+    pub unresolved: Vec<UnresolvedImport>,
+    /// Non-fatal problems such as missing assets.
+    pub warnings: Vec<String>,
+    /// Local files referenced through `url()`.
+    pub assets: Vec<PathBuf>,
+    /// Where each line of `css` came from.
+    pub source_map: SourceMap,
+}
+
+pub fn is_external_url(url: &str) -> bool {
+    let lower = url.to_ascii_lowercase();
+    if lower.starts_with("file://") {
+        return false;
+    }
+    lower.starts_with("//") || lower.starts_with("data:") || lower.contains("://")
+}
+
+/// Find every top-level `@import` rule in `css`.
+///
+/// Comments, strings and nested blocks are skipped, so imports may share a line,
+/// carry comments, or use any mix of `url()` and quoted forms.
+pub fn parse_imports(css: &str) -> Vec<ImportRule> {
+    let bytes = css.as_bytes();
+    let mut rules = Vec::new();
+    let mut depth = 0usize;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'/' if bytes.get(i + 1) == Some(&b'*') => i = skip_comment(bytes, i),
+            b'"' | b'\'' => i = skip_string(bytes, i),
+            b'\\' => i += 2,
+            b'{' => {
+                depth += 1;
+                i += 1;
+            }
+            b'}' => {
+                depth = depth.saturating_sub(1);
+                i += 1;
+            }
+            b'@' if depth == 0 && is_at_keyword(bytes, i, b"import") => {
+                let (end, terminated) = find_statement_end(bytes, i);
+                if terminated {
+                    let prelude_end = if bytes.get(end - 1) == Some(&b';') {
+                        end - 1
+                    } else {
+                        end
+                    };
+                    if let Some(rule) = parse_prelude(&css[i + 7..prelude_end], i..end) {
+                        rules.push(rule);
+                    }
+                }
+                i = end;
+            }
+            _ => i += 1,
+        }
+    }
+
+    rules
+}
+
+/// Remove `/* ... */` comments, leaving strings untouched.
+pub fn strip_comments(css: &str) -> String {
+    let bytes = css.as_bytes();
+    let mut out = String::with_capacity(css.len());
+    let mut last = 0;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                out.push_str(&css[last..i]);
+                i = skip_comment(bytes, i);
+                last = i;
+            }
+            b'"' | b'\'' => i = skip_string(bytes, i),
+            b'\\' => i += 2,
+            _ => i += 1,
+        }
+    }
+    out.push_str(&css[last.min(css.len())..]);
+    out
+}
+
+pub(crate) fn skip_comment(bytes: &[u8], start: usize) -> usize {
+    let mut i = start + 2;
+    while i + 1 < bytes.len() {
+        if bytes[i] == b'*' && bytes[i + 1] == b'/' {
+            return i + 2;
+        }
+        i += 1;
+    }
+    bytes.len()
+}
+
+pub(crate) fn skip_string(bytes: &[u8], start: usize) -> usize {
+    let quote = bytes[start];
+    let mut i = start + 1;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 2,
+            b'\n' => return i,
+            b if b == quote => return i + 1,
+            _ => i += 1,
+        }
+    }
+    bytes.len().min(i)
+}
+
+pub(crate) fn is_ident_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'-' || b == b'_' || b >= 0x80
+}
+
+pub(crate) fn is_at_keyword(bytes: &[u8], at: usize, keyword: &[u8]) -> bool {
+    let start = at + 1;
+    let end = start + keyword.len();
+    end <= bytes.len()
+        && bytes[start..end].eq_ignore_ascii_case(keyword)
+        && !bytes.get(end).copied().is_some_and(is_ident_byte)
+}
+
+/// Returns the index just past the statement's `;` and whether the statement was
+/// terminated by `;` (or EOF) rather than running into a block.
+pub(crate) fn find_statement_end(bytes: &[u8], start: usize) -> (usize, bool) {
+    let mut parens = 0usize;
+    let mut i = start;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'/' if bytes.get(i + 1) == Some(&b'*') => i = skip_comment(bytes, i),
+            b'"' | b'\'' => i = skip_string(bytes, i),
+            b'\\' => i += 2,
+            b'(' => {
+                parens += 1;
+                i += 1;
+            }
+            b')' => {
+                parens = parens.saturating_sub(1);
+                i += 1;
+            }
+            b';' if parens == 0 => return (i + 1, true),
+            b'{' | b'}' if parens == 0 => return (i, false),
+            _ => i += 1,
+        }
+    }
+    (bytes.len(), true)
+}
+
+/// Returns the content between a `(` at `open` and its matching `)`, and the
+/// index after the `)`.
+fn take_parenthesized(text: &str, open: usize) -> Option<(&str, usize)> {
+    let bytes = text.as_bytes();
+    let mut depth = 0usize;
+    let mut i = open;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'"' | b'\'' => {
+                i = skip_string(bytes, i);
+                continue;
+            }
+            b'\\' => {
+                i += 2;
+                continue;
+            }
+            b'(' => depth += 1,
+            b')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some((&text[open + 1..i], i + 1));
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+fn unquote(value: &str) -> String {
+    let value = value.trim();
+    let bytes = value.as_bytes();
+    if bytes.len() >= 2
+        && (bytes[0] == b'"' || bytes[0] == b'\'')
+        && bytes[bytes.len() - 1] == bytes[0]
+    {
+        unescape(&value[1..value.len() - 1])
+    } else {
+        unescape(value)
+    }
+}
+
+fn unescape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            if let Some(next) = chars.next() {
+                out.push(next);
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+fn starts_with_function(text: &str, name: &str) -> bool {
+    let len = name.len();
+    text.len() > len
+        && text.as_bytes()[..len].eq_ignore_ascii_case(name.as_bytes())
+        && text.as_bytes()[len] == b'('
+}
+
+fn parse_prelude(prelude: &str, span: Range<usize>) -> Option<ImportRule> {
+    let prelude = strip_comments(prelude);
+    let rest = prelude.trim_start();
+
+    let (url, mut rest) = if rest.starts_with('"') || rest.starts_with('\'') {
+        let end = skip_string(rest.as_bytes(), 0);
+        (unquote(&rest[..end]), &rest[end..])
+    } else if starts_with_function(rest, "url") {
+        let (inner, end) = take_parenthesized(rest, 3)?;
+        (unquote(inner), &rest[end..])
+    } else {
+        return None;
+    };
+
+    let mut layer = None;
+    let mut supports = None;
+
+    rest = rest.trim_start();
+    if starts_with_function(rest, "layer") {
+        let (inner, end) = take_parenthesized(rest, 5)?;
+        layer = Some(inner.trim().to_string());
+        rest = rest[end..].trim_start();
+    } else if rest.len() >= 5
+        && rest.as_bytes()[..5].eq_ignore_ascii_case(b"layer")
+        && !rest.as_bytes().get(5).copied().is_some_and(is_ident_byte)
+    {
+        layer = Some(String::new());
+        rest = rest[5..].trim_start();
+    }
+
+    if starts_with_function(rest, "supports") {
+        let (inner, end) = take_parenthesized(rest, 8)?;
+        supports = Some(inner.trim().to_string());
+        rest = rest[end..].trim_start();
+    }
+
+    let media = rest.trim();
+    Some(ImportRule {
+        url,
+        layer,
+        supports,
+        media: (!media.is_empty()).then(|| media.to_string()),
+        span,
+    })
+}
+
+/// What to do when a file imports itself, directly or through other files.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum CyclePolicy {
+    /// Fail the whole resolution with a [`CircularImportError`].
+    #[default]
+    Error,
+    /// Skip the import that closes the cycle and record a warning.
+    Warn,
+}
+
+impl CyclePolicy {
+    pub fn parse(name: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        match name {
+            "error" => Ok(CyclePolicy::Error),
+            "warn" => Ok(CyclePolicy::Warn),
+            other => {
+                Err(format!("Unknown cycle policy '{}' (expected error or warn)", other).into())
+            }
+        }
+    }
+}
+
+/// An import chain that leads back to a file that is still being resolved.
+#[derive(Debug, Clone)]
+pub struct CircularImportError {
+    /// The files in the cycle, starting and ending with the same file.
+    pub chain: Vec<PathBuf>,
+}
+
+impl CircularImportError {
+    pub fn describe_chain(&self) -> String {
+        let root = self
+            .chain
+            .first()
+            .and_then(|p| p.parent())
+            .unwrap_or(Path::new(""));
+        self.chain
+            .iter()
+            .map(|p| p.strip_prefix(root).unwrap_or(p).display().to_string())
+            .collect::<Vec<_>>()
+            .join(" \u{2192} ")
+    }
+}
+
+impl std::fmt::Display for CircularImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Circular import: {}", self.describe_chain())
+    }
+}
+
+impl std::error::Error for CircularImportError {}
+
+/// A file as read and preprocessed by the resolver.
+#[derive(Debug, Clone)]
+struct CachedSource {
+    css: String,
+    rules: Vec<ImportRule>,
+    log: RewriteLog,
+}
+
+/// Preprocessed sources and the import graph from previous resolutions.
+///
+/// Passing the same cache to [`ImportResolver::resolve_with_cache`] means only
+/// files that were [invalidated](ImportCache::invalidate) since the last run are
+/// read and parsed again; the rest of the bundle is assembled from the cache.
+#[derive(Debug, Clone, Default)]
+pub struct ImportCache {
+    sources: HashMap<PathBuf, CachedSource>,
+    /// Local files each file imports, including ones that do not exist yet.
+    imports: HashMap<PathBuf, Vec<PathBuf>>,
+}
+
+impl ImportCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether `path` is part of the import graph, either as a resolved file or
+    /// as an import target that did not exist yet.
+    pub fn contains(&self, path: &Path) -> bool {
+        let path = normalize_path(path);
+        self.imports.contains_key(&path) || self.imports.values().any(|deps| deps.contains(&path))
+    }
+
+    /// Drop the cached content of `path`. Returns whether it was part of the graph.
+    pub fn invalidate(&mut self, path: &Path) -> bool {
+        let path = normalize_path(path);
+        let known = self.contains(&path);
+        self.sources.remove(&path);
+        self.imports.remove(&path);
+        known
+    }
+
+    /// Files that import `path`, directly or transitively.
+    pub fn dependents(&self, path: &Path) -> Vec<PathBuf> {
+        let mut found: Vec<PathBuf> = Vec::new();
+        let mut pending = vec![normalize_path(path)];
+        while let Some(current) = pending.pop() {
+            for (file, deps) in &self.imports {
+                if deps.contains(&current) && !found.contains(file) {
+                    found.push(file.clone());
+                    pending.push(file.clone());
+                }
+            }
+        }
+        found
+    }
+
+    /// Every file in the import graph.
+    pub fn files(&self) -> Vec<PathBuf> {
+        let mut files: HashSet<PathBuf> = self.imports.keys().cloned().collect();
+        files.extend(self.imports.values().flatten().cloned());
+        files.into_iter().collect()
+    }
+
+    pub fn clear(&mut self) {
+        self.sources.clear();
+        self.imports.clear();
+    }
+}
+
+/// Resolves a stylesheet's local imports into a single bundle.
+///
+/// A file imported from several places is only inlined the first time; an
+/// import that leads back into a file still being resolved is a cycle and is
+/// handled according to the [`CyclePolicy`].
+#[derive(Default)]
+pub struct ImportResolver {
+    assets: AssetRewriter,
+    cycle_policy: CyclePolicy,
+    stack: Vec<PathBuf>,
+    visited: HashSet<PathBuf>,
+    files: Vec<PathBuf>,
+    external: Vec<(String, SourceLocation)>,
+    unresolved: Vec<UnresolvedImport>,
+    log: RewriteLog,
+    cache: ImportCache,
+}
+
+impl ImportResolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_assets(mut self, assets: AssetRewriter) -> Self {
+        self.assets = assets;
+        self
+    }
+
+    pub fn with_cycle_policy(mut self, policy: CyclePolicy) -> Self {
+        self.cycle_policy = policy;
+        self
+    }
+
+    /// Leave out imports of `files`, as if they had been inlined already.
+    pub fn with_skipped(mut self, files: &[PathBuf]) -> Self {
+        self.visited
+            .extend(files.iter().filter_map(|file| canonicalize(file).ok()));
+        self
+    }
+
+    pub fn resolve(
+        mut self,
+        path: &Path,
+    ) -> Result<ResolvedStylesheet, Box<dyn std::error::Error>> {
+        self.build(path)
+    }
+
+    /// Resolve `path`, reusing and updating the sources cached in `cache`.
+    pub fn resolve_with_cache(
+        mut self,
+        path: &Path,
+        cache: &mut ImportCache,
+    ) -> Result<ResolvedStylesheet, Box<dyn std::error::Error>> {
+        self.cache = std::mem::take(cache);
+        let result = self.build(path);
+        *cache = std::mem::take(&mut self.cache);
+        result
+    }
+
+    fn build(&mut self, path: &Path) -> Result<ResolvedStylesheet, Box<dyn std::error::Error>> {
+        let body = self.inline_file(path)?;
+
+        let mut bundle = MappedCss::default();
+        for (rule, location) in std::mem::take(&mut self.external) {
+            bundle.push_source(&rule, location.source, location.line);
+            bundle.push_generated("\n");
+        }
+        bundle.append(body);
+
+        Ok(ResolvedStylesheet {
+            css: bundle.css,
+            source_map: SourceMap {
+                sources: self.files.clone(),
+                lines: bundle.lines,
+            },
+            files: std::mem::take(&mut self.files),
+            unresolved: std::mem::take(&mut self.unresolved),
+            warnings: std::mem::take(&mut self.log.warnings),
+            assets: std::mem::take(&mut self.log.assets),
+        })
+    }
+
+    fn inline_file(&mut self, path: &Path) -> Result<MappedCss, Box<dyn std::error::Error>> {
+        let canonical = canonicalize(path)?;
+        if let Some(pos) = self.stack.iter().position(|p| *p == canonical) {
+            let mut chain = self.stack[pos..].to_vec();
+            chain.push(canonical);
+            let error = CircularImportError { chain };
+            return match self.cycle_policy {
+                CyclePolicy::Error => Err(error.into()),
+                CyclePolicy::Warn => {
+                    self.log.warnings.push(format!("{} (skipped)", error));
+                    Ok(MappedCss::default())
+                }
+            };
+        }
+        if !self.visited.insert(canonical.clone()) {
+            return Ok(MappedCss::default());
+        }
+        self.files.push(canonical.clone());
+        let source = self.files.len() - 1;
+
+        self.stack.push(canonical.clone());
+        let result = self.inline_imports(&canonical, source);
+        self.stack.pop();
+        result
+    }
+
+    fn inline_imports(
+        &mut self,
+        canonical: &Path,
+        source: usize,
+    ) -> Result<MappedCss, Box<dyn std::error::Error>> {
+        let base = canonical.parent().unwrap_or(Path::new("."));
+        let CachedSource { css, rules, log } = self.load_source(canonical)?;
+        self.log.warnings.extend(log.warnings);
+        for asset in log.assets {
+            if !self.log.assets.contains(&asset) {
+                self.log.assets.push(asset);
+            }
+        }
+
+        let mut out = MappedCss::default();
+        let mut last = 0;
+        let mut line = 1;
+        for rule in rules {
+            out.push_source(&css[last..rule.span.start], source, line);
+            line += css[last..rule.span.start].matches('\n').count();
+            let rule_line = line;
+            line += css[rule.span.clone()].matches('\n').count();
+            last = rule.span.end;
+
+            if rule.is_external() {
+                self.external.push((
+                    css[rule.span.clone()].trim().to_string(),
+                    SourceLocation {
+                        source,
+                        line: rule_line,
+                    },
+                ));
+                continue;
+            }
+
+            let target = local_import_path(base, &rule.url);
+            if !target.is_file() {
+                self.unresolved.push(UnresolvedImport {
+                    url: rule.url.clone(),
+                    from: canonical.to_path_buf(),
+                    reason: "file not found".to_string(),
+                });
+                out.push_source(
+                    &format!("/* unresolved @import: {} */", rule.url),
+                    source,
+                    rule_line,
+                );
+                continue;
+            }
+
+            match self.inline_file(&target) {
+                Ok(content) if content.is_blank() => {}
+                Ok(content) => {
+                    let (open, close) = rule.wrappers();
+                    out.push_generated(&open);
+                    out.append(content);
+                    out.push_generated(&close);
+                }
+                Err(e) if e.is::<CircularImportError>() => return Err(e),
+                Err(e) => {
+                    self.unresolved.push(UnresolvedImport {
+                        url: rule.url.clone(),
+                        from: canonical.to_path_buf(),
+                        reason: e.to_string(),
+                    });
+                    out.push_source(
+                        &format!("/* unresolved @import: {} */", rule.url),
+                        source,
+                        rule_line,
+                    );
+                }
+            }
+        }
+        out.push_source(&css[last..], source, line);
+        Ok(out)
+    }
+
+    fn load_source(
+        &mut self,
+        canonical: &Path,
+    ) -> Result<CachedSource, Box<dyn std::error::Error>> {
+        if let Some(cached) = self.cache.sources.get(canonical) {
+            return Ok(cached.clone());
+        }
+
+        let base = canonical.parent().unwrap_or(Path::new("."));
+        let css = fs::read_to_string(canonical)?;
+        let mut log = RewriteLog::default();
+        let css = self.assets.rewrite(&css, base, &mut log);
+        let rules = parse_imports(&css);
+
+        let deps = rules
+            .iter()
+            .filter(|rule| !rule.is_external())
+            .map(|rule| {
+                let target = local_import_path(base, &rule.url);
+                canonicalize(&target).unwrap_or_else(|_| normalize_path(&target))
+            })
+            .collect();
+        self.cache.imports.insert(canonical.to_path_buf(), deps);
+
+        let source = CachedSource { css, rules, log };
+        self.cache
+            .sources
+            .insert(canonical.to_path_buf(), source.clone());
+        Ok(source)
+    }
+}
+
+/// Map a non-external import URL onto a path relative to `base`.
+pub fn local_import_path(base: &Path, url: &str) -> PathBuf {
+    local_url_path(base, url)
+}
+
+/// Read `path` and inline all of its local imports.
+pub fn resolve_imports(path: &Path) -> Result<ResolvedStylesheet, Box<dyn std::error::Error>> {
+    ImportResolver::new().resolve(path)
+}
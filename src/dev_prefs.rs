@@ -0,0 +1,86 @@
+//! Prefs a browser needs for userChrome development, checked and set by
+//! `setup`.
+//!
+//! Prefs are set on the running browser and saved to its `prefs.js` as usual.
+//! Some are only read at startup, so turning them on needs a restart.
+
+use crate::chrome_js;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// A pref `setup` turns on.
+#[derive(Debug, Clone, Copy)]
+pub struct DevPref {
+    pub name: &'static str,
+    pub purpose: &'static str,
+    /// Whether the browser only reads the pref at startup.
+    pub needs_restart: bool,
+}
+
+pub const DEV_PREFS: &[DevPref] = &[
+    DevPref {
+        name: "toolkit.legacyUserProfileCustomizations.stylesheets",
+        purpose: "loading userChrome.css and userContent.css from the profile",
+        needs_restart: true,
+    },
+    DevPref {
+        name: "devtools.chrome.enabled",
+        purpose: "running code in the chrome context from the Browser Console",
+        needs_restart: false,
+    },
+    DevPref {
+        name: "devtools.debugger.remote-enabled",
+        purpose: "the Browser Toolbox",
+        needs_restart: false,
+    },
+    DevPref {
+        name: "svg.context-properties.content.enabled",
+        purpose: "context-fill and context-stroke in SVG icons",
+        needs_restart: false,
+    },
+];
+
+/// The state of one pref from [`DEV_PREFS`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PrefStatus {
+    pub name: String,
+    /// The value before `setup` ran.
+    pub was: bool,
+    /// Whether `setup` turned it on.
+    #[serde(default)]
+    pub changed: bool,
+    /// Whether the change only takes effect after a restart.
+    #[serde(default)]
+    pub needs_restart: bool,
+}
+
+/// The script that reads [`DEV_PREFS`], turning on the ones that are off
+/// when `apply` is set. It returns what [`statuses`] reads.
+pub fn script(apply: bool) -> String {
+    let names: Vec<&str> = DEV_PREFS.iter().map(|pref| pref.name).collect();
+    chrome_js::render(
+        chrome_js::DEV_PREFS,
+        &[("names", Value::from(names)), ("apply", Value::from(apply))],
+    )
+}
+
+/// What `name` is needed for.
+pub fn purpose(name: &str) -> Option<&'static str> {
+    DEV_PREFS
+        .iter()
+        .find(|pref| pref.name == name)
+        .map(|pref| pref.purpose)
+}
+
+/// Read the result of [`script`].
+pub fn statuses(result: Value, apply: bool) -> Result<Vec<PrefStatus>, Box<dyn std::error::Error>> {
+    let mut statuses: Vec<PrefStatus> = serde_json::from_value(result)?;
+    for status in &mut statuses {
+        status.changed = apply && !status.was;
+        status.needs_restart = status.changed
+            && DEV_PREFS
+                .iter()
+                .any(|pref| pref.name == status.name && pref.needs_restart);
+    }
+    Ok(statuses)
+}
@@ -0,0 +1,145 @@
+//! A JSON lines log of what the tool did, for CI audits and timing.
+//!
+//! [`EventLogLayer`] is a `tracing` layer: every span is written when it
+//! starts and again when it ends, with its fields and how long it took, and
+//! every event is written as it happens. The CLI runs each subcommand in a
+//! `command` span; loading a sheet appends lines such as:
+//!
+//! ```text
+//! {"args":"load -f a.css","command":"load","event":"start","span":"command","time":1760623402117}
+//! {"args":"load -f a.css","command":"load","duration_ms":44.2,"event":"end","result":"ok","span":"command","time":1760623402161}
+//! ```
+
+use serde_json::{Map, Value};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+pub struct EventLogLayer {
+    file: Mutex<File>,
+}
+
+/// Kept in each span's extensions until it closes.
+struct SpanRecord {
+    started: Instant,
+    fields: Map<String, Value>,
+}
+
+impl EventLogLayer {
+    /// Append to `path`, creating it if needed.
+    pub fn open(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| format!("Cannot open {}: {}", path.display(), e))?;
+        Ok(EventLogLayer {
+            file: Mutex::new(file),
+        })
+    }
+
+    fn write(&self, event: &str, mut record: Map<String, Value>) {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        let mut line = Map::new();
+        line.insert("time".into(), time.into());
+        line.insert("event".into(), event.into());
+        line.append(&mut record);
+        if let Ok(mut file) = self.file.lock() {
+            // One write per line, so lines from threads do not interleave
+            let _ = file.write_all(format!("{}\n", Value::Object(line)).as_bytes());
+        }
+    }
+}
+
+impl<S> Layer<S> for EventLogLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut fields = Map::new();
+        fields.insert("span".into(), span.name().into());
+        attrs.record(&mut JsonVisitor(&mut fields));
+        self.write("start", fields.clone());
+        span.extensions_mut().insert(SpanRecord {
+            started: Instant::now(),
+            fields,
+        });
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut extensions = span.extensions_mut();
+        if let Some(record) = extensions.get_mut::<SpanRecord>() {
+            values.record(&mut JsonVisitor(&mut record.fields));
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let mut fields = Map::new();
+        fields.insert("level".into(), event.metadata().level().as_str().into());
+        if let Some(span) = ctx.event_span(event) {
+            fields.insert("span".into(), span.name().into());
+        }
+        event.record(&mut JsonVisitor(&mut fields));
+        self.write("log", fields);
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        let Some(record) = span.extensions_mut().remove::<SpanRecord>() else {
+            return;
+        };
+        let mut fields = record.fields;
+        let ms = record.started.elapsed().as_secs_f64() * 1000.0;
+        fields.insert("duration_ms".into(), ((ms * 10.0).round() / 10.0).into());
+        self.write("end", fields);
+    }
+}
+
+/// Copies `tracing` fields into a JSON object.
+struct JsonVisitor<'a>(&'a mut Map<String, Value>);
+
+impl Visit for JsonVisitor<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.0
+            .insert(field.name().into(), format!("{:?}", value).into());
+    }
+}
@@ -0,0 +1,610 @@
+//! Screenshot sidecars and the static HTML report built from them, for
+//! `gallery`.
+//!
+//! Every PNG that `screenshot`, `scenario run` and `bisect` save gets a JSON
+//! file of the same name next to it, recording what the capture depended on:
+//! the selector, window size, pixel ratio, density, theme and Firefox version.
+//! `gallery` turns a directory of captures into one HTML page, and with a
+//! baseline directory also pairs captures by name, draws a diff of each pair
+//! and shows where their sidecars disagree. With `--grid`, or after a matrix
+//! run of `scenario run`, the page is instead a table with one column per
+//! subdirectory, so the same capture can be compared across runs at a glance.
+//! The page only links to the images, so publish it together with them.
+
+use crate::chrome_js;
+use crate::compare::{diff, Capture};
+use crate::marionette_client::MarionetteConnection;
+use crate::paths::canonicalize;
+use crate::post_process::PostProcess;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// What a screenshot was taken of and under which conditions.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScreenshotMeta {
+    /// `None` for the whole window.
+    pub selector: Option<String>,
+    /// Outer size of the window, in CSS pixels.
+    pub width: u32,
+    pub height: u32,
+    pub pixel_ratio: f64,
+    pub density: String,
+    /// The add-on ID of the active theme.
+    pub theme: String,
+    /// Accessibility and color scheme media features the window matched,
+    /// e.g. `prefers-reduced-motion: reduce`.
+    #[serde(default)]
+    pub media: Vec<String>,
+    /// The UI locale, with the pseudo-locale in parentheses if one is on.
+    #[serde(default)]
+    pub locale: String,
+    pub firefox_version: String,
+    /// Milliseconds since the Unix epoch.
+    pub timestamp: u64,
+}
+
+impl ScreenshotMeta {
+    /// Describe a capture of `selector` in the browser `connection` is
+    /// connected to, which must be in the chrome context.
+    pub fn read(
+        connection: &mut MarionetteConnection,
+        selector: Option<&str>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        #[derive(Deserialize)]
+        struct Window {
+            width: u32,
+            height: u32,
+            pixel_ratio: f64,
+            density: String,
+            theme: String,
+            media: Vec<String>,
+            locale: String,
+        }
+
+        let window_type = connection.app_profile()?.window_type;
+        let script = chrome_js::render(
+            chrome_js::CAPTURE_INFO,
+            &[("windowType", Value::from(window_type))],
+        );
+        let window: Window = serde_json::from_value(connection.execute_script(&script, None)?)?;
+        Ok(ScreenshotMeta {
+            selector: selector.map(String::from),
+            width: window.width,
+            height: window.height,
+            pixel_ratio: window.pixel_ratio,
+            density: window.density,
+            theme: window.theme,
+            media: window.media,
+            locale: window.locale,
+            firefox_version: connection.browser_info()?.version,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or(0),
+        })
+    }
+
+    /// The sidecar of the screenshot at `png`, if it has one.
+    pub fn load(png: &Path) -> Result<Option<Self>, Box<dyn std::error::Error>> {
+        match fs::read_to_string(sidecar_path(png)) {
+            Ok(json) => Ok(Some(serde_json::from_str(&json)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Write this as the sidecar of the screenshot at `png`.
+    pub fn save(&self, png: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        fs::write(
+            sidecar_path(png),
+            serde_json::to_string_pretty(self)? + "\n",
+        )?;
+        Ok(())
+    }
+
+    /// Field names and values, in the order the report shows them.
+    fn fields(&self) -> Vec<(&'static str, String)> {
+        vec![
+            (
+                "Selector",
+                self.selector.clone().unwrap_or_else(|| "(window)".into()),
+            ),
+            ("Window", format!("{}x{}", self.width, self.height)),
+            ("Pixel ratio", self.pixel_ratio.to_string()),
+            ("Density", self.density.clone()),
+            (
+                "Theme",
+                match self.theme.as_str() {
+                    "" => "(default)".to_string(),
+                    theme => theme.to_string(),
+                },
+            ),
+            (
+                "Media",
+                if self.media.is_empty() {
+                    "(none)".to_string()
+                } else {
+                    self.media.join(", ")
+                },
+            ),
+            ("Locale", self.locale.clone()),
+            ("Firefox", self.firefox_version.clone()),
+            ("Taken", utc_time(self.timestamp)),
+        ]
+    }
+}
+
+/// `image.png` -> `image.json`.
+pub fn sidecar_path(png: &Path) -> PathBuf {
+    png.with_extension("json")
+}
+
+/// How a capture compares with the baseline capture of the same name.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Status {
+    Unchanged,
+    /// Differs in this many pixels, counting any difference in size.
+    Changed(u64),
+    /// Not in the baseline.
+    New,
+    /// Only in the baseline.
+    Missing,
+}
+
+/// The counts printed after writing a report.
+#[derive(Debug, Clone, Default)]
+pub struct GallerySummary {
+    pub images: usize,
+    pub unchanged: usize,
+    pub changed: usize,
+    pub new: usize,
+    pub missing: usize,
+}
+
+struct Entry {
+    name: String,
+    status: Status,
+    current: Option<(PathBuf, Option<ScreenshotMeta>)>,
+    baseline: Option<(PathBuf, Option<ScreenshotMeta>)>,
+    diff: Option<PathBuf>,
+}
+
+/// Write an HTML report of the PNGs under `dir` to `out`. With `baseline`,
+/// captures are compared with the ones of the same relative path there, and
+/// diff images are written to a directory next to `out`, through `post`.
+pub fn write_gallery(
+    dir: &Path,
+    baseline: Option<&Path>,
+    out: &Path,
+    post: &PostProcess,
+) -> Result<GallerySummary, Box<dyn std::error::Error>> {
+    let out_dir = match out.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    fs::create_dir_all(&out_dir)?;
+    let stem = out
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "gallery".into());
+    let diff_dir = out_dir.join(format!("{}-diffs", stem));
+    // Diffs from an earlier report would otherwise linger
+    if diff_dir.is_dir() {
+        fs::remove_dir_all(&diff_dir)?;
+    }
+
+    let mut names = find_pngs(dir)?;
+    let baseline_names = match baseline {
+        Some(baseline) => find_pngs(baseline)?,
+        None => Vec::new(),
+    };
+    for name in &baseline_names {
+        if !names.contains(name) {
+            names.push(name.clone());
+        }
+    }
+    names.sort();
+
+    let mut summary = GallerySummary::default();
+    let mut entries = Vec::new();
+    for name in names {
+        let load = |root: &Path| -> Result<_, Box<dyn std::error::Error>> {
+            let path = root.join(&name);
+            let meta = ScreenshotMeta::load(&path)
+                .map_err(|e| format!("{}: {}", sidecar_path(&path).display(), e))?;
+            Ok((path, meta))
+        };
+        let current = if dir.join(&name).is_file() {
+            Some(load(dir)?)
+        } else {
+            None
+        };
+        let base = match baseline {
+            Some(root) if baseline_names.contains(&name) => Some(load(root)?),
+            _ => None,
+        };
+
+        let mut diff_path = None;
+        let status = match (&current, &base) {
+            (Some(_), None) if baseline.is_none() => Status::Unchanged,
+            (Some(_), None) => Status::New,
+            (None, _) => Status::Missing,
+            (Some((a, _)), Some((b, _))) => {
+                let a = Capture::from_png(&fs::read(a)?)?;
+                let b = Capture::from_png(&fs::read(b)?)?;
+                let (image, changed) = diff(&b, &a);
+                if changed == 0 {
+                    Status::Unchanged
+                } else {
+                    let path = diff_dir.join(&name);
+                    if let Some(parent) = path.parent() {
+                        fs::create_dir_all(parent)?;
+                    }
+                    fs::write(&path, post.apply(&image.to_png()?)?)?;
+                    diff_path = Some(path);
+                    Status::Changed(changed)
+                }
+            }
+        };
+        summary.images += 1;
+        match status {
+            Status::Unchanged => summary.unchanged += 1,
+            Status::Changed(_) => summary.changed += 1,
+            Status::New => summary.new += 1,
+            Status::Missing => summary.missing += 1,
+        }
+        entries.push(Entry {
+            name: name.to_string_lossy().replace('\\', "/"),
+            status,
+            current,
+            baseline: base,
+            diff: diff_path,
+        });
+    }
+
+    fs::write(
+        out,
+        render(&entries, &summary, baseline.is_some(), &out_dir)?,
+    )?;
+    Ok(summary)
+}
+
+/// The subdirectories of `dir` holding PNGs, sorted, as columns for
+/// [`write_grid`]. Returns `""` first, standing for `dir` itself, if PNGs sit
+/// directly in it.
+pub fn grid_columns(dir: &Path) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let mut columns = Vec::new();
+    for name in find_pngs(dir)? {
+        let mut parts = name.components();
+        let column = match (parts.next(), parts.next()) {
+            (Some(first), Some(_)) => first.as_os_str().to_string_lossy().into_owned(),
+            _ => String::new(),
+        };
+        if !columns.contains(&column) {
+            columns.push(column);
+        }
+    }
+    columns.sort();
+    Ok(columns)
+}
+
+/// Write an HTML table of the PNGs under each of the `columns` of `dir` to
+/// `out`: one row per path, one column per subdirectory, so the same capture
+/// from several runs sits side by side. `""` stands for the PNGs directly in
+/// `dir`. Returns the number of rows.
+pub fn write_grid(
+    dir: &Path,
+    columns: &[String],
+    out: &Path,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let out_dir = match out.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    fs::create_dir_all(&out_dir)?;
+
+    let mut cells = Vec::new();
+    let mut names: Vec<PathBuf> = Vec::new();
+    for column in columns {
+        let root = dir.join(column);
+        let found: Vec<PathBuf> = if root.is_dir() {
+            find_pngs(&root)?
+                .into_iter()
+                // The top level only has its own PNGs, not those of the others
+                .filter(|name| !column.is_empty() || name.components().count() == 1)
+                .collect()
+        } else {
+            Vec::new()
+        };
+        for name in &found {
+            if !names.contains(name) {
+                names.push(name.clone());
+            }
+        }
+        cells.push((root, found));
+    }
+    names.sort();
+
+    let mut html = String::new();
+    writeln!(
+        html,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Screenshots</title>\n<style>\n{}\n</style>\n</head>\n<body>\n<h1>Screenshots</h1>",
+        STYLE
+    )?;
+    writeln!(
+        html,
+        "<p>{} images, {} columns</p>\n<div class=\"grid\">\n<table>\n<tr><th></th>",
+        names.len(),
+        columns.len()
+    )?;
+    for column in columns {
+        let label = if column.is_empty() {
+            dir.file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_else(|| ".".into())
+        } else {
+            column.clone()
+        };
+        write!(html, "<th>{}</th>", escape(&label))?;
+    }
+    writeln!(html, "</tr>")?;
+    for name in &names {
+        let label = name.to_string_lossy().replace('\\', "/");
+        write!(html, "<tr><th>{}</th>", escape(&label))?;
+        for (root, found) in &cells {
+            if !found.contains(name) {
+                write!(
+                    html,
+                    "<td class=\"missing\"><span class=\"status\">missing</span></td>"
+                )?;
+                continue;
+            }
+            let path = root.join(name);
+            let src = href(&relative_to(&path, &out_dir)?);
+            write!(
+                html,
+                "<td><a href=\"{}\"><img src=\"{}\" alt=\"{}\" loading=\"lazy\"></a></td>",
+                src,
+                src,
+                escape(&label)
+            )?;
+        }
+        writeln!(html, "</tr>")?;
+    }
+    writeln!(html, "</table>\n</div>\n</body>\n</html>")?;
+    fs::write(out, html)?;
+    Ok(names.len())
+}
+
+/// Paths of the PNGs under `root`, relative to it.
+fn find_pngs(root: &Path) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+    fn walk(
+        root: &Path,
+        dir: &Path,
+        found: &mut Vec<PathBuf>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                walk(root, &path, found)?;
+            } else if path
+                .extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("png"))
+            {
+                found.push(path.strip_prefix(root)?.to_path_buf());
+            }
+        }
+        Ok(())
+    }
+
+    if !root.is_dir() {
+        return Err(format!("Not a directory: {}", root.display()).into());
+    }
+    let mut found = Vec::new();
+    walk(root, root, &mut found)?;
+    Ok(found)
+}
+
+const STYLE: &str = "body { font: 14px system-ui, sans-serif; margin: 2em; color: #222; }
+section { border-top: 1px solid #ccc; padding: 1em 0; }
+h2 { font-size: 1.1em; }
+.status { font-size: 0.8em; padding: 0.1em 0.5em; border-radius: 1em; margin-left: 0.5em; background: #ddd; }
+.changed .status, .missing .status { background: #f6c; }
+.new .status { background: #9cf; }
+.images { display: flex; gap: 1em; flex-wrap: wrap; align-items: flex-start; }
+figure { margin: 0; }
+figure img { max-width: 100%; border: 1px solid #ccc; background: repeating-conic-gradient(#eee 0 25%, #fff 0 50%) 0 0 / 16px 16px; }
+figcaption { color: #666; font-size: 0.85em; }
+table { border-collapse: collapse; margin-top: 0.5em; font-size: 0.85em; }
+th, td { text-align: left; padding: 0.2em 1em 0.2em 0; }
+td.differs { color: #c06; font-weight: bold; }
+.grid { overflow-x: auto; }
+.grid td { vertical-align: top; padding: 0.5em; }
+.grid img { max-width: 24em; border: 1px solid #ccc; }
+.grid .missing .status { background: #f6c; }";
+
+fn render(
+    entries: &[Entry],
+    summary: &GallerySummary,
+    compared: bool,
+    out_dir: &Path,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let mut html = String::new();
+    writeln!(
+        html,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Screenshots</title>\n<style>\n{}\n</style>\n</head>\n<body>\n<h1>Screenshots</h1>",
+        STYLE
+    )?;
+    let mut counts = format!("{} images", summary.images);
+    if compared {
+        write!(
+            counts,
+            ": {} changed, {} new, {} missing, {} unchanged",
+            summary.changed, summary.new, summary.missing, summary.unchanged
+        )?;
+    }
+    writeln!(html, "<p>{}</p>", counts)?;
+
+    for entry in entries {
+        let (class, label) = match entry.status {
+            Status::Unchanged if compared => ("unchanged", "unchanged".to_string()),
+            Status::Unchanged => ("", String::new()),
+            Status::Changed(pixels) => ("changed", format!("{} pixels changed", pixels)),
+            Status::New => ("new", "new".to_string()),
+            Status::Missing => ("missing", "missing".to_string()),
+        };
+        write!(
+            html,
+            "<section class=\"{}\" id=\"{}\">\n<h2>{}",
+            class,
+            escape(&entry.name),
+            escape(&entry.name)
+        )?;
+        if !label.is_empty() {
+            write!(html, "<span class=\"status\">{}</span>", label)?;
+        }
+        writeln!(html, "</h2>\n<div class=\"images\">")?;
+        let mut figures = Vec::new();
+        if let Some((path, _)) = &entry.baseline {
+            figures.push(("Baseline", path));
+        }
+        if let Some((path, _)) = &entry.current {
+            figures.push((if compared { "Current" } else { "" }, path));
+        }
+        if let Some(path) = &entry.diff {
+            figures.push(("Difference", path));
+        }
+        for (caption, path) in figures {
+            let src = href(&relative_to(path, out_dir)?);
+            write!(
+                html,
+                "<figure><a href=\"{}\"><img src=\"{}\" alt=\"{}\" loading=\"lazy\"></a>",
+                src,
+                src,
+                escape(caption)
+            )?;
+            if !caption.is_empty() {
+                write!(html, "<figcaption>{}</figcaption>", caption)?;
+            }
+            writeln!(html, "</figure>")?;
+        }
+        writeln!(html, "</div>")?;
+        write_meta_table(&mut html, entry, compared)?;
+        writeln!(html, "</section>")?;
+    }
+    writeln!(html, "</body>\n</html>")?;
+    Ok(html)
+}
+
+/// The sidecars of an entry, side by side when there is a baseline, with
+/// the values that differ marked.
+fn write_meta_table(
+    html: &mut String,
+    entry: &Entry,
+    compared: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let current = entry.current.as_ref().and_then(|(_, meta)| meta.as_ref());
+    let baseline = entry.baseline.as_ref().and_then(|(_, meta)| meta.as_ref());
+    if current.is_none() && baseline.is_none() {
+        return Ok(());
+    }
+    let current = current.map(ScreenshotMeta::fields);
+    let baseline = baseline.map(ScreenshotMeta::fields);
+    writeln!(html, "<table>")?;
+    if compared {
+        writeln!(html, "<tr><th></th><th>Baseline</th><th>Current</th></tr>")?;
+    }
+    let rows = current.as_ref().or(baseline.as_ref()).unwrap();
+    for (i, (name, _)) in rows.iter().enumerate() {
+        let value = |fields: &Option<Vec<(&str, String)>>| {
+            fields
+                .as_ref()
+                .map(|fields| fields[i].1.clone())
+                .unwrap_or_default()
+        };
+        let (old, new) = (value(&baseline), value(&current));
+        // Times always differ, and neither side missing a sidecar is news
+        let differs = *name != "Taken" && baseline.is_some() && current.is_some() && old != new;
+        let class = if differs { " class=\"differs\"" } else { "" };
+        write!(html, "<tr><th>{}</th>", name)?;
+        if compared {
+            write!(html, "<td{}>{}</td>", class, escape(&old))?;
+        }
+        writeln!(html, "<td{}>{}</td></tr>", class, escape(&new))?;
+    }
+    writeln!(html, "</table>")?;
+    Ok(())
+}
+
+/// `path` relative to `base`, going up with `..` where needed.
+fn relative_to(path: &Path, base: &Path) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let path = canonicalize(path)?;
+    let base = canonicalize(base)?;
+    let mut path_parts = path.components().peekable();
+    let mut base_parts = base.components().peekable();
+    while let (Some(a), Some(b)) = (path_parts.peek(), base_parts.peek()) {
+        if a != b {
+            break;
+        }
+        path_parts.next();
+        base_parts.next();
+    }
+    let mut relative: PathBuf = base_parts.map(|_| Component::ParentDir).collect();
+    relative.extend(path_parts);
+    Ok(relative)
+}
+
+/// A relative path as an attribute value, percent-encoding what a URL
+/// cannot hold.
+fn href(path: &Path) -> String {
+    let mut url = String::new();
+    for (i, part) in path.components().enumerate() {
+        if i > 0 {
+            url.push('/');
+        }
+        for byte in part.as_os_str().to_string_lossy().bytes() {
+            if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+                url.push(byte as char);
+            } else {
+                let _ = write!(url, "%{:02X}", byte);
+            }
+        }
+    }
+    url
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// `YYYY-MM-DD HH:MM:SS UTC` for milliseconds since the Unix epoch.
+fn utc_time(millis: u64) -> String {
+    let secs = millis / 1000;
+    let (days, rest) = (secs / 86_400, secs % 86_400);
+    // Days to a civil date, after Howard Hinnant's `civil_from_days`
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        rest / 3600,
+        rest % 3600 / 60,
+        rest % 60
+    )
+}
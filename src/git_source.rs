@@ -0,0 +1,171 @@
+//! Reading stylesheets from git history.
+//!
+//! A revision is written out to a temporary directory so the regular import
+//! resolver can follow `@import`s and `url()`s exactly as it does on disk,
+//! without touching the working tree.
+
+use crate::chrome_manifest::normalize_path;
+use crate::paths::canonicalize;
+use git2::{ObjectType, Oid, Repository, Sort, Tree};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+
+#[derive(Debug, Clone)]
+pub struct CommitInfo {
+    pub id: String,
+    pub short_id: String,
+    pub summary: String,
+}
+
+impl CommitInfo {
+    fn from_commit(commit: &git2::Commit) -> Self {
+        let id = commit.id().to_string();
+        CommitInfo {
+            short_id: id[..7.min(id.len())].to_string(),
+            id,
+            summary: commit.summary().unwrap_or("").to_string(),
+        }
+    }
+}
+
+/// The files of one revision, checked out into a temporary directory that is
+/// removed when the snapshot is dropped.
+pub struct GitSnapshot {
+    dir: TempDir,
+    workdir: PathBuf,
+    commit: CommitInfo,
+}
+
+impl GitSnapshot {
+    /// Check out `rev` of the repository containing `path`.
+    pub fn checkout(path: &Path, rev: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let (repo, workdir) = open_repository(path)?;
+        let commit = repo
+            .revparse_single(rev)
+            .map_err(|e| format!("Unknown revision '{}': {}", rev, e.message()))?
+            .peel_to_commit()?;
+
+        let dir = tempfile::Builder::new().prefix("mus-uc-git-").tempdir()?;
+        write_tree(&repo, &commit.tree()?, dir.path())?;
+
+        Ok(GitSnapshot {
+            dir,
+            workdir,
+            commit: CommitInfo::from_commit(&commit),
+        })
+    }
+
+    pub fn commit(&self) -> &CommitInfo {
+        &self.commit
+    }
+
+    /// Where a working-tree path lives inside the snapshot.
+    pub fn path_for(&self, working_path: &Path) -> Result<PathBuf, Box<dyn std::error::Error>> {
+        let absolute = absolute_path(working_path)?;
+        let relative = absolute.strip_prefix(&self.workdir).map_err(|_| {
+            format!(
+                "{} is outside the repository at {}",
+                working_path.display(),
+                self.workdir.display()
+            )
+        })?;
+        let snapshot_path = self.dir.path().join(relative);
+        if !snapshot_path.is_file() {
+            return Err(format!(
+                "{} does not exist at {}",
+                relative.display(),
+                self.commit.short_id
+            )
+            .into());
+        }
+        Ok(snapshot_path)
+    }
+}
+
+/// Commits after `good` up to and including `bad`, oldest first.
+pub fn commit_range(
+    path: &Path,
+    good: &str,
+    bad: &str,
+) -> Result<Vec<CommitInfo>, Box<dyn std::error::Error>> {
+    let (repo, _) = open_repository(path)?;
+    let resolve = |rev: &str| -> Result<Oid, Box<dyn std::error::Error>> {
+        Ok(repo
+            .revparse_single(rev)
+            .map_err(|e| format!("Unknown revision '{}': {}", rev, e.message()))?
+            .peel_to_commit()?
+            .id())
+    };
+    let good = resolve(good)?;
+    let bad = resolve(bad)?;
+    if !repo.graph_descendant_of(bad, good)? {
+        return Err("The bad revision must be a descendant of the good one".into());
+    }
+
+    let mut walk = repo.revwalk()?;
+    walk.set_sorting(Sort::TOPOLOGICAL | Sort::REVERSE)?;
+    walk.push(bad)?;
+    walk.hide(good)?;
+
+    let mut commits = Vec::new();
+    for oid in walk {
+        commits.push(CommitInfo::from_commit(&repo.find_commit(oid?)?));
+    }
+    Ok(commits)
+}
+
+fn open_repository(path: &Path) -> Result<(Repository, PathBuf), Box<dyn std::error::Error>> {
+    let absolute = absolute_path(path)?;
+    let start = absolute.parent().unwrap_or(&absolute);
+    let repo = Repository::discover(start)
+        .map_err(|_| format!("{} is not inside a git repository", path.display()))?;
+    let workdir = repo
+        .workdir()
+        .ok_or("Bare repositories are not supported")?;
+    let workdir = canonicalize(workdir)?;
+    Ok((repo, workdir))
+}
+
+/// Absolute path that also works for files deleted from the working tree.
+fn absolute_path(path: &Path) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    if let Ok(canonical) = canonicalize(path) {
+        return Ok(canonical);
+    }
+    let absolute = normalize_path(&std::env::current_dir()?.join(path));
+    match (absolute.parent(), absolute.file_name()) {
+        (Some(parent), Some(name)) if parent.exists() => Ok(canonicalize(parent)?.join(name)),
+        _ => Ok(absolute),
+    }
+}
+
+fn write_tree(
+    repo: &Repository,
+    tree: &Tree,
+    dir: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    fs::create_dir_all(dir)?;
+    for entry in tree.iter() {
+        let Some(name) = entry.name() else { continue };
+        let target = dir.join(name);
+        match entry.kind() {
+            Some(ObjectType::Tree) => {
+                write_tree(repo, &repo.find_tree(entry.id())?, &target)?;
+            }
+            Some(ObjectType::Blob) if entry.filemode() == 0o120000 => {
+                #[cfg(unix)]
+                {
+                    let blob = repo.find_blob(entry.id())?;
+                    let link = String::from_utf8_lossy(blob.content()).into_owned();
+                    std::os::unix::fs::symlink(link, &target)?;
+                }
+            }
+            Some(ObjectType::Blob) => {
+                fs::write(&target, repo.find_blob(entry.id())?.content())?;
+            }
+            // Submodules
+            _ => {}
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,148 @@
+//! Chrome scripts for common chores, run by name with `helper run` and the
+//! `helper` step of scenarios, so nobody has to keep their own copy of the
+//! snippet that opens the sidebar.
+//!
+//! Each helper takes named parameters. A value is read as JSON if it parses,
+//! so `open=true` is a boolean and `panel=viewBookmarksSidebar` a string.
+
+use crate::chrome_js;
+use crate::marionette_client::MarionetteConnection;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Helper {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub params: &'static [Param],
+    /// Whether running it changes the browser, which `--read-only` refuses.
+    pub changes_browser: bool,
+    script: &'static str,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Param {
+    pub name: &'static str,
+    pub description: &'static str,
+    /// The JSON value used when the parameter is not given.
+    pub default: &'static str,
+}
+
+pub const HELPERS: &[Helper] = &[
+    Helper {
+        name: "toggle-sidebar",
+        description: "Open or close the sidebar, or switch it to a panel",
+        params: &[
+            Param {
+                name: "panel",
+                description: "Sidebar to show, e.g. viewBookmarksSidebar or viewHistorySidebar (default: the last one)",
+                default: "null",
+            },
+            Param {
+                name: "open",
+                description: "true or false to open or close it whatever its state",
+                default: "null",
+            },
+        ],
+        changes_browser: true,
+        script: chrome_js::HELPER_TOGGLE_SIDEBAR,
+    },
+    Helper {
+        name: "open-overflow-menu",
+        description: "Open the toolbar overflow menu, or close it",
+        params: &[Param {
+            name: "close",
+            description: "true to close it instead",
+            default: "false",
+        }],
+        changes_browser: true,
+        script: chrome_js::HELPER_OVERFLOW_MENU,
+    },
+    Helper {
+        name: "window-info",
+        description: "Size, position, pixel ratio, density, theme and tabs of the browser window",
+        params: &[],
+        changes_browser: false,
+        script: chrome_js::HELPER_WINDOW_INFO,
+    },
+    Helper {
+        name: "flush-caches",
+        description: "Drop the startup, chrome and image caches, so edited chrome files and images show",
+        params: &[Param {
+            name: "images",
+            description: "false to keep the image cache",
+            default: "true",
+        }],
+        changes_browser: true,
+        script: chrome_js::HELPER_FLUSH_CACHES,
+    },
+];
+
+impl Helper {
+    pub fn find(name: &str) -> Result<&'static Helper, Box<dyn std::error::Error>> {
+        HELPERS.iter().find(|h| h.name == name).ok_or_else(|| {
+            let names: Vec<&str> = HELPERS.iter().map(|h| h.name).collect();
+            format!(
+                "Unknown helper '{}' (available: {})",
+                name,
+                names.join(", ")
+            )
+            .into()
+        })
+    }
+
+    /// Check `args` against the parameters, so a typo fails before anything
+    /// runs.
+    pub fn check_args(
+        &self,
+        args: &BTreeMap<String, Value>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        for name in args.keys() {
+            if !self.params.iter().any(|p| p.name == name) {
+                let known: Vec<&str> = self.params.iter().map(|p| p.name).collect();
+                return Err(format!(
+                    "{} has no parameter '{}' ({})",
+                    self.name,
+                    name,
+                    if known.is_empty() {
+                        "it takes none".to_string()
+                    } else {
+                        format!("it takes {}", known.join(", "))
+                    }
+                )
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Run the helper with `args`, the others at their defaults. The
+    /// connection must be in the chrome context.
+    pub fn run(
+        &self,
+        connection: &mut MarionetteConnection,
+        args: &BTreeMap<String, Value>,
+    ) -> Result<Value, Box<dyn std::error::Error>> {
+        self.check_args(args)?;
+        let window_type = connection.app_profile()?.window_type;
+        let mut values = vec![("windowType", Value::from(window_type))];
+        for param in self.params {
+            let value = match args.get(param.name) {
+                Some(value) => value.clone(),
+                None => serde_json::from_str(param.default)?,
+            };
+            values.push((param.name, value));
+        }
+        connection.execute_script(&chrome_js::render(self.script, &values), None)
+    }
+}
+
+/// Read a `name=value` argument. The value is JSON if it parses, and a
+/// string otherwise.
+pub fn parse_arg(text: &str) -> Result<(String, Value), Box<dyn std::error::Error>> {
+    let (name, value) = text
+        .split_once('=')
+        .ok_or_else(|| format!("Invalid argument '{}', expected NAME=VALUE", text))?;
+    let value = serde_json::from_str(value).unwrap_or_else(|_| Value::from(value));
+    Ok((name.trim().to_string(), value))
+}
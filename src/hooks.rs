@@ -0,0 +1,218 @@
+//! Commands and chrome scripts run at points in a session, set in the
+//! `[hooks]` table of `mus-uc.toml`, for a team's own notifications, logs or
+//! metrics:
+//!
+//! ```toml
+//! [hooks]
+//! on_connect = "echo connected >> mus-uc.log"
+//! on_reload_error = ["notify-send 'CSS broke' \"$MUS_UC_FILE\"", { script = "hooks/flash.js" }]
+//! ```
+//!
+//! Each hook is a shell command, a `{ run = "..." }` table meaning the same,
+//! a `{ script = "file.js" }` table naming a chrome script, or a list of
+//! these, run in order. A hook that fails is reported and does not stop
+//! anything.
+
+use crate::shared_connection::SharedConnection;
+use serde::Deserialize;
+use serde_json::json;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// When hooks run.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HookEvent {
+    /// A command connected to the browser, or a watch reconnected.
+    Connect,
+    /// `load` or the start of a watch loaded a sheet.
+    Load,
+    /// A watch reloaded a sheet without errors.
+    ReloadSuccess,
+    /// A watch could not read a sheet, the reload had CSS errors or was
+    /// rolled back, or the connection was lost.
+    ReloadError,
+}
+
+impl HookEvent {
+    /// The key of the event in `[hooks]`.
+    pub fn name(self) -> &'static str {
+        match self {
+            HookEvent::Connect => "on_connect",
+            HookEvent::Load => "on_load",
+            HookEvent::ReloadSuccess => "on_reload_success",
+            HookEvent::ReloadError => "on_reload_error",
+        }
+    }
+}
+
+/// What a hook is told about the event.
+#[derive(Debug, Clone, Default)]
+pub struct HookContext {
+    pub id: Option<String>,
+    pub file: Option<PathBuf>,
+    pub errors: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "RawAction")]
+pub enum HookAction {
+    /// Run by the shell, in the directory of `mus-uc.toml`.
+    Command(String),
+    /// A chrome script, read each time it runs.
+    Script(PathBuf),
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawAction {
+    Command(String),
+    Table(ActionTable),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ActionTable {
+    run: Option<String>,
+    script: Option<PathBuf>,
+}
+
+impl TryFrom<RawAction> for HookAction {
+    type Error = String;
+
+    fn try_from(raw: RawAction) -> Result<Self, Self::Error> {
+        match raw {
+            RawAction::Command(command)
+            | RawAction::Table(ActionTable {
+                run: Some(command),
+                script: None,
+            }) => Ok(HookAction::Command(command)),
+            RawAction::Table(ActionTable {
+                run: None,
+                script: Some(script),
+            }) => Ok(HookAction::Script(script)),
+            RawAction::Table(_) => Err("a hook needs one of `run` or `script`".to_string()),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrMany {
+    One(HookAction),
+    Many(Vec<HookAction>),
+}
+
+fn one_or_many<'de, D>(deserializer: D) -> Result<Vec<HookAction>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(action) => vec![action],
+        OneOrMany::Many(actions) => actions,
+    })
+}
+
+/// The `[hooks]` table.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Hooks {
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub on_connect: Vec<HookAction>,
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub on_load: Vec<HookAction>,
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub on_reload_success: Vec<HookAction>,
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub on_reload_error: Vec<HookAction>,
+    /// Where commands run and script paths are taken from: the directory of
+    /// the config, or the current directory when empty.
+    #[serde(skip)]
+    pub dir: PathBuf,
+}
+
+impl Hooks {
+    pub fn actions(&self, event: HookEvent) -> &[HookAction] {
+        match event {
+            HookEvent::Connect => &self.on_connect,
+            HookEvent::Load => &self.on_load,
+            HookEvent::ReloadSuccess => &self.on_reload_success,
+            HookEvent::ReloadError => &self.on_reload_error,
+        }
+    }
+
+    /// Run the hooks for `event`, waiting for each. Failures are reported,
+    /// not returned.
+    pub fn run(&self, event: HookEvent, context: &HookContext, connection: &SharedConnection) {
+        for action in self.actions(event) {
+            let result = match action {
+                HookAction::Command(command) => self.run_command(command, event, context),
+                HookAction::Script(script) => self.run_script(script, event, context, connection),
+            };
+            if let Err(e) = result {
+                eprintln!("Warning: {} hook failed: {}", event.name(), e);
+            }
+        }
+    }
+
+    fn run_command(
+        &self,
+        command: &str,
+        event: HookEvent,
+        context: &HookContext,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut process = shell(command);
+        if !self.dir.as_os_str().is_empty() {
+            process.current_dir(&self.dir);
+        }
+        process.env("MUS_UC_HOOK", event.name());
+        process.env("MUS_UC_SHEET_ID", context.id.as_deref().unwrap_or(""));
+        process.env(
+            "MUS_UC_FILE",
+            context.file.as_deref().unwrap_or(Path::new("")),
+        );
+        process.env("MUS_UC_ERRORS", context.errors.join("\n"));
+        let status = process
+            .status()
+            .map_err(|e| format!("could not run `{}`: {}", command, e))?;
+        if !status.success() {
+            return Err(format!("`{}` exited with {}", command, status).into());
+        }
+        Ok(())
+    }
+
+    fn run_script(
+        &self,
+        script: &Path,
+        event: HookEvent,
+        context: &HookContext,
+        connection: &SharedConnection,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let path = self.dir.join(script);
+        let js = std::fs::read_to_string(&path)
+            .map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+        let details = json!({
+            "event": event.name(),
+            "id": context.id,
+            "file": context.file,
+            "errors": context.errors,
+        });
+        connection
+            .execute_script(&js, Some(vec![details]))
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(())
+    }
+}
+
+#[cfg(windows)]
+fn shell(command: &str) -> Command {
+    let mut process = Command::new("cmd");
+    process.arg("/C").arg(command);
+    process
+}
+
+#[cfg(not(windows))]
+fn shell(command: &str) -> Command {
+    let mut process = Command::new("sh");
+    process.arg("-c").arg(command);
+    process
+}
@@ -0,0 +1,223 @@
+//! Checks for the SVG icons of a theme, for `icons`.
+//!
+//! Toolbar icons follow the theme's colors through `context-fill`: the
+//! icon's shapes are filled with `context-fill`, and the rule showing it sets
+//! `-moz-context-properties: fill` and a `fill` color. Icons that hard-code
+//! their color, lack a `viewBox` and so do not scale to the toolbar, or are
+//! loaded from `file://` without `svg.context-properties.content.enabled`
+//! all end up drawn in the wrong color or size.
+//!
+//! Only the attribute and property syntax icons actually use is understood;
+//! this is a linter, not an SVG parser.
+
+use crate::css_imports::ImportResolver;
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+/// The pref that lets icons outside `chrome://` and `resource://` use
+/// context properties.
+pub const CONTEXT_PROPERTIES_PREF: &str = "svg.context-properties.content.enabled";
+
+/// Fill values that are not a color of their own.
+const NOT_COLORS: &[&str] = &["none", "context-fill", "context-stroke", "transparent"];
+
+/// What `icons` found in one SVG.
+#[derive(Debug, Clone)]
+pub struct IconReport {
+    pub path: PathBuf,
+    pub issues: Vec<String>,
+    /// Things that may well be intended, like a multicolored icon.
+    pub notes: Vec<String>,
+    /// Whether the icon uses `context-fill` or `context-stroke`.
+    pub uses_context: bool,
+    /// The fixed SVG, when fills could be rewritten to `context-fill`.
+    pub fixed: Option<String>,
+}
+
+/// The icons to check for `paths`: SVG files as given, every SVG under a
+/// directory, and the SVGs a stylesheet and its imports refer to. Also
+/// returns whether any of the stylesheets sets `-moz-context-properties`,
+/// or `None` if no stylesheet was given.
+pub fn collect_icons(
+    paths: &[PathBuf],
+) -> Result<(Vec<PathBuf>, Option<bool>), Box<dyn std::error::Error>> {
+    let mut icons = Vec::new();
+    let mut sets_properties = None;
+    for path in paths {
+        if path.is_dir() {
+            find_svgs(path, &mut icons)?;
+        } else if is_svg(path) {
+            icons.push(path.clone());
+        } else {
+            let resolved = ImportResolver::new().resolve(path)?;
+            icons.extend(resolved.assets.into_iter().filter(|asset| is_svg(asset)));
+            let sets = resolved.css.contains("-moz-context-properties");
+            sets_properties = Some(sets_properties.unwrap_or(false) || sets);
+        }
+    }
+    let mut unique = Vec::new();
+    for icon in icons {
+        if !unique.contains(&icon) {
+            unique.push(icon);
+        }
+    }
+    Ok((unique, sets_properties))
+}
+
+fn is_svg(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("svg"))
+}
+
+fn find_svgs(dir: &Path, found: &mut Vec<PathBuf>) -> Result<(), Box<dyn std::error::Error>> {
+    let mut entries: Vec<PathBuf> = fs::read_dir(dir)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<Result<_, _>>()?;
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            find_svgs(&path, found)?;
+        } else if is_svg(&path) {
+            found.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Check the icon at `path`.
+pub fn check_icon(path: &Path) -> Result<IconReport, Box<dyn std::error::Error>> {
+    let svg = fs::read_to_string(path)?;
+    let mut issues = Vec::new();
+    let mut notes = Vec::new();
+    let Some(root) = root_tag(&svg) else {
+        issues.push("no <svg> element".to_string());
+        return Ok(IconReport {
+            path: path.to_path_buf(),
+            issues,
+            notes: Vec::new(),
+            uses_context: false,
+            fixed: None,
+        });
+    };
+
+    if !svg[root.clone()].contains("viewBox") {
+        issues.push(
+            "no viewBox, so the icon is drawn at its own size instead of scaling to the toolbar"
+                .to_string(),
+        );
+    }
+
+    let uses_context = svg.contains("context-fill") || svg.contains("context-stroke");
+    let fills = fills(&svg);
+    let mut colors: Vec<String> = fills
+        .iter()
+        .map(|range| svg[range.clone()].to_ascii_lowercase())
+        .filter(|value| !NOT_COLORS.contains(&value.as_str()) && !value.starts_with("url("))
+        .collect();
+    colors.sort();
+    colors.dedup();
+
+    let fixed = match colors.len() {
+        0 if uses_context || !fills.is_empty() => None,
+        0 => {
+            issues.push(
+                "no fill, so it is drawn black; fill it with context-fill to follow the theme"
+                    .to_string(),
+            );
+            Some(format!(
+                "{} fill=\"context-fill\"{}",
+                &svg[..root.start + 4],
+                &svg[root.start + 4..]
+            ))
+        }
+        1 => {
+            issues.push(format!(
+                "filled with {}; use context-fill to follow the theme",
+                colors[0]
+            ));
+            let mut fixed = svg.clone();
+            for range in fills.iter().rev() {
+                if colors.contains(&svg[range.clone()].to_ascii_lowercase()) {
+                    fixed.replace_range(range.clone(), "context-fill");
+                }
+            }
+            Some(fixed)
+        }
+        n if !uses_context => {
+            notes.push(format!(
+                "uses {} colors ({}), so it does not follow the theme and --fix leaves it alone",
+                n,
+                colors.join(", ")
+            ));
+            None
+        }
+        _ => None,
+    };
+
+    Ok(IconReport {
+        path: path.to_path_buf(),
+        issues,
+        notes,
+        uses_context,
+        fixed,
+    })
+}
+
+/// The byte range of the `<svg ...>` start tag.
+fn root_tag(svg: &str) -> Option<Range<usize>> {
+    let start = svg.find("<svg")?;
+    let end = svg[start..].find('>')? + start + 1;
+    Some(start..end)
+}
+
+/// The byte ranges of every `fill` value, as an attribute (`fill="red"`) or
+/// a property (`style="fill: red"`, or in a `<style>` element).
+fn fills(svg: &str) -> Vec<Range<usize>> {
+    let bytes = svg.as_bytes();
+    let mut ranges = Vec::new();
+    for (at, _) in svg.match_indices("fill") {
+        let before = at.checked_sub(1).map(|i| bytes[i]);
+        if !matches!(
+            before,
+            Some(b' ' | b'\t' | b'\n' | b'\r' | b';' | b'{' | b'"' | b'\'')
+        ) {
+            continue;
+        }
+        let mut i = at + 4;
+        while bytes.get(i).is_some_and(u8::is_ascii_whitespace) {
+            i += 1;
+        }
+        let range = match bytes.get(i) {
+            Some(b'=') => {
+                i += 1;
+                while bytes.get(i).is_some_and(u8::is_ascii_whitespace) {
+                    i += 1;
+                }
+                let Some(&quote @ (b'"' | b'\'')) = bytes.get(i) else {
+                    continue;
+                };
+                let Some(len) = svg[i + 1..].find(quote as char) else {
+                    continue;
+                };
+                i + 1..i + 1 + len
+            }
+            Some(b':') => {
+                let start = i + 1;
+                let len = svg[start..]
+                    .find([';', '"', '\'', '}', '<'])
+                    .unwrap_or(svg.len() - start);
+                start..start + len
+            }
+            _ => continue,
+        };
+        // Trim the whitespace around the value
+        let value = &svg[range.clone()];
+        let start = range.start + (value.len() - value.trim_start().len());
+        let end = start + value.trim().len();
+        if start < end {
+            ranges.push(start..end);
+        }
+    }
+    ranges
+}
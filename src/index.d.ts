@@ -20,6 +20,7 @@ export interface ClientInstance {
         unload: (id: string) => ResultBool;
         clearAll: () => ResultString;
         list: () => ResultList;
+        setQueue: (minIntervalMs: number, maxPending?: number) => ResultString;
     };
     screen: {
         capture: (selector?: string) => ResultBytes;
@@ -55,7 +55,16 @@ export const client = {
                          * List all loaded CSS sheet IDs
                          * @returns {string[]} List of IDs
                          */
-                        list: () => conn.cssList()
+                        list: () => conn.cssList(),
+
+                        /**
+                         * Space out CSS operations, and refuse new ones with a
+                         * "Busy" error while maxPending are waiting
+                         * @param {number} minIntervalMs - Least time between operations
+                         * @param {number} [maxPending] - Waiting operations to allow
+                         * @returns {object} Result object {tag: 'ok'|'err', val: string}
+                         */
+                        setQueue: (minIntervalMs, maxPending) => conn.cssSetQueue(minIntervalMs, maxPending)
                     },
                     screen: {
                         /**
@@ -0,0 +1,234 @@
+//! Finding the browser profiles on this machine, and every browser that
+//! Marionette can reach, for `--all-profiles` and `import-profile`.
+//!
+//! Each profile of Firefox and the known forks is looked up in its
+//! `profiles.ini`. A running browser with Marionette enabled writes the port
+//! it listens on to `MarionetteActivePort` in its profile; otherwise the port
+//! is taken from `marionette.port` in `user.js` or `prefs.js`. Those ports,
+//! the default one and any given by hand are then probed, and the ones that
+//! accept a connection are the instances.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const PROBE_TIMEOUT: Duration = Duration::from_millis(300);
+
+/// A browser listening for Marionette.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instance {
+    pub host: String,
+    pub port: u16,
+    /// The profile the browser runs with, when it can be told.
+    pub profile: Option<PathBuf>,
+}
+
+impl fmt::Display for Instance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.host, self.port)?;
+        if let Some(profile) = &self.profile {
+            let name = profile.file_name().unwrap_or(profile.as_os_str());
+            write!(f, " ({})", name.to_string_lossy())?;
+        }
+        Ok(())
+    }
+}
+
+/// The instances on `host` among the ports the profiles use, plus `ports`.
+pub fn discover(host: &str, ports: &[u16]) -> Vec<Instance> {
+    // Port -> profile; a profile with an active port takes precedence
+    let mut candidates: BTreeMap<u16, Option<PathBuf>> =
+        ports.iter().map(|&port| (port, None)).collect();
+    let mut active = Vec::new();
+    for profile in profile_dirs() {
+        if let Some(port) = active_port(&profile) {
+            active.push(port);
+            candidates.insert(port, Some(profile));
+        } else if let Some(port) = configured_port(&profile) {
+            if !active.contains(&port) {
+                // Several profiles may share a port; only one can be running
+                let entry = candidates.entry(port).or_insert(Some(profile.clone()));
+                if entry.as_ref() != Some(&profile) {
+                    *entry = None;
+                }
+            }
+        }
+    }
+
+    candidates
+        .into_iter()
+        .filter(|(port, _)| accepts(host, *port))
+        .map(|(port, profile)| Instance {
+            host: host.to_string(),
+            port,
+            profile,
+        })
+        .collect()
+}
+
+/// Whether something accepts connections on `port` of `host`.
+pub(crate) fn accepts(host: &str, port: u16) -> bool {
+    let Ok(addrs) = (host, port).to_socket_addrs() else {
+        return false;
+    };
+    addrs
+        .into_iter()
+        .any(|addr| TcpStream::connect_timeout(&addr, PROBE_TIMEOUT).is_ok())
+}
+
+/// Profile directories listed in the `profiles.ini` of Firefox and the forks.
+pub fn profile_dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    for (_, dir) in profiles() {
+        if !dirs.contains(&dir) {
+            dirs.push(dir);
+        }
+    }
+    dirs
+}
+
+/// The profile called `name` in a `profiles.ini`, or whose directory is
+/// called `name`.
+pub fn find_profile(name: &str) -> Option<PathBuf> {
+    profiles()
+        .into_iter()
+        .find(|(profile, dir)| {
+            profile.as_deref() == Some(name) || dir.file_name().is_some_and(|n| n == name)
+        })
+        .map(|(_, dir)| dir)
+}
+
+/// Name and directory of every profile in the `profiles.ini` files.
+fn profiles() -> Vec<(Option<String>, PathBuf)> {
+    profile_roots()
+        .into_iter()
+        .filter_map(|root| {
+            let ini = fs::read_to_string(root.join("profiles.ini")).ok()?;
+            Some(parse_profiles_ini(&ini, &root))
+        })
+        .flatten()
+        .collect()
+}
+
+/// Where each browser keeps its `profiles.ini` on this platform.
+fn profile_roots() -> Vec<PathBuf> {
+    let env = |name: &str| {
+        std::env::var_os(name)
+            .filter(|v| !v.is_empty())
+            .map(PathBuf::from)
+    };
+    let mut roots = Vec::new();
+    if let Some(appdata) = env("APPDATA") {
+        for dir in ["Mozilla/Firefox", "librewolf", "Waterfox", "Floorp", "zen"] {
+            roots.push(appdata.join(dir));
+        }
+    }
+    if let Some(home) = env("HOME") {
+        for dir in [
+            ".mozilla/firefox",
+            ".librewolf",
+            ".waterfox",
+            ".floorp",
+            ".zen",
+            // Flatpak and Snap builds
+            ".var/app/org.mozilla.firefox/.mozilla/firefox",
+            "snap/firefox/common/.mozilla/firefox",
+            "Library/Application Support/Firefox",
+            "Library/Application Support/librewolf",
+            "Library/Application Support/Waterfox",
+            "Library/Application Support/Floorp",
+            "Library/Application Support/zen",
+        ] {
+            roots.push(home.join(dir));
+        }
+    }
+    roots
+}
+
+/// The `Name` and `Path` of each `[Profile…]` section, the path relative to
+/// `root` unless `IsRelative=0`.
+fn parse_profiles_ini(ini: &str, root: &Path) -> Vec<(Option<String>, PathBuf)> {
+    let mut profiles = Vec::new();
+    let mut in_profile = false;
+    let mut name = None;
+    let mut path = None;
+    let mut relative = true;
+    let mut finish = |name: &mut Option<String>, path: &mut Option<String>, relative: bool| {
+        let name = name.take();
+        if let Some(path) = path.take() {
+            let dir = if relative {
+                root.join(path)
+            } else {
+                PathBuf::from(path)
+            };
+            profiles.push((name, dir));
+        }
+    };
+    for line in ini.lines().map(str::trim) {
+        if line.starts_with('[') {
+            if in_profile {
+                finish(&mut name, &mut path, relative);
+            }
+            in_profile = line.starts_with("[Profile");
+            relative = true;
+        } else if in_profile {
+            match line.split_once('=') {
+                Some(("Name", value)) => name = Some(value.to_string()),
+                Some(("Path", value)) => path = Some(value.to_string()),
+                Some(("IsRelative", value)) => relative = value != "0",
+                _ => {}
+            }
+        }
+    }
+    if in_profile {
+        finish(&mut name, &mut path, relative);
+    }
+    profiles
+}
+
+/// The port a running browser wrote to its profile.
+fn active_port(profile: &Path) -> Option<u16> {
+    fs::read_to_string(profile.join("MarionetteActivePort"))
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// `marionette.port` from `user.js`, which wins over `prefs.js`.
+fn configured_port(profile: &Path) -> Option<u16> {
+    ["user.js", "prefs.js"].iter().find_map(|name| {
+        let prefs = fs::read_to_string(profile.join(name)).ok()?;
+        prefs.lines().rev().find_map(|line| {
+            let rest = line.trim().strip_prefix("user_pref(\"marionette.port\",")?;
+            rest.trim_end_matches(';')
+                .trim_end_matches(')')
+                .trim()
+                .parse()
+                .ok()
+        })
+    })
+}
+
+/// Ports given as a comma-separated list, where each item is a port or a
+/// range like `2828-2832`.
+pub fn parse_ports(spec: &str) -> Result<Vec<u16>, Box<dyn std::error::Error>> {
+    let mut ports = Vec::new();
+    for item in spec.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        let invalid = || format!("Invalid port or port range: {}", item);
+        let (start, end) = match item.split_once('-') {
+            Some((start, end)) => (start.trim(), end.trim()),
+            None => (item, item),
+        };
+        let start: u16 = start.parse().map_err(|_| invalid())?;
+        let end: u16 = end.parse().map_err(|_| invalid())?;
+        if start > end {
+            return Err(invalid().into());
+        }
+        ports.extend(start..=end);
+    }
+    Ok(ports)
+}
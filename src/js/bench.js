@@ -0,0 +1,59 @@
+// Times common window operations, split into style recalculation, layout
+// and painting, each forced synchronously after the operation.
+// Rendered with: windowType, iterations
+
+const iterations = {{iterations}};
+const window = Services.wm.getMostRecentWindow({{windowType}});
+if (!window) throw new Error("No browser window found");
+const { document, gBrowser } = window;
+const root = document.documentElement;
+const utils = window.windowUtils;
+const sidebar = window.SidebarController ?? window.SidebarUI;
+
+// Leave nothing pending from before the first measurement
+root.getBoundingClientRect();
+utils.updateLayerTree?.();
+
+const measure = (operation) => {
+    operation();
+    const start = window.performance.now();
+    // Any computed value flushes style for the whole document
+    window.getComputedStyle(root).color;
+    const styled = window.performance.now();
+    root.getBoundingClientRect();
+    const laidOut = window.performance.now();
+    utils.updateLayerTree?.();
+    const painted = window.performance.now();
+    return [styled - start, laidOut - styled, painted - laidOut];
+};
+
+const operations = {
+    // Every selector is matched again against the whole window
+    "full-restyle": [() => root.toggleAttribute("mus-uc-bench")],
+    "open-close-tab": [
+        () => {
+            gBrowser.selectedTab = gBrowser.addTrustedTab("about:blank");
+        },
+        () => gBrowser.removeTab(gBrowser.selectedTab, { animate: false }),
+    ],
+    "toggle-sidebar": [
+        () => sidebar.toggle("viewBookmarksSidebar"),
+        () => sidebar.toggle("viewBookmarksSidebar"),
+    ],
+};
+
+// An array, to keep the operations in order
+const results = [];
+for (const [name, steps] of Object.entries(operations)) {
+    const timings = [];
+    for (let i = 0; i < iterations; i++) {
+        const total = [0, 0, 0];
+        for (const step of steps) {
+            measure(step).forEach((ms, phase) => (total[phase] += ms));
+        }
+        timings.push(total);
+    }
+    results.push([name, timings]);
+}
+root.removeAttribute("mus-uc-bench");
+return results;
@@ -0,0 +1,24 @@
+// Describes the running browser for MarionetteConnection::browser_info.
+
+// --headless is passed on to the rest of Gecko through this variable
+const env = Cc["@mozilla.org/process/environment;1"].getService(Ci.nsIEnvironment);
+// Either may be unavailable on unusual builds
+const optional = read => {
+    try {
+        return read();
+    } catch (e) {
+        return "";
+    }
+};
+return {
+    name: Services.appinfo.name,
+    app_id: Services.appinfo.ID,
+    version: Services.appinfo.version,
+    platform_version: Services.appinfo.platformVersion,
+    build_id: Services.appinfo.appBuildID,
+    channel: Services.prefs.getDefaultBranch("").getCharPref("app.update.channel", "default"),
+    platform: Services.appinfo.OS,
+    os_version: optional(() => Services.sysinfo.getProperty("version")),
+    abi: optional(() => Services.appinfo.XPCOMABI),
+    headless: env.get("MOZ_HEADLESS") === "1",
+};
@@ -0,0 +1,45 @@
+// Reads the values the placeholders of a sheet loaded with --browser-vars
+// can stand for, each as a string.
+// Rendered with: windowType
+
+const window = Services.wm.getMostRecentWindow({{windowType}});
+if (!window) throw new Error("No browser window found");
+const root = window.document.documentElement;
+const style = window.getComputedStyle(root);
+const probe = window.document.createElementNS("http://www.w3.org/1999/xhtml", "span");
+root.appendChild(probe);
+// A custom property or system color as the color it computes to, or "" if
+// it is not set
+const color = (value) => {
+    probe.style.color = "";
+    probe.style.color = value;
+    return probe.style.color ? window.getComputedStyle(probe).color : "";
+};
+const property = (name) => (style.getPropertyValue(name).trim() ? color(`var(${name})`) : "");
+
+try {
+    const version = Services.appinfo.version;
+    const os = { Linux: "linux", Darwin: "macos", WINNT: "windows" }[Services.appinfo.OS] || Services.appinfo.OS.toLowerCase();
+    const density = ["normal", "compact", "touch"][Services.prefs.getIntPref("browser.uidensity", 0)] || "normal";
+    return {
+        "firefox.version": version,
+        "firefox.major": version.split(".")[0],
+        "firefox.channel": Services.prefs.getDefaultBranch("").getCharPref("app.update.channel", "default"),
+        "app.name": Services.appinfo.name,
+        "os": os,
+        "theme.id": Services.prefs.getStringPref("extensions.activeThemeID", ""),
+        "theme.dark": String(window.matchMedia("(prefers-color-scheme: dark)").matches),
+        "theme.accentcolor": property("--lwt-accent-color"),
+        "theme.textcolor": property("--lwt-text-color"),
+        "theme.toolbar": property("--toolbar-bgcolor"),
+        "theme.toolbar-text": property("--toolbar-color"),
+        "system.accent": color("AccentColor"),
+        "system.accent-text": color("AccentColorText"),
+        "locale": Services.locale.appLocaleAsBCP47,
+        "locale.dir": Services.locale.isAppLocaleRTL ? "rtl" : "ltr",
+        "density": density,
+        "dpr": String(window.devicePixelRatio),
+    };
+} finally {
+    probe.remove();
+}
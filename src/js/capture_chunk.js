@@ -0,0 +1,12 @@
+// Returns a slice of a capture screenshot.js kept in a window, and forgets
+// the capture once its last slice is read.
+// Arguments: key, offset, size
+
+const [key, offset, size] = arguments;
+for (const window of Services.wm.getEnumerator(null)) {
+    const url = window.musUcCaptures?.get(key);
+    if (url === undefined) continue;
+    if (offset + size >= url.length) window.musUcCaptures.delete(key);
+    return url.slice(offset, offset + size);
+}
+throw new Error(`Capture ${key} is gone (was the window closed?)`);
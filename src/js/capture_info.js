@@ -0,0 +1,26 @@
+// Describes what a screenshot depends on, for the sidecar written next to it.
+// Rendered with: windowType
+
+const window = Services.wm.getMostRecentWindow({{windowType}});
+if (!window) throw new Error("No browser window found");
+const densities = ["normal", "compact", "touch"];
+// Media features themes commonly respond to, as the window matches them now
+const media = [
+    "prefers-color-scheme: dark",
+    "prefers-reduced-motion: reduce",
+    "prefers-reduced-transparency: reduce",
+    "prefers-contrast: more",
+    "prefers-contrast: less",
+    "forced-colors: active",
+].filter((query) => window.matchMedia(`(${query})`).matches);
+const pseudo = Services.prefs.getStringPref("intl.l10n.pseudo", "");
+const locale = Services.locale.appLocaleAsBCP47 + (pseudo ? ` (${pseudo})` : "");
+return {
+    width: window.outerWidth,
+    height: window.outerHeight,
+    pixel_ratio: window.devicePixelRatio,
+    density: densities[Services.prefs.getIntPref("browser.uidensity", 0)] ?? "normal",
+    theme: Services.prefs.getStringPref("extensions.activeThemeID", ""),
+    media,
+    locale,
+};
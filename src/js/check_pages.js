@@ -0,0 +1,26 @@
+// Checks whether a registered sheet applies to the open tabs showing some
+// in-content pages. Pages in the parent process, like about:preferences, are
+// looked at directly; pages in a content process cannot be from here.
+// Rendered with: windowType. Arguments: sheet ID, page URL prefixes
+
+const [id, pages] = arguments;
+const uri = window.chromeCssManager.sheets.get(id);
+if (!uri) throw new Error(`No stylesheet loaded with ID: ${id}`);
+
+return pages.map((page) => {
+    let status = "not-open";
+    for (const win of Services.wm.getEnumerator({{windowType}})) {
+        for (const browser of win.gBrowser?.browsers ?? []) {
+            if (!browser.currentURI?.spec.startsWith(page)) continue;
+            if (browser.isRemoteBrowser) {
+                if (status === "not-open") status = "content-process";
+                continue;
+            }
+            const applied = win.InspectorUtils.getAllStyleSheets(browser.contentDocument, false)
+                .some((sheet) => sheet.href === uri.spec);
+            if (applied) return { page, status: "applied" };
+            status = "not-applied";
+        }
+    }
+    return { page, status };
+});
@@ -0,0 +1,20 @@
+// Sets the prefs of the CI rendering profile and closes notifications that
+// are already open, so they do not end up in screenshots.
+// Rendered with: prefs, windowType
+
+const prefs = {{prefs}};
+for (const [name, value] of Object.entries(prefs)) {
+    if (typeof value === "boolean") {
+        Services.prefs.setBoolPref(name, value);
+    } else if (typeof value === "number") {
+        Services.prefs.setIntPref(name, value);
+    } else {
+        Services.prefs.setStringPref(name, value);
+    }
+}
+
+for (const window of Services.wm.getEnumerator({{windowType}})) {
+    window.PopupNotifications?.panel?.hidePopup();
+    window.gNotificationBox?.removeAllNotifications(true);
+    window.gBrowser?.getNotificationBox?.()?.removeAllNotifications(true);
+}
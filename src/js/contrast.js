@@ -0,0 +1,87 @@
+// Reads the text and background color of browser UI elements, for a11y-contrast.
+// Rendered with: windowType, checks ([{ label, text, background }], where text and
+// background are { selector } or { variable })
+
+const checks = {{checks}};
+const window = Services.wm.getMostRecentWindow({{windowType}});
+if (!window) throw new Error("No browser window found");
+const root = window.document.documentElement;
+const probe = window.document.createElementNS("http://www.w3.org/1999/xhtml", "span");
+root.appendChild(probe);
+
+// Computed colors come back as rgb(), rgba() or color(srgb ...); [r, g, b, alpha]
+const parse = (value) => {
+    if (value === "transparent") return [0, 0, 0, 0];
+    const match = /^(rgba?|color)\((.*)\)$/.exec(value.trim());
+    if (!match) return null;
+    let body = match[2];
+    if (match[1] === "color") {
+        if (!body.startsWith("srgb ")) return null;
+        body = body.slice(5);
+    }
+    const parts = body.split(/[\s,/]+/).filter(Boolean).map(parseFloat);
+    if (parts.length < 3 || parts.some(Number.isNaN)) return null;
+    const scale = match[1] === "color" ? 255 : 1;
+    return [parts[0] * scale, parts[1] * scale, parts[2] * scale, parts[3] ?? 1];
+};
+// `top` drawn over `bottom`
+const over = (top, bottom) => {
+    const alpha = top[3] + bottom[3] * (1 - top[3]);
+    if (alpha === 0) return [0, 0, 0, 0];
+    const mix = (i) => (top[i] * top[3] + bottom[i] * bottom[3] * (1 - top[3])) / alpha;
+    return [mix(0), mix(1), mix(2), alpha];
+};
+const parentOf = (element) =>
+    element.parentElement ?? (element.getRootNode() instanceof window.ShadowRoot ? element.getRootNode().host : null);
+
+const variable = (name) => {
+    if (!window.getComputedStyle(root).getPropertyValue(name).trim()) {
+        return { note: `${name} is not set` };
+    }
+    probe.style.color = "";
+    probe.style.color = `var(${name})`;
+    const color = parse(window.getComputedStyle(probe).color);
+    return color ? { color } : { note: `${name} is not a color` };
+};
+const text = (source) => {
+    if (source.variable) return variable(source.variable);
+    const element = window.document.querySelector(source.selector);
+    if (!element) return { note: `no element matches ${source.selector}` };
+    const color = parse(window.getComputedStyle(element).color);
+    return color ? { color } : { note: `${source.selector} has an unsupported color` };
+};
+// The backgrounds of the element and its ancestors, layered until one is opaque
+const background = (source) => {
+    if (source.variable) return variable(source.variable);
+    let element = window.document.querySelector(source.selector);
+    if (!element) return { note: `no element matches ${source.selector}` };
+    const layers = [];
+    let note = null;
+    for (; element; element = parentOf(element)) {
+        const style = window.getComputedStyle(element);
+        if (!note && style.backgroundImage !== "none") {
+            note = `the background of ${element.id ? "#" + element.id : element.localName} includes an image, which is not counted`;
+        }
+        const color = parse(style.backgroundColor);
+        if (!color || color[3] === 0) continue;
+        layers.push(color);
+        if (color[3] >= 1) break;
+    }
+    const color = layers.reduceRight((below, layer) => over(layer, below), [255, 255, 255, 1]);
+    return { color, note };
+};
+
+try {
+    return checks.map((check) => {
+        const fg = text(check.text);
+        const bg = background(check.background);
+        return {
+            label: check.label,
+            text: fg.color ?? null,
+            background: bg.color ?? null,
+            note: fg.note ?? bg.note ?? null,
+        };
+    });
+} finally {
+    probe.remove();
+}
@@ -0,0 +1,33 @@
+// Switches the toolbar density, or reads it, and waits until every browser
+// window has restyled for it.
+// Rendered with: windowType, density (a browser.uidensity value, or null to
+// only read it)
+
+const NAMES = ["normal", "compact", "touch"];
+const TIMEOUT_MS = 5000;
+const density = {{density}};
+if (density !== null) {
+    Services.prefs.setIntPref("browser.uidensity", density);
+}
+const name = NAMES[Services.prefs.getIntPref("browser.uidensity", 0)] ?? "normal";
+// Windows mark their root with the density, except for normal
+const attribute = name === "normal" ? null : name;
+const windows = [...Services.wm.getEnumerator({{windowType}})];
+const switched = () => windows.every((w) => w.document.documentElement.getAttribute("uidensity") === attribute);
+
+return new Promise((resolve, reject) => {
+    const started = Date.now();
+    const check = () => {
+        if (windows.length === 0) {
+            resolve(name);
+        } else if (switched()) {
+            // Two frames, so layout has caught up with the attribute
+            windows[0].requestAnimationFrame(() => windows[0].requestAnimationFrame(() => resolve(name)));
+        } else if (Date.now() - started > TIMEOUT_MS) {
+            reject(new Error(`The browser UI did not switch to ${name} density`));
+        } else {
+            windows[0].setTimeout(check, 50);
+        }
+    };
+    check();
+});
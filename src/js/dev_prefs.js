@@ -0,0 +1,10 @@
+// Reads the boolean prefs userChrome development relies on and, when asked,
+// turns on the ones that are off.
+// Rendered with: names, apply
+
+const apply = {{apply}};
+return {{names}}.map(name => {
+    const was = Services.prefs.getBoolPref(name, false);
+    if (apply && !was) Services.prefs.setBoolPref(name, true);
+    return { name, was };
+});
@@ -0,0 +1,29 @@
+// Locks pseudo-classes such as :hover on an element in the browser window,
+// like the :hov panel of the Inspector, or releases every lock set this way.
+// Rendered with: windowType, selector (null to release), states
+
+const selector = {{selector}};
+const states = {{states}};
+
+if (selector === null) {
+    let released = 0;
+    for (const win of Services.wm.getEnumerator({{windowType}})) {
+        for (const element of win.musUcLocked ?? []) {
+            win.InspectorUtils.clearPseudoClassLocks(element);
+            released++;
+        }
+        delete win.musUcLocked;
+    }
+    return released;
+}
+
+const window = Services.wm.getMostRecentWindow({{windowType}});
+if (!window) throw new Error("No browser window found");
+const element = window.document.querySelector(selector);
+if (!element) throw new Error(`No element matches ${selector}`);
+for (const state of states) {
+    window.InspectorUtils.addPseudoClassLock(element, state);
+}
+// Remembered so a later call can release them
+(window.musUcLocked ??= new Set()).add(element);
+return element.localName + (element.id ? `#${element.id}` : "");
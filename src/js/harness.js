@@ -0,0 +1,263 @@
+// Installs window.chromeCssManager, the chrome-side half of the CSS manager.
+// Rendered with: version, auditLimit
+
+const version = {{version}};
+const existing = window.chromeCssManager;
+if (existing && existing.version === version) return "current";
+
+const harness = {
+    version,
+    sheets: new Map(),
+    sources: new Map(),
+    types: new Map(),
+    // chrome.manifest paths registered through the tool, which
+    // nsIComponentRegistrar cannot list
+    manifests: new Set(),
+    // resource:// aliases registered through the tool, to the directory
+    // each maps to
+    resources: new Map(),
+    // The last loads, unloads and clears, oldest first, so one process can
+    // see what another did; `caller` is set by each script that calls in
+    audit: [],
+    caller: null,
+    sss: Cc["@mozilla.org/content/style-sheet-service;1"]
+         .getService(Ci.nsIStyleSheetService),
+
+    sheetType(name) {
+        const types = { user: this.sss.USER_SHEET, agent: this.sss.AGENT_SHEET, author: this.sss.AUTHOR_SHEET };
+        return types[name || "user"] ?? this.sss.USER_SHEET;
+    },
+
+    record(op, id, count) {
+        if (this.audit.length >= {{auditLimit}}) this.audit.shift();
+        this.audit.push({ time: Date.now(), op, id, count, caller: this.caller });
+    },
+
+    sheetTypeName(kind) {
+        return kind === this.sss.AGENT_SHEET ? "agent"
+            : kind === this.sss.AUTHOR_SHEET ? "author" : "user";
+    },
+
+    load(css, id, type) {
+        const started = Cu.now();
+        const sheetId = id || `sheet-${Date.now()}`;
+        // The marker lets panic-reset find the sheet without the harness
+        const uri = Services.io.newURI(
+            `data:text/css;charset=utf-8;mus-uc-devtools=${encodeURIComponent(sheetId)},${encodeURIComponent(css)}`
+        );
+        const kind = this.sheetType(type);
+        // Loading under a taken ID replaces that sheet. The new one is
+        // registered first, so the UI is never left unstyled.
+        const previous = this.sheets.get(sheetId);
+        const previousKind = this.types.get(sheetId) ?? this.sss.USER_SHEET;
+        const registered = previous && this.sss.sheetRegistered(previous, previousKind);
+        if (registered && previous.spec === uri.spec && previousKind === kind) return sheetId;
+
+        this.sss.loadAndRegisterSheet(uri, kind);
+        if (registered) {
+            this.sss.unregisterSheet(previous, previousKind);
+        }
+        // Keep the map in registration order, which is the cascade order
+        // between sheets of one type
+        this.sheets.delete(sheetId);
+        this.sheets.set(sheetId, uri);
+        this.types.set(sheetId, kind);
+        if (this.profiling) {
+            ChromeUtils.addProfilerMarker("mus-uc load", started, sheetId);
+        }
+        this.record("load", sheetId, null);
+        return sheetId;
+    },
+
+    unload(id) {
+        if (!this.remove(id)) return false;
+        this.record("unload", id, null);
+        return true;
+    },
+
+    remove(id) {
+        const uri = this.sheets.get(id);
+        if (!uri) return false;
+
+        const kind = this.types.get(id) ?? this.sss.USER_SHEET;
+        if (this.sss.sheetRegistered(uri, kind)) {
+            this.sss.unregisterSheet(uri, kind);
+        }
+        this.sheets.delete(id);
+        this.sources.delete(id);
+        this.types.delete(id);
+        return true;
+    },
+
+    loadMany(entries) {
+        return entries.map(({ css, id, source, type }) => {
+            try {
+                const sheetId = this.load(css, id, type);
+                if (source) this.sources.set(sheetId, source);
+                return { id: sheetId, error: null };
+            } catch (e) {
+                return { id, error: e.toString() };
+            }
+        });
+    },
+
+    list() {
+        return [...this.sheets.keys()];
+    },
+
+    get(id) {
+        const uri = this.sheets.get(id);
+        if (!uri) return null;
+        return {
+            css: decodeURIComponent(uri.spec.slice(uri.spec.indexOf(",") + 1)),
+            source: this.sources.get(id) || null,
+            type: this.sheetTypeName(this.types.get(id) ?? this.sss.USER_SHEET),
+        };
+    },
+
+    errors(id) {
+        const uri = this.sheets.get(id);
+        if (!uri) return [];
+        const seen = new Set();
+        return Services.console.getMessageArray()
+            .filter(m => m instanceof Ci.nsIScriptError && m.sourceName === uri.spec)
+            .map(m => ({ line: m.lineNumber, column: m.columnNumber, message: m.errorMessage }))
+            .filter(e => {
+                const key = `${e.line}:${e.column}:${e.message}`;
+                if (seen.has(key)) return false;
+                seen.add(key);
+                return true;
+            });
+    },
+
+    clear() {
+        const count = this.sheets.size;
+        for (const id of this.sheets.keys()) {
+            this.remove(id);
+        }
+        this.record("clear", null, count);
+    },
+
+    resourceHandler() {
+        return Services.io.getProtocolHandler("resource")
+            .QueryInterface(Ci.nsIResProtocolHandler);
+    },
+
+    registerResource(alias, path) {
+        const handler = this.resourceHandler();
+        // Aliases the browser or an add-on set up are not ours to replace
+        if (!this.resources.has(alias) && handler.hasSubstitution(alias)) {
+            throw new Error(`resource://${alias}/ is already registered by the browser`);
+        }
+        const dir = Cc["@mozilla.org/file/local;1"].createInstance(Ci.nsIFile);
+        dir.initWithPath(path);
+        if (!dir.exists() || !dir.isDirectory()) {
+            throw new Error(`No such directory in the browser's file system: ${path}`);
+        }
+        handler.setSubstitution(alias, Services.io.newFileURI(dir));
+        this.resources.set(alias, path);
+        return `resource://${alias}/`;
+    },
+
+    unregisterResource(alias) {
+        if (!this.resources.has(alias)) return false;
+        this.resourceHandler().setSubstitution(alias, null);
+        this.resources.delete(alias);
+        return true;
+    },
+
+    consoleRecords: [],
+    consoleListener: null,
+
+    startConsole() {
+        if (this.consoleListener) return;
+        this.consoleListener = {
+            QueryInterface: ChromeUtils.generateQI(["nsIConsoleListener"]),
+            observe: (m) => {
+                // Keep memory bounded if nobody drains the buffer
+                if (this.consoleRecords.length >= 10000) this.consoleRecords.shift();
+                this.consoleRecords.push(this.consoleRecord(m));
+            },
+        };
+        Services.console.registerListener(this.consoleListener);
+    },
+
+    consoleRecord(m) {
+        const record = {
+            time: m.timeStamp || Date.now(),
+            level: "info",
+            category: "",
+            message: m.message,
+            source: null,
+            line: null,
+            column: null,
+            sheet: null,
+        };
+        if (m instanceof Ci.nsIScriptError) {
+            record.message = m.errorMessage;
+            record.category = m.category || "";
+            record.line = m.lineNumber || null;
+            record.column = m.columnNumber || null;
+            record.level = (m.flags & Ci.nsIScriptError.warningFlag) ? "warning"
+                : (m.flags & Ci.nsIScriptError.infoFlag) ? "info" : "error";
+            for (const [id, uri] of this.sheets) {
+                if (uri.spec === m.sourceName) record.sheet = id;
+            }
+            // Sheets are data: URIs holding the whole stylesheet
+            record.source = record.sheet
+                ? (this.sources.get(record.sheet) || null)
+                : (m.sourceName || null);
+        }
+        return record;
+    },
+
+    drainConsole() {
+        const records = this.consoleRecords;
+        this.consoleRecords = [];
+        return records;
+    },
+
+    // Whether the Gecko profiler was started through the harness; loads are
+    // marked in the profile while it runs
+    profiling: false,
+
+    startProfiler() {
+        if (!Services.profiler) throw new Error("This build has no Gecko profiler");
+        Services.profiler.StartProfiler(
+            8 * 1024 * 1024,
+            1,
+            ["js", "stackwalk", "cpu"],
+            ["GeckoMain", "StyleThread", "Compositor", "Renderer"],
+        );
+        this.profiling = true;
+    },
+
+    // The profile of the parent process as JSON, or null if the harness did
+    // not start the profiler
+    stopProfiler() {
+        if (!this.profiling) return null;
+        this.profiling = false;
+        const profile = Services.profiler.GetProfile();
+        Services.profiler.StopProfiler();
+        return profile;
+    }
+};
+
+// A harness left by another version of the tool hands over its sheets
+// and console capture, so mixing versions never loses loaded sheets.
+if (existing) {
+    for (const name of ["sheets", "sources", "types"]) {
+        for (const [id, value] of existing[name] || []) harness[name].set(id, value);
+    }
+    for (const path of existing.manifests || []) harness.manifests.add(path);
+    for (const [alias, path] of existing.resources || []) harness.resources.set(alias, path);
+    harness.audit = (existing.audit || []).slice(-{{auditLimit}});
+    harness.profiling = existing.profiling || false;
+    if (existing.consoleListener) {
+        Services.console.unregisterListener(existing.consoleListener);
+        harness.consoleRecords = existing.consoleRecords || [];
+        harness.startConsole();
+    }
+}
+window.chromeCssManager = harness;
+return existing ? "upgraded" : "initialized";
@@ -0,0 +1,6 @@
+// Prepended to scripts that use the harness; reports when it needs installing.
+// Rendered with: status, version, caller
+
+if (!window.chromeCssManager) return { [{{status}}]: "missing" };
+if (window.chromeCssManager.version !== {{version}}) return { [{{status}}]: "outdated" };
+window.chromeCssManager.caller = {{caller}};
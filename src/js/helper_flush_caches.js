@@ -0,0 +1,17 @@
+// Helper flush-caches: drops the caches that keep an edited chrome file or
+// image from showing: the startup and chrome caches, and the image cache.
+// Rendered with: images (false to keep the image cache)
+
+const images = {{images}};
+const flushed = [];
+Services.obs.notifyObservers(null, "startupcache-invalidate");
+Services.obs.notifyObservers(null, "chrome-flush-caches");
+flushed.push("startup", "chrome");
+if (images) {
+    const tools = Cc["@mozilla.org/image/tools;1"].getService(Ci.imgITools);
+    const cache = tools.getImgCacheForDocument(null);
+    cache.clearCache(true);
+    cache.clearCache(false);
+    flushed.push("images");
+}
+return flushed;
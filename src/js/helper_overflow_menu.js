@@ -0,0 +1,16 @@
+// Helper open-overflow-menu: opens the toolbar overflow menu (the ">>" button),
+// or closes it.
+// Rendered with: windowType, close (true to close it instead)
+
+const close = {{close}};
+const window = Services.wm.getMostRecentWindow({{windowType}});
+if (!window) throw new Error("No browser window found");
+const navBar = window.document.getElementById("nav-bar");
+const overflowable = navBar?.overflowable;
+if (!overflowable) throw new Error("The toolbar has no overflow menu");
+
+if (close) {
+    window.document.getElementById("widget-overflow")?.hidePopup();
+    return "closed";
+}
+return Promise.resolve(overflowable.show()).then(() => "open");
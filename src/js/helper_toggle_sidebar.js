@@ -0,0 +1,24 @@
+// Helper toggle-sidebar: opens or closes the sidebar, or switches it to a panel.
+// Rendered with: windowType, panel (a sidebar command ID such as
+// "viewBookmarksSidebar", or null for the last one shown), open (true or false
+// to force a state, null to toggle)
+
+const panel = {{panel}};
+const open = {{open}};
+const window = Services.wm.getMostRecentWindow({{windowType}});
+if (!window) throw new Error("No browser window found");
+// Renamed in Firefox 130
+const sidebar = window.SidebarController ?? window.SidebarUI;
+if (!sidebar) throw new Error("This browser has no sidebar");
+
+const shown = () => sidebar.isOpen && (panel === null || sidebar.currentID === panel);
+const wanted = open ?? !shown();
+if (wanted === shown()) {
+    return { open: sidebar.isOpen, panel: sidebar.isOpen ? sidebar.currentID : null };
+}
+if (!wanted) {
+    sidebar.hide();
+    return { open: false, panel: null };
+}
+return Promise.resolve(sidebar.show(panel ?? sidebar.lastOpenedId ?? sidebar.currentID ?? "viewBookmarksSidebar"))
+    .then(() => ({ open: sidebar.isOpen, panel: sidebar.currentID }));
@@ -0,0 +1,28 @@
+// Helper window-info: describes the browser window: size, position, state,
+// pixel ratio, density, theme and tabs.
+// Rendered with: windowType
+
+const window = Services.wm.getMostRecentWindow({{windowType}});
+if (!window) throw new Error("No browser window found");
+const root = window.document.documentElement;
+const { gBrowser } = window;
+const theme = Services.prefs.getStringPref("extensions.activeThemeID", "default-theme@mozilla.org");
+
+return {
+    title: window.document.title,
+    x: window.screenX,
+    y: window.screenY,
+    width: window.outerWidth,
+    height: window.outerHeight,
+    innerWidth: window.innerWidth,
+    innerHeight: window.innerHeight,
+    sizemode: root.getAttribute("sizemode") ?? "normal",
+    devicePixelRatio: window.devicePixelRatio,
+    density: root.getAttribute("uidensity") ?? "normal",
+    theme,
+    darkTheme: window.matchMedia("(prefers-color-scheme: dark)").matches,
+    private: window.PrivateBrowsingUtils?.isWindowPrivate(window) ?? false,
+    windows: [...Services.wm.getEnumerator({{windowType}})].length,
+    tabs: gBrowser?.tabs.length ?? 0,
+    selectedTab: gBrowser ? { title: gBrowser.selectedTab.label, url: gBrowser.currentURI.spec } : null,
+};
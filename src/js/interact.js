@@ -0,0 +1,24 @@
+// Moves the mouse to the middle of an element in the browser window, and
+// clicks it, with real mouse events so :hover styles and menus react as they
+// would to the user.
+// Rendered with: windowType, selector, action ("hover" or "click")
+
+const selector = {{selector}};
+const action = {{action}};
+const window = Services.wm.getMostRecentWindow({{windowType}});
+if (!window) throw new Error("No browser window found");
+const element = window.document.querySelector(selector);
+if (!element) throw new Error(`No element matches ${selector}`);
+
+const rect = element.getBoundingClientRect();
+if (rect.width === 0 || rect.height === 0) {
+    throw new Error(`${selector} is not visible`);
+}
+const x = rect.left + rect.width / 2;
+const y = rect.top + rect.height / 2;
+const utils = window.windowUtils;
+utils.sendMouseEvent("mousemove", x, y, 0, 0, 0);
+if (action === "click") {
+    utils.sendMouseEvent("mousedown", x, y, 0, 1, 0);
+    utils.sendMouseEvent("mouseup", x, y, 0, 1, 0);
+}
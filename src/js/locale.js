@@ -0,0 +1,45 @@
+// Switches the language of the browser UI and reports the locales in use.
+// Rendered with: locale (a locale, "accented" or "bidi" for a pseudo-locale, or
+// null to change nothing), restore ({ requested, pseudo } pref values to put
+// back, null clearing one, or null to change nothing)
+
+const REQUESTED = "intl.locale.requested";
+const PSEUDO = "intl.l10n.pseudo";
+const locale = {{locale}};
+const restore = {{restore}};
+
+const userValue = (name) => (Services.prefs.prefHasUserValue(name) ? Services.prefs.getStringPref(name) : null);
+const setOrClear = (name, value) => {
+    if (value === null) {
+        Services.prefs.clearUserPref(name);
+    } else {
+        Services.prefs.setStringPref(name, value);
+    }
+};
+const previous = { requested: userValue(REQUESTED), pseudo: userValue(PSEUDO) };
+
+if (locale === "accented" || locale === "bidi") {
+    Services.prefs.setStringPref(PSEUDO, locale);
+} else if (locale !== null) {
+    const available = Services.locale.availableLocales;
+    if (!available.includes(locale)) {
+        throw new Error(`No language pack for ${locale} is installed (available: ${available.join(", ")})`);
+    }
+    Services.prefs.clearUserPref(PSEUDO);
+    Services.locale.requestedLocales = [locale];
+}
+if (restore !== null) {
+    setOrClear(REQUESTED, restore.requested);
+    setOrClear(PSEUDO, restore.pseudo);
+}
+
+return {
+    previous,
+    status: {
+        app_locale: Services.locale.appLocaleAsBCP47,
+        requested: Services.locale.requestedLocales,
+        available: Services.locale.availableLocales,
+        pseudo: Services.prefs.getStringPref(PSEUDO, ""),
+        rtl: Services.locale.isAppLocaleRTL,
+    },
+};
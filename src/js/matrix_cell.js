@@ -0,0 +1,23 @@
+// Sets or clears the look-and-feel and density prefs of one matrix cell and
+// returns their values from before, to put back afterwards.
+// Rendered with: changes ({ pref: integer or string value, or null to clear it })
+
+const changes = {{changes}};
+const previous = {};
+for (const [name, value] of Object.entries(changes)) {
+    if (!Services.prefs.prefHasUserValue(name)) {
+        previous[name] = null;
+    } else if (Services.prefs.getPrefType(name) === Services.prefs.PREF_STRING) {
+        previous[name] = Services.prefs.getStringPref(name);
+    } else {
+        previous[name] = Services.prefs.getIntPref(name);
+    }
+    if (value === null) {
+        Services.prefs.clearUserPref(name);
+    } else if (typeof value === "string") {
+        Services.prefs.setStringPref(name, value);
+    } else {
+        Services.prefs.setIntPref(name, value);
+    }
+}
+return previous;
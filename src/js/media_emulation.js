@@ -0,0 +1,27 @@
+// Sets or clears the prefs behind the accessibility media queries and reports
+// what the browser window matches afterwards.
+// Rendered with: windowType, changes ({ pref: integer value, or null to clear it })
+
+const changes = {{changes}};
+const previous = {};
+for (const [name, value] of Object.entries(changes)) {
+    previous[name] = Services.prefs.prefHasUserValue(name) ? Services.prefs.getIntPref(name) : null;
+    if (value === null) {
+        Services.prefs.clearUserPref(name);
+    } else {
+        Services.prefs.setIntPref(name, value);
+    }
+}
+
+const window = Services.wm.getMostRecentWindow({{windowType}});
+if (!window) throw new Error("No browser window found");
+const matches = (query) => window.matchMedia(`(${query})`).matches;
+return {
+    previous,
+    media: {
+        reduced_motion: matches("prefers-reduced-motion: reduce"),
+        reduced_transparency: matches("prefers-reduced-transparency: reduce"),
+        contrast: ["more", "less", "custom"].find((value) => matches(`prefers-contrast: ${value}`)) ?? "no-preference",
+        forced_colors: matches("forced-colors: active"),
+    },
+};
@@ -0,0 +1,47 @@
+// Reads the color of one pixel of the browser window, as a screenshot shows it.
+// Rendered with: windowType, selector (null to measure from the window), at
+// ("center", a corner such as "top-left", or [x, y] from the top left), snapshot
+// (use drawSnapshot instead of the older canvas drawWindow)
+
+const selector = {{selector}};
+const at = {{at}};
+const snapshot = {{snapshot}};
+const window = Services.wm.getMostRecentWindow({{windowType}});
+let rect = new window.DOMRect(0, 0, window.innerWidth, window.innerHeight);
+if (selector !== null) {
+    const element = window.document.querySelector(selector);
+    if (!element) throw new Error(`No element matches ${selector}`);
+    rect = element.getBoundingClientRect();
+}
+
+const points = {
+    center: [rect.left + rect.width / 2, rect.top + rect.height / 2],
+    "top-left": [rect.left, rect.top],
+    "top-right": [rect.right - 1, rect.top],
+    "bottom-left": [rect.left, rect.bottom - 1],
+    "bottom-right": [rect.right - 1, rect.bottom - 1],
+};
+const point = Array.isArray(at) ? [rect.left + at[0], rect.top + at[1]] : points[at];
+const [x, y] = point.map(Math.floor);
+if (x < 0 || y < 0 || x >= window.innerWidth || y >= window.innerHeight) {
+    throw new Error(`(${x}, ${y}) is outside the ${window.innerWidth}x${window.innerHeight} window`);
+}
+
+const canvas = document.createElementNS("http://www.w3.org/1999/xhtml", "canvas");
+canvas.width = 1;
+canvas.height = 1;
+const ctx = canvas.getContext("2d");
+const read = () => {
+    const [r, g, b, a] = ctx.getImageData(0, 0, 1, 1).data;
+    return { x, y, r, g, b, a };
+};
+if (!snapshot) {
+    ctx.drawWindow(window, x, y, 1, 1, "rgb(255,255,255)");
+    return read();
+}
+return window.browsingContext.currentWindowGlobal
+    .drawSnapshot(new window.DOMRect(x, y, 1, 1), 1, "rgb(255,255,255)")
+    .then((bitmap) => {
+        ctx.drawImage(bitmap, 0, 0);
+        return read();
+    });
@@ -0,0 +1,16 @@
+// Reads a registered sheet as the browser window has it now. Edits made in
+// the Browser Toolbox change the sheet's rules, not its data: URI, so the
+// rules are serialized; the URI's content is returned when the window has
+// no such sheet.
+// Rendered with: windowType. Arguments: sheet ID
+
+const uri = window.chromeCssManager.sheets.get(arguments[0]);
+if (!uri) return null;
+const target = Services.wm.getMostRecentWindow({{windowType}});
+const sheet = target?.InspectorUtils.getAllStyleSheets(target.document, false)
+    .find(s => s.href === uri.spec);
+if (!sheet) {
+    return { css: decodeURIComponent(uri.spec.slice(uri.spec.indexOf(",") + 1)), live: false };
+}
+const css = Array.from(sheet.cssRules, rule => rule.cssText).join("\n");
+return { css: css ? `${css}\n` : "", live: true };
@@ -0,0 +1,11 @@
+// Reads a file with the browser's own file access and returns it as
+// base64url, or null when there is no such file.
+// Arguments: absolute path
+
+return IOUtils.read(arguments[0]).then(
+    bytes => ChromeUtils.base64URLEncode(bytes, { pad: true }),
+    e => {
+        if (DOMException.isInstance(e) && e.name === "NotFoundError") return null;
+        throw e;
+    }
+);
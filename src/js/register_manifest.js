@@ -0,0 +1,12 @@
+// Registers a chrome.manifest with the component registrar.
+// Rendered with: path
+
+try {
+    const file = Cc['@mozilla.org/file/local;1'].createInstance(Ci.nsIFile);
+    file.initWithPath({{path}});
+    const registrar = Components.manager.QueryInterface(Ci.nsIComponentRegistrar);
+    registrar.autoRegister(file);
+    return { success: true };
+} catch (e) {
+    return { success: false, error: e.toString() };
+}
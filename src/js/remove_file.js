@@ -0,0 +1,5 @@
+// Removes a file or a whole directory with the browser's own file access.
+// Missing files are not an error.
+// Arguments: absolute path
+
+return IOUtils.remove(arguments[0], { recursive: true, ignoreAbsent: true }).then(() => null);
@@ -0,0 +1,30 @@
+// Raises the browser window and returns where it, or an element in it, is on
+// the screen, in the desktop pixels the OS counts in (points on macOS).
+// Rendered with: windowType, selector (null for the whole window, titlebar
+// and all)
+
+const selector = {{selector}};
+const window = Services.wm.getMostRecentWindow({{windowType}});
+if (!window) throw new Error("No browser window found");
+window.focus();
+
+// CSS pixels to desktop pixels; they differ under layout.css.devPixelsPerPx
+const scale = window.devicePixelRatio / (window.desktopToDeviceScale || window.devicePixelRatio);
+let rect = { x: window.screenX, y: window.screenY, width: window.outerWidth, height: window.outerHeight };
+if (selector !== null) {
+    const element = window.document.querySelector(selector);
+    if (!element) throw new Error(`No element matches ${selector}`);
+    const bounds = element.getBoundingClientRect();
+    rect = {
+        x: window.mozInnerScreenX + bounds.left,
+        y: window.mozInnerScreenY + bounds.top,
+        width: bounds.width,
+        height: bounds.height,
+    };
+}
+return {
+    x: rect.x * scale,
+    y: rect.y * scale,
+    width: rect.width * scale,
+    height: rect.height * scale,
+};
@@ -0,0 +1,40 @@
+// Draws the browser window, or the element matching the selector, to a PNG data URL.
+// A data URL longer than chunkSize is kept in the window instead, and
+// { key, length } returned for capture_chunk.js to fetch in slices.
+// Rendered with: windowType, selector (null for the whole window), snapshot (use
+// drawSnapshot instead of the older canvas drawWindow), chunkSize
+
+const selector = {{selector}};
+const snapshot = {{snapshot}};
+const chunkSize = {{chunkSize}};
+const window = Services.wm.getMostRecentWindow({{windowType}});
+let rect = { left: 0, top: 0, width: window.innerWidth, height: window.innerHeight };
+if (selector !== null) {
+    const element = window.document.querySelector(selector);
+    if (!element) throw new Error(`No element matches ${selector}`);
+    rect = element.getBoundingClientRect();
+}
+
+function finish(url) {
+    if (url.length <= chunkSize) return url;
+    window.musUcCaptures ??= new Map();
+    const key = `capture-${Date.now()}-${window.musUcCaptures.size}`;
+    window.musUcCaptures.set(key, url);
+    return { key, length: url.length };
+}
+
+const canvas = document.createElementNS("http://www.w3.org/1999/xhtml", "canvas");
+canvas.width = rect.width;
+canvas.height = rect.height;
+const ctx = canvas.getContext("2d");
+if (!snapshot) {
+    ctx.drawWindow(window, rect.left, rect.top, rect.width, rect.height, "rgb(255,255,255)");
+    return finish(canvas.toDataURL("image/png"));
+}
+const area = new window.DOMRect(rect.left, rect.top, rect.width, rect.height);
+return window.browsingContext.currentWindowGlobal
+    .drawSnapshot(area, 1, "rgb(255,255,255)")
+    .then((bitmap) => {
+        ctx.drawImage(bitmap, 0, 0);
+        return finish(canvas.toDataURL("image/png"));
+    });
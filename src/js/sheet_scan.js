@@ -0,0 +1,57 @@
+// Finds the data: sheets registered with nsIStyleSheetService, which cannot
+// list its sheets, by looking through the style sheets of every window and
+// the harness of each. Sheets from mus-uc-devtools carry their ID in a
+// `;mus-uc-devtools=<id>` parameter of the URI. With remove, every marked
+// sheet is unregistered and each window's harness is dropped, which works
+// when the harness is missing or broken.
+// Rendered with: remove, anyDataUri (with remove, unmarked data: sheets too)
+
+const remove = {{remove}};
+const anyDataUri = {{anyDataUri}};
+const marker = /^data:text\/css[^,]*;mus-uc-devtools=([^;,]*)/;
+const sss = Cc["@mozilla.org/content/style-sheet-service;1"]
+    .getService(Ci.nsIStyleSheetService);
+const types = { agent: sss.AGENT_SHEET, user: sss.USER_SHEET, author: sss.AUTHOR_SHEET };
+
+const candidates = new Set();
+const tracked = new Set();
+for (const win of Services.wm.getEnumerator(null)) {
+    try {
+        for (const sheet of win.InspectorUtils.getAllStyleSheets(win.document, false)) {
+            if (sheet.href?.startsWith("data:text/css")) candidates.add(sheet.href);
+        }
+    } catch (e) {
+        // Windows that are still loading have no sheets to give
+    }
+    const harness = win.chromeCssManager;
+    if (!harness) continue;
+    try {
+        for (const uri of harness.sheets?.values() ?? []) {
+            candidates.add(uri.spec);
+            tracked.add(uri.spec);
+        }
+        if (remove && harness.consoleListener) {
+            Services.console.unregisterListener(harness.consoleListener);
+        }
+    } catch (e) {
+        // A broken harness is dropped below all the same
+    }
+    if (remove) delete win.chromeCssManager;
+}
+
+const found = [];
+for (const href of candidates) {
+    const match = marker.exec(href);
+    if (remove && !match && !anyDataUri) continue;
+    const uri = Services.io.newURI(href);
+    for (const [type, kind] of Object.entries(types)) {
+        if (!sss.sheetRegistered(uri, kind)) continue;
+        if (remove) sss.unregisterSheet(uri, kind);
+        found.push({
+            id: match ? decodeURIComponent(match[1]) : null,
+            type,
+            tracked: tracked.has(href),
+        });
+    }
+}
+return found;
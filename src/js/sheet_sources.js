@@ -0,0 +1,5 @@
+// Registered sheet IDs and the files they were loaded from.
+
+const manager = window.chromeCssManager;
+return Object.fromEntries(
+    [...manager.sheets.keys()].map(id => [id, manager.sources.get(id) || null]));
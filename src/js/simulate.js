@@ -0,0 +1,62 @@
+// Opens a browser surface that is otherwise hard to hold open, or closes
+// everything opened this way.
+// Rendered with: windowType, surface (null to close), query (urlbar text),
+// pin (keep popups open when the window loses focus)
+
+const surface = {{surface}};
+const query = {{query}};
+const pin = {{pin}};
+const window = Services.wm.getMostRecentWindow({{windowType}});
+if (!window) throw new Error("No browser window found");
+const AUTOHIDE = "ui.popup.disable_autohide";
+
+if (surface === null) {
+    for (const win of Services.wm.getEnumerator({{windowType}})) {
+        if (win.musUcSimulated) {
+            win.close();
+            continue;
+        }
+        if ("musUcAutohide" in win) {
+            Services.prefs.setBoolPref(AUTOHIDE, win.musUcAutohide);
+            delete win.musUcAutohide;
+        }
+        for (const popup of win.document.querySelectorAll("panel, menupopup")) {
+            if (popup.state === "open") popup.hidePopup();
+        }
+        win.gURLBar.view.close();
+        if (win.CustomizationHandler.isCustomizing()) win.gCustomizeMode.exit();
+    }
+    return null;
+}
+
+// Pinning flips the same pref as the devtools "Disable Popup Auto-Hide" option
+if (pin && !("musUcAutohide" in window)) {
+    window.musUcAutohide = Services.prefs.getBoolPref(AUTOHIDE, false);
+    Services.prefs.setBoolPref(AUTOHIDE, true);
+}
+
+switch (surface) {
+    case "urlbar":
+        window.gURLBar.focus();
+        window.gURLBar.search(query ?? "");
+        break;
+    case "all-tabs":
+        window.gTabsPanel.showAllTabsPanel();
+        break;
+    case "bookmark":
+        return window.PlacesCommandHook.bookmarkPage().then(() => surface);
+    case "app-menu":
+        window.PanelUI.show();
+        break;
+    case "customize":
+        window.gCustomizeMode.enter();
+        break;
+    case "private-window": {
+        const win = window.OpenBrowserWindow({ private: true });
+        win.musUcSimulated = true;
+        break;
+    }
+    default:
+        throw new Error(`Unknown surface: ${surface}`);
+}
+return surface;
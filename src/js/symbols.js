@@ -0,0 +1,37 @@
+// Collects the IDs, classes and attributes used in the browser window,
+// including markup still inside <template>s, which menus and panels are
+// built from when first opened.
+// Rendered with: windowType, ignoredAttributes
+
+const ignored = new Set({{ignoredAttributes}});
+const window = Services.wm.getMostRecentWindow({{windowType}});
+if (!window) throw new Error("No browser window found");
+
+const ids = {};
+const classes = {};
+const attributes = {};
+const visit = (root) => {
+    for (const element of root.querySelectorAll("*")) {
+        if (element.id) ids[element.id] ??= element.localName;
+        for (const name of element.classList) {
+            classes[name] = (classes[name] ?? 0) + 1;
+        }
+        for (const { name, value } of element.attributes) {
+            if (ignored.has(name) || name.startsWith("on") || name.startsWith("data-l10n")) {
+                continue;
+            }
+            const values = (attributes[name] ??= new Set());
+            if (value.length <= 40 && !/\s/.test(value)) values.add(value);
+        }
+        if (element.localName === "template") visit(element.content);
+    }
+};
+visit(window.document);
+
+return {
+    ids,
+    classes,
+    attributes: Object.fromEntries(
+        Object.entries(attributes).map(([name, values]) => [name, [...values]])
+    ),
+};
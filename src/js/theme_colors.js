@@ -0,0 +1,40 @@
+// Reads the color custom properties of the browser window and the system
+// colors, each resolved to the color it computes to.
+// Rendered with: windowType
+
+const window = Services.wm.getMostRecentWindow({{windowType}});
+if (!window) throw new Error("No browser window found");
+const root = window.document.documentElement;
+const probe = window.document.createElementNS("http://www.w3.org/1999/xhtml", "span");
+root.appendChild(probe);
+const resolve = (color) => {
+    probe.style.color = "";
+    probe.style.color = color;
+    return probe.style.color ? window.getComputedStyle(probe).color : null;
+};
+
+try {
+    const style = window.getComputedStyle(root);
+    const variables = [];
+    for (const name of style) {
+        if (!name.startsWith("--")) continue;
+        const value = style.getPropertyValue(name).trim();
+        if (!value || !window.CSS.supports("color", value)) continue;
+        const color = resolve(`var(${name})`);
+        if (color) variables.push([name, color]);
+    }
+
+    const system = [];
+    for (const name of [
+        "AccentColor", "AccentColorText", "ActiveText", "ButtonBorder", "ButtonFace",
+        "ButtonText", "Canvas", "CanvasText", "Field", "FieldText", "GrayText",
+        "Highlight", "HighlightText", "LinkText", "Mark", "MarkText",
+        "SelectedItem", "SelectedItemText", "VisitedText",
+    ]) {
+        const color = resolve(name);
+        if (color) system.push([name, color]);
+    }
+    return { variables, system };
+} finally {
+    probe.remove();
+}
@@ -0,0 +1,19 @@
+// Checks one wait condition in the browser window. A window that is not open
+// yet counts as the condition not holding.
+// Rendered with: windowType, condition ({ selector, gone?, attribute?,
+// value? } or { predicate })
+
+const condition = {{condition}};
+const window = Services.wm.getMostRecentWindow({{windowType}});
+if (!window) return false;
+
+if (condition.predicate !== undefined) {
+    const predicate = new Function("window", "document", `return (${condition.predicate});`);
+    return Boolean(predicate(window, window.document));
+}
+const element = window.document.querySelector(condition.selector);
+if (condition.gone) return !element;
+if (!element) return false;
+if (condition.attribute === undefined) return true;
+if (!element.hasAttribute(condition.attribute)) return false;
+return condition.value === null || element.getAttribute(condition.attribute) === condition.value;
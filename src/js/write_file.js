@@ -0,0 +1,9 @@
+// Writes a file with the browser's own file access, creating its directory.
+// The file is written to a temporary name first and then moved into place.
+// Arguments: absolute path, content as base64url
+
+const [path, content] = arguments;
+const bytes = new Uint8Array(ChromeUtils.base64URLDecode(content, { padding: "require" }));
+return IOUtils.makeDirectory(PathUtils.parent(path), { createAncestors: true })
+    .then(() => IOUtils.write(path, bytes, { tmpPath: path + ".tmp" }))
+    .then(() => null);
@@ -0,0 +1,124 @@
+//! Picking values out of a script's result, for `exec --extract`.
+//!
+//! Two spellings are accepted:
+//!
+//! - a JSON pointer (RFC 6901), starting with `/`: `/id`, `/tabs/0/title`
+//! - a small part of jq, starting with `.`: `.id`, `.tabs[0].title`,
+//!   `.["odd key"]`, and `[]` for every element, as in `.tabs[].title`
+//!
+//! `.` on its own is the whole value.
+
+use serde_json::Value;
+
+/// The values `expr` selects from `value`: one, or any number when the
+/// expression iterates with `[]`. A missing key or index is an error.
+pub fn extract(value: &Value, expr: &str) -> Result<Vec<Value>, Box<dyn std::error::Error>> {
+    let expr = expr.trim();
+    if expr.is_empty() || expr == "." {
+        return Ok(vec![value.clone()]);
+    }
+    if expr.starts_with('/') {
+        return value
+            .pointer(expr)
+            .cloned()
+            .map(|found| vec![found])
+            .ok_or_else(|| format!("Nothing at {} in the result", expr).into());
+    }
+    if !expr.starts_with('.') {
+        return Err(format!(
+            "Invalid expression '{}': start a JSON pointer with / or a jq path with .",
+            expr
+        )
+        .into());
+    }
+    let mut current = vec![value.clone()];
+    for step in parse_steps(expr)? {
+        let mut next = Vec::new();
+        for value in &current {
+            match &step {
+                Step::Key(key) => next.push(
+                    value
+                        .get(key.as_str())
+                        .cloned()
+                        .ok_or_else(|| format!("No key '{}' in {}", key, kind(value)))?,
+                ),
+                Step::Index(index) => next.push(index_value(value, *index)?),
+                Step::Each => match value {
+                    Value::Array(items) => next.extend(items.iter().cloned()),
+                    Value::Object(map) => next.extend(map.values().cloned()),
+                    _ => return Err(format!("Cannot iterate over {}", kind(value)).into()),
+                },
+            }
+        }
+        current = next;
+    }
+    Ok(current)
+}
+
+enum Step {
+    Key(String),
+    /// Negative counts from the end, as in jq.
+    Index(i64),
+    Each,
+}
+
+fn parse_steps(expr: &str) -> Result<Vec<Step>, Box<dyn std::error::Error>> {
+    let invalid = || format!("Invalid expression '{}'", expr);
+    let mut steps = Vec::new();
+    let mut rest = expr;
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix('[') {
+            let end = after.find(']').ok_or_else(invalid)?;
+            let inside = after[..end].trim();
+            steps.push(if inside.is_empty() {
+                Step::Each
+            } else if let Some(key) = inside
+                .strip_prefix('"')
+                .and_then(|key| key.strip_suffix('"'))
+            {
+                Step::Key(key.to_string())
+            } else {
+                Step::Index(inside.parse().map_err(|_| invalid())?)
+            });
+            rest = &after[end + 1..];
+        } else if let Some(after) = rest.strip_prefix('.') {
+            let end = after.find(['.', '[']).unwrap_or(after.len());
+            let key = &after[..end];
+            if !key.is_empty() {
+                steps.push(Step::Key(key.to_string()));
+            } else if !after.starts_with('[') {
+                return Err(invalid().into());
+            }
+            rest = &after[end..];
+        } else {
+            return Err(invalid().into());
+        }
+    }
+    Ok(steps)
+}
+
+fn index_value(value: &Value, index: i64) -> Result<Value, Box<dyn std::error::Error>> {
+    let Value::Array(items) = value else {
+        return Err(format!("Cannot index {} with {}", kind(value), index).into());
+    };
+    let position = if index < 0 {
+        items.len().checked_sub(index.unsigned_abs() as usize)
+    } else {
+        Some(index as usize)
+    };
+    position
+        .and_then(|i| items.get(i))
+        .cloned()
+        .ok_or_else(|| format!("No index {} in an array of {}", index, items.len()).into())
+}
+
+fn kind(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "a boolean",
+        Value::Number(_) => "a number",
+        Value::String(_) => "a string",
+        Value::Array(_) => "an array",
+        Value::Object(_) => "an object",
+    }
+}
@@ -0,0 +1,409 @@
+//! Starting Firefox with Marionette enabled, for `launch`.
+//!
+//! Firefox is started with the flags the tool needs (`--marionette`,
+//! `--remote-allow-system-access`, `--no-remote`) on a fresh profile, or a
+//! given one, and the launch returns once Marionette accepts connections.
+//!
+//! On Linux the display matters for screenshots. A Wayland session is used
+//! natively and an X11 one through `DISPLAY`; with no desktop at all, as in
+//! CI, `--virtual-display` starts Xvfb, or falls back to `--headless` when
+//! Xvfb is not installed. Either way Firefox gets an environment that agrees
+//! with the display it is on: GTK picking Wayland while `DISPLAY` points at
+//! Xvfb is the usual reason captures come out empty.
+
+use crate::instances::accepts;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+/// How long Xvfb gets to create its socket.
+const XVFB_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The first X display number tried for Xvfb, out of the way of real ones.
+const FIRST_XVFB_DISPLAY: u32 = 99;
+
+/// What `--virtual-display` asks for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VirtualDisplay {
+    /// The desktop if there is one, else Xvfb, else headless.
+    #[default]
+    Auto,
+    /// Always a new Xvfb display, even on a desktop.
+    Xvfb,
+    /// Firefox's own `--headless`.
+    Headless,
+}
+
+impl VirtualDisplay {
+    pub fn parse(name: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        match name {
+            "auto" => Ok(VirtualDisplay::Auto),
+            "xvfb" => Ok(VirtualDisplay::Xvfb),
+            "headless" => Ok(VirtualDisplay::Headless),
+            other => Err(format!(
+                "Unknown virtual display '{}' (expected auto, xvfb or headless)",
+                other
+            )
+            .into()),
+        }
+    }
+}
+
+/// The display server of the session this process runs in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DisplayServer {
+    /// A Wayland compositor, from `WAYLAND_DISPLAY`.
+    Wayland,
+    /// An X server, from `DISPLAY`.
+    X11(String),
+    /// Neither, as on a server, in a container or on a CI runner.
+    None,
+}
+
+/// The display server of this session, from the environment. Wayland wins
+/// when both are set, since `DISPLAY` then points at XWayland.
+pub fn detect_display() -> DisplayServer {
+    let set = |name: &str| std::env::var(name).ok().filter(|value| !value.is_empty());
+    if set("WAYLAND_DISPLAY").is_some() {
+        DisplayServer::Wayland
+    } else if let Some(display) = set("DISPLAY") {
+        DisplayServer::X11(display)
+    } else {
+        DisplayServer::None
+    }
+}
+
+/// The display Firefox runs on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DisplaySetup {
+    /// The desktop of macOS or Windows, which needs nothing set up.
+    Desktop,
+    Wayland,
+    /// An existing X display.
+    X11(String),
+    /// A new Xvfb display, stopped with Firefox.
+    Xvfb,
+    Headless,
+}
+
+impl DisplaySetup {
+    /// Decide on a display for `requested` (`None` without
+    /// `--virtual-display`) in a session on `detected`.
+    pub fn choose(
+        requested: Option<VirtualDisplay>,
+        detected: DisplayServer,
+        xvfb_installed: bool,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        if requested == Some(VirtualDisplay::Headless) {
+            return Ok(DisplaySetup::Headless);
+        }
+        if !cfg!(target_os = "linux") {
+            return match requested {
+                Some(VirtualDisplay::Xvfb) => {
+                    Err("--virtual-display=xvfb is only for Linux".into())
+                }
+                _ => Ok(DisplaySetup::Desktop),
+            };
+        }
+        match (requested, detected) {
+            (Some(VirtualDisplay::Xvfb), _) if xvfb_installed => Ok(DisplaySetup::Xvfb),
+            (Some(VirtualDisplay::Xvfb), _) => {
+                Err("Xvfb is not installed (e.g. apt install xvfb)".into())
+            }
+            (_, DisplayServer::Wayland) => Ok(DisplaySetup::Wayland),
+            (_, DisplayServer::X11(display)) => Ok(DisplaySetup::X11(display)),
+            (None, DisplayServer::None) => Err(
+                "No display: neither WAYLAND_DISPLAY nor DISPLAY is set; pass --virtual-display to start Xvfb or run headless"
+                    .into(),
+            ),
+            (Some(_), DisplayServer::None) if xvfb_installed => Ok(DisplaySetup::Xvfb),
+            (Some(_), DisplayServer::None) => Ok(DisplaySetup::Headless),
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            DisplaySetup::Desktop => "desktop".to_string(),
+            DisplaySetup::Wayland => "Wayland".to_string(),
+            DisplaySetup::X11(display) => format!("X11 display {}", display),
+            DisplaySetup::Xvfb => "Xvfb".to_string(),
+            DisplaySetup::Headless => "headless".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LaunchOptions {
+    /// The Firefox executable; found with [`find_firefox`] when not given.
+    pub firefox: Option<PathBuf>,
+    /// The profile to run with; a fresh one, removed afterwards, when not
+    /// given.
+    pub profile: Option<PathBuf>,
+    pub port: u16,
+    pub virtual_display: Option<VirtualDisplay>,
+    /// Screen size of Xvfb and of headless windows.
+    pub size: (u32, u32),
+    /// How long to wait for Marionette.
+    pub timeout: Duration,
+}
+
+impl Default for LaunchOptions {
+    fn default() -> Self {
+        LaunchOptions {
+            firefox: None,
+            profile: None,
+            port: 2828,
+            virtual_display: None,
+            size: (1920, 1080),
+            timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+/// A running Firefox, and the Xvfb and profile it uses. Dropping it stops
+/// Firefox if it is still running, then Xvfb, and removes a fresh profile.
+pub struct Launched {
+    firefox: Child,
+    xvfb: Option<Child>,
+    display: DisplaySetup,
+    /// `DISPLAY` of the Xvfb display.
+    xvfb_display: Option<String>,
+    profile: PathBuf,
+    _fresh_profile: Option<TempDir>,
+}
+
+impl Launched {
+    pub fn display(&self) -> &DisplaySetup {
+        &self.display
+    }
+
+    /// `DISPLAY` for other programs that should draw on Firefox's Xvfb.
+    pub fn xvfb_display(&self) -> Option<&str> {
+        self.xvfb_display.as_deref()
+    }
+
+    pub fn profile(&self) -> &Path {
+        &self.profile
+    }
+
+    pub fn pid(&self) -> u32 {
+        self.firefox.id()
+    }
+
+    /// Wait for Firefox to exit, and return its exit code.
+    pub fn wait(mut self) -> Result<i32, Box<dyn std::error::Error>> {
+        let status = self.firefox.wait()?;
+        Ok(status.code().unwrap_or(1))
+    }
+
+    /// Kill Firefox.
+    pub fn stop(mut self) -> Result<(), Box<dyn std::error::Error>> {
+        self.firefox.kill()?;
+        self.firefox.wait()?;
+        Ok(())
+    }
+}
+
+impl Drop for Launched {
+    fn drop(&mut self) {
+        // Firefox first, as it crashes when its display goes away
+        if let Ok(None) = self.firefox.try_wait() {
+            let _ = self.firefox.kill();
+            let _ = self.firefox.wait();
+        }
+        if let Some(xvfb) = &mut self.xvfb {
+            let _ = xvfb.kill();
+            let _ = xvfb.wait();
+        }
+    }
+}
+
+/// Start Firefox as `options` say and wait until Marionette accepts
+/// connections on `options.port`.
+pub fn launch(options: &LaunchOptions) -> Result<Launched, Box<dyn std::error::Error>> {
+    let firefox = match &options.firefox {
+        Some(path) => path.clone(),
+        None => find_firefox().ok_or("Firefox not found on PATH; give it with --firefox")?,
+    };
+    let display = DisplaySetup::choose(
+        options.virtual_display,
+        detect_display(),
+        find_executable("Xvfb").is_some(),
+    )?;
+
+    let (profile, fresh_profile) = match &options.profile {
+        Some(profile) => (profile.clone(), None),
+        None => {
+            let dir = tempfile::Builder::new()
+                .prefix("mus-uc-profile-")
+                .tempdir()?;
+            (dir.path().to_path_buf(), Some(dir))
+        }
+    };
+    if fresh_profile.is_some() || options.port != 2828 {
+        set_marionette_port(&profile, options.port)?;
+    }
+
+    let (width, height) = options.size;
+    let mut command = Command::new(&firefox);
+    command
+        .args([
+            "--marionette",
+            "--no-remote",
+            "--remote-allow-system-access",
+        ])
+        .arg("--profile")
+        .arg(&profile)
+        .stdout(Stdio::null());
+    let mut xvfb = None;
+    let mut xvfb_display = None;
+    match &display {
+        DisplaySetup::Desktop => {}
+        DisplaySetup::Wayland => {
+            command.env("MOZ_ENABLE_WAYLAND", "1");
+        }
+        DisplaySetup::X11(display) => {
+            x11_env(&mut command, display.into());
+        }
+        DisplaySetup::Xvfb => {
+            let (child, display) = start_xvfb(width, height)?;
+            x11_env(&mut command, display.clone().into());
+            xvfb = Some(child);
+            xvfb_display = Some(display);
+        }
+        DisplaySetup::Headless => {
+            command
+                .arg("--headless")
+                .env("MOZ_HEADLESS", "1")
+                .env("MOZ_HEADLESS_WIDTH", width.to_string())
+                .env("MOZ_HEADLESS_HEIGHT", height.to_string());
+        }
+    }
+
+    let child = command
+        .spawn()
+        .map_err(|e| format!("Could not start {}: {}", firefox.display(), e))?;
+    let mut launched = Launched {
+        firefox: child,
+        xvfb,
+        display,
+        xvfb_display,
+        profile,
+        _fresh_profile: fresh_profile,
+    };
+
+    let started = Instant::now();
+    while !accepts("127.0.0.1", options.port) {
+        if let Some(status) = launched.firefox.try_wait()? {
+            return Err(format!("Firefox exited before Marionette started ({})", status).into());
+        }
+        if started.elapsed() > options.timeout {
+            let _ = launched.firefox.kill();
+            let _ = launched.firefox.wait();
+            return Err(format!(
+                "Marionette did not start on port {} within {:?}",
+                options.port, options.timeout
+            )
+            .into());
+        }
+        std::thread::sleep(Duration::from_millis(200));
+    }
+    Ok(launched)
+}
+
+/// Firefox on `PATH`, or where its installer puts it.
+pub fn find_firefox() -> Option<PathBuf> {
+    if let Some(path) = find_executable("firefox") {
+        return Some(path);
+    }
+    let installed: &[&str] = if cfg!(target_os = "macos") {
+        &["/Applications/Firefox.app/Contents/MacOS/firefox"]
+    } else if cfg!(windows) {
+        &[
+            r"C:\Program Files\Mozilla Firefox\firefox.exe",
+            r"C:\Program Files (x86)\Mozilla Firefox\firefox.exe",
+        ]
+    } else {
+        &[]
+    };
+    installed
+        .iter()
+        .map(PathBuf::from)
+        .find(|path| path.is_file())
+}
+
+/// The first file called `name` (`name.exe` on Windows) on `PATH`.
+fn find_executable(name: &str) -> Option<PathBuf> {
+    let file = if cfg!(windows) {
+        format!("{}.exe", name)
+    } else {
+        name.to_string()
+    };
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path)
+        .map(|dir| dir.join(&file))
+        .find(|path| path.is_file())
+}
+
+/// Have Firefox listen for Marionette on `port`, through the profile's
+/// `user.js`.
+fn set_marionette_port(profile: &Path, port: u16) -> Result<(), Box<dyn std::error::Error>> {
+    let user_js = profile.join("user.js");
+    let mut prefs: Vec<String> = fs::read_to_string(&user_js)
+        .unwrap_or_default()
+        .lines()
+        .filter(|line| !line.contains("\"marionette.port\""))
+        .map(str::to_string)
+        .collect();
+    prefs.push(format!("user_pref(\"marionette.port\", {});", port));
+    fs::create_dir_all(profile)?;
+    fs::write(&user_js, prefs.join("\n") + "\n")?;
+    Ok(())
+}
+
+/// Point Firefox at the X display `display`, and keep GTK from picking a
+/// Wayland compositor instead.
+fn x11_env(command: &mut Command, display: OsString) {
+    command
+        .env("DISPLAY", display)
+        .env("MOZ_ENABLE_WAYLAND", "0")
+        .env("GDK_BACKEND", "x11")
+        .env_remove("WAYLAND_DISPLAY");
+}
+
+/// Start Xvfb on the first free display from [`FIRST_XVFB_DISPLAY`], and
+/// wait for its socket. Returns the process and its `DISPLAY`. Xvfb exits by
+/// itself once its last client, Firefox, disconnects, so it does not outlive
+/// Firefox even when `launch` is killed.
+fn start_xvfb(width: u32, height: u32) -> Result<(Child, String), Box<dyn std::error::Error>> {
+    let number = (FIRST_XVFB_DISPLAY..FIRST_XVFB_DISPLAY + 100)
+        .find(|n| !Path::new(&format!("/tmp/.X{}-lock", n)).exists())
+        .ok_or("No free X display number for Xvfb")?;
+    let display = format!(":{}", number);
+    let mut child = Command::new("Xvfb")
+        .arg(&display)
+        .args(["-screen", "0", &format!("{}x{}x24", width, height)])
+        .args(["-nolisten", "tcp", "-terminate"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| format!("Could not start Xvfb: {}", e))?;
+
+    let socket = PathBuf::from(format!("/tmp/.X11-unix/X{}", number));
+    let started = Instant::now();
+    while !socket.exists() {
+        if let Some(status) = child.try_wait()? {
+            return Err(format!("Xvfb exited on {} ({})", display, status).into());
+        }
+        if started.elapsed() > XVFB_TIMEOUT {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(format!("Xvfb did not start on {}", display).into());
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    Ok((child, display))
+}
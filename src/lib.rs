@@ -6,10 +6,82 @@
 //! Note: This library is primarily designed for WASI environments and CLI usage.
 //! The wasm-pack build support is experimental and may have limitations.
 
+pub mod analyze;
+pub mod app_profile;
+pub mod asset_server;
+pub mod bench;
+#[cfg(not(target_family = "wasm"))]
+pub mod bidi_client;
+pub mod browser_vars;
 pub mod chrome_css_manager;
+pub mod chrome_js;
 pub mod chrome_manifest;
+pub mod ci_profile;
+pub mod compare;
+pub mod compat;
+pub mod config_layers;
+pub mod conflicts;
+pub mod connection;
+pub mod console_log;
+#[cfg(not(target_family = "wasm"))]
+pub mod container;
+pub mod content_pages;
+pub mod contrast;
+pub mod css_assets;
+pub mod css_format;
+pub mod css_imports;
+pub mod dev_prefs;
+pub mod event_log;
+pub mod gallery;
+#[cfg(not(target_family = "wasm"))]
+pub mod git_source;
+pub mod helpers;
+pub mod hooks;
+pub mod icons;
+pub mod instances;
+pub mod json_extract;
+#[cfg(not(target_family = "wasm"))]
+pub mod launcher;
+pub mod locale;
 pub mod marionette_client;
+pub mod matrix;
+pub mod media_emulation;
+pub mod native_capture;
+pub mod navigation;
+pub mod notifications;
+pub mod op_queue;
+pub mod package;
+pub mod paths;
+pub mod persist;
+pub mod plugins;
+pub mod poll_watcher;
+pub mod post_process;
+pub mod preprocess;
+pub mod profile_import;
+pub mod project_config;
+pub mod pseudo_state;
+pub mod rdp_client;
+pub mod retry;
+pub mod scenario;
 pub mod screenshot;
+pub mod session;
+pub mod shared_connection;
+pub mod sheet_state;
+pub mod simulate;
+pub mod source_map;
+#[cfg(all(feature = "ssh", not(target_family = "wasm")))]
+pub mod ssh_tunnel;
+pub mod state_archive;
+pub mod symbols;
+pub mod targets;
+pub mod term_status;
+pub mod theme_colors;
+pub mod wait;
+pub mod watch;
+pub mod watch_filter;
+pub mod watch_lock;
+pub mod window;
+pub mod wsl;
 
 #[cfg(feature = "component")]
 pub mod component;
@@ -17,4 +89,6 @@ pub mod component;
 // Re-export main types
 pub use chrome_css_manager::ChromeCSSManager;
 pub use chrome_manifest::ChromeManifestRegistrar;
-pub use marionette_client::{MarionetteConnection, MarionetteSettings};
+pub use marionette_client::{BrowserInfo, MarionetteConnection, MarionetteSettings};
+pub use screenshot::ScreenshotManager;
+pub use shared_connection::SharedConnection;
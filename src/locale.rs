@@ -0,0 +1,131 @@
+//! The language of the browser UI, for `locale` and the `--locale` option of
+//! `scenario run`, so themes can be checked against long strings and
+//! right-to-left layouts.
+//!
+//! A real locale needs its language pack installed and is requested through
+//! `intl.locale.requested`. Firefox also has two pseudo-locales that need
+//! none: `accented` makes every string longer, and `bidi` mirrors the UI and
+//! its strings right to left. Strings translated with Fluent change at once;
+//! older strings, and the layout direction in some windows, only change when
+//! the browser restarts.
+
+use crate::chrome_js;
+use crate::marionette_client::MarionetteConnection;
+use crate::shared_connection::SharedConnection;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::time::Duration;
+
+/// Locales that need no language pack.
+pub const PSEUDO_LOCALES: &[&str] = &["accented", "bidi"];
+
+/// How long the UI is given to retranslate before `f` of [`with_locale`] runs.
+const SETTLE_TIME: Duration = Duration::from_millis(500);
+
+/// The locales of the connected browser.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LocaleStatus {
+    /// The locale the UI is shown in, e.g. `de`.
+    pub app_locale: String,
+    pub requested: Vec<String>,
+    /// Locales with a language pack installed, or built in.
+    pub available: Vec<String>,
+    /// `accented`, `bidi`, or empty.
+    pub pseudo: String,
+    /// Whether the UI is laid out right to left.
+    pub rtl: bool,
+}
+
+impl LocaleStatus {
+    pub fn to_text(&self) -> String {
+        let mut text = format!("Locale: {}", self.app_locale);
+        if !self.pseudo.is_empty() {
+            text.push_str(&format!(" (pseudo-locale {})", self.pseudo));
+        }
+        if self.rtl {
+            text.push_str(", right to left");
+        }
+        text.push_str(&format!(
+            "\nRequested: {}\nAvailable: {}\n",
+            self.requested.join(", "),
+            self.available.join(", ")
+        ));
+        text
+    }
+}
+
+/// The locale prefs from before [`set_locale`], to put back with
+/// [`restore_locale`].
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SavedLocale {
+    requested: Option<String>,
+    pseudo: Option<String>,
+}
+
+fn run(
+    connection: &mut MarionetteConnection,
+    locale: Option<&str>,
+    restore: Option<&SavedLocale>,
+) -> Result<(SavedLocale, LocaleStatus), Box<dyn std::error::Error>> {
+    #[derive(Deserialize)]
+    struct Outcome {
+        previous: SavedLocale,
+        status: LocaleStatus,
+    }
+
+    let script = chrome_js::render(
+        chrome_js::LOCALE,
+        &[
+            ("locale", locale.map_or(Value::Null, Value::from)),
+            ("restore", json!(restore)),
+        ],
+    );
+    let outcome: Outcome = serde_json::from_value(connection.execute_script(&script, None)?)?;
+    Ok((outcome.previous, outcome.status))
+}
+
+/// The locales in use, changing nothing. The connection must be in the chrome
+/// context.
+pub fn locale_status(
+    connection: &mut MarionetteConnection,
+) -> Result<LocaleStatus, Box<dyn std::error::Error>> {
+    Ok(run(connection, None, None)?.1)
+}
+
+/// Show the UI in `locale`, a locale with a language pack installed or one of
+/// [`PSEUDO_LOCALES`].
+pub fn set_locale(
+    connection: &mut MarionetteConnection,
+    locale: &str,
+) -> Result<(SavedLocale, LocaleStatus), Box<dyn std::error::Error>> {
+    run(connection, Some(locale), None)
+}
+
+/// Put back the prefs [`set_locale`] changed.
+pub fn restore_locale(
+    connection: &mut MarionetteConnection,
+    saved: &SavedLocale,
+) -> Result<LocaleStatus, Box<dyn std::error::Error>> {
+    Ok(run(connection, None, Some(saved))?.1)
+}
+
+/// Go back to the locale the browser picks by itself, with no pseudo-locale.
+pub fn reset_locale(
+    connection: &mut MarionetteConnection,
+) -> Result<LocaleStatus, Box<dyn std::error::Error>> {
+    restore_locale(connection, &SavedLocale::default())
+}
+
+/// Run `f` with the UI in `locale`, and put the prefs back afterwards whether
+/// or not it succeeds. Only what changes without a restart changes.
+pub fn with_locale<T>(
+    connection: &SharedConnection,
+    locale: &str,
+    f: impl FnOnce() -> Result<T, Box<dyn std::error::Error>>,
+) -> Result<T, Box<dyn std::error::Error>> {
+    let (saved, _) = connection.with(|c| set_locale(c, locale))?;
+    std::thread::sleep(SETTLE_TIME);
+    let result = f();
+    connection.with(|c| restore_locale(c, &saved))?;
+    result
+}
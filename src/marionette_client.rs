@@ -1,11 +1,19 @@
+use crate::app_profile::AppProfile;
+#[cfg(not(target_family = "wasm"))]
+use crate::bidi_client::BidiClient;
+use crate::chrome_js;
+use crate::connection::{Backend, Connection, Stream};
+use crate::rdp_client::RdpClient;
+use base64::{engine::general_purpose, Engine as _};
+use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 use std::io::{BufRead, BufReader, Write};
-use std::net::TcpStream;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
 #[derive(Debug)]
 pub struct MarionetteClient {
-    stream: TcpStream,
+    stream: Stream,
     message_id: u32,
 }
 
@@ -27,9 +35,17 @@ struct MarionetteMessage {
 
 impl MarionetteClient {
     pub fn connect(host: &str, port: u16) -> Result<Self, Box<dyn std::error::Error>> {
-        let stream = TcpStream::connect((host, port))?;
-        stream.set_read_timeout(Some(Duration::from_secs(60)))?;
-        stream.set_write_timeout(Some(Duration::from_secs(60)))?;
+        Self::handshake(Stream::connect_tcp(host, port)?)
+    }
+
+    /// Connect through a Unix socket that leads to Marionette, e.g. one made
+    /// with `socat UNIX-LISTEN:/tmp/marionette.sock,fork TCP:localhost:2828`.
+    pub fn connect_socket(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::handshake(Stream::connect_unix(path)?)
+    }
+
+    fn handshake(stream: Stream) -> Result<Self, Box<dyn std::error::Error>> {
+        stream.set_timeouts(Some(Duration::from_secs(60)))?;
 
         // Read handshake
         let mut reader = BufReader::new(stream.try_clone()?);
@@ -60,6 +76,21 @@ impl MarionetteClient {
         &mut self,
         name: &str,
         params: Value,
+    ) -> Result<Value, Box<dyn std::error::Error>> {
+        let response = self.send_raw(name, params)?;
+        if let Some(error) = response.get("error").filter(|e| !e.is_null()) {
+            return Err(MarionetteError::from_value(error).into());
+        }
+
+        Ok(response.get("value").unwrap_or(&Value::Null).clone())
+    }
+
+    /// Send a command and return the response as Firefox sent it, with an
+    /// `error` instead of failing when the command did.
+    pub fn send_raw(
+        &mut self,
+        name: &str,
+        params: Value,
     ) -> Result<Value, Box<dyn std::error::Error>> {
         self.message_id += 1;
 
@@ -78,13 +109,7 @@ impl MarionetteClient {
         reader.read_line(&mut response_line)?;
 
         let colon_pos = response_line.find(':').ok_or("Invalid response format")?;
-        let response: Value = serde_json::from_str(&response_line[colon_pos + 1..])?;
-
-        if let Some(error) = response.get("error") {
-            return Err(format!("Marionette error: {}", error).into());
-        }
-
-        Ok(response.get("value").unwrap_or(&Value::Null).clone())
+        Ok(serde_json::from_str(&response_line[colon_pos + 1..])?)
     }
 
     pub fn set_context(&mut self, context: &str) -> Result<(), Box<dyn std::error::Error>> {
@@ -105,10 +130,160 @@ impl MarionetteClient {
     }
 }
 
-#[derive(Debug, Default)]
+impl Connection for MarionetteClient {
+    fn set_context(&mut self, context: &str) -> Result<(), Box<dyn std::error::Error>> {
+        MarionetteClient::set_context(self, context)
+    }
+
+    fn execute_script(
+        &mut self,
+        script: &str,
+        args: Option<Vec<Value>>,
+    ) -> Result<Value, Box<dyn std::error::Error>> {
+        MarionetteClient::execute_script(self, script, args)
+    }
+
+    fn send_command(
+        &mut self,
+        name: &str,
+        params: Value,
+    ) -> Result<Value, Box<dyn std::error::Error>> {
+        MarionetteClient::send_command(self, name, params)
+    }
+
+    fn send_raw(&mut self, name: &str, params: Value) -> Result<Value, Box<dyn std::error::Error>> {
+        MarionetteClient::send_raw(self, name, params)
+    }
+}
+
+/// An error Firefox sent back for a command, with the fields WebDriver
+/// defines for it.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct MarionetteError {
+    /// The WebDriver error code, e.g. `javascript error` or `no such element`.
+    pub error: String,
+    pub message: String,
+    /// The JavaScript stack, one `function@file:line:column` frame per line,
+    /// for errors thrown by scripts; empty otherwise.
+    pub stacktrace: String,
+    /// Where the error happened in terms the user knows, such as a line of
+    /// a script file or of the chrome harness, when that could be worked out.
+    pub location: Option<String>,
+}
+
+/// A frame of [`MarionetteError::stacktrace`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct StackFrame {
+    /// Empty for top-level code.
+    pub function: String,
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl MarionetteError {
+    /// Read the `error` of a response: an object with `error`, `message` and
+    /// `stacktrace`, or a bare message as some proxies send.
+    pub fn from_value(value: &Value) -> Self {
+        let field = |name: &str| value[name].as_str().unwrap_or_default().to_string();
+        match value {
+            Value::Object(_) => MarionetteError {
+                error: field("error"),
+                message: field("message"),
+                stacktrace: field("stacktrace"),
+                location: None,
+            },
+            Value::String(message) => MarionetteError {
+                error: "unknown error".to_string(),
+                message: message.clone(),
+                ..Default::default()
+            },
+            other => MarionetteError {
+                error: "unknown error".to_string(),
+                message: other.to_string(),
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Whether a script threw, as opposed to Marionette refusing the command.
+    pub fn is_script_error(&self) -> bool {
+        self.error == "javascript error"
+    }
+
+    /// The frames of the stack trace, innermost first. Lines that do not
+    /// look like frames are skipped.
+    pub fn frames(&self) -> Vec<StackFrame> {
+        self.stacktrace
+            .lines()
+            .filter_map(|line| {
+                let (function, rest) = line.trim().split_once('@')?;
+                let (rest, column) = rest.rsplit_once(':')?;
+                let (file, line) = rest.rsplit_once(':')?;
+                Some(StackFrame {
+                    function: function.to_string(),
+                    file: file.to_string(),
+                    line: line.parse().ok()?,
+                    column: column.parse().ok()?,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Fill in [`MarionetteError::location`] of a script error from the first
+/// frame `locate` can place; other errors are returned as they are.
+pub fn locate_error(
+    error: Box<dyn std::error::Error>,
+    locate: impl Fn(&StackFrame) -> Option<String>,
+) -> Box<dyn std::error::Error> {
+    match error.downcast::<MarionetteError>() {
+        Ok(mut error) => {
+            if error.is_script_error() && error.location.is_none() {
+                error.location = error.frames().iter().find_map(locate);
+            }
+            error
+        }
+        Err(error) => error,
+    }
+}
+
+/// Place a frame of a script read from `path` and run as it is, skipping
+/// frames in the browser's own code.
+pub fn script_file_frame(path: &Path) -> impl Fn(&StackFrame) -> Option<String> + '_ {
+    move |frame| {
+        let browser_code = ["chrome://", "resource://", "moz-src://"]
+            .iter()
+            .any(|scheme| frame.file.starts_with(scheme));
+        (!browser_code).then(|| format!("{}:{}:{}", path.display(), frame.line, frame.column))
+    }
+}
+
+impl std::fmt::Display for MarionetteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Marionette error: {}", self.message)?;
+        if !self.error.is_empty() && self.error != "unknown error" {
+            write!(f, " ({})", self.error)?;
+        }
+        if let Some(location) = &self.location {
+            write!(f, " at {}", location)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for MarionetteError {}
+
+#[derive(Debug, Clone, Default)]
 pub struct MarionetteSettings {
     pub host: String,
     pub port: u16,
+    /// Connect through this Unix socket instead of `host` and `port`.
+    pub socket: Option<PathBuf>,
+    /// The protocol spoken on `port`; Marionette unless set.
+    pub backend: Backend,
+    /// The browser's [`AppProfile`]; detected from the browser when unset.
+    pub app: Option<AppProfile>,
 }
 
 impl MarionetteSettings {
@@ -116,18 +291,261 @@ impl MarionetteSettings {
         Self {
             host: "localhost".to_string(),
             port: 2828,
+            socket: None,
+            backend: Backend::Marionette,
+            app: None,
+        }
+    }
+
+    /// Where the browser is reached, e.g. `localhost:2828`.
+    pub fn address(&self) -> String {
+        match &self.socket {
+            Some(path) => path.display().to_string(),
+            None => format!("{}:{}", self.host, self.port),
+        }
+    }
+}
+
+/// What to do when Firefox cannot be reached.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ReconnectPolicy {
+    /// Fail on the first error.
+    #[default]
+    Never,
+    /// Try again up to `attempts` times, `delay` apart, e.g. while Firefox restarts.
+    Retry { attempts: u32, delay: Duration },
+}
+
+impl ReconnectPolicy {
+    /// Connect according to the policy; returns the last error if every attempt fails.
+    pub fn connect(
+        &self,
+        settings: &MarionetteSettings,
+    ) -> Result<MarionetteConnection, Box<dyn std::error::Error>> {
+        let (attempts, delay) = match *self {
+            ReconnectPolicy::Never => (1, Duration::ZERO),
+            ReconnectPolicy::Retry { attempts, delay } => (attempts.max(1), delay),
+        };
+        let mut attempt = 1;
+        loop {
+            match MarionetteConnection::connect(settings) {
+                Ok(connection) => return Ok(connection),
+                Err(e) if attempt >= attempts => return Err(e),
+                Err(_) => {
+                    attempt += 1;
+                    std::thread::sleep(delay);
+                }
+            }
         }
     }
 }
 
+/// The browser at the other end of a connection.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BrowserInfo {
+    /// Application name, e.g. `Firefox`.
+    pub name: String,
+    /// Application ID, `{ec8030f7-c20a-464f-9b0e-13a3a9e97384}` for Firefox.
+    pub app_id: String,
+    pub version: String,
+    /// Gecko version, the same as `version` in Firefox itself.
+    pub platform_version: String,
+    pub build_id: String,
+    /// Update channel: `release`, `beta`, `nightly`, `esr` or `default` for local builds.
+    pub channel: String,
+    /// Operating system as Gecko names it: `Linux`, `WINNT` or `Darwin`.
+    pub platform: String,
+    /// Operating system version, empty if the browser does not say.
+    pub os_version: String,
+    /// Processor and compiler, e.g. `x86_64-gcc3`.
+    pub abi: String,
+    pub headless: bool,
+}
+
+impl BrowserInfo {
+    /// The major version, e.g. 128 for `128.0.3esr`.
+    pub fn major_version(&self) -> Option<u32> {
+        let digits: String = self
+            .version
+            .chars()
+            .take_while(|c| c.is_ascii_digit())
+            .collect();
+        digits.parse().ok()
+    }
+
+    /// Whether the browser is at least version `major`; false if its version
+    /// cannot be read.
+    pub fn at_least(&self, major: u32) -> bool {
+        self.major_version().is_some_and(|v| v >= major)
+    }
+}
+
+/// The [`BrowserInfo`] fields that WebDriver capabilities give.
+fn capability_fields(caps: &Value) -> Vec<(String, Value)> {
+    let mut fields = Vec::new();
+    let mut copy = |field: &str, value: &Value| {
+        if !value.is_null() {
+            fields.push((field.to_string(), value.clone()));
+        }
+    };
+    copy("version", &caps["browserVersion"]);
+    copy("build_id", &caps["moz:buildID"]);
+    copy("platform_version", &caps["moz:platformVersion"]);
+    copy("headless", &caps["moz:headless"]);
+    // Gecko's names for the platforms WebDriver names
+    let platform = match caps["platformName"].as_str() {
+        Some("linux") => "Linux",
+        Some("windows") => "WINNT",
+        Some("mac") => "Darwin",
+        _ => return fields,
+    };
+    fields.push(("platform".to_string(), Value::from(platform)));
+    fields
+}
+
 pub struct MarionetteConnection {
-    client: MarionetteClient,
+    client: Box<dyn Connection>,
+    browser_info: Option<BrowserInfo>,
+    app: Option<AppProfile>,
+}
+
+/// WebDriver BiDi needs a WebSocket client, which WASI builds leave out.
+#[cfg(not(target_family = "wasm"))]
+fn bidi_handshake(
+    stream: Stream,
+    settings: &MarionetteSettings,
+) -> Result<Box<dyn Connection>, Box<dyn std::error::Error>> {
+    let host = match settings.socket {
+        Some(_) => "localhost",
+        None => settings.host.as_str(),
+    };
+    Ok(Box::new(BidiClient::handshake(
+        stream,
+        host,
+        settings.port,
+    )?))
+}
+
+#[cfg(target_family = "wasm")]
+fn bidi_handshake(
+    _stream: Stream,
+    _settings: &MarionetteSettings,
+) -> Result<Box<dyn Connection>, Box<dyn std::error::Error>> {
+    Err("WebDriver BiDi is not available in WASI builds".into())
 }
 
 impl MarionetteConnection {
     pub fn connect(settings: &MarionetteSettings) -> Result<Self, Box<dyn std::error::Error>> {
-        let client = MarionetteClient::connect(&settings.host, settings.port)?;
-        Ok(MarionetteConnection { client })
+        let stream = match &settings.socket {
+            Some(path) => Stream::connect_unix(path)?,
+            None => Stream::connect_tcp(&settings.host, settings.port)?,
+        };
+        let client: Box<dyn Connection> = match settings.backend {
+            Backend::Marionette => Box::new(MarionetteClient::handshake(stream)?),
+            Backend::Rdp => Box::new(RdpClient::handshake(stream)?),
+            Backend::Bidi => bidi_handshake(stream, settings)?,
+        };
+        Ok(MarionetteConnection {
+            client,
+            browser_info: None,
+            app: settings.app.clone(),
+        })
+    }
+
+    /// Describe the connected browser. Asked once per connection, which must
+    /// be in the chrome context for the first call. What the new session's
+    /// capabilities say wins over the chrome script, which fills in the rest.
+    pub fn browser_info(&mut self) -> Result<BrowserInfo, Box<dyn std::error::Error>> {
+        if let Some(info) = &self.browser_info {
+            return Ok(info.clone());
+        }
+        let mut result = self.execute_script(chrome_js::BROWSER_INFO, None)?;
+        if let (Some(fields), Some(caps)) = (result.as_object_mut(), self.client.capabilities()) {
+            fields.extend(capability_fields(caps));
+        }
+        let info: BrowserInfo = serde_json::from_value(result)
+            .map_err(|e| format!("Unexpected browser info from Firefox: {}", e))?;
+        self.browser_info = Some(info.clone());
+        Ok(info)
+    }
+
+    /// The profile set in [`MarionetteSettings::app`], or else the one
+    /// detected from [`Self::browser_info`].
+    pub fn app_profile(&mut self) -> Result<AppProfile, Box<dyn std::error::Error>> {
+        if self.app.is_none() {
+            self.app = Some(AppProfile::detect(&self.browser_info()?));
+        }
+        Ok(self.app.clone().unwrap_or_default())
+    }
+
+    /// Read a file through the browser, which can reach its profile even when
+    /// this process cannot, e.g. in a WASI build or on another machine. `path`
+    /// is absolute, in the browser's terms. Returns `None` if there is no such
+    /// file. Needs the chrome context and a backend that awaits promises,
+    /// which RDP does not.
+    pub fn read_browser_file(
+        &mut self,
+        path: &str,
+    ) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> {
+        let result = self.execute_script(chrome_js::READ_FILE, Some(vec![Value::from(path)]))?;
+        match result.as_str() {
+            Some(content) => Ok(Some(general_purpose::URL_SAFE.decode(content)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Write a file through the browser, creating its directory; see
+    /// [`Self::read_browser_file`].
+    pub fn write_browser_file(
+        &mut self,
+        path: &str,
+        bytes: &[u8],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let content = general_purpose::URL_SAFE.encode(bytes);
+        self.execute_script(
+            chrome_js::WRITE_FILE,
+            Some(vec![Value::from(path), Value::from(content)]),
+        )?;
+        Ok(())
+    }
+
+    /// Remove a file, or a directory with everything in it, through the
+    /// browser; see [`Self::read_browser_file`]. A missing file is not an error.
+    pub fn remove_browser_file(&mut self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        self.execute_script(chrome_js::REMOVE_FILE, Some(vec![Value::from(path)]))?;
+        Ok(())
+    }
+
+    /// The profile directory of the browser, in its own terms.
+    pub fn browser_profile_dir(&mut self) -> Result<String, Box<dyn std::error::Error>> {
+        let result = self.execute_script(
+            "return Services.dirsvc.get(\"ProfD\", Ci.nsIFile).path;",
+            None,
+        )?;
+        result
+            .as_str()
+            .map(String::from)
+            .ok_or_else(|| "The browser did not report its profile directory".into())
+    }
+
+    /// Send any Marionette command, e.g. `WebDriver:Navigate`.
+    pub fn send_command(
+        &mut self,
+        name: &str,
+        params: Value,
+    ) -> Result<Value, Box<dyn std::error::Error>> {
+        self.client.send_command(name, params)
+    }
+
+    /// Send any Marionette command and return the whole response, errors
+    /// included, for debugging protocol features mus-uc-devtools does not
+    /// wrap yet.
+    pub fn send_raw(
+        &mut self,
+        name: &str,
+        params: Value,
+    ) -> Result<Value, Box<dyn std::error::Error>> {
+        self.client.send_raw(name, params)
     }
 
     pub fn set_context(&mut self, context: &str) -> Result<(), Box<dyn std::error::Error>> {
@@ -142,3 +560,47 @@ impl MarionetteConnection {
         self.client.execute_script(script, args)
     }
 }
+
+/// `parts` appended to `base`, a path in the browser's terms, with the
+/// separator `base` uses. The browser may run on another OS, e.g. Windows
+/// under WSL.
+pub fn browser_path(base: &str, parts: &[&str]) -> String {
+    let separator = if base.contains('\\') && !base.contains('/') {
+        '\\'
+    } else {
+        '/'
+    };
+    let mut path = base.trim_end_matches(['/', '\\']).to_string();
+    for part in parts {
+        path.push(separator);
+        path.push_str(&part.replace('/', &separator.to_string()));
+    }
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::capability_fields;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn reads_browser_info_from_capabilities() {
+        let caps = json!({
+            "browserName": "firefox",
+            "browserVersion": "128.0.3",
+            "platformName": "windows",
+            "moz:buildID": "20240801000000",
+            "moz:headless": true,
+        });
+        let fields = capability_fields(&caps);
+        assert_eq!(
+            fields,
+            vec![
+                ("version".to_string(), Value::from("128.0.3")),
+                ("build_id".to_string(), Value::from("20240801000000")),
+                ("headless".to_string(), Value::from(true)),
+                ("platform".to_string(), Value::from("WINNT")),
+            ]
+        );
+    }
+}
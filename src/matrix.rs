@@ -0,0 +1,272 @@
+//! Capturing the same states under every combination of color scheme, accent
+//! color, toolbar density and window size, for the matrix runs of
+//! `scenario run`.
+//!
+//! ```toml
+//! [matrix]
+//! color-scheme = ["light", "dark"]
+//! accent = ["#0061e0", "#e66000"]
+//! density = ["normal", "compact"]
+//! size = ["1280x800", "800x600"]
+//! ```
+//!
+//! An axis left out keeps the browser's setting. The color scheme and accent
+//! color are the operating system's, overridden through Firefox's
+//! look-and-feel prefs, so the browser UI only follows them under themes that
+//! follow the system, such as the default one.
+
+use crate::chrome_js;
+use crate::contrast::contrast_ratio;
+use crate::marionette_client::MarionetteConnection;
+use crate::screenshot::parse_hex_color;
+use crate::shared_connection::SharedConnection;
+use crate::window::{density, parse_size, set_density, set_window_size, window_rect, Density};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::time::Duration;
+
+/// How long the UI is given to restyle before `f` of [`with_cell`] runs.
+const SETTLE_TIME: Duration = Duration::from_millis(300);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorScheme {
+    Light,
+    Dark,
+}
+
+impl ColorScheme {
+    pub const NAMES: &'static [&'static str] = &["light", "dark"];
+
+    pub fn parse(name: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        match name {
+            "light" => Ok(ColorScheme::Light),
+            "dark" => Ok(ColorScheme::Dark),
+            other => {
+                Err(format!("Unknown color scheme '{}' (expected light or dark)", other).into())
+            }
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ColorScheme::Light => "light",
+            ColorScheme::Dark => "dark",
+        }
+    }
+}
+
+/// The axes as written in the `[matrix]` table of `mus-uc.toml` or given on
+/// the command line.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct MatrixSettings {
+    #[serde(default)]
+    pub color_scheme: Vec<String>,
+    /// `#rrggbb` colors.
+    #[serde(default)]
+    pub accent: Vec<String>,
+    #[serde(default)]
+    pub density: Vec<String>,
+    /// `WIDTHxHEIGHT` outer window sizes.
+    #[serde(default)]
+    pub size: Vec<String>,
+}
+
+impl MatrixSettings {
+    pub fn is_empty(&self) -> bool {
+        self.color_scheme.is_empty()
+            && self.accent.is_empty()
+            && self.density.is_empty()
+            && self.size.is_empty()
+    }
+
+    /// Check the values, giving every combination of them.
+    pub fn cells(&self) -> Result<Vec<MatrixCell>, Box<dyn std::error::Error>> {
+        // An empty axis is one cell that leaves the setting alone
+        fn axis<T>(
+            values: &[String],
+            parse: impl Fn(&str) -> Result<T, Box<dyn std::error::Error>>,
+        ) -> Result<Vec<Option<T>>, Box<dyn std::error::Error>> {
+            if values.is_empty() {
+                return Ok(vec![None]);
+            }
+            values.iter().map(|value| parse(value).map(Some)).collect()
+        }
+
+        let color_schemes = axis(&self.color_scheme, ColorScheme::parse)?;
+        let accents = axis(&self.accent, |color| {
+            let [r, g, b, _] = parse_hex_color(color)?;
+            Ok(format!("#{:02x}{:02x}{:02x}", r, g, b))
+        })?;
+        let densities = axis(&self.density, Density::parse)?;
+        let sizes = axis(&self.size, parse_size)?;
+
+        let mut cells = Vec::new();
+        for color_scheme in &color_schemes {
+            for accent in &accents {
+                for density in &densities {
+                    for size in &sizes {
+                        cells.push(MatrixCell {
+                            color_scheme: *color_scheme,
+                            accent: accent.clone(),
+                            density: *density,
+                            size: *size,
+                        });
+                    }
+                }
+            }
+        }
+        Ok(cells)
+    }
+}
+
+/// One combination of settings; `None` leaves a setting as it is.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MatrixCell {
+    pub color_scheme: Option<ColorScheme>,
+    /// `#rrggbb`.
+    pub accent: Option<String>,
+    pub density: Option<Density>,
+    pub size: Option<(u32, u32)>,
+}
+
+impl MatrixCell {
+    /// A directory name for the cell's captures, e.g.
+    /// `dark-accent-e66000-compact-1280x800`.
+    pub fn name(&self) -> String {
+        let mut parts = Vec::new();
+        parts.extend(self.color_scheme.map(|scheme| scheme.as_str().to_string()));
+        parts.extend(
+            self.accent
+                .as_ref()
+                .map(|accent| format!("accent-{}", accent.trim_start_matches('#'))),
+        );
+        parts.extend(self.density.map(|density| density.as_str().to_string()));
+        parts.extend(
+            self.size
+                .map(|(width, height)| format!("{}x{}", width, height)),
+        );
+        if parts.is_empty() {
+            "default".to_string()
+        } else {
+            parts.join("-")
+        }
+    }
+
+    /// The prefs the cell sets, by name.
+    fn prefs(&self) -> Map<String, Value> {
+        let mut prefs = Map::new();
+        if let Some(scheme) = self.color_scheme {
+            let dark = i64::from(scheme == ColorScheme::Dark);
+            prefs.insert("ui.systemUsesDarkTheme".into(), Value::from(dark));
+        }
+        if let Some(accent) = &self.accent {
+            prefs.insert("ui.accentcolor".into(), Value::from(accent.as_str()));
+            prefs.insert(
+                "ui.accentcolortext".into(),
+                Value::from(accent_text_color(accent)),
+            );
+        }
+        prefs
+    }
+}
+
+/// Black or white, whichever reads better on `accent`.
+fn accent_text_color(accent: &str) -> &'static str {
+    let Ok([r, g, b, _]) = parse_hex_color(accent) else {
+        return "#ffffff";
+    };
+    let accent = [f64::from(r), f64::from(g), f64::from(b), 1.0];
+    let black = contrast_ratio(accent, [0.0, 0.0, 0.0, 1.0]);
+    let white = contrast_ratio(accent, [255.0, 255.0, 255.0, 1.0]);
+    if black > white {
+        "#000000"
+    } else {
+        "#ffffff"
+    }
+}
+
+/// The prefs, density and window size from before [`apply_cell`], to put
+/// back with [`restore_cell`].
+#[derive(Debug, Clone, Default)]
+pub struct SavedCell {
+    prefs: Map<String, Value>,
+    density: Option<Density>,
+    size: Option<(u32, u32)>,
+}
+
+fn set_prefs(
+    connection: &mut MarionetteConnection,
+    changes: Map<String, Value>,
+) -> Result<Map<String, Value>, Box<dyn std::error::Error>> {
+    if changes.is_empty() {
+        return Ok(changes);
+    }
+    let script = chrome_js::render(
+        chrome_js::MATRIX_CELL,
+        &[("changes", Value::Object(changes))],
+    );
+    Ok(serde_json::from_value(
+        connection.execute_script(&script, None)?,
+    )?)
+}
+
+/// Switch to the settings of `cell`. The connection must be in the chrome
+/// context.
+pub fn apply_cell(
+    connection: &mut MarionetteConnection,
+    cell: &MatrixCell,
+) -> Result<SavedCell, Box<dyn std::error::Error>> {
+    let size = match cell.size {
+        Some((width, height)) => {
+            let before = window_rect(connection)?;
+            set_window_size(connection, width, height)?;
+            Some((before.width, before.height))
+        }
+        None => None,
+    };
+    let previous_density = match cell.density {
+        Some(wanted) => {
+            let before = density(connection)?;
+            set_density(connection, wanted)?;
+            Some(before)
+        }
+        None => None,
+    };
+    let prefs = set_prefs(connection, cell.prefs())?;
+    Ok(SavedCell {
+        prefs,
+        density: previous_density,
+        size,
+    })
+}
+
+/// Put back what [`apply_cell`] changed.
+pub fn restore_cell(
+    connection: &mut MarionetteConnection,
+    saved: SavedCell,
+) -> Result<(), Box<dyn std::error::Error>> {
+    set_prefs(connection, saved.prefs)?;
+    if let Some(previous) = saved.density {
+        set_density(connection, previous)?;
+    }
+    if let Some((width, height)) = saved.size {
+        set_window_size(connection, width, height)?;
+    }
+    Ok(())
+}
+
+/// Run `f` with the settings of `cell`, and put them back afterwards whether
+/// or not it succeeds.
+pub fn with_cell<T>(
+    connection: &SharedConnection,
+    cell: &MatrixCell,
+    f: impl FnOnce() -> Result<T, Box<dyn std::error::Error>>,
+) -> Result<T, Box<dyn std::error::Error>> {
+    let saved = connection.with(|c| apply_cell(c, cell))?;
+    // Look and feel changes restyle the windows asynchronously
+    std::thread::sleep(SETTLE_TIME);
+    let result = f();
+    connection.with(|c| restore_cell(c, saved))?;
+    result
+}
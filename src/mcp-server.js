@@ -549,7 +549,10 @@ server.setRequestHandler(CallToolRequestSchema, async (request) => {
             .getService(Ci.nsIStyleSheetService);
           
           const cssStr = arguments[0];
-          const uri = Services.io.newURI("data:text/css," + encodeURIComponent(cssStr));
+          const uri = Services.io.newURI(
+            "data:text/css;charset=utf-8;mus-uc-devtools=" + encodeURIComponent(arguments[1]) +
+            "," + encodeURIComponent(cssStr)
+          );
           
           if (!sss.sheetRegistered(uri, sss.USER_SHEET)) {
             sss.loadAndRegisterSheet(uri, sss.USER_SHEET);
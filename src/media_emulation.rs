@@ -0,0 +1,182 @@
+//! Accessibility settings turned on in the browser, for `a11y-emulate` and the
+//! `--variant` option of `compare` and `scenario run`.
+//!
+//! The browser UI follows the operating system's settings through Firefox's
+//! look-and-feel prefs, which override them when set. Firefox derives both
+//! `prefers-contrast` and `forced-colors` in the browser UI from its
+//! accessibility theme switch, so they cannot be emulated separately.
+//! Like the prefs of `--ci`, the overrides are saved in the profile until
+//! reset.
+
+use crate::chrome_js;
+use crate::marionette_client::MarionetteConnection;
+use crate::shared_connection::SharedConnection;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::time::Duration;
+
+/// How long the UI is given to restyle before `f` of [`with_emulation`] runs.
+const SETTLE_TIME: Duration = Duration::from_millis(300);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MediaSetting {
+    /// `prefers-reduced-motion: reduce`.
+    ReducedMotion,
+    /// `prefers-reduced-transparency: reduce`, from Firefox 113.
+    ReducedTransparency,
+    /// `prefers-contrast: more` and `forced-colors: active`, with web pages
+    /// drawn in the system colors too.
+    HighContrast,
+}
+
+impl MediaSetting {
+    pub const NAMES: &'static [&'static str] =
+        &["reduced-motion", "reduced-transparency", "high-contrast"];
+    pub const ALL: &'static [MediaSetting] = &[
+        MediaSetting::ReducedMotion,
+        MediaSetting::ReducedTransparency,
+        MediaSetting::HighContrast,
+    ];
+
+    pub fn parse(name: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        match name {
+            "reduced-motion" => Ok(MediaSetting::ReducedMotion),
+            "reduced-transparency" => Ok(MediaSetting::ReducedTransparency),
+            "high-contrast" => Ok(MediaSetting::HighContrast),
+            other => Err(format!(
+                "Unknown setting '{}' (expected {})",
+                other,
+                Self::NAMES.join(", ")
+            )
+            .into()),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            MediaSetting::ReducedMotion => "reduced-motion",
+            MediaSetting::ReducedTransparency => "reduced-transparency",
+            MediaSetting::HighContrast => "high-contrast",
+        }
+    }
+
+    /// The prefs that turn the setting on or off, whatever the system says.
+    fn prefs(self, on: bool) -> Vec<(&'static str, i64)> {
+        let flag = i64::from(on);
+        match self {
+            MediaSetting::ReducedMotion => vec![("ui.prefersReducedMotion", flag)],
+            MediaSetting::ReducedTransparency => vec![("ui.prefersReducedTransparency", flag)],
+            // document_color_use: 2 always overrides page colors, 1 never does
+            MediaSetting::HighContrast => vec![
+                ("ui.useAccessibilityTheme", flag),
+                ("browser.display.document_color_use", if on { 2 } else { 1 }),
+            ],
+        }
+    }
+}
+
+/// What the browser window's media queries match.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MediaState {
+    pub reduced_motion: bool,
+    pub reduced_transparency: bool,
+    /// `more`, `less`, `custom` or `no-preference`.
+    pub contrast: String,
+    pub forced_colors: bool,
+}
+
+impl MediaState {
+    pub fn to_text(&self) -> String {
+        let flag = |on: bool, value: &'static str, off: &'static str| if on { value } else { off };
+        format!(
+            "prefers-reduced-motion: {}\nprefers-reduced-transparency: {}\nprefers-contrast: {}\nforced-colors: {}\n",
+            flag(self.reduced_motion, "reduce", "no-preference"),
+            flag(self.reduced_transparency, "reduce", "no-preference"),
+            self.contrast,
+            flag(self.forced_colors, "active", "none"),
+        )
+    }
+}
+
+/// Pref values from before [`emulate`], to put back with [`restore`].
+#[derive(Debug, Clone, Default)]
+pub struct SavedPrefs(Map<String, Value>);
+
+fn apply(
+    connection: &mut MarionetteConnection,
+    changes: Map<String, Value>,
+) -> Result<(SavedPrefs, MediaState), Box<dyn std::error::Error>> {
+    #[derive(Deserialize)]
+    struct Applied {
+        previous: Map<String, Value>,
+        media: MediaState,
+    }
+
+    let window_type = connection.app_profile()?.window_type;
+    let script = chrome_js::render(
+        chrome_js::MEDIA_EMULATION,
+        &[
+            ("windowType", Value::from(window_type)),
+            ("changes", Value::Object(changes)),
+        ],
+    );
+    let applied: Applied = serde_json::from_value(connection.execute_script(&script, None)?)?;
+    Ok((SavedPrefs(applied.previous), applied.media))
+}
+
+/// Turn `settings` on, or off with `on` false. The connection must be in the
+/// chrome context.
+pub fn emulate(
+    connection: &mut MarionetteConnection,
+    settings: &[MediaSetting],
+    on: bool,
+) -> Result<(SavedPrefs, MediaState), Box<dyn std::error::Error>> {
+    let changes = settings
+        .iter()
+        .flat_map(|setting| setting.prefs(on))
+        .map(|(name, value)| (name.to_string(), Value::from(value)))
+        .collect();
+    apply(connection, changes)
+}
+
+/// Put back the prefs [`emulate`] changed.
+pub fn restore(
+    connection: &mut MarionetteConnection,
+    saved: SavedPrefs,
+) -> Result<MediaState, Box<dyn std::error::Error>> {
+    Ok(apply(connection, saved.0)?.1)
+}
+
+/// Clear every override, so the browser follows the system again.
+pub fn reset(
+    connection: &mut MarionetteConnection,
+) -> Result<MediaState, Box<dyn std::error::Error>> {
+    let changes = MediaSetting::ALL
+        .iter()
+        .flat_map(|setting| setting.prefs(true))
+        .map(|(name, _)| (name.to_string(), Value::Null))
+        .collect();
+    Ok(apply(connection, changes)?.1)
+}
+
+/// What the window matches now, changing nothing.
+pub fn media_state(
+    connection: &mut MarionetteConnection,
+) -> Result<MediaState, Box<dyn std::error::Error>> {
+    Ok(apply(connection, Map::new())?.1)
+}
+
+/// Run `f` with `settings` turned on, and put the prefs back afterwards
+/// whether or not it succeeds.
+pub fn with_emulation<T>(
+    connection: &SharedConnection,
+    settings: &[MediaSetting],
+    f: impl FnOnce() -> Result<T, Box<dyn std::error::Error>>,
+) -> Result<T, Box<dyn std::error::Error>> {
+    let (saved, _) = connection.with(|c| emulate(c, settings, true))?;
+    // Look and feel changes restyle the windows asynchronously
+    std::thread::sleep(SETTLE_TIME);
+    let result = f();
+    connection.with(|c| restore(c, saved))?;
+    result
+}
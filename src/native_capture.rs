@@ -0,0 +1,103 @@
+//! Screenshots taken by the operating system instead of Firefox, for what
+//! `drawSnapshot` leaves out: on macOS, the native titlebar and the traffic
+//! lights that themes restyle with `-moz-window-transparent` and similar
+//! tricks.
+//!
+//! The capture runs `screencapture` on the part of the screen the window, or
+//! an element, covers. The window is raised first, but anything still on top
+//! of it shows, and the terminal needs the Screen Recording permission. Only
+//! builds with the `native-capture` feature on macOS capture; elsewhere
+//! [`capture`] returns an error.
+
+use crate::chrome_js;
+use crate::marionette_client::MarionetteConnection;
+use serde::Deserialize;
+use serde_json::Value;
+
+/// Where a window or element is on the screen, in desktop pixels (points on
+/// macOS).
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct ScreenRect {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+/// Raise the browser window and find where it, or the first element matching
+/// `selector`, is on the screen. The whole window includes the titlebar.
+pub fn screen_rect(
+    connection: &mut MarionetteConnection,
+    selector: Option<&str>,
+) -> Result<ScreenRect, Box<dyn std::error::Error>> {
+    let window_type = connection.app_profile()?.window_type;
+    let script = chrome_js::render(
+        chrome_js::SCREEN_RECT,
+        &[
+            ("windowType", Value::from(window_type)),
+            ("selector", selector.map_or(Value::Null, Value::from)),
+        ],
+    );
+    let rect: ScreenRect = serde_json::from_value(connection.execute_script(&script, None)?)?;
+    if rect.width < 1.0 || rect.height < 1.0 {
+        return Err(match selector {
+            Some(selector) => format!("{} has no size on screen", selector),
+            None => "The browser window has no size on screen".to_string(),
+        }
+        .into());
+    }
+    Ok(rect)
+}
+
+/// A PNG of the browser window as the OS draws it, or of the first element
+/// matching `selector`.
+#[cfg(all(feature = "native-capture", target_os = "macos"))]
+pub fn capture(
+    connection: &mut MarionetteConnection,
+    selector: Option<&str>,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    use std::process::Command;
+    use std::time::Duration;
+
+    /// How long the window manager gets to bring the window forward.
+    const RAISE_DELAY: Duration = Duration::from_millis(250);
+
+    let rect = screen_rect(connection, selector)?;
+    std::thread::sleep(RAISE_DELAY);
+    let file = tempfile::Builder::new().suffix(".png").tempfile()?;
+    let region = format!(
+        "{},{},{},{}",
+        rect.x.round(),
+        rect.y.round(),
+        rect.width.round(),
+        rect.height.round()
+    );
+    let output = Command::new("screencapture")
+        .args(["-x", "-t", "png", "-R", &region])
+        .arg(file.path())
+        .output()
+        .map_err(|e| format!("Failed to run screencapture: {}", e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "screencapture failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .into());
+    }
+    let png = std::fs::read(file.path())?;
+    if png.is_empty() {
+        // screencapture writes nothing when Screen Recording is not allowed
+        return Err("screencapture wrote no image; allow Screen Recording for this terminal in System Settings > Privacy & Security".into());
+    }
+    Ok(png)
+}
+
+/// A PNG of the browser window as the OS draws it, or of the first element
+/// matching `selector`.
+#[cfg(not(all(feature = "native-capture", target_os = "macos")))]
+pub fn capture(
+    _connection: &mut MarionetteConnection,
+    _selector: Option<&str>,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    Err("Native capture needs a macOS build with the native-capture feature".into())
+}
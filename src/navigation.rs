@@ -0,0 +1,78 @@
+//! Tab control for content testing: open a test page before a screenshot of
+//! userContent.css.
+//!
+//! These are WebDriver commands run in the content context. Each function
+//! switches back to the chrome context afterwards, which the other managers
+//! on the connection expect.
+
+use crate::marionette_client::MarionetteConnection;
+use serde_json::{json, Value};
+
+/// Load `url` in the current tab and wait for it to finish loading.
+pub fn navigate(
+    connection: &mut MarionetteConnection,
+    url: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    in_content(connection, |connection| {
+        connection.send_command("WebDriver:Navigate", json!({ "url": url }))?;
+        Ok(())
+    })
+}
+
+/// Open a tab, select it, and load `url` in it if given. Returns its handle.
+pub fn new_tab(
+    connection: &mut MarionetteConnection,
+    url: Option<&str>,
+) -> Result<String, Box<dyn std::error::Error>> {
+    in_content(connection, |connection| {
+        let result = connection.send_command("WebDriver:NewWindow", json!({ "type": "tab" }))?;
+        let handle = result["handle"]
+            .as_str()
+            .ok_or("Firefox did not return a handle for the new tab")?
+            .to_string();
+        select(connection, &handle)?;
+        if let Some(url) = url {
+            connection.send_command("WebDriver:Navigate", json!({ "url": url }))?;
+        }
+        Ok(handle)
+    })
+}
+
+/// Handles of the open tabs, across all windows.
+pub fn tab_handles(
+    connection: &mut MarionetteConnection,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    in_content(connection, |connection| {
+        let result = connection.send_command("WebDriver:GetWindowHandles", Value::Null)?;
+        Ok(serde_json::from_value(result)?)
+    })
+}
+
+/// Select the tab with `handle`, bringing it to the front.
+pub fn switch_tab(
+    connection: &mut MarionetteConnection,
+    handle: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    in_content(connection, |connection| select(connection, handle))
+}
+
+fn select(
+    connection: &mut MarionetteConnection,
+    handle: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    connection.send_command(
+        "WebDriver:SwitchToWindow",
+        json!({ "handle": handle, "focus": true }),
+    )?;
+    Ok(())
+}
+
+fn in_content<T>(
+    connection: &mut MarionetteConnection,
+    f: impl FnOnce(&mut MarionetteConnection) -> Result<T, Box<dyn std::error::Error>>,
+) -> Result<T, Box<dyn std::error::Error>> {
+    connection.set_context("content")?;
+    let result = f(connection);
+    connection.set_context("chrome")?;
+    result
+}
@@ -0,0 +1,66 @@
+//! Desktop notifications for watch mode.
+
+/// Which watch events raise a desktop notification.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum NotifyLevel {
+    #[default]
+    Off,
+    /// CSS errors, lost connections and unreadable files.
+    Errors,
+    /// Successful reloads as well as errors.
+    All,
+}
+
+impl NotifyLevel {
+    pub fn parse(name: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        match name {
+            "off" => Ok(NotifyLevel::Off),
+            "errors" => Ok(NotifyLevel::Errors),
+            "all" => Ok(NotifyLevel::All),
+            other => Err(format!(
+                "Unknown notification level '{}' (expected errors, all or off)",
+                other
+            )
+            .into()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Notifier {
+    level: NotifyLevel,
+}
+
+impl Notifier {
+    pub fn new(level: NotifyLevel) -> Self {
+        Notifier { level }
+    }
+
+    pub fn error(&self, summary: &str, body: &str) {
+        if self.level != NotifyLevel::Off {
+            show(summary, body);
+        }
+    }
+
+    pub fn info(&self, summary: &str, body: &str) {
+        if self.level == NotifyLevel::All {
+            show(summary, body);
+        }
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn show(summary: &str, body: &str) {
+    let result = notify_rust::Notification::new()
+        .appname("mus-uc-devtools")
+        .summary(summary)
+        .body(body)
+        .show();
+    // A missing notification daemon should not interrupt watching
+    if let Err(e) = result {
+        eprintln!("Could not show desktop notification: {}", e);
+    }
+}
+
+#[cfg(target_family = "wasm")]
+fn show(_summary: &str, _body: &str) {}
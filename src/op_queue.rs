@@ -0,0 +1,149 @@
+//! Taking manager operations on one connection in turn.
+//!
+//! The lock of a [`SharedConnection`] keeps single commands whole, but most
+//! manager operations are several commands: the harness check, installing
+//! the harness, then the script again. Two managers on one connection could
+//! interleave those. [`OpQueue`] runs whole operations one after another, in
+//! the order they arrived.
+//!
+//! It can also space operations out, for a caller such as an editor plugin
+//! that loads a sheet on every save, and refuse new ones while too many are
+//! waiting, so the caller can back off instead of piling up work.
+//!
+//! [`SharedConnection`]: crate::shared_connection::SharedConnection
+
+use std::fmt;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::{self, ThreadId};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct QueueSettings {
+    /// Least time from the start of one operation to the start of the next.
+    pub min_interval: Duration,
+    /// Refuse an operation when this many are already waiting; `None` for no
+    /// limit.
+    pub max_pending: Option<usize>,
+}
+
+/// The error of an operation refused because the queue is full. Callers can
+/// find it with `downcast_ref` to back off and try again.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueueFull {
+    pub pending: usize,
+}
+
+impl fmt::Display for QueueFull {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Busy: {} operations are already waiting for the browser; try again later",
+            self.pending
+        )
+    }
+}
+
+impl std::error::Error for QueueFull {}
+
+/// A first-come, first-served line for operations. Clones share the line.
+#[derive(Clone, Default)]
+pub struct OpQueue {
+    inner: Arc<(Mutex<Line>, Condvar)>,
+}
+
+#[derive(Default)]
+struct Line {
+    settings: QueueSettings,
+    /// The ticket the next operation to arrive gets.
+    next_ticket: u64,
+    /// The ticket whose turn it is.
+    serving: u64,
+    /// The thread running the current operation, which may queue nested
+    /// operations without waiting on itself.
+    holder: Option<ThreadId>,
+    last_start: Option<Instant>,
+}
+
+impl OpQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn settings(&self) -> QueueSettings {
+        self.line().settings
+    }
+
+    pub fn set_settings(&self, settings: QueueSettings) {
+        self.line().settings = settings;
+    }
+
+    /// How many operations are waiting for their turn, not counting the one
+    /// running.
+    pub fn pending(&self) -> usize {
+        self.line().waiting()
+    }
+
+    /// Run `op` once the operations ahead of it are done and the minimum
+    /// interval has passed, or fail at once with [`QueueFull`].
+    pub fn run<T>(
+        &self,
+        op: impl FnOnce() -> Result<T, Box<dyn std::error::Error>>,
+    ) -> Result<T, Box<dyn std::error::Error>> {
+        let (lock, turn) = &*self.inner;
+        let mut line = self.line();
+        if line.holder == Some(thread::current().id()) {
+            drop(line);
+            return op();
+        }
+        let busy = line.next_ticket != line.serving;
+        if let Some(max) = line.settings.max_pending {
+            let waiting = line.waiting();
+            if busy && waiting >= max {
+                return Err(QueueFull { pending: waiting }.into());
+            }
+        }
+        let ticket = line.next_ticket;
+        line.next_ticket += 1;
+        while line.serving != ticket {
+            line = turn.wait(line).unwrap_or_else(|e| e.into_inner());
+        }
+        line.holder = Some(thread::current().id());
+        let _done = Done(&self.inner);
+        if let Some(last) = line.last_start {
+            let ready = last + line.settings.min_interval;
+            let now = Instant::now();
+            if ready > now {
+                drop(line);
+                thread::sleep(ready - now);
+                line = lock.lock().unwrap_or_else(|e| e.into_inner());
+            }
+        }
+        line.last_start = Some(Instant::now());
+        drop(line);
+        op()
+    }
+
+    fn line(&self) -> MutexGuard<'_, Line> {
+        self.inner.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Line {
+    fn waiting(&self) -> usize {
+        let running = self.holder.is_some() as u64;
+        (self.next_ticket - self.serving).saturating_sub(running) as usize
+    }
+}
+
+/// Hands the turn on when an operation ends, even by panicking.
+struct Done<'a>(&'a (Mutex<Line>, Condvar));
+
+impl Drop for Done<'_> {
+    fn drop(&mut self) {
+        let (lock, turn) = self.0;
+        let mut line = lock.lock().unwrap_or_else(|e| e.into_inner());
+        line.holder = None;
+        line.serving += 1;
+        turn.notify_all();
+    }
+}
@@ -0,0 +1,209 @@
+//! Release packaging: bundle an exported theme with its assets into a zip archive.
+//!
+//! Archive layout, under a `<name>-<version>/` directory:
+//!
+//! ```text
+//! chrome/userChrome.css   the exported bundle
+//! chrome/...              referenced assets, at their paths relative to the theme
+//! chrome/chrome.manifest  when one is given
+//! theme.json              metadata
+//! INSTALL.md              installation instructions
+//! ```
+
+use crate::chrome_manifest::{normalize_path, ChromeManifest};
+use crate::css_assets::{AssetMode, AssetRewriter};
+use crate::css_format::CssFormat;
+use crate::css_imports::{CyclePolicy, ImportResolver};
+use crate::paths::canonicalize;
+use serde::Serialize;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use zip::write::SimpleFileOptions;
+use zip::ZipWriter;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ThemeMetadata {
+    pub name: String,
+    pub version: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub firefox: FirefoxRange,
+    pub files: Vec<String>,
+    pub generator: String,
+}
+
+/// Supported Firefox versions, both ends inclusive.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct FirefoxRange {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct PackageOptions {
+    pub entry: PathBuf,
+    pub name: String,
+    pub version: String,
+    pub description: Option<String>,
+    pub firefox: FirefoxRange,
+    pub manifest: Option<PathBuf>,
+    pub format: CssFormat,
+    pub cycle_policy: CyclePolicy,
+}
+
+#[derive(Debug, Clone)]
+pub struct PackageSummary {
+    pub metadata: ThemeMetadata,
+    pub warnings: Vec<String>,
+}
+
+/// Build a release archive at `out`.
+pub fn build_package(
+    options: &PackageOptions,
+    out: &Path,
+) -> Result<PackageSummary, Box<dyn std::error::Error>> {
+    let entry = canonicalize(&options.entry)?;
+    let entry_dir = entry.parent().unwrap_or(Path::new("/")).to_path_buf();
+
+    // First pass finds the assets, so the theme root can cover all of them
+    let probe = resolver(options, &entry_dir).resolve(&entry)?;
+    let root = probe
+        .assets
+        .iter()
+        .filter_map(|asset| asset.parent())
+        .fold(entry_dir.clone(), |root, dir| common_ancestor(&root, dir));
+
+    let resolved = resolver(options, &root).resolve(&entry)?;
+    let mut warnings = resolved.warnings.clone();
+    for import in &resolved.unresolved {
+        warnings.push(format!(
+            "unresolved @import '{}' in {}: {}",
+            import.url,
+            import.from.display(),
+            import.reason
+        ));
+    }
+    if let Some(manifest) = &options.manifest {
+        for issue in ChromeManifest::parse_file(manifest)?.issues {
+            warnings.push(format!(
+                "{}:{}: {}",
+                manifest.display(),
+                issue.line,
+                issue.message
+            ));
+        }
+    }
+    let css = options.format.apply(&resolved.css);
+
+    let prefix = format!("{}-{}", options.name, options.version);
+    let file_options = SimpleFileOptions::default();
+    let mut zip = ZipWriter::new(File::create(out)?);
+    let mut files = Vec::new();
+
+    let mut add = |zip: &mut ZipWriter<File>,
+                   name: String,
+                   data: &[u8]|
+     -> Result<(), Box<dyn std::error::Error>> {
+        zip.start_file(format!("{}/{}", prefix, name), file_options)?;
+        zip.write_all(data)?;
+        files.push(name);
+        Ok(())
+    };
+
+    add(
+        &mut zip,
+        "chrome/userChrome.css".to_string(),
+        css.as_bytes(),
+    )?;
+    for asset in &resolved.assets {
+        let relative = asset.strip_prefix(&root).unwrap_or(asset);
+        let relative: Vec<String> = relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy().into_owned())
+            .collect();
+        add(
+            &mut zip,
+            format!("chrome/{}", relative.join("/")),
+            &fs::read(asset)?,
+        )?;
+    }
+    if let Some(manifest) = &options.manifest {
+        add(
+            &mut zip,
+            "chrome/chrome.manifest".to_string(),
+            &fs::read(manifest)?,
+        )?;
+    }
+    add(
+        &mut zip,
+        "INSTALL.md".to_string(),
+        install_instructions(options).as_bytes(),
+    )?;
+
+    let metadata = ThemeMetadata {
+        name: options.name.clone(),
+        version: options.version.clone(),
+        description: options.description.clone(),
+        firefox: options.firefox.clone(),
+        files: files.clone(),
+        generator: format!("mus-uc-devtools {}", env!("CARGO_PKG_VERSION")),
+    };
+    zip.start_file(format!("{}/theme.json", prefix), file_options)?;
+    zip.write_all(serde_json::to_string_pretty(&metadata)?.as_bytes())?;
+    zip.finish()?;
+
+    Ok(PackageSummary { metadata, warnings })
+}
+
+fn resolver(options: &PackageOptions, output_dir: &Path) -> ImportResolver {
+    ImportResolver::new()
+        .with_cycle_policy(options.cycle_policy)
+        .with_assets(AssetRewriter::new(AssetMode::Rebase).with_output_dir(output_dir))
+}
+
+fn common_ancestor(a: &Path, b: &Path) -> PathBuf {
+    let b = normalize_path(b);
+    let mut root = PathBuf::new();
+    for (x, y) in normalize_path(a).components().zip(b.components()) {
+        if x != y {
+            break;
+        }
+        root.push(x.as_os_str());
+    }
+    root
+}
+
+fn install_instructions(options: &PackageOptions) -> String {
+    let range = match (&options.firefox.min, &options.firefox.max) {
+        (Some(min), Some(max)) => format!("Firefox {} to {}", min, max),
+        (Some(min), None) => format!("Firefox {} or later", min),
+        (None, Some(max)) => format!("Firefox {} or earlier", max),
+        (None, None) => "Firefox".to_string(),
+    };
+    format!(
+        r#"# {name} {version}
+
+Supports {range}.
+
+## Installation
+
+1. Open `about:config` and set `toolkit.legacyUserProfileCustomizations.stylesheets` to `true`.
+2. Open `about:support` and click "Open Folder" next to "Profile Folder".
+3. Copy the `chrome` folder from this archive into the profile folder. If a
+   `chrome` folder already exists, merge the contents and back up any existing
+   `userChrome.css` first.
+4. Restart Firefox.
+
+## Uninstallation
+
+Remove `chrome/userChrome.css` (and the files listed in `theme.json`) from the
+profile folder and restart Firefox.
+"#,
+        name = options.name,
+        version = options.version,
+        range = range,
+    )
+}
@@ -0,0 +1,132 @@
+//! Local paths as Firefox and CSS want them, whatever the OS.
+//!
+//! On Windows, `canonicalize` returns `\\?\C:\...` paths, which Firefox's
+//! `nsIFile` and `file://` URLs do not take, and paths may be on a drive or
+//! a UNC share. Paths handed to the browser go through [`canonicalize`], and
+//! URLs are built with [`file_url`] and read back with [`file_url_path`].
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// `path` made absolute with links resolved, without the `\\?\` prefix
+/// Windows adds.
+pub fn canonicalize(path: &Path) -> io::Result<PathBuf> {
+    Ok(strip_verbatim(&path.canonicalize()?))
+}
+
+/// `path` without a `\\?\` prefix: `\\?\C:\x` becomes `C:\x` and
+/// `\\?\UNC\server\share` becomes `\\server\share`. Other paths are returned
+/// as they are.
+pub fn strip_verbatim(path: &Path) -> PathBuf {
+    let Some(text) = path.to_str() else {
+        return path.to_path_buf();
+    };
+    if let Some(share) = text.strip_prefix(r"\\?\UNC\") {
+        return PathBuf::from(format!(r"\\{}", share));
+    }
+    match text.strip_prefix(r"\\?\") {
+        Some(rest) if has_drive(rest) => PathBuf::from(rest),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// Whether `text` starts with a drive, as in `C:`.
+fn has_drive(text: &str) -> bool {
+    let bytes = text.as_bytes();
+    bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':'
+}
+
+/// The `file://` URL of `path`: `file:///home/me/a.css`,
+/// `file:///C:/Users/me/a.css` or `file://server/share/a.css`.
+pub fn file_url(path: &Path) -> String {
+    let path = strip_verbatim(path).to_string_lossy().replace('\\', "/");
+    let path = encode(&path);
+    if let Some(share) = path.strip_prefix("//") {
+        format!("file://{}", share)
+    } else if path.starts_with('/') {
+        format!("file://{}", path)
+    } else {
+        format!("file:///{}", path)
+    }
+}
+
+/// The local path of a `file://` URL, or `None` for other URLs. The inverse
+/// of [`file_url`], also taking `file://localhost/...`.
+pub fn file_url_path(url: &str) -> Option<PathBuf> {
+    let prefix = url.get(..7)?;
+    if !prefix.eq_ignore_ascii_case("file://") {
+        return None;
+    }
+    let rest = decode(&url[7..]);
+    let rest = match rest.strip_prefix("localhost") {
+        Some(path) if path.starts_with('/') => path,
+        _ => &rest,
+    };
+    Some(PathBuf::from(if let Some(path) = rest.strip_prefix('/') {
+        if has_drive(path) {
+            // file:///C:/x
+            path.to_string()
+        } else {
+            rest.to_string()
+        }
+    } else {
+        // file://server/share
+        format!("//{}", rest)
+    }))
+}
+
+/// `url`, a local URL found in CSS, as a path: a `file://` URL, or a path
+/// relative to `base`. A query or fragment is dropped.
+pub fn local_url_path(base: &Path, url: &str) -> PathBuf {
+    let url = url.split(['?', '#']).next().unwrap_or(url);
+    if let Some(path) = file_url_path(url) {
+        return path;
+    }
+    let path = PathBuf::from(decode(url));
+    if path.is_absolute() {
+        path
+    } else {
+        base.join(path)
+    }
+}
+
+/// `path` with the characters that mean something else in a URL
+/// percent-encoded.
+pub fn encode(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+    for c in path.chars() {
+        match c {
+            '%' | '#' | '?' | ' ' | '"' | '\0'..='\x1f' | '\x7f' => {
+                encoded.push_str(&format!("%{:02X}", c as u8))
+            }
+            c => encoded.push(c),
+        }
+    }
+    encoded
+}
+
+/// `text` with `%XX` escapes decoded. A `%` not followed by two hex digits
+/// is kept.
+pub fn decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = (bytes[i] == b'%')
+            .then(|| text.get(i + 1..i + 3))
+            .flatten()
+            .filter(|hex| hex.bytes().all(|b| b.is_ascii_hexdigit()))
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match hex {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
@@ -0,0 +1,167 @@
+//! Sheets that survive a browser restart, for `load --persist` and
+//! `watch --persist`.
+//!
+//! Sheets registered through Marionette are gone when the browser restarts,
+//! leaving the UI unstyled until the tool reconnects. A persisted sheet is
+//! also written to `chrome/mus-uc-persisted/` in the profile, one file per
+//! sheet, and an `index.css` importing them is imported from the top of
+//! `userChrome.css`, which the browser loads at startup. Everything goes
+//! through the browser (see [`MarionetteConnection::read_browser_file`]), so
+//! it works wherever the tool runs.
+//!
+//! Startup only loads `userChrome.css` into browser windows, and only with
+//! `toolkit.legacyUserProfileCustomizations.stylesheets` on, so a persisted
+//! sheet acts as a user sheet for the browser UI whatever its sheet type.
+//! `clear --persisted` removes the directory and the import.
+
+use crate::marionette_client::{browser_path, MarionetteConnection};
+
+const PERSIST_DIR: &str = "mus-uc-persisted";
+
+/// The line added to the top of `userChrome.css`.
+const IMPORT_LINE: &str = "@import \"mus-uc-persisted/index.css\"; /* mus-uc-devtools: remove with `mus-uc clear --persisted` */";
+
+/// The persisted sheets of one profile.
+#[derive(Debug, Clone)]
+pub struct PersistStore {
+    /// The profile's `chrome` directory, in the browser's terms.
+    chrome: String,
+}
+
+impl PersistStore {
+    /// The store of the browser `connection` is connected to, which must be
+    /// in the chrome context.
+    pub fn open(connection: &mut MarionetteConnection) -> Result<Self, Box<dyn std::error::Error>> {
+        let profile = connection.browser_profile_dir()?;
+        Ok(PersistStore {
+            chrome: browser_path(&profile, &["chrome"]),
+        })
+    }
+
+    fn path(&self, name: &str) -> String {
+        browser_path(&self.chrome, &[PERSIST_DIR, name])
+    }
+
+    /// File names of the persisted sheets, in load order.
+    pub fn files(
+        &self,
+        connection: &mut MarionetteConnection,
+    ) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        let Some(index) = connection.read_browser_file(&self.path("index.css"))? else {
+            return Ok(Vec::new());
+        };
+        Ok(String::from_utf8_lossy(&index)
+            .lines()
+            .filter_map(|line| {
+                line.trim()
+                    .strip_prefix("@import \"")?
+                    .strip_suffix("\";")
+                    .map(String::from)
+            })
+            .collect())
+    }
+
+    /// Write the sheet `id` with `css`, replacing an earlier version, and
+    /// make sure `userChrome.css` imports the index.
+    pub fn save(
+        &self,
+        connection: &mut MarionetteConnection,
+        id: &str,
+        css: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let file = sheet_file(id);
+        connection.write_browser_file(&self.path(&file), css.as_bytes())?;
+        let mut files = self.files(connection)?;
+        if !files.contains(&file) {
+            files.push(file);
+            self.write_index(connection, &files)?;
+        }
+
+        let user_chrome = browser_path(&self.chrome, &["userChrome.css"]);
+        let current = connection
+            .read_browser_file(&user_chrome)?
+            .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+            .unwrap_or_default();
+        if !current.lines().any(|line| line == IMPORT_LINE) {
+            // Keep the file's line endings, CRLF if it was written on Windows
+            let newline = if current.contains("\r\n") {
+                "\r\n"
+            } else {
+                "\n"
+            };
+            // @import has to come first, after any @charset
+            let updated = match current.strip_prefix("@charset") {
+                Some(_) => {
+                    let (charset, rest) = current.split_once(newline).unwrap_or((&current, ""));
+                    format!("{}{}{}{}{}", charset, newline, IMPORT_LINE, newline, rest)
+                }
+                None => format!("{}{}{}", IMPORT_LINE, newline, current),
+            };
+            connection.write_browser_file(&user_chrome, updated.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Forget the sheet `id`. Returns whether it was persisted.
+    pub fn remove(
+        &self,
+        connection: &mut MarionetteConnection,
+        id: &str,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        let file = sheet_file(id);
+        let mut files = self.files(connection)?;
+        if !files.contains(&file) {
+            return Ok(false);
+        }
+        files.retain(|f| *f != file);
+        self.write_index(connection, &files)?;
+        connection.remove_browser_file(&self.path(&file))?;
+        Ok(true)
+    }
+
+    /// Remove every persisted sheet and the import from `userChrome.css`.
+    pub fn clear(
+        &self,
+        connection: &mut MarionetteConnection,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let user_chrome = browser_path(&self.chrome, &["userChrome.css"]);
+        if let Some(bytes) = connection.read_browser_file(&user_chrome)? {
+            let current = String::from_utf8_lossy(&bytes);
+            if current.lines().any(|line| line == IMPORT_LINE) {
+                let updated: Vec<&str> = current
+                    .split('\n')
+                    .filter(|line| line.trim_end_matches('\r') != IMPORT_LINE)
+                    .collect();
+                connection.write_browser_file(&user_chrome, updated.join("\n").as_bytes())?;
+            }
+        }
+        connection.remove_browser_file(&browser_path(&self.chrome, &[PERSIST_DIR]))
+    }
+
+    fn write_index(
+        &self,
+        connection: &mut MarionetteConnection,
+        files: &[String],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let index: String = files
+            .iter()
+            .map(|file| format!("@import \"{}\";\n", file))
+            .collect();
+        connection.write_browser_file(&self.path("index.css"), index.as_bytes())
+    }
+}
+
+/// The file a sheet is persisted in, named after its ID.
+pub fn sheet_file(id: &str) -> String {
+    let name: String = id
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("{}.css", name.trim_start_matches('.'))
+}
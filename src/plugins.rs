@@ -0,0 +1,145 @@
+//! Subcommands provided by other programs, as cargo does: `mus-uc foo ...`
+//! runs an executable named `mus-uc-foo` found on `PATH` with the remaining
+//! arguments, so features can be added without forking the tool.
+//!
+//! The plugin is told where the browser is through environment variables
+//! (see [`plugin_env`]), so it can connect with the same settings, or call
+//! back into `$MUS_UC`, which reads the same variables (see
+//! [`crate::config_layers`]), for anything the built-in commands already do.
+
+use crate::marionette_client::MarionetteSettings;
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// What the executables of plugins are named before the subcommand.
+pub const PREFIX: &str = "mus-uc-";
+
+/// The executable for the subcommand `name`: the first `mus-uc-<name>` on
+/// `PATH`.
+pub fn find(name: &str) -> Option<PathBuf> {
+    if name.is_empty() || name.contains(['/', '\\']) {
+        return None;
+    }
+    search_path().into_iter().find_map(|dir| {
+        executable_names(&format!("{}{}", PREFIX, name))
+            .into_iter()
+            .map(|file| dir.join(file))
+            .find(|path| is_plugin(path))
+    })
+}
+
+/// Every plugin on `PATH` by subcommand, each the one [`find`] would run.
+pub fn list() -> BTreeMap<String, PathBuf> {
+    let mut found = BTreeMap::new();
+    for dir in search_path() {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            // `mus-uc-foo.exe` on Windows
+            let file = if cfg!(windows) {
+                path.file_stem()
+            } else {
+                path.file_name()
+            };
+            let Some(name) = file
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.strip_prefix(PREFIX))
+            else {
+                continue;
+            };
+            if !name.is_empty() && is_plugin(&path) {
+                found.entry(name.to_string()).or_insert(path);
+            }
+        }
+    }
+    found
+}
+
+/// The environment a plugin runs with:
+///
+/// - `MUS_UC`: this executable, to call back into
+/// - `MUS_UC_HOST` and `MUS_UC_PORT`: where Marionette listens
+/// - `MUS_UC_SOCKET`: the Unix socket, if one is used
+/// - `MUS_UC_BACKEND`: `marionette`, `rdp` or `bidi`
+/// - `MUS_UC_APP`: the browser, if one was named
+/// - `MUS_UC_READ_ONLY`: `1` with `--read-only`, which makes every `$MUS_UC`
+///   the plugin runs read-only too
+pub fn plugin_env(settings: &MarionetteSettings, read_only: bool) -> Vec<(&'static str, OsString)> {
+    let mut env = Vec::new();
+    if let Ok(exe) = std::env::current_exe() {
+        env.push(("MUS_UC", exe.into_os_string()));
+    }
+    env.push(("MUS_UC_HOST", settings.host.clone().into()));
+    env.push(("MUS_UC_PORT", settings.port.to_string().into()));
+    if let Some(socket) = &settings.socket {
+        env.push(("MUS_UC_SOCKET", socket.clone().into_os_string()));
+    }
+    env.push(("MUS_UC_BACKEND", settings.backend.as_str().into()));
+    if let Some(app) = &settings.app {
+        env.push(("MUS_UC_APP", app.name.into()));
+    }
+    if read_only {
+        env.push(("MUS_UC_READ_ONLY", "1".into()));
+    }
+    env
+}
+
+/// Run the plugin at `path` with `args` and `env`, waiting for it to exit,
+/// and return its exit code.
+pub fn run(
+    path: &Path,
+    args: &[OsString],
+    env: &[(&'static str, OsString)],
+) -> Result<i32, Box<dyn std::error::Error>> {
+    let status = Command::new(path)
+        .args(args)
+        .envs(env.iter().map(|(name, value)| (name, value)))
+        .status()
+        .map_err(|e| format!("Could not run {}: {}", path.display(), e))?;
+    // Killed by a signal
+    Ok(status.code().unwrap_or(1))
+}
+
+/// Whether `path` is an executable other than this one, which may be
+/// installed as `mus-uc-devtools`.
+fn is_plugin(path: &Path) -> bool {
+    let this = std::env::current_exe().and_then(|exe| exe.canonicalize());
+    is_executable(path) && path.canonicalize().ok() != this.ok()
+}
+
+fn search_path() -> Vec<PathBuf> {
+    std::env::var_os("PATH")
+        .map(|path| std::env::split_paths(&path).collect())
+        .unwrap_or_default()
+}
+
+#[cfg(windows)]
+fn executable_names(name: &str) -> Vec<String> {
+    let extensions = std::env::var("PATHEXT").unwrap_or_else(|_| ".COM;.EXE;.BAT;.CMD".into());
+    extensions
+        .split(';')
+        .filter(|ext| !ext.is_empty())
+        .map(|ext| format!("{}{}", name, ext.to_ascii_lowercase()))
+        .collect()
+}
+
+#[cfg(not(windows))]
+fn executable_names(name: &str) -> Vec<String> {
+    vec![name.to_string()]
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata()
+        .is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
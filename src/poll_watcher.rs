@@ -0,0 +1,122 @@
+//! Finding file changes by reading the files at an interval, for
+//! `watch --poll`: on network mounts and in containers where change
+//! notifications never arrive, and in WASI builds, which have none. `watch`
+//! also falls back to it when the platform's watcher cannot be set up.
+//!
+//! Each poll hashes the watched files and reports those whose content
+//! changed, that appeared or that went away, so a save that only touches
+//! the modification time reloads nothing. Directories are listed too, so a
+//! new subdirectory is reported and can be watched in turn.
+
+use crate::watch::RecursiveMode;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// How often to poll when falling back from the platform's watcher.
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(1);
+
+pub struct PollWatcher {
+    interval: Duration,
+    watched: Vec<(PathBuf, RecursiveMode)>,
+    /// What each path held at the last poll: the hash of a file, `None` for
+    /// a directory.
+    seen: BTreeMap<PathBuf, Option<[u8; 32]>>,
+    last_poll: Instant,
+}
+
+impl PollWatcher {
+    pub fn new(interval: Duration) -> Self {
+        PollWatcher {
+            interval,
+            watched: Vec::new(),
+            seen: BTreeMap::new(),
+            last_poll: Instant::now(),
+        }
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Start watching `path`, a file or directory. What it holds now is the
+    /// baseline later polls compare with.
+    pub fn watch(
+        &mut self,
+        path: &Path,
+        mode: RecursiveMode,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if !path.exists() {
+            return Err(format!("{} does not exist", path.display()).into());
+        }
+        let mut found = BTreeMap::new();
+        scan(path, mode, &mut found);
+        for (file, state) in found {
+            self.seen.entry(file).or_insert(state);
+        }
+        self.watched.retain(|(p, _)| p != path);
+        self.watched.push((path.to_path_buf(), mode));
+        Ok(())
+    }
+
+    pub fn unwatch(&mut self, path: &Path) {
+        self.watched.retain(|(p, _)| p != path);
+    }
+
+    /// The paths that changed since the last poll, or `None` if the interval
+    /// has not passed yet.
+    pub fn poll(&mut self) -> Option<Vec<PathBuf>> {
+        if self.last_poll.elapsed() < self.interval {
+            return None;
+        }
+        self.last_poll = Instant::now();
+        let mut current = BTreeMap::new();
+        for (path, mode) in &self.watched {
+            scan(path, *mode, &mut current);
+        }
+        let mut changed: Vec<PathBuf> = current
+            .iter()
+            .filter(|(path, state)| self.seen.get(*path) != Some(state))
+            .map(|(path, _)| path.clone())
+            .collect();
+        // Gone from disk, rather than no longer watched
+        changed.extend(
+            self.seen
+                .keys()
+                .filter(|path| !current.contains_key(*path) && !path.exists())
+                .cloned(),
+        );
+        self.seen = current;
+        Some(changed)
+    }
+}
+
+/// Record what `path` holds, and with a directory what is in it: its direct
+/// entries, or everything under it with `RecursiveMode::Recursive`.
+fn scan(path: &Path, mode: RecursiveMode, found: &mut BTreeMap<PathBuf, Option<[u8; 32]>>) {
+    if !path.is_dir() {
+        if let Ok(content) = fs::read(path) {
+            found.insert(path.to_path_buf(), Some(Sha256::digest(content).into()));
+        }
+        return;
+    }
+    found.insert(path.to_path_buf(), None);
+    let Ok(entries) = fs::read_dir(path) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let child = entry.path();
+        if child.is_dir() {
+            match mode {
+                RecursiveMode::Recursive => scan(&child, mode, found),
+                RecursiveMode::NonRecursive => {
+                    found.insert(child, None);
+                }
+            }
+        } else {
+            scan(&child, mode, found);
+        }
+    }
+}
@@ -0,0 +1,129 @@
+//! Cropping, scaling down and recompressing screenshots before they are
+//! written, to keep the artifacts of CI runs and reports small.
+//!
+//! Every step is optional, and a capture nobody asked to change is written
+//! byte for byte as Firefox encoded it. A changed one is encoded anew from
+//! its pixels, which leaves out the text, time and physical size chunks of
+//! the original. With `optimize`, the encoder also does what `oxipng` does
+//! first: it drops an alpha channel that is opaque everywhere and color
+//! channels that are all gray, and tries the PNG row filters for each row
+//! under the strongest compression.
+
+use image::codecs::png::{CompressionType, FilterType, PngEncoder};
+use image::imageops::FilterType as ScaleFilter;
+use image::{DynamicImage, ImageEncoder, ImageFormat};
+
+/// A rectangle of the capture to keep, in its pixels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CropRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl CropRect {
+    /// Parse `X,Y,WIDTH,HEIGHT`, e.g. `0,0,800,120`.
+    pub fn parse(text: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let parts: Vec<u32> = text
+            .split(',')
+            .map(|part| part.trim().parse())
+            .collect::<Result<_, _>>()
+            .map_err(|_| format!("Invalid crop '{}', expected X,Y,WIDTH,HEIGHT", text))?;
+        let [x, y, width, height] = parts[..] else {
+            return Err(format!("Invalid crop '{}', expected X,Y,WIDTH,HEIGHT", text).into());
+        };
+        if width == 0 || height == 0 {
+            return Err(format!("Invalid crop '{}': the size must not be zero", text).into());
+        }
+        Ok(CropRect {
+            x,
+            y,
+            width,
+            height,
+        })
+    }
+}
+
+/// What to do to a capture before it is written. The default leaves it
+/// alone.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct PostProcess {
+    /// Keep only this part, applied before `max_width`.
+    pub crop: Option<CropRect>,
+    /// Scale down, keeping the aspect ratio, to at most this many pixels
+    /// wide. Narrower captures are not scaled up.
+    pub max_width: Option<u32>,
+    /// Encode anew even if nothing else changes, to drop the metadata.
+    pub strip: bool,
+    /// Reduce the color type and compress as hard as the encoder can.
+    pub optimize: bool,
+}
+
+impl PostProcess {
+    /// Whether [`Self::apply`] returns its input as it is.
+    pub fn is_noop(&self) -> bool {
+        *self == PostProcess::default()
+    }
+
+    /// `png` with the steps applied.
+    pub fn apply(&self, png: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        if self.is_noop() {
+            return Ok(png.to_vec());
+        }
+        let mut image = image::load_from_memory_with_format(png, ImageFormat::Png)?;
+        if let Some(crop) = self.crop {
+            if crop.x.saturating_add(crop.width) > image.width()
+                || crop.y.saturating_add(crop.height) > image.height()
+            {
+                return Err(format!(
+                    "Crop {},{},{},{} is outside the {}x{} capture",
+                    crop.x,
+                    crop.y,
+                    crop.width,
+                    crop.height,
+                    image.width(),
+                    image.height()
+                )
+                .into());
+            }
+            image = image.crop_imm(crop.x, crop.y, crop.width, crop.height);
+        }
+        if let Some(max_width) = self.max_width {
+            if image.width() > max_width {
+                let height =
+                    (image.height() as u64 * max_width as u64 / image.width() as u64).max(1) as u32;
+                image = image.resize_exact(max_width, height, ScaleFilter::Triangle);
+            }
+        }
+        if self.optimize {
+            image = reduce(image);
+        }
+        let (compression, filter) = if self.optimize {
+            (CompressionType::Best, FilterType::Adaptive)
+        } else {
+            (CompressionType::Default, FilterType::Adaptive)
+        };
+        let mut out = Vec::new();
+        PngEncoder::new_with_quality(&mut out, compression, filter).write_image(
+            image.as_bytes(),
+            image.width(),
+            image.height(),
+            image.color().into(),
+        )?;
+        Ok(out)
+    }
+}
+
+/// `image` with the channels that carry nothing dropped.
+fn reduce(image: DynamicImage) -> DynamicImage {
+    let rgba = image.to_rgba8();
+    let opaque = rgba.pixels().all(|p| p[3] == 255);
+    let gray = rgba.pixels().all(|p| p[0] == p[1] && p[1] == p[2]);
+    match (gray, opaque) {
+        (true, true) => DynamicImage::ImageLuma8(image.to_luma8()),
+        (true, false) => DynamicImage::ImageLumaA8(image.to_luma_alpha8()),
+        (false, true) => DynamicImage::ImageRgb8(image.to_rgb8()),
+        (false, false) => DynamicImage::ImageRgba8(rgba),
+    }
+}
@@ -0,0 +1,151 @@
+//! Transformations applied to CSS just before it is registered.
+
+use crate::css_imports::{find_statement_end, is_at_keyword, skip_comment, strip_comments};
+use std::path::Path;
+
+/// Rewrites a stylesheet before it is loaded. `source` is the file the sheet
+/// was loaded from, if any.
+///
+/// Reported error lines are mapped back to the original files line by line,
+/// so a preprocessor that adds or removes lines shifts those locations.
+pub trait Preprocessor: Send {
+    fn process(
+        &self,
+        css: String,
+        source: Option<&Path>,
+    ) -> Result<String, Box<dyn std::error::Error>>;
+}
+
+impl<F> Preprocessor for F
+where
+    F: Fn(String, Option<&Path>) -> Result<String, Box<dyn std::error::Error>> + Send,
+{
+    fn process(
+        &self,
+        css: String,
+        source: Option<&Path>,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        self(css, source)
+    }
+}
+
+/// The XUL namespace, which old snippets declare as the default namespace.
+pub const XUL_NAMESPACE: &str = "http://www.mozilla.org/keymaster/gatekeeper/there.is.only.xul";
+
+/// Declares the XUL namespace as the default, as many older snippets expect,
+/// unless the sheet declares a default namespace of its own.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LegacyNamespace;
+
+impl Preprocessor for LegacyNamespace {
+    fn process(
+        &self,
+        css: String,
+        _source: Option<&Path>,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let end = prelude_end(&css);
+        let has_default = css[..end].split('@').any(|rule| {
+            let rule = strip_comments(rule);
+            let Some(namespace) = strip_prefix_ignore_case(&rule, "namespace") else {
+                return false;
+            };
+            let namespace = namespace.trim_start().to_ascii_lowercase();
+            namespace.starts_with("url(") || namespace.starts_with(['"', '\''])
+        });
+        if has_default {
+            return Ok(css);
+        }
+        // On the line of the prelude's end, so reported lines stay the same
+        Ok(format!(
+            "{}@namespace url(\"{}\");{}",
+            &css[..end],
+            XUL_NAMESPACE,
+            &css[end..]
+        ))
+    }
+}
+
+/// Wraps the sheet in `@-moz-document`, so it only applies to some documents,
+/// e.g. `chrome://browser/content/browser.xhtml`.
+#[derive(Debug, Clone, Default)]
+pub struct DocumentFilter {
+    /// URLs, or `@-moz-document` functions such as `url-prefix(about:)`.
+    pub documents: Vec<String>,
+}
+
+impl DocumentFilter {
+    pub fn new(documents: &[&str]) -> Self {
+        DocumentFilter {
+            documents: documents.iter().map(|d| d.to_string()).collect(),
+        }
+    }
+
+    fn condition(&self) -> String {
+        let functions = ["url(", "url-prefix(", "domain(", "regexp("];
+        self.documents
+            .iter()
+            .map(|document| {
+                if functions.iter().any(|f| document.starts_with(f)) {
+                    document.clone()
+                } else {
+                    format!("url(\"{}\")", document.replace('"', "\\\""))
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+impl Preprocessor for DocumentFilter {
+    fn process(
+        &self,
+        css: String,
+        _source: Option<&Path>,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        if self.documents.is_empty() {
+            return Ok(css);
+        }
+        // `@import`, `@namespace` and `@charset` cannot be nested, so they
+        // stay in front
+        let end = prelude_end(&css);
+        Ok(format!(
+            "{}@-moz-document {} {{{}\n}}\n",
+            &css[..end],
+            self.condition(),
+            css[end..].trim_end()
+        ))
+    }
+}
+
+/// The offset just past the leading `@charset`, `@import` and `@namespace`
+/// statements, which must come before any other rule.
+fn prelude_end(css: &str) -> usize {
+    let bytes = css.as_bytes();
+    let mut end = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'/' if bytes.get(i + 1) == Some(&b'*') => i = skip_comment(bytes, i),
+            b if b.is_ascii_whitespace() => i += 1,
+            b'@' if [&b"charset"[..], b"import", b"namespace"]
+                .iter()
+                .any(|keyword| is_at_keyword(bytes, i, keyword)) =>
+            {
+                let (statement_end, terminated) = find_statement_end(bytes, i);
+                if !terminated {
+                    break;
+                }
+                i = statement_end;
+                end = i;
+            }
+            _ => break,
+        }
+    }
+    end
+}
+
+fn strip_prefix_ignore_case<'a>(text: &'a str, prefix: &str) -> Option<&'a str> {
+    let head = text.get(..prefix.len())?;
+    head.eq_ignore_ascii_case(prefix)
+        .then(|| &text[prefix.len()..])
+}
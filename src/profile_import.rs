@@ -0,0 +1,186 @@
+//! Taking over a hand-maintained `userChrome.css` from a profile, for
+//! `import-profile`.
+//!
+//! Each local file the `userChrome.css` imports without conditions becomes a
+//! sheet of its own, loaded from that file so it can be reloaded and watched
+//! like any other. Whatever is left in `userChrome.css` itself, including
+//! imports under `layer()`, `supports()` or media conditions, becomes one more
+//! sheet. Sheets are loaded in the order the imports come in, so the cascade
+//! stays the same.
+//!
+//! A profile this process cannot read, as from a WASI build or with the
+//! browser on another machine, is first copied out through the browser with
+//! [`copy_through_browser`].
+
+use crate::chrome_css_manager::ChromeCSSManager;
+use crate::css_imports::{
+    local_import_path, parse_imports, strip_comments, ImportResolver, ResolvedStylesheet,
+};
+use crate::marionette_client::browser_path;
+use crate::paths::canonicalize;
+use crate::shared_connection::SharedConnection;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// How a `userChrome.css` is split into sheets.
+#[derive(Debug, Clone)]
+pub struct ImportPlan {
+    pub user_chrome: PathBuf,
+    /// Files loaded as sheets of their own, in import order.
+    pub parts: Vec<PathBuf>,
+    /// Whether `userChrome.css` has anything besides the imports of `parts`.
+    pub has_rest: bool,
+}
+
+/// A sheet loaded by [`import_profile`].
+#[derive(Debug, Clone)]
+pub struct ImportedSheet {
+    pub id: String,
+    /// The file the sheet was loaded from, or `userChrome.css` for its own
+    /// rules.
+    pub file: PathBuf,
+    /// Whether the sheet is what is left of `userChrome.css`, which is loaded
+    /// without a source so that reloading it does not bring the parts back.
+    pub rest: bool,
+    pub resolved: ResolvedStylesheet,
+}
+
+/// The `chrome/userChrome.css` of `profile`.
+pub fn user_chrome_path(profile: &Path) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let path = profile.join("chrome").join("userChrome.css");
+    if !path.is_file() {
+        return Err(format!("{} does not exist", path.display()).into());
+    }
+    Ok(path)
+}
+
+/// Work out how to split `user_chrome` without loading anything.
+pub fn plan_import(user_chrome: &Path) -> Result<ImportPlan, Box<dyn std::error::Error>> {
+    let css = fs::read_to_string(user_chrome)
+        .map_err(|e| format!("Cannot read {}: {}", user_chrome.display(), e))?;
+    let base = user_chrome.parent().unwrap_or(Path::new("."));
+
+    let mut parts: Vec<PathBuf> = Vec::new();
+    let mut rest = String::new();
+    let mut last = 0;
+    for rule in parse_imports(&css) {
+        rest.push_str(&css[last..rule.span.start]);
+        last = rule.span.end;
+        let conditional = rule.layer.is_some() || rule.supports.is_some() || rule.media.is_some();
+        let path = local_import_path(base, &rule.url);
+        if rule.is_external() || conditional || !path.is_file() {
+            rest.push_str(&css[rule.span.clone()]);
+            continue;
+        }
+        let path = canonicalize(&path)?;
+        if !parts.contains(&path) {
+            parts.push(path);
+        }
+    }
+    rest.push_str(&css[last..]);
+
+    Ok(ImportPlan {
+        user_chrome: user_chrome.to_path_buf(),
+        parts,
+        has_rest: !strip_comments(&rest).trim().is_empty(),
+    })
+}
+
+/// Split `user_chrome` and load the pieces. Stops at the first piece that
+/// fails to load; the ones before it stay loaded.
+pub fn import_user_chrome(
+    manager: &mut ChromeCSSManager,
+    user_chrome: &Path,
+) -> Result<Vec<ImportedSheet>, Box<dyn std::error::Error>> {
+    let plan = plan_import(user_chrome)?;
+    let mut sheets = Vec::new();
+    for part in &plan.parts {
+        let (id, resolved) = manager.load_css_file(part, None)?;
+        sheets.push(ImportedSheet {
+            id,
+            file: part.clone(),
+            rest: false,
+            resolved,
+        });
+    }
+
+    if plan.has_rest {
+        let resolved = ImportResolver::new()
+            .with_skipped(&plan.parts)
+            .resolve(&plan.user_chrome)?;
+        let id = manager.generate_id(Some(&plan.user_chrome), &resolved.css)?;
+        let id = manager.load_css(&resolved.css, Some(&id))?;
+        sheets.push(ImportedSheet {
+            id,
+            file: plan.user_chrome.clone(),
+            rest: true,
+            resolved,
+        });
+    }
+    Ok(sheets)
+}
+
+/// Copy `chrome/userChrome.css` of `profile`, a directory in the browser's
+/// terms, and the local files it imports into `dir`, reading them through the
+/// browser. Files outside the profile's `chrome` directory and assets used by
+/// `url()` are not copied. Returns the copy of `userChrome.css`.
+pub fn copy_through_browser(
+    connection: &SharedConnection,
+    profile: &str,
+    dir: &Path,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let chrome = browser_path(profile, &["chrome"]);
+    let mut queue = vec!["userChrome.css".to_string()];
+    let mut copied: Vec<String> = Vec::new();
+    while let Some(relative) = queue.pop() {
+        if copied.contains(&relative) {
+            continue;
+        }
+        let path = browser_path(&chrome, &[&relative]);
+        let Some(bytes) = connection.with(|c| c.read_browser_file(&path))? else {
+            if copied.is_empty() {
+                return Err(format!("{} does not exist", path).into());
+            }
+            // Reported as unresolved when the copy is loaded
+            continue;
+        };
+        let local = dir.join(&relative);
+        if let Some(parent) = local.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&local, &bytes)?;
+
+        let css = String::from_utf8_lossy(&bytes);
+        for rule in parse_imports(&css).iter().rev() {
+            if rule.is_external() {
+                continue;
+            }
+            if let Some(import) = chrome_relative(&relative, &rule.url) {
+                queue.push(import);
+            }
+        }
+        copied.push(relative);
+    }
+    Ok(dir.join("userChrome.css"))
+}
+
+/// `url`, imported by the file at `from`, as a `/`-separated path relative
+/// to the `chrome` directory; `None` if it is absolute or leaves the directory.
+fn chrome_relative(from: &str, url: &str) -> Option<String> {
+    let url = url.split(['?', '#']).next()?;
+    if url.starts_with('/') || url.contains(':') {
+        return None;
+    }
+    let mut parts: Vec<&str> = from.split('/').collect();
+    parts.pop();
+    for segment in url.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                parts.pop()?;
+            }
+            segment => parts.push(segment),
+        }
+    }
+    Some(parts.join("/"))
+}
@@ -0,0 +1,279 @@
+//! Project settings read from `mus-uc.toml`.
+//!
+//! ```toml
+//! [[watch]]
+//! file = "chrome/userChrome.css"
+//! id = "userchrome"
+//! dir = "chrome"
+//!
+//! [[watch]]
+//! file = "chrome/userContent.css"
+//!
+//! [[watch]]
+//! file = "chrome/tabs.css"
+//! group = "tabs"
+//!
+//! [[watch]]
+//! kind = "script"
+//! file = "chrome/tweaks.uc.js"
+//!
+//! [analyze]
+//! max-bytes = 65536
+//!
+//! [connection]
+//! port = 6000
+//!
+//! [retry]
+//! attempts = 10
+//! interval = 200
+//!
+//! [matrix]
+//! color-scheme = ["light", "dark"]
+//! density = ["normal", "compact"]
+//!
+//! [hooks]
+//! on_reload_error = "notify-send 'CSS broke' \"$MUS_UC_FILE\""
+//! ```
+//!
+//! A config can also be the root of a workspace of sub-projects, each a
+//! directory with a `mus-uc.toml` of its own:
+//!
+//! ```toml
+//! [workspace]
+//! members = ["base", "addons/compact-tabs"]
+//! ```
+
+use crate::analyze::AnalyzeThresholds;
+use crate::config_layers::ConnectionSettings;
+use crate::hooks::Hooks;
+use crate::matrix::MatrixSettings;
+use crate::retry::RetrySettings;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const CONFIG_FILE: &str = "mus-uc.toml";
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WatchKind {
+    /// A stylesheet, loaded with its local imports inlined.
+    #[default]
+    Css,
+    /// JavaScript run in the chrome context each time it changes.
+    Script,
+}
+
+/// One file watched by a `watch` session.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WatchTarget {
+    pub file: PathBuf,
+    #[serde(default)]
+    pub kind: WatchKind,
+    /// Sheet ID; derived from the file name when absent.
+    pub id: Option<String>,
+    /// Watch every file under this directory instead of just the import graph.
+    pub dir: Option<PathBuf>,
+    /// Files under `dir` not to watch, as `.gitignore` patterns (see
+    /// [`crate::watch_filter`]).
+    #[serde(default)]
+    pub ignore: Vec<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub description: Option<String>,
+    /// Group the sheet is in, for switching it with others by `toggle`.
+    pub group: Option<String>,
+}
+
+impl WatchTarget {
+    pub fn css(file: &Path, dir: Option<&Path>, id: Option<&str>) -> Self {
+        WatchTarget {
+            file: file.to_path_buf(),
+            kind: WatchKind::Css,
+            id: id.map(String::from),
+            dir: dir.map(Path::to_path_buf),
+            ignore: Vec::new(),
+            tags: Vec::new(),
+            description: None,
+            group: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProjectConfig {
+    #[serde(default)]
+    pub watch: Vec<WatchTarget>,
+    /// Limits checked by `analyze`.
+    #[serde(default)]
+    pub analyze: AnalyzeThresholds,
+    /// Where to reach the browser, over the user config and under the
+    /// environment and options (see [`crate::config_layers`]).
+    #[serde(default)]
+    pub connection: ConnectionSettings,
+    /// How long element-dependent commands wait for the UI.
+    #[serde(default)]
+    pub retry: RetrySettings,
+    /// The combinations `scenario run --matrix` captures.
+    #[serde(default)]
+    pub matrix: MatrixSettings,
+    #[serde(default)]
+    pub workspace: WorkspaceSettings,
+    /// Commands and scripts run on connecting, loading and reloading.
+    #[serde(default)]
+    pub hooks: Hooks,
+}
+
+/// The `[workspace]` table: sub-projects handled together, as `-p <member>`
+/// and `watch --all` do.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WorkspaceSettings {
+    /// Directories with a `mus-uc.toml` each, relative to the root config.
+    /// A member is named after the last part of its path.
+    #[serde(default)]
+    pub members: Vec<PathBuf>,
+}
+
+impl ProjectConfig {
+    /// The nearest `mus-uc.toml` in `start` or one of its ancestors.
+    pub fn find(start: &Path) -> Option<PathBuf> {
+        start
+            .ancestors()
+            .map(|dir| dir.join(CONFIG_FILE))
+            .find(|path| path.is_file())
+    }
+
+    /// The nearest `mus-uc.toml` in `start` or one of its ancestors that has
+    /// `[workspace]` members, so a workspace is found from inside a member.
+    pub fn find_workspace(start: &Path) -> Option<PathBuf> {
+        start
+            .ancestors()
+            .map(|dir| dir.join(CONFIG_FILE))
+            .filter(|path| path.is_file())
+            .find(|path| {
+                ProjectConfig::load(path).is_ok_and(|config| !config.workspace.members.is_empty())
+            })
+    }
+
+    /// The config a command reads: `config` if given, the config of the
+    /// workspace member named `member`, or the nearest one to `start`.
+    pub fn locate(
+        start: &Path,
+        config: Option<&Path>,
+        member: Option<&str>,
+    ) -> Result<Option<PathBuf>, Box<dyn std::error::Error>> {
+        let Some(member) = member else {
+            return Ok(config.map(Path::to_path_buf).or_else(|| Self::find(start)));
+        };
+        let root = match config {
+            Some(config) => config.to_path_buf(),
+            None => Self::find_workspace(start).ok_or_else(|| {
+                format!(
+                    "-p {} needs a mus-uc.toml with [workspace] members in this directory or its parents",
+                    member
+                )
+            })?,
+        };
+        let members = Self::load(&root)?.members();
+        match members.iter().find(|(name, _)| name == member) {
+            Some((_, path)) => Ok(Some(path.clone())),
+            None => Err(format!(
+                "No member '{}' in {} (members: {})",
+                member,
+                root.display(),
+                members
+                    .iter()
+                    .map(|(name, _)| name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+            .into()),
+        }
+    }
+
+    /// The workspace members by name, with the path of each one's config.
+    pub fn members(&self) -> Vec<(String, PathBuf)> {
+        self.workspace
+            .members
+            .iter()
+            .map(|dir| (member_name(dir), dir.join(CONFIG_FILE)))
+            .collect()
+    }
+
+    /// Read a config file. Relative paths in it are taken from the directory
+    /// the file is in, not the current directory.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+        let mut config: ProjectConfig =
+            toml::from_str(&content).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+        let base = path.parent().unwrap_or(Path::new("."));
+        config.hooks.dir = base.to_path_buf();
+        let mut ids = HashSet::new();
+        for target in &mut config.watch {
+            target.file = base.join(&target.file);
+            target.dir = target.dir.as_ref().map(|dir| base.join(dir));
+            if target.kind == WatchKind::Script
+                && (target.id.is_some()
+                    || target.dir.is_some()
+                    || !target.tags.is_empty()
+                    || target.group.is_some())
+            {
+                return Err(format!(
+                    "{}: script target {} cannot have an id, dir, tags or group",
+                    path.display(),
+                    target.file.display()
+                )
+                .into());
+            }
+            if target.dir.is_none() && !target.ignore.is_empty() {
+                return Err(format!(
+                    "{}: {} has ignore patterns but no dir to apply them to",
+                    path.display(),
+                    target.file.display()
+                )
+                .into());
+            }
+            if let Some(id) = &target.id {
+                if !ids.insert(id.clone()) {
+                    return Err(
+                        format!("{}: sheet ID '{}' is used twice", path.display(), id).into(),
+                    );
+                }
+            }
+        }
+        let mut names = HashSet::new();
+        for member in &mut config.workspace.members {
+            if !names.insert(member_name(member)) {
+                return Err(format!(
+                    "{}: two workspace members are named '{}'",
+                    path.display(),
+                    member_name(member)
+                )
+                .into());
+            }
+            *member = base.join(&*member);
+        }
+        Ok(config)
+    }
+
+    /// The stylesheet targets in `group`.
+    pub fn group(&self, group: &str) -> Vec<&WatchTarget> {
+        self.watch
+            .iter()
+            .filter(|t| t.kind == WatchKind::Css && t.group.as_deref() == Some(group))
+            .collect()
+    }
+}
+
+/// The name of the member in `dir`: the last part of the path.
+fn member_name(dir: &Path) -> String {
+    dir.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| dir.display().to_string())
+}
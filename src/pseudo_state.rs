@@ -0,0 +1,95 @@
+//! Force pseudo-classes such as `:hover` on browser UI elements, so styles
+//! that depend on them can be inspected and captured without holding the
+//! mouse in place.
+//!
+//! Locks are set with `InspectorUtils`, as the Inspector's `:hov` panel does,
+//! and stay until [`release_states`] or the browser restarts.
+
+use crate::chrome_js;
+use crate::marionette_client::MarionetteConnection;
+use serde_json::Value;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PseudoState {
+    Hover,
+    Active,
+    Focus,
+    FocusWithin,
+    FocusVisible,
+}
+
+impl PseudoState {
+    pub const NAMES: &'static [&'static str] = &[
+        ":hover",
+        ":active",
+        ":focus",
+        ":focus-within",
+        ":focus-visible",
+    ];
+
+    /// Parse a pseudo-class, with or without its leading colon.
+    pub fn parse(name: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        match name.strip_prefix(':').unwrap_or(name) {
+            "hover" => Ok(PseudoState::Hover),
+            "active" => Ok(PseudoState::Active),
+            "focus" => Ok(PseudoState::Focus),
+            "focus-within" => Ok(PseudoState::FocusWithin),
+            "focus-visible" => Ok(PseudoState::FocusVisible),
+            _ => Err(format!(
+                "Unknown state '{}' (expected one of: {})",
+                name,
+                Self::NAMES.join(", ")
+            )
+            .into()),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PseudoState::Hover => ":hover",
+            PseudoState::Active => ":active",
+            PseudoState::Focus => ":focus",
+            PseudoState::FocusWithin => ":focus-within",
+            PseudoState::FocusVisible => ":focus-visible",
+        }
+    }
+}
+
+/// Lock `states` on the first element matching `selector` in the most recent
+/// browser window. Returns a short description of the element, such as
+/// `toolbarbutton#back-button`. The connection must be in the chrome context.
+pub fn force_states(
+    connection: &mut MarionetteConnection,
+    selector: &str,
+    states: &[PseudoState],
+) -> Result<String, Box<dyn std::error::Error>> {
+    let states = states.iter().map(|s| Value::from(s.as_str())).collect();
+    let result = run(connection, Value::from(selector), Value::Array(states))?;
+    Ok(result.as_str().unwrap_or(selector).to_string())
+}
+
+/// Release every lock set with [`force_states`]. Returns how many elements
+/// were released.
+pub fn release_states(
+    connection: &mut MarionetteConnection,
+) -> Result<u64, Box<dyn std::error::Error>> {
+    let result = run(connection, Value::Null, Value::Array(Vec::new()))?;
+    Ok(result.as_u64().unwrap_or(0))
+}
+
+fn run(
+    connection: &mut MarionetteConnection,
+    selector: Value,
+    states: Value,
+) -> Result<Value, Box<dyn std::error::Error>> {
+    let window_type = connection.app_profile()?.window_type;
+    let script = chrome_js::render(
+        chrome_js::FORCE_STATE,
+        &[
+            ("windowType", Value::from(window_type)),
+            ("selector", selector),
+            ("states", states),
+        ],
+    );
+    connection.execute_script(&script, None)
+}
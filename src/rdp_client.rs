@@ -0,0 +1,208 @@
+//! A [`Connection`] over the DevTools remote debugging protocol (RDP), for
+//! browsers that cannot be started with Marionette.
+//!
+//! Firefox serves RDP when started with `--start-debugger-server 6000` and
+//! these prefs set:
+//!
+//! - `devtools.debugger.remote-enabled` and `devtools.chrome.enabled` to true
+//! - `devtools.debugger.prompt-connection` to false, or every connection
+//!   waits for a click in the browser
+//!
+//! Scripts are evaluated by the console of the parent process, like in the
+//! Browser Console, where `window` is the browser window. That is enough for
+//! the CSS harness. Only the chrome context exists, results must be JSON, and
+//! scripts cannot return promises. Marionette commands are not available.
+
+use crate::connection::{Connection, Stream};
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::time::Duration;
+
+/// The largest packet accepted. Long strings arrive in pieces, so real
+/// packets stay far below this; a larger length means a broken or hostile
+/// server, and is refused rather than allocated.
+const MAX_PACKET: usize = 256 * 1024 * 1024;
+
+pub struct RdpClient {
+    reader: BufReader<Stream>,
+    writer: Stream,
+    console: String,
+}
+
+impl RdpClient {
+    pub(crate) fn handshake(stream: Stream) -> Result<Self, Box<dyn std::error::Error>> {
+        stream.set_timeouts(Some(Duration::from_secs(60)))?;
+        let mut client = RdpClient {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+            console: String::new(),
+        };
+
+        let greeting = client.read_packet()?;
+        if greeting["from"] != "root" {
+            return Err(
+                format!("Unexpected greeting from the debugger server: {}", greeting).into(),
+            );
+        }
+
+        // Process 0 is the parent process, which runs the browser UI
+        let process = client.request("root", json!({ "type": "getProcess", "id": 0 }))?;
+        let descriptor = process["processDescriptor"]["actor"]
+            .as_str()
+            .ok_or("The debugger server did not describe the parent process")?
+            .to_string();
+        let target = client.request(&descriptor, json!({ "type": "getTarget" }))?;
+        client.console = target
+            .as_object()
+            .and_then(|fields| {
+                fields
+                    .values()
+                    .find_map(|form| form["consoleActor"].as_str())
+            })
+            .ok_or("The parent process has no console; is devtools.chrome.enabled set?")?
+            .to_string();
+        Ok(client)
+    }
+
+    /// Send a request to `actor` and wait for its reply, skipping events.
+    fn request(
+        &mut self,
+        actor: &str,
+        mut packet: Value,
+    ) -> Result<Value, Box<dyn std::error::Error>> {
+        packet["to"] = json!(actor);
+        self.write_packet(&packet)?;
+        loop {
+            let reply = self.read_packet()?;
+            if reply["from"] != actor || reply.get("type").is_some() {
+                continue;
+            }
+            if let Some(error) = reply.get("error") {
+                return Err(format!(
+                    "RDP error: {} {}",
+                    error,
+                    reply["message"].as_str().unwrap_or("")
+                )
+                .into());
+            }
+            return Ok(reply);
+        }
+    }
+
+    fn write_packet(&mut self, packet: &Value) -> Result<(), Box<dyn std::error::Error>> {
+        let text = serde_json::to_string(packet)?;
+        write!(self.writer, "{}:{}", text.len(), text)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// Read one `length:JSON` packet.
+    fn read_packet(&mut self) -> Result<Value, Box<dyn std::error::Error>> {
+        let mut length = Vec::new();
+        // Stop early on a length too long to be one
+        (&mut self.reader).take(16).read_until(b':', &mut length)?;
+        if length.pop() != Some(b':') {
+            return Err(if length.is_empty() {
+                "The debugger server closed the connection"
+            } else {
+                "Invalid packet from the debugger server"
+            }
+            .into());
+        }
+        let length: usize = std::str::from_utf8(&length)?
+            .trim()
+            .parse()
+            .map_err(|_| "Invalid packet from the debugger server")?;
+        if length > MAX_PACKET {
+            return Err(format!(
+                "The debugger server sent a packet of {} bytes, more than the {} allowed",
+                length, MAX_PACKET
+            )
+            .into());
+        }
+        let mut body = vec![0u8; length];
+        self.reader.read_exact(&mut body)?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    /// Evaluate `text` in the console and return the result grip.
+    fn evaluate(&mut self, text: &str) -> Result<Value, Box<dyn std::error::Error>> {
+        let console = self.console.clone();
+        let started = self.request(&console, json!({ "type": "evaluateJSAsync", "text": text }))?;
+        let id = started["resultID"].clone();
+        loop {
+            let packet = self.read_packet()?;
+            if packet["from"] != console.as_str()
+                || packet["type"] != "evaluationResult"
+                || packet["resultID"] != id
+            {
+                continue;
+            }
+            if let Some(message) = packet.get("exceptionMessage").and_then(Value::as_str) {
+                return Err(format!("Script error: {}", message).into());
+            }
+            return Ok(packet["result"].clone());
+        }
+    }
+
+    /// The text of a string grip, fetching long strings in full.
+    fn grip_string(&mut self, grip: &Value) -> Result<String, Box<dyn std::error::Error>> {
+        if let Some(text) = grip.as_str() {
+            return Ok(text.to_string());
+        }
+        if grip["type"] == "longString" {
+            let actor = grip["actor"]
+                .as_str()
+                .ok_or("Long string without an actor")?
+                .to_string();
+            let reply = self.request(
+                &actor,
+                json!({ "type": "substring", "start": 0, "end": grip["length"] }),
+            )?;
+            return Ok(reply["substring"].as_str().unwrap_or_default().to_string());
+        }
+        Err(format!("Unexpected result from the debugger server: {}", grip).into())
+    }
+}
+
+impl Connection for RdpClient {
+    fn set_context(&mut self, context: &str) -> Result<(), Box<dyn std::error::Error>> {
+        match context {
+            "chrome" => Ok(()),
+            other => {
+                Err(format!("The RDP backend only has the chrome context, not {}", other).into())
+            }
+        }
+    }
+
+    fn execute_script(
+        &mut self,
+        script: &str,
+        args: Option<Vec<Value>>,
+    ) -> Result<Value, Box<dyn std::error::Error>> {
+        // Run the script like Marionette does, and bring the result back as
+        // JSON text, since the console hands out object grips
+        let text = format!(
+            "(() => {{\n\
+             const value = (function () {{\n{}\n}}).apply(null, {});\n\
+             if (value && typeof value.then === \"function\") \
+             throw new Error(\"Scripts cannot return promises over RDP\");\n\
+             return JSON.stringify({{ value }});\n\
+             }})()",
+            script,
+            Value::Array(args.unwrap_or_default())
+        );
+        let grip = self.evaluate(&text)?;
+        let json = self.grip_string(&grip)?;
+        let result: Value = serde_json::from_str(&json)?;
+        Ok(result.get("value").cloned().unwrap_or(Value::Null))
+    }
+
+    fn send_command(
+        &mut self,
+        name: &str,
+        _params: Value,
+    ) -> Result<Value, Box<dyn std::error::Error>> {
+        Err(format!("{} needs Marionette; it is not available over RDP", name).into())
+    }
+}
@@ -0,0 +1,108 @@
+//! Waiting for browser UI that is not there yet: an element that appears once
+//! a panel finishes opening, or a window that is still starting.
+//!
+//! Element screenshots, forced states, simulated surfaces and scenario steps
+//! all go through one [`RetryPolicy`]. Only errors that waiting can fix are
+//! retried; a lost connection or a script error fails at once.
+
+use serde::Deserialize;
+use std::time::{Duration, Instant};
+
+/// What scripts report when the UI they look for is not there (yet).
+const RETRYABLE: &[&str] = &[
+    "No element matches",
+    "is not visible",
+    "No browser window found",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    /// Tries in all, including the first.
+    pub attempts: u32,
+    pub interval: Duration,
+    /// Give up once this much time has passed, whatever the attempts left.
+    pub timeout: Option<Duration>,
+}
+
+impl Default for RetryPolicy {
+    /// No retries.
+    fn default() -> Self {
+        RetryPolicy {
+            attempts: 1,
+            interval: Duration::from_millis(250),
+            timeout: None,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Run `op` until it succeeds, fails with an error waiting cannot fix,
+    /// or the attempts or time run out. Returns the last result.
+    pub fn run<T>(
+        &self,
+        mut op: impl FnMut() -> Result<T, Box<dyn std::error::Error>>,
+    ) -> Result<T, Box<dyn std::error::Error>> {
+        let started = Instant::now();
+        let mut attempt = 1;
+        loop {
+            match op() {
+                Err(e)
+                    if is_retryable(&*e) && attempt < self.attempts && self.has_time(started) =>
+                {
+                    std::thread::sleep(self.interval);
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn has_time(&self, started: Instant) -> bool {
+        self.timeout
+            .is_none_or(|timeout| started.elapsed() + self.interval <= timeout)
+    }
+}
+
+/// Whether `error` says something was missing from the UI.
+pub fn is_retryable(error: &dyn std::error::Error) -> bool {
+    let message = error.to_string();
+    RETRYABLE.iter().any(|pattern| message.contains(pattern))
+}
+
+/// Retry settings as written in `mus-uc.toml` or given on the command line;
+/// unset ones keep their current value.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct RetrySettings {
+    pub attempts: Option<u32>,
+    /// Milliseconds.
+    pub interval: Option<u64>,
+    /// Milliseconds.
+    pub timeout: Option<u64>,
+}
+
+impl RetrySettings {
+    /// Overwrite the settings set in `other`.
+    pub fn merge(&mut self, other: RetrySettings) {
+        self.attempts = other.attempts.or(self.attempts);
+        self.interval = other.interval.or(self.interval);
+        self.timeout = other.timeout.or(self.timeout);
+    }
+
+    /// The policy these settings describe. A timeout without a number of
+    /// attempts retries until the timeout.
+    pub fn policy(&self) -> RetryPolicy {
+        let default = RetryPolicy::default();
+        RetryPolicy {
+            attempts: match (self.attempts, self.timeout) {
+                (Some(attempts), _) => attempts.max(1),
+                (None, Some(_)) => u32::MAX,
+                (None, None) => default.attempts,
+            },
+            interval: self
+                .interval
+                .map_or(default.interval, Duration::from_millis),
+            timeout: self.timeout.map(Duration::from_millis),
+        }
+    }
+}
@@ -0,0 +1,466 @@
+//! Step scripts for capturing states that take more than a loaded sheet:
+//! open menus, hover styles, a panel three clicks deep.
+//!
+//! ```toml
+//! [[step]]
+//! click = "#PanelUI-menu-button"
+//!
+//! [[step]]
+//! wait_for = "#appMenu-popup[panelopen]"
+//!
+//! [[step]]
+//! screenshot = "app-menu"
+//!
+//! [[step]]
+//! hover = "#appMenu-zoom-controls"
+//!
+//! [[step]]
+//! screenshot = "zoom-hover"
+//! selector = "#appMenu-popup"
+//! ```
+//!
+//! The same steps can be written as JSON, `{"step": [{"click": "..."}, ...]}`.
+//! `macro record` writes scenario files from steps typed one per line, in the
+//! form [`Step::parse_line`] reads.
+
+use crate::chrome_css_manager::ChromeCSSManager;
+use crate::chrome_js;
+use crate::helpers::{parse_arg, Helper};
+use crate::simulate::{simulate, SimulateOptions, Surface};
+use crate::wait::{WaitCondition, DEFAULT_TIMEOUT};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Step {
+    /// Run JavaScript in the chrome context.
+    Exec(String),
+    Wait(Duration),
+    /// Wait until a condition holds, failing after `timeout`.
+    WaitFor {
+        condition: WaitCondition,
+        timeout: Duration,
+    },
+    /// Move the mouse over the first element matching a selector.
+    Hover(String),
+    Click(String),
+    /// Open a surface and keep it open, as `simulate` does.
+    Simulate {
+        surface: Surface,
+        query: Option<String>,
+    },
+    /// Run a built-in script from [`crate::helpers`].
+    Helper {
+        name: String,
+        args: BTreeMap<String, Value>,
+    },
+    /// Save a PNG named `name` in the output directory, of the window or of
+    /// the element matching `selector`.
+    Screenshot {
+        name: String,
+        selector: Option<String>,
+    },
+}
+
+/// The keys a step can do its one thing with.
+const ACTIONS: &str =
+    "exec, wait, wait_for, wait_until, hover, click, simulate, helper or screenshot";
+
+/// A step as written: exactly one action key, plus `selector` for
+/// screenshots, `timeout` for waits, `query` for the urlbar and `args` for
+/// helpers.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawStep {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    exec: Option<String>,
+    /// Milliseconds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    wait: Option<u64>,
+    /// A selector to wait for.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    wait_for: Option<String>,
+    /// A JavaScript expression to wait for.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    wait_until: Option<String>,
+    /// Milliseconds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timeout: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hover: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    click: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    simulate: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    query: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    helper: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    args: Option<BTreeMap<String, Value>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    screenshot: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    selector: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawScenario {
+    #[serde(default, alias = "steps")]
+    step: Vec<RawStep>,
+}
+
+impl TryFrom<RawStep> for Step {
+    type Error = String;
+
+    fn try_from(raw: RawStep) -> Result<Self, String> {
+        if raw.selector.is_some() && raw.screenshot.is_none() {
+            return Err("`selector` only goes with `screenshot`".to_string());
+        }
+        if raw.timeout.is_some() && raw.wait_for.is_none() && raw.wait_until.is_none() {
+            return Err("`timeout` only goes with `wait_for` or `wait_until`".to_string());
+        }
+        if raw.query.is_some() && raw.simulate.is_none() {
+            return Err("`query` only goes with `simulate`".to_string());
+        }
+        if raw.args.is_some() && raw.helper.is_none() {
+            return Err("`args` only goes with `helper`".to_string());
+        }
+        if let Some(name) = &raw.helper {
+            Helper::find(name)
+                .and_then(|helper| helper.check_args(raw.args.as_ref().unwrap_or(&BTreeMap::new())))
+                .map_err(|e| e.to_string())?;
+        }
+        let surface = raw
+            .simulate
+            .as_deref()
+            .map(Surface::parse)
+            .transpose()
+            .map_err(|e| e.to_string())?;
+        let timeout = raw.timeout.map_or(DEFAULT_TIMEOUT, Duration::from_millis);
+        let mut steps = Vec::new();
+        steps.extend(raw.exec.map(Step::Exec));
+        steps.extend(raw.wait.map(|ms| Step::Wait(Duration::from_millis(ms))));
+        steps.extend(raw.wait_for.map(|selector| Step::WaitFor {
+            condition: WaitCondition::Selector(selector),
+            timeout,
+        }));
+        steps.extend(raw.wait_until.map(|expression| Step::WaitFor {
+            condition: WaitCondition::Predicate(expression),
+            timeout,
+        }));
+        steps.extend(raw.hover.map(Step::Hover));
+        steps.extend(raw.click.map(Step::Click));
+        steps.extend(surface.map(|surface| Step::Simulate {
+            surface,
+            query: raw.query,
+        }));
+        steps.extend(raw.helper.map(|name| Step::Helper {
+            name,
+            args: raw.args.unwrap_or_default(),
+        }));
+        steps.extend(raw.screenshot.map(|name| Step::Screenshot {
+            name,
+            selector: raw.selector,
+        }));
+        match steps.len() {
+            1 => Ok(steps.remove(0)),
+            0 => Err(format!("expected one of {}", ACTIONS)),
+            _ => Err(format!("a step can only do one of {}", ACTIONS)),
+        }
+    }
+}
+
+impl From<&Step> for RawStep {
+    fn from(step: &Step) -> Self {
+        match step.clone() {
+            Step::Exec(js) => RawStep {
+                exec: Some(js),
+                ..Default::default()
+            },
+            Step::Wait(duration) => RawStep {
+                wait: Some(duration.as_millis() as u64),
+                ..Default::default()
+            },
+            Step::WaitFor { condition, timeout } => {
+                let timeout = (timeout != DEFAULT_TIMEOUT).then_some(timeout.as_millis() as u64);
+                match condition {
+                    WaitCondition::Selector(selector) => RawStep {
+                        wait_for: Some(selector),
+                        timeout,
+                        ..Default::default()
+                    },
+                    // The other conditions have no key of their own
+                    condition => RawStep {
+                        wait_until: Some(condition.to_expression()),
+                        timeout,
+                        ..Default::default()
+                    },
+                }
+            }
+            Step::Hover(selector) => RawStep {
+                hover: Some(selector),
+                ..Default::default()
+            },
+            Step::Click(selector) => RawStep {
+                click: Some(selector),
+                ..Default::default()
+            },
+            Step::Simulate { surface, query } => RawStep {
+                simulate: Some(surface.as_str().to_string()),
+                query,
+                ..Default::default()
+            },
+            Step::Helper { name, args } => RawStep {
+                helper: Some(name),
+                args: (!args.is_empty()).then_some(args),
+                ..Default::default()
+            },
+            Step::Screenshot { name, selector } => RawStep {
+                screenshot: Some(name),
+                selector,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Scenario {
+    pub steps: Vec<Step>,
+}
+
+impl Scenario {
+    /// Read a scenario from a `.json` file, or TOML otherwise.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+        let raw: RawScenario = if path.extension().is_some_and(|ext| ext == "json") {
+            serde_json::from_str(&content).map_err(|e| format!("{}: {}", path.display(), e))?
+        } else {
+            toml::from_str(&content).map_err(|e| format!("{}: {}", path.display(), e))?
+        };
+
+        let mut steps = Vec::new();
+        for (i, step) in raw.step.into_iter().enumerate() {
+            steps.push(
+                Step::try_from(step)
+                    .map_err(|e| format!("{}: step {}: {}", path.display(), i + 1, e))?,
+            );
+        }
+        if steps.is_empty() {
+            return Err(format!("{} has no steps", path.display()).into());
+        }
+        Ok(Scenario { steps })
+    }
+
+    /// Write the scenario to `path`, as JSON for a `.json` file and TOML
+    /// otherwise.
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let raw = RawScenario {
+            step: self.steps.iter().map(RawStep::from).collect(),
+        };
+        let content = if path.extension().is_some_and(|ext| ext == "json") {
+            serde_json::to_string_pretty(&raw)? + "\n"
+        } else {
+            toml::to_string(&raw)?
+        };
+        fs::write(path, content)?;
+        Ok(())
+    }
+}
+
+impl Step {
+    /// Read a step written on one line, as typed into `macro record`:
+    ///
+    /// ```text
+    /// exec window.gBrowser.selectedTab.toggleMuteAudio()
+    /// wait 300
+    /// wait-for #appMenu-popup[panelopen]
+    /// wait-until window.gBrowser.tabs.length > 1
+    /// hover #appMenu-zoom-controls
+    /// click #PanelUI-menu-button
+    /// simulate urlbar firefox
+    /// helper toggle-sidebar panel=viewHistorySidebar open=true
+    /// screenshot app-menu #appMenu-popup
+    /// ```
+    ///
+    /// What follows the action is taken whole, so selectors and scripts may
+    /// hold spaces; `simulate` takes a query and `screenshot` a selector
+    /// after the first word, and `helper` takes `name=value` arguments
+    /// separated by spaces.
+    pub fn parse_line(line: &str) -> Result<Self, String> {
+        let line = line.trim();
+        let (action, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let rest = rest.trim();
+        let required = |what: &str| {
+            if rest.is_empty() {
+                Err(format!("`{}` needs {}", action, what))
+            } else {
+                Ok(rest.to_string())
+            }
+        };
+        // The first word, and the rest if there is any
+        let split = |rest: &str| match rest.split_once(char::is_whitespace) {
+            Some((first, more)) => (first.to_string(), Some(more.trim().to_string())),
+            None => (rest.to_string(), None),
+        };
+        match action {
+            "exec" => Ok(Step::Exec(required("a script")?)),
+            "wait" => {
+                let ms: u64 = required("a number of milliseconds")?
+                    .parse()
+                    .map_err(|_| format!("Invalid number of milliseconds '{}'", rest))?;
+                Ok(Step::Wait(Duration::from_millis(ms)))
+            }
+            "wait-for" | "wait_for" => Ok(Step::WaitFor {
+                condition: WaitCondition::Selector(required("a selector")?),
+                timeout: DEFAULT_TIMEOUT,
+            }),
+            "wait-until" | "wait_until" => Ok(Step::WaitFor {
+                condition: WaitCondition::Predicate(required("a JavaScript expression")?),
+                timeout: DEFAULT_TIMEOUT,
+            }),
+            "hover" => Ok(Step::Hover(required("a selector")?)),
+            "click" => Ok(Step::Click(required("a selector")?)),
+            "simulate" => {
+                let (surface, query) = split(&required("a surface")?);
+                let surface = Surface::parse(&surface).map_err(|e| e.to_string())?;
+                Ok(Step::Simulate { surface, query })
+            }
+            "helper" => {
+                let (name, rest) = split(&required("a helper name")?);
+                let args = rest
+                    .iter()
+                    .flat_map(|rest| rest.split_whitespace())
+                    .map(parse_arg)
+                    .collect::<Result<BTreeMap<_, _>, _>>()
+                    .map_err(|e| e.to_string())?;
+                Helper::find(&name)
+                    .and_then(|helper| helper.check_args(&args))
+                    .map_err(|e| e.to_string())?;
+                Ok(Step::Helper { name, args })
+            }
+            "screenshot" => {
+                let (name, selector) = split(&required("a name")?);
+                Ok(Step::Screenshot { name, selector })
+            }
+            "" => Err("Empty step".to_string()),
+            other => Err(format!(
+                "Unknown step '{}' (expected exec, wait, wait-for, wait-until, hover, click, simulate, helper or screenshot)",
+                other
+            )),
+        }
+    }
+
+    /// Perform the step. Returns the file written by a screenshot step.
+    pub fn run(
+        &self,
+        manager: &ChromeCSSManager,
+        output: &Path,
+    ) -> Result<Option<PathBuf>, Box<dyn std::error::Error>> {
+        match self {
+            Step::Exec(js) => {
+                manager.connection().execute_script(js, None)?;
+            }
+            Step::Wait(duration) => std::thread::sleep(*duration),
+            Step::WaitFor { condition, timeout } => {
+                manager.wait_for(condition, *timeout)?;
+            }
+            Step::Hover(selector) => interact(manager, selector, "hover")?,
+            Step::Click(selector) => interact(manager, selector, "click")?,
+            Step::Simulate { surface, query } => {
+                let options = SimulateOptions {
+                    query: query.clone(),
+                    pin: true,
+                };
+                manager.retry_policy().run(|| {
+                    manager
+                        .connection()
+                        .with(|c| simulate(c, *surface, &options))
+                })?;
+            }
+            Step::Helper { name, args } => {
+                let helper = Helper::find(name)?;
+                manager.connection().with(|c| helper.run(c, args))?;
+            }
+            Step::Screenshot { name, selector } => {
+                let mut path = output.join(name);
+                if path.extension().is_none() {
+                    path.set_extension("png");
+                }
+                if let Some(dir) = path.parent() {
+                    fs::create_dir_all(dir)?;
+                }
+                manager.save_screenshot(&path, selector.as_deref())?;
+                return Ok(Some(path));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Hover or click `selector`, waiting for it as the manager's
+/// [`RetryPolicy`](crate::retry::RetryPolicy) allows.
+fn interact(
+    manager: &ChromeCSSManager,
+    selector: &str,
+    action: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    manager.retry_policy().run(|| {
+        manager.connection().with(|connection| {
+            let window_type = connection.app_profile()?.window_type;
+            let script = chrome_js::render(
+                chrome_js::INTERACT,
+                &[
+                    ("windowType", Value::from(window_type)),
+                    ("selector", Value::from(selector)),
+                    ("action", Value::from(action)),
+                ],
+            );
+            connection.execute_script(&script, None)?;
+            Ok(())
+        })
+    })
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Step::Exec(js) => {
+                let first_line = js.lines().next().unwrap_or_default();
+                write!(f, "exec {}", first_line)
+            }
+            Step::Wait(duration) => write!(f, "wait {}ms", duration.as_millis()),
+            Step::WaitFor { condition, .. } => write!(f, "wait for {}", condition),
+            Step::Hover(selector) => write!(f, "hover {}", selector),
+            Step::Click(selector) => write!(f, "click {}", selector),
+            Step::Simulate {
+                surface,
+                query: Some(query),
+            } => write!(f, "simulate {} {}", surface.as_str(), query),
+            Step::Simulate { surface, .. } => write!(f, "simulate {}", surface.as_str()),
+            Step::Helper { name, args } => {
+                write!(f, "helper {}", name)?;
+                for (key, value) in args {
+                    match value {
+                        Value::String(text) => write!(f, " {}={}", key, text)?,
+                        other => write!(f, " {}={}", key, other)?,
+                    }
+                }
+                Ok(())
+            }
+            Step::Screenshot {
+                name,
+                selector: Some(selector),
+            } => write!(f, "screenshot {} of {}", name, selector),
+            Step::Screenshot { name, .. } => write!(f, "screenshot {}", name),
+        }
+    }
+}
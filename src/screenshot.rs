@@ -1,77 +1,45 @@
+use crate::chrome_js;
 use crate::marionette_client::MarionetteConnection;
+use crate::shared_connection::SharedConnection;
 use base64::{engine::general_purpose, Engine as _};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 use std::fs;
 use std::path::Path;
 
+/// Takes screenshots over a connection shared with other managers, such as
+/// the one from [`crate::ChromeCSSManager::connection`].
 pub struct ScreenshotManager {
-    connection: MarionetteConnection,
+    connection: SharedConnection,
 }
 
 impl ScreenshotManager {
-    pub fn new(mut connection: MarionetteConnection) -> Result<Self, Box<dyn std::error::Error>> {
+    pub fn new(connection: &SharedConnection) -> Result<Self, Box<dyn std::error::Error>> {
         // Set context to chrome for privileged operations
         connection.set_context("chrome")?;
-        Ok(ScreenshotManager { connection })
+        Ok(ScreenshotManager {
+            connection: connection.clone(),
+        })
     }
 
     pub fn capture_full_screen(&mut self) -> Result<String, Box<dyn std::error::Error>> {
-        let script = r#"
-            const canvas = document.createElementNS("http://www.w3.org/1999/xhtml", "canvas");
-            const window = Services.wm.getMostRecentWindow("navigator:browser");
-            canvas.width = window.innerWidth;
-            canvas.height = window.innerHeight;
-            const ctx = canvas.getContext("2d");
-            ctx.drawWindow(window, 0, 0, canvas.width, canvas.height, "rgb(255,255,255)");
-            return canvas.toDataURL("image/png");
-        "#;
-
-        let result = self.connection.execute_script(script, None)?;
-        result
-            .as_str()
-            .ok_or("Failed to get data URL from screenshot".into())
-            .map(String::from)
+        self.connection
+            .with(|connection| capture_data_url(connection, None))
     }
 
     pub fn capture_element(
         &mut self,
         selector: &str,
     ) -> Result<String, Box<dyn std::error::Error>> {
-        let escaped_selector = selector.replace('\\', "\\\\").replace('"', "\\\"");
-        let script = format!(
-            r#"
-            const window = Services.wm.getMostRecentWindow("navigator:browser");
-            const element = window.document.querySelector("{}");
-            if (!element) throw new Error("Element not found: {}");
-            
-            const rect = element.getBoundingClientRect();
-            const canvas = document.createElementNS("http://www.w3.org/1999/xhtml", "canvas");
-            canvas.width = rect.width;
-            canvas.height = rect.height;
-            const ctx = canvas.getContext("2d");
-            ctx.drawWindow(window, rect.left, rect.top, rect.width, rect.height, "rgb(255,255,255)");
-            return canvas.toDataURL("image/png");
-        "#,
-            escaped_selector, escaped_selector
-        );
-
-        let result = self.connection.execute_script(&script, None)?;
-        result
-            .as_str()
-            .ok_or("Failed to get data URL from screenshot".into())
-            .map(String::from)
+        self.connection
+            .with(|connection| capture_data_url(connection, Some(selector)))
     }
 
     pub fn save_data_url_to_file(
         data_url: &str,
         output_path: &Path,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        let base64_data = data_url
-            .split(',')
-            .nth(1)
-            .ok_or("Invalid data URL format")?;
-
-        let image_data = general_purpose::STANDARD.decode(base64_data)?;
-        fs::write(output_path, image_data)?;
+        fs::write(output_path, decode_data_url(data_url)?)?;
         Ok(())
     }
 
@@ -93,48 +61,208 @@ pub fn take_screenshot(
     connection: &mut MarionetteConnection,
     selector: Option<&str>,
 ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-    let script = match selector {
-        Some(sel) => {
-            let escaped_selector = sel.replace('\\', "\\\\").replace('"', "\\\"");
-            format!(
-                r#"
-                const window = Services.wm.getMostRecentWindow("navigator:browser");
-                const element = window.document.querySelector("{}");
-                if (!element) throw new Error("Element not found: {}");
-                
-                const rect = element.getBoundingClientRect();
-                const canvas = document.createElementNS("http://www.w3.org/1999/xhtml", "canvas");
-                canvas.width = rect.width;
-                canvas.height = rect.height;
-                const ctx = canvas.getContext("2d");
-                ctx.drawWindow(window, rect.left, rect.top, rect.width, rect.height, "rgb(255,255,255)");
-                return canvas.toDataURL("image/png");
-            "#,
-                escaped_selector, escaped_selector
-            )
+    decode_data_url(&capture_data_url(connection, selector)?)
+}
+
+/// What takes a screenshot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CaptureMode {
+    /// Firefox draws the window itself, without the native titlebar.
+    #[default]
+    Browser,
+    /// The OS captures the screen where the window is, titlebar included.
+    /// See [`crate::native_capture`].
+    Native,
+}
+
+/// Versions from this one are captured with `drawSnapshot`, as Marionette
+/// does; older ones with the canvas `drawWindow`.
+const DRAW_SNAPSHOT_SINCE: u32 = 115;
+
+/// The longest data URL one script returns; larger captures come back in
+/// slices of this size, to stay well under what Marionette and the other
+/// backends carry in one message.
+const CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+fn capture_data_url(
+    connection: &mut MarionetteConnection,
+    selector: Option<&str>,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let snapshot = connection.browser_info()?.at_least(DRAW_SNAPSHOT_SINCE);
+    let window_type = connection.app_profile()?.window_type;
+    let script = chrome_js::render(
+        chrome_js::SCREENSHOT,
+        &[
+            ("windowType", Value::from(window_type)),
+            ("selector", selector.map_or(Value::Null, Value::from)),
+            ("snapshot", Value::from(snapshot)),
+            ("chunkSize", Value::from(CHUNK_SIZE)),
+        ],
+    );
+    let result = connection.execute_script(&script, None)?;
+    if let Some(url) = result.as_str() {
+        return Ok(url.to_string());
+    }
+    let (Some(key), Some(length)) = (result["key"].as_str(), result["length"].as_u64()) else {
+        return Err("Failed to get data URL from screenshot".into());
+    };
+    let length = length as usize;
+    let mut url = String::with_capacity(length);
+    while url.len() < length {
+        let chunk = connection.execute_script(
+            chrome_js::CAPTURE_CHUNK,
+            Some(vec![
+                Value::from(key),
+                Value::from(url.len()),
+                Value::from(CHUNK_SIZE),
+            ]),
+        )?;
+        match chunk.as_str() {
+            Some(chunk) if !chunk.is_empty() => url.push_str(chunk),
+            _ => return Err(format!("Screenshot {} ended early", key).into()),
+        }
+    }
+    Ok(url)
+}
+
+/// Where `probe-color` reads a pixel, relative to an element or the window.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProbePoint {
+    Center,
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+    /// CSS pixels right of and below the top left corner.
+    Offset(f64, f64),
+}
+
+impl ProbePoint {
+    /// Parse `center`, a corner such as `top-left`, or `X,Y`.
+    pub fn parse(point: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        match point {
+            "center" => Ok(ProbePoint::Center),
+            "top-left" => Ok(ProbePoint::TopLeft),
+            "top-right" => Ok(ProbePoint::TopRight),
+            "bottom-left" => Ok(ProbePoint::BottomLeft),
+            "bottom-right" => Ok(ProbePoint::BottomRight),
+            other => {
+                let invalid = || {
+                    format!(
+                        "Invalid point '{}' (expected X,Y, center, top-left, top-right, bottom-left or bottom-right)",
+                        other
+                    )
+                };
+                let (x, y) = other.split_once(',').ok_or_else(invalid)?;
+                let x: f64 = x.trim().parse().map_err(|_| invalid())?;
+                let y: f64 = y.trim().parse().map_err(|_| invalid())?;
+                Ok(ProbePoint::Offset(x, y))
+            }
+        }
+    }
+
+    fn to_value(self) -> Value {
+        match self {
+            ProbePoint::Center => Value::from("center"),
+            ProbePoint::TopLeft => Value::from("top-left"),
+            ProbePoint::TopRight => Value::from("top-right"),
+            ProbePoint::BottomLeft => Value::from("bottom-left"),
+            ProbePoint::BottomRight => Value::from("bottom-right"),
+            ProbePoint::Offset(x, y) => json!([x, y]),
+        }
+    }
+}
+
+/// The color of one rendered pixel, at window coordinates.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct PixelColor {
+    pub x: i64,
+    pub y: i64,
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+}
+
+impl PixelColor {
+    /// `#rrggbb`, or `#rrggbbaa` when not opaque.
+    pub fn hex(&self) -> String {
+        let rgb = format!("#{:02x}{:02x}{:02x}", self.r, self.g, self.b);
+        match self.a {
+            255 => rgb,
+            a => format!("{}{:02x}", rgb, a),
         }
-        None => r#"
-            const canvas = document.createElementNS("http://www.w3.org/1999/xhtml", "canvas");
-            const window = Services.wm.getMostRecentWindow("navigator:browser");
-            canvas.width = window.innerWidth;
-            canvas.height = window.innerHeight;
-            const ctx = canvas.getContext("2d");
-            ctx.drawWindow(window, 0, 0, canvas.width, canvas.height, "rgb(255,255,255)");
-            return canvas.toDataURL("image/png");
-        "#
-        .to_string(),
+    }
+
+    /// The largest difference in any channel from `rgba`.
+    pub fn distance(&self, rgba: [u8; 4]) -> u8 {
+        [self.r, self.g, self.b, self.a]
+            .iter()
+            .zip(rgba)
+            .map(|(a, b)| a.abs_diff(b))
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+/// Parse a hex color: `#rgb`, `#rgba`, `#rrggbb` or `#rrggbbaa`.
+pub fn parse_hex_color(color: &str) -> Result<[u8; 4], Box<dyn std::error::Error>> {
+    let invalid = || {
+        format!(
+            "Invalid color '{}' (expected #rgb, #rgba, #rrggbb or #rrggbbaa)",
+            color
+        )
     };
+    let digits = color.strip_prefix('#').ok_or_else(invalid)?;
+    if !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(invalid().into());
+    }
+    let channels: Vec<u8> = match digits.len() {
+        3 | 4 => digits
+            .chars()
+            .map(|c| c.to_digit(16).unwrap() as u8 * 17)
+            .collect(),
+        6 | 8 => (0..digits.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).unwrap())
+            .collect(),
+        _ => return Err(invalid().into()),
+    };
+    Ok([
+        channels[0],
+        channels[1],
+        channels[2],
+        channels.get(3).copied().unwrap_or(255),
+    ])
+}
 
-    let result = connection.execute_script(&script, None)?;
-    let data_url = result
-        .as_str()
-        .ok_or("Failed to get data URL from screenshot")?;
+/// The color of the pixel at `at` within the first element matching
+/// `selector`, or within the window.
+pub fn probe_color(
+    connection: &mut MarionetteConnection,
+    selector: Option<&str>,
+    at: ProbePoint,
+) -> Result<PixelColor, Box<dyn std::error::Error>> {
+    let snapshot = connection.browser_info()?.at_least(DRAW_SNAPSHOT_SINCE);
+    let window_type = connection.app_profile()?.window_type;
+    let script = chrome_js::render(
+        chrome_js::PROBE_COLOR,
+        &[
+            ("windowType", Value::from(window_type)),
+            ("selector", selector.map_or(Value::Null, Value::from)),
+            ("at", at.to_value()),
+            ("snapshot", Value::from(snapshot)),
+        ],
+    );
+    Ok(serde_json::from_value(
+        connection.execute_script(&script, None)?,
+    )?)
+}
 
+fn decode_data_url(data_url: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
     let base64_data = data_url
         .split(',')
         .nth(1)
         .ok_or("Invalid data URL format")?;
-
-    let image_data = general_purpose::STANDARD.decode(base64_data)?;
-    Ok(image_data)
+    Ok(general_purpose::STANDARD.decode(base64_data)?)
 }
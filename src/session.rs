@@ -0,0 +1,150 @@
+//! A portable record of the styling loaded in a browser, for a teammate to
+//! reproduce with one command.
+//!
+//! A session lists the registered chrome.manifest files, `resource://`
+//! aliases and every sheet in load order, with the exact CSS the browser has,
+//! so it does not depend on the files on the other machine. Each sheet carries a SHA-256 of its CSS to
+//! tell whether two sessions loaded the same thing.
+
+use crate::chrome_css_manager::ChromeCSSManager;
+use crate::sheet_state::SheetState;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Session {
+    pub firefox_version: String,
+    pub manifests: Vec<String>,
+    /// `resource://` aliases and the directories they map to.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub resources: BTreeMap<String, String>,
+    pub sheets: Vec<SessionSheet>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionSheet {
+    pub id: String,
+    /// `user`, `agent` or `author`.
+    #[serde(rename = "type")]
+    pub sheet_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    /// Hex SHA-256 of `css`.
+    pub sha256: String,
+    pub css: String,
+}
+
+impl Session {
+    /// Record what is loaded in the browser `manager` is connected to. Tags,
+    /// descriptions and groups come from the manager's state file.
+    pub fn capture(manager: &mut ChromeCSSManager) -> Result<Self, Box<dyn std::error::Error>> {
+        let firefox_version = manager.connection().browser_info()?.version;
+        let manifests = manager.registered_manifests()?;
+        let resources = manager.registered_resources()?;
+        let state = SheetState::load_in(&manager.state_dir())?;
+
+        let mut sheets = Vec::new();
+        for id in manager.registered_ids()? {
+            // Unloaded by someone else since the list was taken
+            let Some(sheet) = manager.fetch_sheet(&id)? else {
+                continue;
+            };
+            let meta = state.sheets.get(&id).cloned().unwrap_or_default();
+            sheets.push(SessionSheet {
+                sha256: sha256_hex(&sheet.css),
+                sheet_type: sheet.sheet_type.as_str().to_string(),
+                source: meta.source.or(sheet.source),
+                tags: meta.tags,
+                description: meta.description,
+                group: meta.group,
+                css: sheet.css,
+                id,
+            });
+        }
+
+        Ok(Session {
+            firefox_version,
+            manifests,
+            resources,
+            sheets,
+        })
+    }
+
+    /// A POSIX shell script that clears the target browser and loads the
+    /// session into it with the `mus-uc` CLI.
+    pub fn to_shell_script(&self) -> String {
+        let mut script = String::from("#!/bin/sh\n");
+        script.push_str(&format!(
+            "# mus-uc session: {} sheet(s), {} chrome.manifest file(s) and {} resource alias(es), exported from Firefox {}.\n",
+            self.sheets.len(),
+            self.manifests.len(),
+            self.resources.len(),
+            self.firefox_version
+        ));
+        script.push_str("# Replaces every sheet loaded in the target browser. Set MUS_UC if\n");
+        script.push_str("# mus-uc is not on the PATH.\n");
+        script.push_str("set -e\n");
+        script.push_str("mus_uc=\"${MUS_UC:-mus-uc}\"\n\n");
+        script.push_str("\"$mus_uc\" clear --yes\n");
+        for manifest in &self.manifests {
+            script.push_str(&format!(
+                "\"$mus_uc\" register-manifest -m {}\n",
+                shell_quote(manifest)
+            ));
+        }
+        for (alias, dir) in &self.resources {
+            script.push_str(&format!(
+                "\"$mus_uc\" register-resource {} {}\n",
+                shell_quote(alias),
+                shell_quote(dir)
+            ));
+        }
+
+        for sheet in &self.sheets {
+            script.push('\n');
+            if let Some(source) = &sheet.source {
+                script.push_str(&format!("# {} from {}\n", sheet.id, source));
+            }
+            script.push_str(&format!("# sha256 {}\n", sheet.sha256));
+            let mut command = format!("\"$mus_uc\" load -i {}", shell_quote(&sheet.id));
+            if sheet.sheet_type != "user" {
+                command.push_str(&format!(" --sheet-type {}", sheet.sheet_type));
+            }
+            for tag in &sheet.tags {
+                command.push_str(&format!(" -t {}", shell_quote(tag)));
+            }
+            if let Some(description) = &sheet.description {
+                command.push_str(&format!(" --description {}", shell_quote(description)));
+            }
+            if let Some(group) = &sheet.group {
+                command.push_str(&format!(" --group {}", shell_quote(group)));
+            }
+            // printf keeps the CSS byte for byte, so the hash still matches
+            script.push_str(&format!(
+                "printf '%s' {} | {}\n",
+                shell_quote(&sheet.css),
+                command
+            ));
+        }
+        script
+    }
+}
+
+pub fn sha256_hex(content: &str) -> String {
+    Sha256::digest(content.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Quote `value` as one word for a POSIX shell.
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
@@ -0,0 +1,141 @@
+//! One Marionette connection shared between managers.
+//!
+//! Marionette serves a single client at a time, so a second
+//! [`MarionetteConnection`] to the same browser is refused while the first is
+//! open. [`SharedConnection`] is a cloneable handle to one connection, for
+//! managers that live side by side or on different threads.
+//!
+//! ## Locking
+//!
+//! Every call locks the connection for exactly one command, so calls from
+//! different handles never interleave within a message. Commands that depend
+//! on each other, such as switching context and then running a script, go
+//! through [`SharedConnection::with`] so no other handle can slip in between.
+//!
+//! The lock is not reentrant: inside `with`, use the `&mut MarionetteConnection`
+//! it passes in, never another `SharedConnection` method, or the thread waits
+//! on itself. [`SharedConnection::try_with`] gives up after a timeout instead
+//! of waiting forever, for callers that cannot rule that out.
+//!
+//! Whole manager operations, which take several commands, also wait their
+//! turn in the connection's [`OpQueue`].
+//!
+//! A handle that panicked while holding the lock may have left half a message
+//! on the wire, so later calls report an error rather than reuse the stream.
+
+use crate::marionette_client::{BrowserInfo, MarionetteConnection, MarionetteSettings};
+use crate::op_queue::OpQueue;
+use serde_json::Value;
+use std::sync::{Arc, Mutex, MutexGuard, TryLockError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[derive(Clone)]
+pub struct SharedConnection {
+    inner: Arc<Mutex<MarionetteConnection>>,
+    queue: OpQueue,
+}
+
+impl SharedConnection {
+    pub fn new(connection: MarionetteConnection) -> Self {
+        SharedConnection {
+            inner: Arc::new(Mutex::new(connection)),
+            queue: OpQueue::new(),
+        }
+    }
+
+    pub fn connect(settings: &MarionetteSettings) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Self::new(MarionetteConnection::connect(settings)?))
+    }
+
+    /// The line manager operations on this connection wait in, shared by
+    /// every handle.
+    pub fn queue(&self) -> &OpQueue {
+        &self.queue
+    }
+
+    pub fn set_context(&self, context: &str) -> Result<(), Box<dyn std::error::Error>> {
+        self.with(|connection| connection.set_context(context))
+    }
+
+    pub fn execute_script(
+        &self,
+        script: &str,
+        args: Option<Vec<Value>>,
+    ) -> Result<Value, Box<dyn std::error::Error>> {
+        self.with(|connection| connection.execute_script(script, args))
+    }
+
+    /// See [`MarionetteConnection::browser_info`]; switches to the chrome context.
+    pub fn browser_info(&self) -> Result<BrowserInfo, Box<dyn std::error::Error>> {
+        self.in_context("chrome", |connection| connection.browser_info())
+    }
+
+    /// Swap in a new connection, e.g. after Firefox restarted. Every handle
+    /// uses it from the next call on.
+    pub fn replace(
+        &self,
+        connection: MarionetteConnection,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.with(|current| {
+            *current = connection;
+            Ok(())
+        })
+    }
+
+    /// Run `f` with the connection locked for its whole duration.
+    pub fn with<T>(
+        &self,
+        f: impl FnOnce(&mut MarionetteConnection) -> Result<T, Box<dyn std::error::Error>>,
+    ) -> Result<T, Box<dyn std::error::Error>> {
+        let mut guard = self.inner.lock().map_err(|_| POISONED)?;
+        f(&mut guard)
+    }
+
+    /// Like [`Self::with`], but fail if the connection stays busy for longer
+    /// than `timeout`.
+    pub fn try_with<T>(
+        &self,
+        timeout: Duration,
+        f: impl FnOnce(&mut MarionetteConnection) -> Result<T, Box<dyn std::error::Error>>,
+    ) -> Result<T, Box<dyn std::error::Error>> {
+        let mut guard = self.try_lock_for(timeout)?;
+        f(&mut guard)
+    }
+
+    /// Set `context` and run `f` in it as one locked sequence. The context is
+    /// left as set; every manager sets the context it needs before relying on it.
+    pub fn in_context<T>(
+        &self,
+        context: &str,
+        f: impl FnOnce(&mut MarionetteConnection) -> Result<T, Box<dyn std::error::Error>>,
+    ) -> Result<T, Box<dyn std::error::Error>> {
+        self.with(|connection| {
+            connection.set_context(context)?;
+            f(connection)
+        })
+    }
+
+    fn try_lock_for(
+        &self,
+        timeout: Duration,
+    ) -> Result<MutexGuard<'_, MarionetteConnection>, Box<dyn std::error::Error>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            match self.inner.try_lock() {
+                Ok(guard) => return Ok(guard),
+                Err(TryLockError::Poisoned(_)) => return Err(POISONED.into()),
+                Err(TryLockError::WouldBlock) if Instant::now() >= deadline => {
+                    return Err(format!(
+                        "Marionette connection still busy after {} ms",
+                        timeout.as_millis()
+                    )
+                    .into());
+                }
+                Err(TryLockError::WouldBlock) => thread::sleep(Duration::from_millis(10)),
+            }
+        }
+    }
+}
+
+const POISONED: &str = "Marionette connection is unusable after a panic in another thread";
@@ -0,0 +1,214 @@
+//! Metadata about loaded sheets, kept in a state file between invocations.
+//!
+//! The browser only knows sheet IDs and the CSS behind them. The state file
+//! remembers where each sheet came from, how it was tagged and which group it
+//! is in, so sheets can be listed and unloaded by tag or group. The browser
+//! stays authoritative for which sheets exist: entries for IDs it no longer has
+//! are dropped with [`SheetState::retain_loaded`].
+//!
+//! Sheets of a group that was switched off with `toggle` are kept here with
+//! their CSS, so they can be registered again later.
+
+use crate::chrome_css_manager::RegisteredSheet;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SheetMetadata {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    /// Seconds since the Unix epoch.
+    #[serde(default)]
+    pub loaded_at: u64,
+}
+
+impl SheetMetadata {
+    pub fn new(source: Option<String>, tags: Vec<String>, description: Option<String>) -> Self {
+        SheetMetadata {
+            source,
+            tags,
+            description,
+            group: None,
+            loaded_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+        }
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t == tag)
+    }
+
+    pub fn in_group(&self, group: &str) -> bool {
+        self.group.as_deref() == Some(group)
+    }
+}
+
+/// A sheet unloaded by switching its group off.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DisabledSheet {
+    pub metadata: SheetMetadata,
+    pub sheet: RegisteredSheet,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SheetState {
+    #[serde(default)]
+    pub sheets: BTreeMap<String, SheetMetadata>,
+    /// Sheets of groups that are switched off, by ID.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub disabled: BTreeMap<String, DisabledSheet>,
+}
+
+impl SheetState {
+    /// `$MUS_UC_STATE_DIR`, or the platform's per-user state directory.
+    pub fn dir() -> PathBuf {
+        let env = |name: &str| std::env::var_os(name).filter(|v| !v.is_empty());
+        if let Some(dir) = env("MUS_UC_STATE_DIR") {
+            return PathBuf::from(dir);
+        }
+        let base = if let Some(dir) = env("XDG_STATE_HOME") {
+            PathBuf::from(dir)
+        } else if let Some(dir) = env("LOCALAPPDATA") {
+            PathBuf::from(dir)
+        } else if let Some(home) = env("HOME") {
+            PathBuf::from(home).join(".local").join("state")
+        } else {
+            std::env::temp_dir()
+        };
+        base.join("mus-uc-devtools")
+    }
+
+    pub fn path() -> PathBuf {
+        Self::dir().join("sheets.json")
+    }
+
+    /// Read the state file; a missing file is an empty state.
+    pub fn load() -> Result<Self, Box<dyn std::error::Error>> {
+        Self::load_in(&Self::dir())
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
+        self.save_in(&Self::dir())
+    }
+
+    /// Like [`Self::load`], with the state file in `dir`.
+    pub fn load_in(dir: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        match fs::read_to_string(dir.join("sheets.json")) {
+            Ok(content) => Ok(serde_json::from_str(&content)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save_in(&self, dir: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        fs::create_dir_all(dir)?;
+        // Write then rename, so a concurrent reader never sees half a file
+        let path = dir.join("sheets.json");
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(self)?)?;
+        fs::rename(tmp, path)?;
+        Ok(())
+    }
+
+    /// Read the state file in `dir`, apply `change` and write it back, holding
+    /// a lock throughout, so commands that update it at the same time do not
+    /// drop each other's changes.
+    pub fn update_in<T>(
+        dir: &Path,
+        change: impl FnOnce(&mut Self) -> T,
+    ) -> Result<T, Box<dyn std::error::Error>> {
+        fs::create_dir_all(dir)?;
+        let lock = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(dir.join("sheets.json.lock"))?;
+        match lock.lock() {
+            Ok(()) => {}
+            // Platforms without file locking (such as WASI) run unguarded
+            Err(e) if e.kind() == ErrorKind::Unsupported => {}
+            Err(e) => return Err(e.into()),
+        }
+        let mut state = Self::load_in(dir)?;
+        let result = change(&mut state);
+        state.save_in(dir)?;
+        Ok(result)
+    }
+
+    /// Remember `metadata` for the sheet `id`, which is loaded again if its
+    /// group was switched off.
+    pub fn record(&mut self, id: &str, metadata: SheetMetadata) {
+        self.disabled.remove(id);
+        self.sheets.insert(id.to_string(), metadata);
+    }
+
+    /// Drop entries for sheets that are no longer registered in the browser.
+    pub fn retain_loaded(&mut self, loaded: &[String]) {
+        self.sheets.retain(|id, _| loaded.contains(id));
+    }
+
+    /// IDs of the sheets tagged `tag`.
+    pub fn tagged(&self, tag: &str) -> Vec<String> {
+        self.sheets
+            .iter()
+            .filter(|(_, meta)| meta.has_tag(tag))
+            .map(|(id, _)| id.clone())
+            .collect()
+    }
+
+    /// IDs of the loaded sheets in `group`.
+    pub fn grouped(&self, group: &str) -> Vec<String> {
+        self.sheets
+            .iter()
+            .filter(|(_, meta)| meta.in_group(group))
+            .map(|(id, _)| id.clone())
+            .collect()
+    }
+
+    /// IDs of the switched-off sheets in `group`.
+    pub fn disabled_in(&self, group: &str) -> Vec<String> {
+        self.disabled
+            .iter()
+            .filter(|(_, disabled)| disabled.metadata.in_group(group))
+            .map(|(id, _)| id.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SheetMetadata, SheetState};
+
+    #[test]
+    fn concurrent_updates_are_all_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let threads: Vec<_> = (0..8)
+            .map(|i| {
+                let dir = dir.path().to_path_buf();
+                std::thread::spawn(move || {
+                    SheetState::update_in(&dir, |state| {
+                        state.record(&format!("sheet-{}", i), SheetMetadata::default())
+                    })
+                    .unwrap();
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        let state = SheetState::load_in(dir.path()).unwrap();
+        assert_eq!(state.sheets.len(), 8);
+    }
+}
@@ -0,0 +1,108 @@
+//! Hold open browser surfaces that close as soon as the mouse moves away, so
+//! they can be styled and captured.
+
+use crate::chrome_js;
+use crate::marionette_client::MarionetteConnection;
+use serde_json::Value;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Surface {
+    /// The address bar results panel.
+    Urlbar,
+    /// The "List all tabs" menu shown when tabs overflow.
+    AllTabs,
+    /// The edit bookmark panel. Bookmarks the current page if it is not
+    /// bookmarked yet.
+    Bookmark,
+    AppMenu,
+    CustomizeMode,
+    /// A new private browsing window.
+    PrivateWindow,
+}
+
+impl Surface {
+    pub const NAMES: &'static [&'static str] = &[
+        "urlbar",
+        "all-tabs",
+        "bookmark",
+        "app-menu",
+        "customize",
+        "private-window",
+    ];
+
+    pub fn parse(name: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        match name {
+            "urlbar" => Ok(Surface::Urlbar),
+            "all-tabs" => Ok(Surface::AllTabs),
+            "bookmark" => Ok(Surface::Bookmark),
+            "app-menu" => Ok(Surface::AppMenu),
+            "customize" => Ok(Surface::CustomizeMode),
+            "private-window" => Ok(Surface::PrivateWindow),
+            other => Err(format!(
+                "Unknown surface '{}' (expected one of: {})",
+                other,
+                Self::NAMES.join(", ")
+            )
+            .into()),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Surface::Urlbar => "urlbar",
+            Surface::AllTabs => "all-tabs",
+            Surface::Bookmark => "bookmark",
+            Surface::AppMenu => "app-menu",
+            Surface::CustomizeMode => "customize",
+            Surface::PrivateWindow => "private-window",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SimulateOptions {
+    /// Text to type into the address bar for [`Surface::Urlbar`].
+    pub query: Option<String>,
+    /// Keep popups open when the browser loses focus, until [`release`].
+    pub pin: bool,
+}
+
+/// Open `surface` in the most recent browser window. The connection must be
+/// in the chrome context.
+pub fn simulate(
+    connection: &mut MarionetteConnection,
+    surface: Surface,
+    options: &SimulateOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    run(
+        connection,
+        Value::from(surface.as_str()),
+        options.query.clone().map_or(Value::Null, Value::from),
+        options.pin,
+    )
+}
+
+/// Close everything opened by [`simulate`] and undo pinning.
+pub fn release(connection: &mut MarionetteConnection) -> Result<(), Box<dyn std::error::Error>> {
+    run(connection, Value::Null, Value::Null, false)
+}
+
+fn run(
+    connection: &mut MarionetteConnection,
+    surface: Value,
+    query: Value,
+    pin: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let window_type = connection.app_profile()?.window_type;
+    let script = chrome_js::render(
+        chrome_js::SIMULATE,
+        &[
+            ("windowType", Value::from(window_type)),
+            ("surface", surface),
+            ("query", query),
+            ("pin", Value::from(pin)),
+        ],
+    );
+    connection.execute_script(&script, None)?;
+    Ok(())
+}
@@ -0,0 +1,179 @@
+//! Line-level source maps for bundled stylesheets.
+//!
+//! The import resolver records, for every line of the bundle, which file and line
+//! it came from. The table is used to translate line numbers reported by Firefox
+//! back to the original sources, and can be written out as a standard (v3)
+//! source map next to exported files.
+
+use crate::css_assets::{file_url, relative_path};
+use serde_json::json;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SourceLocation {
+    /// Index into [`SourceMap::sources`].
+    pub source: usize,
+    /// 1-based line in the source file.
+    pub line: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap {
+    pub sources: Vec<PathBuf>,
+    /// One entry per generated line; `None` for lines the resolver generated
+    /// itself, such as the blocks wrapping a conditional import.
+    pub lines: Vec<Option<SourceLocation>>,
+}
+
+impl SourceMap {
+    /// Translate a 1-based line of the bundle to the original file and line.
+    pub fn lookup(&self, generated_line: usize) -> Option<(&Path, usize)> {
+        let location = (*self.lines.get(generated_line.checked_sub(1)?)?)?;
+        let source = self.sources.get(location.source)?;
+        Some((source.as_path(), location.line))
+    }
+
+    /// Describe a bundle line as `path:line`, with `path` relative to `root` when
+    /// possible.
+    pub fn describe(&self, generated_line: usize, root: Option<&Path>) -> Option<String> {
+        let (path, line) = self.lookup(generated_line)?;
+        let path = root
+            .and_then(|root| path.strip_prefix(root).ok())
+            .unwrap_or(path);
+        Some(format!("{}:{}", path.display(), line))
+    }
+
+    /// Serialize as a v3 source map. Source paths are made relative to
+    /// `map_dir` when given, and written as `file://` URLs otherwise.
+    pub fn to_json(&self, file: &str, map_dir: Option<&Path>) -> String {
+        let sources: Vec<String> = self
+            .sources
+            .iter()
+            .map(|source| match map_dir {
+                Some(dir) => relative_path(dir, source),
+                None => file_url(source),
+            })
+            .collect();
+
+        let mut mappings = String::new();
+        let mut prev_source = 0i64;
+        let mut prev_line = 0i64;
+        for (i, location) in self.lines.iter().enumerate() {
+            if i > 0 {
+                mappings.push(';');
+            }
+            if let Some(location) = location {
+                let source = location.source as i64;
+                let line = location.line as i64 - 1;
+                encode_vlq(&mut mappings, 0);
+                encode_vlq(&mut mappings, source - prev_source);
+                encode_vlq(&mut mappings, line - prev_line);
+                encode_vlq(&mut mappings, 0);
+                prev_source = source;
+                prev_line = line;
+            }
+        }
+
+        json!({
+            "version": 3,
+            "file": file,
+            "sources": sources,
+            "names": [],
+            "mappings": mappings,
+        })
+        .to_string()
+    }
+}
+
+fn encode_vlq(out: &mut String, value: i64) {
+    const BASE64: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut vlq = if value < 0 {
+        ((-value) << 1) | 1
+    } else {
+        value << 1
+    };
+    loop {
+        let mut digit = vlq & 0b11111;
+        vlq >>= 5;
+        if vlq > 0 {
+            digit |= 0b100000;
+        }
+        out.push(BASE64[digit as usize] as char);
+        if vlq == 0 {
+            break;
+        }
+    }
+}
+
+/// CSS text that remembers where each of its lines came from.
+#[derive(Debug, Clone)]
+pub(crate) struct MappedCss {
+    pub css: String,
+    pub lines: Vec<Option<SourceLocation>>,
+}
+
+impl Default for MappedCss {
+    fn default() -> Self {
+        MappedCss {
+            css: String::new(),
+            lines: vec![None],
+        }
+    }
+}
+
+impl MappedCss {
+    /// Append text from `source`, whose first line is `first_line`.
+    pub fn push_source(&mut self, text: &str, source: usize, first_line: usize) {
+        for (k, piece) in text.split('\n').enumerate() {
+            if k > 0 {
+                self.new_line();
+            }
+            self.css.push_str(piece);
+            if !piece.trim().is_empty() {
+                self.mark(Some(SourceLocation {
+                    source,
+                    line: first_line + k,
+                }));
+            }
+        }
+    }
+
+    /// Append text the resolver produced itself.
+    pub fn push_generated(&mut self, text: &str) {
+        for (k, piece) in text.split('\n').enumerate() {
+            if k > 0 {
+                self.new_line();
+            }
+            self.css.push_str(piece);
+        }
+    }
+
+    pub fn append(&mut self, other: MappedCss) {
+        for (k, piece) in other.css.split('\n').enumerate() {
+            if k > 0 {
+                self.new_line();
+            }
+            self.css.push_str(piece);
+            if !piece.trim().is_empty() {
+                self.mark(other.lines.get(k).copied().flatten());
+            }
+        }
+    }
+
+    pub fn is_blank(&self) -> bool {
+        self.css.trim().is_empty()
+    }
+
+    fn new_line(&mut self) {
+        self.css.push('\n');
+        self.lines.push(None);
+    }
+
+    fn mark(&mut self, location: Option<SourceLocation>) {
+        if let Some(last) = self.lines.last_mut() {
+            if last.is_none() {
+                *last = location;
+            }
+        }
+    }
+}
@@ -0,0 +1,252 @@
+//! Reach a Marionette port on another machine through SSH, for Firefox running
+//! on a test box or VM.
+//!
+//! [`SshTunnel::open`] logs in and listens on a local port; each connection
+//! to it is forwarded to the Marionette port on the remote machine's
+//! loopback interface, as `ssh -L` would. Point [`MarionetteSettings`] at
+//! [`SshTunnel::local_port`] and connect as usual.
+//!
+//! Authentication uses the SSH agent, then the default keys in `~/.ssh`. The
+//! server's host key must already be in `~/.ssh/known_hosts`.
+//!
+//! [`MarionetteSettings`]: crate::marionette_client::MarionetteSettings
+
+use ssh2::{CheckResult, KnownHostFileKind, Session};
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+/// A remote machine to log in to, written `[user@]host[:port]`, with an
+/// IPv6 host in brackets when a port follows it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SshTarget {
+    pub user: String,
+    pub host: String,
+    pub port: u16,
+}
+
+impl SshTarget {
+    pub fn parse(target: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let (user, rest) = match target.split_once('@') {
+            Some((user, rest)) => (user.to_string(), rest),
+            None => (
+                std::env::var("USER")
+                    .or_else(|_| std::env::var("USERNAME"))
+                    .map_err(|_| format!("No user in '{}' and $USER is not set", target))?,
+                target,
+            ),
+        };
+        let invalid_port = || format!("Invalid SSH port in '{}'", target);
+        let (host, port) = if let Some(bracketed) = rest.strip_prefix('[') {
+            // An IPv6 address, as in `[::1]:22`
+            let (host, after) = bracketed
+                .split_once(']')
+                .ok_or_else(|| format!("Missing ] in SSH target '{}'", target))?;
+            let port = match after {
+                "" => 22,
+                after => after
+                    .strip_prefix(':')
+                    .and_then(|port| port.parse().ok())
+                    .ok_or_else(invalid_port)?,
+            };
+            (host, port)
+        } else if rest.matches(':').count() > 1 {
+            // An IPv6 address without a port
+            (rest, 22)
+        } else {
+            match rest.rsplit_once(':') {
+                Some((host, port)) => (host, port.parse().map_err(|_| invalid_port())?),
+                None => (rest, 22),
+            }
+        };
+        if user.is_empty() || host.is_empty() {
+            return Err(format!("Invalid SSH target '{}' (expected user@host)", target).into());
+        }
+        Ok(SshTarget {
+            user,
+            host: host.to_string(),
+            port,
+        })
+    }
+}
+
+/// A forward from a local port to a port on the remote machine. Forwarding
+/// runs on a background thread for the life of the process.
+pub struct SshTunnel {
+    local_port: u16,
+}
+
+impl SshTunnel {
+    pub fn open(target: &SshTarget, remote_port: u16) -> Result<Self, Box<dyn std::error::Error>> {
+        let session = login(target)?;
+        let listener = TcpListener::bind(("127.0.0.1", 0))?;
+        let local_port = listener.local_addr()?.port();
+
+        thread::spawn(move || {
+            // Marionette serves one client at a time, so connections are
+            // forwarded one after another
+            for local in listener.incoming().flatten() {
+                if let Err(e) = forward(&session, local, remote_port) {
+                    eprintln!("SSH tunnel: {}", e);
+                }
+            }
+        });
+        Ok(SshTunnel { local_port })
+    }
+
+    pub fn local_port(&self) -> u16 {
+        self.local_port
+    }
+}
+
+fn login(target: &SshTarget) -> Result<Session, Box<dyn std::error::Error>> {
+    let tcp = TcpStream::connect((target.host.as_str(), target.port))
+        .map_err(|e| format!("Cannot reach {}:{}: {}", target.host, target.port, e))?;
+    let mut session = Session::new()?;
+    session.set_tcp_stream(tcp);
+    session.handshake()?;
+    check_host_key(&session, target)?;
+
+    if session.userauth_agent(&target.user).is_err() {
+        for key in default_keys() {
+            if session
+                .userauth_pubkey_file(&target.user, None, &key, None)
+                .is_ok()
+            {
+                break;
+            }
+        }
+    }
+    if !session.authenticated() {
+        return Err(format!(
+            "SSH login as {} on {} failed (tried the agent and the keys in ~/.ssh)",
+            target.user, target.host
+        )
+        .into());
+    }
+    Ok(session)
+}
+
+fn check_host_key(session: &Session, target: &SshTarget) -> Result<(), Box<dyn std::error::Error>> {
+    let (key, _) = session.host_key().ok_or("SSH server sent no host key")?;
+    let mut known_hosts = session.known_hosts()?;
+    if let Some(file) = ssh_dir().map(|dir| dir.join("known_hosts")) {
+        if file.is_file() {
+            known_hosts.read_file(&file, KnownHostFileKind::OpenSSH)?;
+        }
+    }
+    match known_hosts.check_port(&target.host, target.port, key) {
+        CheckResult::Match => Ok(()),
+        CheckResult::Mismatch => Err(format!(
+            "Host key of {} does not match ~/.ssh/known_hosts; refusing to connect",
+            target.host
+        )
+        .into()),
+        CheckResult::NotFound => Err(format!(
+            "{} is not in ~/.ssh/known_hosts; connect once with `ssh {}` to add it",
+            target.host, target.host
+        )
+        .into()),
+        CheckResult::Failure => Err("Could not check the SSH host key".into()),
+    }
+}
+
+fn ssh_dir() -> Option<PathBuf> {
+    std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .map(|home| PathBuf::from(home).join(".ssh"))
+}
+
+fn default_keys() -> Vec<PathBuf> {
+    let Some(dir) = ssh_dir() else {
+        return Vec::new();
+    };
+    ["id_ed25519", "id_ecdsa", "id_rsa"]
+        .iter()
+        .map(|name| dir.join(name))
+        .filter(|path| path.is_file())
+        .collect()
+}
+
+/// Copy data both ways between `local` and the remote port until either side
+/// closes.
+fn forward(session: &Session, mut local: TcpStream, remote_port: u16) -> io::Result<()> {
+    session.set_blocking(true);
+    let mut channel = session
+        .channel_direct_tcpip("localhost", remote_port, None)
+        .map_err(io::Error::other)?;
+    session.set_blocking(false);
+    local.set_nonblocking(true)?;
+
+    let mut buf = [0u8; 16 * 1024];
+    loop {
+        let mut idle = true;
+        match local.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => {
+                write_all(&mut channel, &buf[..n])?;
+                idle = false;
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+            Err(e) => return Err(e),
+        }
+        match channel.read(&mut buf) {
+            Ok(0) if channel.eof() => break,
+            Ok(0) => {}
+            Ok(n) => {
+                write_all(&mut local, &buf[..n])?;
+                idle = false;
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+            Err(e) => return Err(e),
+        }
+        if idle {
+            thread::sleep(Duration::from_millis(2));
+        }
+    }
+    session.set_blocking(true);
+    let _ = channel.close();
+    Ok(())
+}
+
+/// `write_all` for a non-blocking stream. Never flushes: flushing an SSH
+/// channel discards data waiting to be read.
+fn write_all(stream: &mut impl Write, mut data: &[u8]) -> io::Result<()> {
+    while !data.is_empty() {
+        match stream.write(data) {
+            Ok(0) => return Err(ErrorKind::WriteZero.into()),
+            Ok(n) => data = &data[n..],
+            Err(e) if e.kind() == ErrorKind::WouldBlock => thread::sleep(Duration::from_millis(1)),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SshTarget;
+
+    fn parse(target: &str) -> (String, String, u16) {
+        let target = SshTarget::parse(target).unwrap();
+        (target.user, target.host, target.port)
+    }
+
+    #[test]
+    fn parses_host_and_port() {
+        assert_eq!(parse("me@testbox"), ("me".into(), "testbox".into(), 22));
+        assert_eq!(parse("me@vm:2222"), ("me".into(), "vm".into(), 2222));
+        assert!(SshTarget::parse("me@vm:ssh").is_err());
+    }
+
+    #[test]
+    fn parses_ipv6_hosts() {
+        assert_eq!(parse("me@[::1]:2222"), ("me".into(), "::1".into(), 2222));
+        assert_eq!(parse("me@[fe80::1]"), ("me".into(), "fe80::1".into(), 22));
+        assert_eq!(parse("me@::1"), ("me".into(), "::1".into(), 22));
+        assert!(SshTarget::parse("me@[::1").is_err());
+        assert!(SshTarget::parse("me@[::1]22").is_err());
+    }
+}
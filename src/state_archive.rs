@@ -0,0 +1,208 @@
+//! Backups of the tool's state, also for carrying it to another machine, for
+//! `state export` and `state import`.
+//!
+//! Archive layout:
+//!
+//! ```text
+//! state/sheets.json     the state file (see [`SheetState`]), and anything else in its directory
+//! project/mus-uc.toml   the project config, when there is one
+//! project/<dir>/...     baseline screenshot directories, at their paths relative to the project
+//! ```
+//!
+//! Project files are kept relative to the directory of `mus-uc.toml`, so the
+//! archive can be unpacked into a checkout at a different path.
+//!
+//! [`SheetState`]: crate::sheet_state::SheetState
+
+use crate::paths::canonicalize;
+use crate::project_config::CONFIG_FILE;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+
+const STATE_PREFIX: &str = "state";
+const PROJECT_PREFIX: &str = "project";
+
+/// What goes into an archive.
+#[derive(Debug, Clone)]
+pub struct StateExport {
+    /// The directory of the state file, usually [`SheetState::dir`].
+    ///
+    /// [`SheetState::dir`]: crate::sheet_state::SheetState::dir
+    pub state_dir: PathBuf,
+    /// The directory of `mus-uc.toml`. Baselines must be under it.
+    pub project_dir: PathBuf,
+    /// Whether to include `mus-uc.toml`.
+    pub config: bool,
+    /// Directories of baseline screenshots.
+    pub baselines: Vec<PathBuf>,
+}
+
+/// Where an archive is unpacked.
+#[derive(Debug, Clone)]
+pub struct StateImport {
+    pub state_dir: PathBuf,
+    pub project_dir: PathBuf,
+    /// Replace files that exist with different content.
+    pub overwrite: bool,
+}
+
+/// Write the archive to `out`. Returns the paths written inside it.
+pub fn export_state(
+    out: &Path,
+    export: &StateExport,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let mut files = Vec::new();
+    if export.state_dir.is_dir() {
+        collect(
+            &export.state_dir,
+            &export.state_dir,
+            STATE_PREFIX,
+            &mut files,
+        )?;
+    }
+    if export.config {
+        let config = export.project_dir.join(CONFIG_FILE);
+        if !config.is_file() {
+            return Err(format!("{} not found", config.display()).into());
+        }
+        files.push((format!("{}/{}", PROJECT_PREFIX, CONFIG_FILE), config));
+    }
+    for baseline in &export.baselines {
+        if !baseline.is_dir() {
+            return Err(format!("Baseline directory not found: {}", baseline.display()).into());
+        }
+        let project = canonicalize(&export.project_dir)?;
+        if !canonicalize(baseline)?.starts_with(&project) {
+            return Err(format!(
+                "{} is outside the project at {}; only baselines inside it can be exported",
+                baseline.display(),
+                export.project_dir.display()
+            )
+            .into());
+        }
+        collect(
+            &project,
+            &canonicalize(baseline)?,
+            PROJECT_PREFIX,
+            &mut files,
+        )?;
+    }
+    if files.is_empty() {
+        return Err("Nothing to export: no state file, project config or baselines".into());
+    }
+    files.sort();
+    files.dedup_by(|a, b| a.0 == b.0);
+
+    let mut builder = tar::Builder::new(File::create(out)?);
+    for (name, path) in &files {
+        builder.append_path_with_name(path, name)?;
+    }
+    builder.into_inner()?;
+    Ok(files.into_iter().map(|(name, _)| name).collect())
+}
+
+/// The files under `dir`, named by `prefix` and their path relative to
+/// `root`. Temporary files left by an interrupted write and lock files are
+/// skipped.
+fn collect(
+    root: &Path,
+    dir: &Path,
+    prefix: &str,
+    files: &mut Vec<(String, PathBuf)>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut entries: Vec<_> = fs::read_dir(dir)?.collect::<Result<_, _>>()?;
+    entries.sort_by_key(|entry| entry.path());
+    for entry in entries {
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            collect(root, &path, prefix, files)?;
+        } else if path
+            .extension()
+            .is_some_and(|ext| ext == "tmp" || ext == "lock")
+        {
+            continue;
+        } else {
+            let relative = path.strip_prefix(root)?;
+            let parts: Vec<_> = relative
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy().into_owned())
+                .collect();
+            files.push((format!("{}/{}", prefix, parts.join("/")), path));
+        }
+    }
+    Ok(())
+}
+
+/// What [`import_state`] did.
+#[derive(Debug, Clone, Default)]
+pub struct ImportReport {
+    pub written: Vec<PathBuf>,
+    /// Files that were already there with the same content.
+    pub unchanged: Vec<PathBuf>,
+}
+
+/// Unpack the archive at `archive`. Nothing is written if a file in it
+/// exists with different content, unless `import.overwrite` is set.
+pub fn import_state(
+    archive: &Path,
+    import: &StateImport,
+) -> Result<ImportReport, Box<dyn std::error::Error>> {
+    let mut files = Vec::new();
+    let mut reader = tar::Archive::new(File::open(archive)?);
+    for entry in reader.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let name = entry.path()?.into_owned();
+        let target = destination(&name, import)
+            .ok_or_else(|| format!("Unexpected path in archive: {}", name.display()))?;
+        let mut content = Vec::new();
+        entry.read_to_end(&mut content)?;
+        files.push((target, content));
+    }
+
+    let mut report = ImportReport::default();
+    let mut conflicts = Vec::new();
+    for (target, content) in &files {
+        match fs::read(target) {
+            Ok(existing) if existing == *content => report.unchanged.push(target.clone()),
+            Ok(_) => conflicts.push(target.display().to_string()),
+            Err(_) => {}
+        }
+    }
+    if !conflicts.is_empty() && !import.overwrite {
+        return Err(format!(
+            "These files exist with different content (use --force to replace them):\n  {}",
+            conflicts.join("\n  ")
+        )
+        .into());
+    }
+    for (target, content) in files {
+        if report.unchanged.contains(&target) {
+            continue;
+        }
+        if let Some(dir) = target.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&target, content)?;
+        report.written.push(target);
+    }
+    Ok(report)
+}
+
+/// Where the archive entry `name` goes, or `None` if it is not one this
+/// module writes or would leave its directory.
+fn destination(name: &Path, import: &StateImport) -> Option<PathBuf> {
+    let mut components = name.components();
+    let base = match components.next()? {
+        Component::Normal(first) if first == STATE_PREFIX => &import.state_dir,
+        Component::Normal(first) if first == PROJECT_PREFIX => &import.project_dir,
+        _ => return None,
+    };
+    let rest = components.as_path();
+    let safe = rest.components().next().is_some()
+        && rest.components().all(|c| matches!(c, Component::Normal(_)));
+    safe.then(|| base.join(rest))
+}
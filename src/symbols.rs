@@ -0,0 +1,115 @@
+//! Names used in the browser UI, harvested from the chrome DOM as completion
+//! data for editors, so theme authors do not need the Browser Toolbox open to
+//! remember them.
+
+use crate::chrome_js;
+use crate::marionette_client::MarionetteConnection;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Attributes that are not useful in selectors: identity, styling, text and
+/// accessibility attributes.
+const IGNORED_ATTRIBUTES: &[&str] = &[
+    "id",
+    "class",
+    "style",
+    "label",
+    "value",
+    "tooltiptext",
+    "accesskey",
+    "aria-label",
+    "aria-labelledby",
+    "aria-describedby",
+    "aria-controls",
+    "href",
+    "src",
+    "image",
+    "command",
+    "key",
+    "observes",
+    "flex",
+    "width",
+    "height",
+];
+
+/// Above this many distinct values, an attribute is only offered bare, as
+/// `[name]`.
+const MAX_ATTRIBUTE_VALUES: usize = 12;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ChromeSymbols {
+    /// Element IDs, with the tag name of the element.
+    pub ids: BTreeMap<String, String>,
+    /// Class names, with how many elements use them.
+    pub classes: BTreeMap<String, u64>,
+    /// Attribute names, with the short values seen on them.
+    pub attributes: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl ChromeSymbols {
+    /// Every selector with a short description, IDs first.
+    pub fn selectors(&self) -> Vec<(String, String)> {
+        let mut selectors = Vec::new();
+        for (id, tag) in &self.ids {
+            selectors.push((format!("#{}", id), format!("<{}> id", tag)));
+        }
+        for (class, count) in &self.classes {
+            selectors.push((
+                format!(".{}", class),
+                format!("class on {} element(s)", count),
+            ));
+        }
+        for (name, values) in &self.attributes {
+            selectors.push((format!("[{}]", name), "attribute".to_string()));
+            if values.len() <= MAX_ATTRIBUTE_VALUES {
+                for value in values.iter().filter(|v| !v.is_empty()) {
+                    selectors.push((
+                        format!("[{}=\"{}\"]", name, value),
+                        "attribute value".to_string(),
+                    ));
+                }
+            }
+        }
+        selectors
+    }
+
+    /// A VS Code snippets file (`.code-snippets`) offering each selector in
+    /// CSS files.
+    pub fn to_vscode_snippets(&self) -> Value {
+        let snippets: serde_json::Map<String, Value> = self
+            .selectors()
+            .into_iter()
+            .map(|(selector, description)| {
+                // `$` starts a snippet placeholder
+                let body = selector.replace('$', "\\$");
+                let snippet = json!({
+                    "scope": "css,scss,less",
+                    "prefix": selector,
+                    "body": body,
+                    "description": description,
+                });
+                (selector, snippet)
+            })
+            .collect();
+        Value::Object(snippets)
+    }
+}
+
+/// Walk the most recent browser window. The connection must be in the chrome
+/// context.
+pub fn dump_symbols(
+    connection: &mut MarionetteConnection,
+) -> Result<ChromeSymbols, Box<dyn std::error::Error>> {
+    let window_type = connection.app_profile()?.window_type;
+    let script = chrome_js::render(
+        chrome_js::SYMBOLS,
+        &[
+            ("windowType", Value::from(window_type)),
+            ("ignoredAttributes", json!(IGNORED_ATTRIBUTES)),
+        ],
+    );
+    let result = connection.execute_script(&script, None)?;
+    Ok(serde_json::from_value(result)
+        .map_err(|e| format!("Unexpected symbols from Firefox: {}", e))?)
+}
@@ -0,0 +1,198 @@
+//! Common theming hooks in the browser window, checked against the live DOM.
+
+use crate::marionette_client::MarionetteConnection;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+/// A selector theme authors commonly rely on.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct ThemingTarget {
+    pub area: &'static str,
+    pub selector: &'static str,
+    /// For attribute states, the element the state applies to. The state
+    /// itself may just not be active at the moment of the check.
+    #[serde(skip)]
+    pub element: Option<&'static str>,
+    pub description: &'static str,
+}
+
+const fn element(
+    area: &'static str,
+    selector: &'static str,
+    description: &'static str,
+) -> ThemingTarget {
+    ThemingTarget {
+        area,
+        selector,
+        element: None,
+        description,
+    }
+}
+
+const fn state(
+    area: &'static str,
+    selector: &'static str,
+    element: &'static str,
+    description: &'static str,
+) -> ThemingTarget {
+    ThemingTarget {
+        area,
+        selector,
+        element: Some(element),
+        description,
+    }
+}
+
+#[rustfmt::skip]
+pub const TARGETS: &[ThemingTarget] = &[
+    element("window", ":root", "Browser window root (#main-window)"),
+    state("window", ":root[sizemode=\"maximized\"]", ":root", "Maximized window"),
+    state("window", ":root[inFullscreen]", ":root", "Fullscreen window"),
+    state("window", ":root[customtitlebar]", ":root", "Tabs drawn in the title bar (Firefox 133+)"),
+    state("window", ":root[tabsintitlebar]", ":root", "Tabs drawn in the title bar (before Firefox 133)"),
+    state("window", ":root[lwtheme]", ":root", "A lightweight theme is active"),
+    element("toolbox", "#navigator-toolbox", "Container of all toolbars"),
+    element("toolbox", "#titlebar", "Title bar holding the menu bar and tab strip"),
+    element("toolbox", "#toolbar-menubar", "Menu bar"),
+    state("toolbox", "#toolbar-menubar[autohide=\"true\"]", "#toolbar-menubar", "Menu bar hidden until Alt is pressed"),
+    element("tabs", "#TabsToolbar", "Tab strip toolbar"),
+    state("tabs", "#TabsToolbar[brighttext]", "#TabsToolbar", "Tab strip on a dark background"),
+    element("tabs", "#tabbrowser-tabs", "Tab container"),
+    element("tabs", "#tabbrowser-arrowscrollbox", "Scrollable tab list"),
+    element("tabs", ".tabbrowser-tab", "A tab"),
+    state("tabs", ".tabbrowser-tab[selected]", ".tabbrowser-tab", "Selected tab"),
+    state("tabs", ".tabbrowser-tab[pinned]", ".tabbrowser-tab", "Pinned tab"),
+    state("tabs", ".tabbrowser-tab[multiselected]", ".tabbrowser-tab", "Tab in a multi-selection"),
+    element("tabs", ".tab-background", "Tab background"),
+    element("tabs", ".tab-label", "Tab title"),
+    element("tabs", ".tab-icon-image", "Tab favicon"),
+    element("tabs", ".tab-close-button", "Tab close button"),
+    element("tabs", "#tabs-newtab-button", "New tab button after the last tab"),
+    element("tabs", "#alltabs-button", "List all tabs button"),
+    element("navbar", "#nav-bar", "Navigation toolbar"),
+    element("navbar", "#nav-bar-customization-target", "Customizable area of the navigation toolbar"),
+    element("navbar", "#back-button", "Back button"),
+    element("navbar", "#forward-button", "Forward button"),
+    element("navbar", "#reload-button", "Reload button"),
+    element("navbar", "#stop-button", "Stop button"),
+    element("navbar", "#unified-extensions-button", "Extensions button"),
+    element("navbar", "#PanelUI-menu-button", "Application menu button"),
+    element("urlbar", "#urlbar-container", "Address bar container"),
+    element("urlbar", "#urlbar", "Address bar"),
+    state("urlbar", "#urlbar[focused]", "#urlbar", "Address bar has focus"),
+    state("urlbar", "#urlbar[breakout-extend]", "#urlbar", "Address bar expanded with results"),
+    element("urlbar", "#urlbar-input", "Address bar text field"),
+    element("urlbar", "#identity-box", "Site identity button"),
+    element("urlbar", "#tracking-protection-icon-container", "Tracking protection icon"),
+    element("urlbar", "#page-action-buttons", "Page action buttons"),
+    element("urlbar", "#star-button-box", "Bookmark star"),
+    element("urlbar", ".urlbarView", "Address bar results panel"),
+    element("bookmarks", "#PersonalToolbar", "Bookmarks toolbar"),
+    state("bookmarks", "#PersonalToolbar[collapsed=\"false\"]", "#PersonalToolbar", "Bookmarks toolbar shown"),
+    element("bookmarks", "#PlacesToolbarItems", "Bookmarks on the toolbar"),
+    element("sidebar", "#sidebar-box", "Sidebar container"),
+    state("sidebar", "#sidebar-box[hidden]", "#sidebar-box", "Sidebar closed"),
+    element("sidebar", "#sidebar-header", "Sidebar header"),
+    element("sidebar", "#sidebar", "Sidebar content browser"),
+    element("sidebar", "#sidebar-splitter", "Sidebar resize handle"),
+    element("sidebar", "#sidebar-main", "Sidebar launcher (Firefox 131+)"),
+    element("content", "#browser", "Box holding the sidebar and content"),
+    element("content", "#tabbrowser-tabbox", "Tab content area"),
+    element("content", "#tabbrowser-tabpanels", "Panels holding the tab browsers"),
+    element("content", ".browserContainer", "Container of one tab's browser"),
+    element("content", "#statuspanel", "Link target and loading status"),
+    element("popups", "#appMenu-popup", "Application menu"),
+    element("popups", "#contentAreaContextMenu", "Page context menu"),
+    element("popups", "#tabContextMenu", "Tab context menu"),
+    element("popups", "#customization-container", "Customize toolbar view"),
+];
+
+/// Hooks Zen adds around Firefox's chrome; see [`crate::app_profile`].
+#[rustfmt::skip]
+pub const ZEN_TARGETS: &[ThemingTarget] = &[
+    element("zen", "#zen-main-app-wrapper", "Wrapper around the toolbox and content"),
+    element("zen", "#zen-appcontent-wrapper", "Wrapper around the browser content"),
+    element("zen", "#zen-sidebar-top-buttons", "Buttons above the vertical tabs"),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TargetStatus {
+    /// The selector matches now.
+    Present,
+    /// The element exists but the state is not active right now.
+    Inactive,
+    /// Nothing matches: the hook was likely renamed or removed.
+    Missing,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TargetCheck {
+    #[serde(flatten)]
+    pub target: ThemingTarget,
+    pub status: TargetStatus,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TargetReport {
+    pub firefox_version: String,
+    pub checks: Vec<TargetCheck>,
+}
+
+/// Check every target in [`TARGETS`], and those of the browser's
+/// [`AppProfile`](crate::app_profile::AppProfile), against the most recent
+/// browser window. The connection must be in the chrome context.
+pub fn check_targets(
+    connection: &mut MarionetteConnection,
+) -> Result<TargetReport, Box<dyn std::error::Error>> {
+    let profile = connection.app_profile()?;
+    let all_targets: Vec<&ThemingTarget> = TARGETS.iter().chain(profile.extra_targets).collect();
+    let script = r#"
+        const [targets, windowType] = arguments;
+        const window = Services.wm.getMostRecentWindow(windowType);
+        if (!window) throw new Error("No browser window found");
+        const matches = (selector) => {
+            try {
+                return window.document.querySelector(selector) !== null;
+            } catch (e) {
+                return false;
+            }
+        };
+        return {
+            results: targets.map(([selector, element]) =>
+                matches(selector) ? "present"
+                    : element && matches(element) ? "inactive"
+                    : "missing"),
+        };
+    "#;
+    let targets: Vec<Value> = all_targets
+        .iter()
+        .map(|target| json!([target.selector, target.element]))
+        .collect();
+    let result = connection.execute_script(
+        script,
+        Some(vec![Value::Array(targets), json!(profile.window_type)]),
+    )?;
+
+    let firefox_version = connection.browser_info()?.version;
+    let results = result["results"]
+        .as_array()
+        .ok_or("Unexpected result from the target check")?;
+    let checks = all_targets
+        .iter()
+        .zip(results)
+        .map(|(target, status)| TargetCheck {
+            target: **target,
+            status: match status.as_str() {
+                Some("present") => TargetStatus::Present,
+                Some("inactive") => TargetStatus::Inactive,
+                _ => TargetStatus::Missing,
+            },
+        })
+        .collect();
+
+    Ok(TargetReport {
+        firefox_version,
+        checks,
+    })
+}
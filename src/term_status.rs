@@ -0,0 +1,87 @@
+//! Watch status in the terminal's title and progress indicator, for
+//! `watch --term-status`.
+//!
+//! The title is set with OSC 2, which tmux shows as the pane title and most
+//! terminals as the window or tab title. The state is also sent as an OSC 9;4
+//! progress update, which Windows Terminal, WezTerm, Ghostty and ConEmu show
+//! on the tab or taskbar; terminals that do not know it ignore it. Inside
+//! tmux it is wrapped for tmux to pass on, which needs `allow-passthrough`.
+
+use std::io::{IsTerminal, Write};
+
+/// What a watch session is doing, as shown in the terminal.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WatchStatus {
+    /// A reload or reconnect is under way.
+    Reloading,
+    /// The last load went through without CSS errors.
+    Ok,
+    /// The last load went through with CSS errors.
+    Warning,
+    /// A file could not be read, a reload was rolled back, or the
+    /// connection was lost.
+    Error,
+}
+
+impl WatchStatus {
+    /// The OSC 9;4 state and value.
+    fn progress(self) -> &'static str {
+        match self {
+            WatchStatus::Reloading => "3",
+            WatchStatus::Ok => "0",
+            WatchStatus::Warning => "4;100",
+            WatchStatus::Error => "2;100",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TermStatus {
+    enabled: bool,
+}
+
+impl TermStatus {
+    pub fn new(enabled: bool) -> Self {
+        TermStatus { enabled }
+    }
+
+    /// Show `status`, with `detail` such as the file name in the title.
+    pub fn update(&self, status: WatchStatus, detail: &str) {
+        let label = match status {
+            WatchStatus::Reloading => "reloading",
+            WatchStatus::Ok => "ok",
+            WatchStatus::Warning => "warning",
+            WatchStatus::Error => "error",
+        };
+        self.write(&format!(
+            "\x1b]2;mus-uc: {} {}\x07{}",
+            label,
+            detail,
+            passthrough(&format!("\x1b]9;4;{}\x07", status.progress()))
+        ));
+    }
+
+    /// Take the progress indicator away, when the session ends.
+    pub fn clear(&self) {
+        self.write(&passthrough("\x1b]9;4;0\x07"));
+    }
+
+    /// Write to stderr if it is a terminal, so piped output stays clean.
+    fn write(&self, sequence: &str) {
+        let mut stderr = std::io::stderr();
+        if self.enabled && stderr.is_terminal() {
+            stderr.write_all(sequence.as_bytes()).ok();
+            stderr.flush().ok();
+        }
+    }
+}
+
+/// `sequence` wrapped in a tmux passthrough when running inside tmux, which
+/// otherwise swallows the sequences it does not know.
+fn passthrough(sequence: &str) -> String {
+    if std::env::var_os("TMUX").is_some() {
+        format!("\x1bPtmux;{}\x1b\\", sequence.replace('\x1b', "\x1b\x1b"))
+    } else {
+        sequence.to_string()
+    }
+}
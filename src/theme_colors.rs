@@ -0,0 +1,74 @@
+//! The color palette of the running browser: the active theme's custom
+//! properties (`--lwt-*`, `--toolbar-bgcolor`, ...) and the system colors.
+
+use crate::chrome_js;
+use crate::marionette_client::MarionetteConnection;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// Colors by name, each resolved to `rgb()` or `rgba()`.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ThemeColors {
+    /// Custom properties on the browser window's root that hold a color.
+    pub variables: BTreeMap<String, String>,
+    /// CSS system colors such as `AccentColor` and `Canvas`.
+    pub system: BTreeMap<String, String>,
+}
+
+impl ThemeColors {
+    /// Keep only the variables whose name starts with `prefix`, e.g. `--lwt-`.
+    pub fn retain_prefix(&mut self, prefix: &str) {
+        self.variables.retain(|name, _| name.starts_with(prefix));
+    }
+
+    /// A `:root` block to paste into a stylesheet. System colors are named
+    /// `--system-<color>`, e.g. `--system-accent-color`.
+    pub fn to_css(&self) -> String {
+        let mut css = String::from(":root {\n");
+        for (name, value) in &self.variables {
+            css.push_str(&format!("  {}: {};\n", name, value));
+        }
+        if !self.system.is_empty() {
+            if !self.variables.is_empty() {
+                css.push('\n');
+            }
+            for (name, value) in &self.system {
+                css.push_str(&format!("  --system-{}: {};\n", kebab_case(name), value));
+            }
+        }
+        css.push_str("}\n");
+        css
+    }
+}
+
+/// Read the palette from the most recent browser window. The connection must
+/// be in the chrome context.
+pub fn read_theme_colors(
+    connection: &mut MarionetteConnection,
+) -> Result<ThemeColors, Box<dyn std::error::Error>> {
+    let window_type = connection.app_profile()?.window_type;
+    let script = chrome_js::render(
+        chrome_js::THEME_COLORS,
+        &[("windowType", Value::from(window_type))],
+    );
+    let result = connection.execute_script(&script, None)?;
+    let list = |key: &str| -> Result<BTreeMap<String, String>, Box<dyn std::error::Error>> {
+        Ok(serde_json::from_value(result[key].clone())?)
+    };
+    Ok(ThemeColors {
+        variables: list("variables")?,
+        system: list("system")?,
+    })
+}
+
+fn kebab_case(name: &str) -> String {
+    let mut out = String::new();
+    for (i, c) in name.chars().enumerate() {
+        if c.is_ascii_uppercase() && i > 0 {
+            out.push('-');
+        }
+        out.push(c.to_ascii_lowercase());
+    }
+    out
+}
@@ -0,0 +1,169 @@
+//! Waiting for the browser UI to reach a state, for `wait`, the `wait_for`
+//! and `wait_until` steps of scenarios, and `screenshot --wait-for`.
+//!
+//! A condition is checked every [`POLL_INTERVAL`] until it holds or the
+//! timeout runs out, so a run goes as fast as the browser allows instead of
+//! sleeping for a guessed time, and still waits long enough on a slow
+//! machine.
+
+use crate::chrome_js;
+use crate::marionette_client::MarionetteConnection;
+use crate::shared_connection::SharedConnection;
+use serde_json::{json, Value};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// How often a condition is checked.
+pub const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How long to wait when no timeout is given.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Something about the browser window to wait for.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WaitCondition {
+    /// An element matches the selector.
+    Selector(String),
+    /// No element matches the selector, e.g. once a panel has closed.
+    Gone(String),
+    /// The first element matching `selector` has the attribute, with `value`
+    /// if given.
+    Attribute {
+        selector: String,
+        name: String,
+        value: Option<String>,
+    },
+    /// A JavaScript expression that is truthy, evaluated in the chrome
+    /// context with `window` and `document` those of the browser window.
+    Predicate(String),
+}
+
+impl WaitCondition {
+    fn to_value(&self) -> Value {
+        match self {
+            WaitCondition::Selector(selector) => json!({ "selector": selector }),
+            WaitCondition::Gone(selector) => json!({ "selector": selector, "gone": true }),
+            WaitCondition::Attribute {
+                selector,
+                name,
+                value,
+            } => json!({ "selector": selector, "attribute": name, "value": value }),
+            WaitCondition::Predicate(expression) => json!({ "predicate": expression }),
+        }
+    }
+
+    /// The condition as a JavaScript expression, as [`WaitCondition::Predicate`]
+    /// takes it.
+    pub fn to_expression(&self) -> String {
+        let query = |selector: &str| format!("document.querySelector({})", json!(selector));
+        match self {
+            WaitCondition::Selector(selector) => format!("{} !== null", query(selector)),
+            WaitCondition::Gone(selector) => format!("{} === null", query(selector)),
+            WaitCondition::Attribute {
+                selector,
+                name,
+                value: Some(value),
+            } => format!(
+                "{}?.getAttribute({}) === {}",
+                query(selector),
+                json!(name),
+                json!(value)
+            ),
+            WaitCondition::Attribute { selector, name, .. } => {
+                format!(
+                    "{}?.hasAttribute({}) === true",
+                    query(selector),
+                    json!(name)
+                )
+            }
+            WaitCondition::Predicate(expression) => expression.clone(),
+        }
+    }
+}
+
+impl fmt::Display for WaitCondition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WaitCondition::Selector(selector) => write!(f, "{}", selector),
+            WaitCondition::Gone(selector) => write!(f, "{} to go away", selector),
+            WaitCondition::Attribute {
+                selector,
+                name,
+                value: Some(value),
+            } => write!(f, "{} to have {}=\"{}\"", selector, name, value),
+            WaitCondition::Attribute { selector, name, .. } => {
+                write!(f, "{} to have {}", selector, name)
+            }
+            WaitCondition::Predicate(expression) => write!(f, "`{}`", expression),
+        }
+    }
+}
+
+/// Parse a duration written as `5s`, `500ms` or `2m`; a bare number is in
+/// milliseconds, like the other options that take times.
+pub fn parse_duration(text: &str) -> Result<Duration, Box<dyn std::error::Error>> {
+    let invalid = || {
+        format!(
+            "Invalid duration '{}' (expected e.g. 500ms, 5s or 2m)",
+            text
+        )
+    };
+    let text = text.trim();
+    let split = text
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number: f64 = number.parse().map_err(|_| invalid())?;
+    let millis = match unit.trim() {
+        "" | "ms" => number,
+        "s" => number * 1000.0,
+        "m" => number * 60_000.0,
+        _ => return Err(invalid().into()),
+    };
+    Ok(Duration::from_millis(millis.round() as u64))
+}
+
+/// Whether `condition` holds now. The connection must be in the chrome
+/// context.
+pub fn check(
+    connection: &mut MarionetteConnection,
+    condition: &WaitCondition,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let window_type = connection.app_profile()?.window_type;
+    let script = chrome_js::render(
+        chrome_js::WAIT_CONDITION,
+        &[
+            ("windowType", Value::from(window_type)),
+            ("condition", condition.to_value()),
+        ],
+    );
+    Ok(connection
+        .execute_script(&script, None)?
+        .as_bool()
+        .unwrap_or(false))
+}
+
+/// Wait until `condition` holds, checking every [`POLL_INTERVAL`]. Returns
+/// how long it took, or an error once `timeout` has passed. A selector or
+/// script that is itself broken fails at once.
+pub fn wait_for(
+    connection: &SharedConnection,
+    condition: &WaitCondition,
+    timeout: Duration,
+) -> Result<Duration, Box<dyn std::error::Error>> {
+    let started = Instant::now();
+    loop {
+        if connection.with(|c| check(c, condition))? {
+            return Ok(started.elapsed());
+        }
+        if started.elapsed() >= timeout {
+            return Err(format!(
+                "Timed out after {}ms waiting for {}",
+                timeout.as_millis(),
+                condition
+            )
+            .into());
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
@@ -0,0 +1,1090 @@
+//! The event loop behind `watch`.
+//!
+//! An [`Engine`] multiplexes everything a watch session reacts to over one
+//! channel: file changes from the notify watcher, and reconnect or shutdown
+//! events from anything holding an [`Engine::sender`]. Browser console
+//! messages are polled on the loop's own thread, since the connection is not
+//! shared, and so is the heartbeat that finds a dead connection between
+//! reloads, and the files themselves when watching by polling (see
+//! [`crate::poll_watcher`]). Each event is passed to every [`Handler`] in the
+//! order they were added.
+
+use crate::chrome_css_manager::{file_name, report_unresolved, ChromeCSSManager};
+use crate::chrome_manifest::{normalize_path, ChromeManifest};
+use crate::console_log::{ConsoleLog, ConsoleRecord};
+use crate::css_assets::{cache_busted, file_url, map_urls};
+use crate::css_imports::{ImportCache, ResolvedStylesheet};
+use crate::hooks::{HookContext, HookEvent};
+use crate::marionette_client::{locate_error, script_file_frame, ReconnectPolicy};
+use crate::paths::canonicalize;
+use crate::poll_watcher::{self, PollWatcher};
+use crate::project_config::WatchTarget;
+use crate::sheet_state::{SheetMetadata, SheetState};
+use crate::term_status::WatchStatus;
+use crate::watch_filter::WatchFilter;
+use crate::watch_lock::WatchLock;
+#[cfg(not(target_family = "wasm"))]
+use notify::{Event, EventKind, Watcher};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Whether a watched directory covers its subdirectories.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecursiveMode {
+    Recursive,
+    NonRecursive,
+}
+
+#[derive(Debug, Clone)]
+pub enum WatchEvent {
+    /// Files were created, modified or removed.
+    FilesChanged(Vec<PathBuf>),
+    /// Messages read from the browser console since the last poll.
+    Console(Vec<ConsoleRecord>),
+    /// The heartbeat found the browser no longer answering, with the error.
+    /// Sent before reconnecting, so handlers must not use the connection.
+    ConnectionLost(String),
+    /// The connection to Firefox was re-established; sheets may need loading again.
+    Reconnected,
+    /// Stop the loop after the handlers have seen this event.
+    Shutdown,
+}
+
+/// Reacts to watch events. An error from a handler stops the engine.
+pub trait Handler {
+    /// Called once before the loop starts, for the initial load.
+    fn start(&mut self, _manager: &mut ChromeCSSManager) -> Result<(), Box<dyn std::error::Error>> {
+        Ok(())
+    }
+
+    /// Paths to watch. Asked again after every file change, so the set can
+    /// follow an import graph as it grows and shrinks.
+    fn watch_paths(&self) -> Vec<(PathBuf, RecursiveMode)> {
+        Vec::new()
+    }
+
+    /// Whether the engine should capture the browser console for this handler.
+    fn wants_console(&self) -> bool {
+        false
+    }
+
+    fn handle(
+        &mut self,
+        event: &WatchEvent,
+        manager: &mut ChromeCSSManager,
+    ) -> Result<(), Box<dyn std::error::Error>>;
+}
+
+pub struct Engine {
+    handlers: Vec<Box<dyn Handler>>,
+    sender: Sender<WatchEvent>,
+    receiver: Receiver<WatchEvent>,
+    console_interval: Duration,
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Engine {
+    pub fn new() -> Self {
+        let (sender, receiver) = channel();
+        Engine {
+            handlers: Vec::new(),
+            sender,
+            receiver,
+            console_interval: Duration::from_millis(500),
+        }
+    }
+
+    pub fn add_handler(&mut self, handler: impl Handler + 'static) {
+        self.handlers.push(Box::new(handler));
+    }
+
+    /// A sender for injecting events, such as [`WatchEvent::Shutdown`], from
+    /// another thread.
+    pub fn sender(&self) -> Sender<WatchEvent> {
+        self.sender.clone()
+    }
+
+    /// Start the handlers and dispatch events until a [`WatchEvent::Shutdown`]
+    /// or a handler error.
+    pub fn run(
+        &mut self,
+        manager: &mut ChromeCSSManager,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let result = self.run_loop(manager);
+        manager.term_status().clear();
+        result
+    }
+
+    fn run_loop(
+        &mut self,
+        manager: &mut ChromeCSSManager,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let wants_console = self.handlers.iter().any(|h| h.wants_console());
+        if wants_console {
+            manager.start_console_capture()?;
+        }
+        for handler in &mut self.handlers {
+            handler.start(manager)?;
+        }
+
+        let mut source = Source::open(manager.poll_interval(), &self.sender);
+        let mut watched = Vec::new();
+        sync_watches(&mut source, &mut watched, &self.watch_paths(), &[]);
+
+        let mut last_console_poll = Instant::now();
+        let mut last_heartbeat = Instant::now();
+        loop {
+            if manager
+                .heartbeat()
+                .is_some_and(|interval| last_heartbeat.elapsed() >= interval)
+            {
+                if let Err(e) = manager.ping() {
+                    tracing::warn!(health = "lost", error = %e, "connection lost");
+                    self.dispatch(&WatchEvent::ConnectionLost(e.to_string()), manager)?;
+                    if !self.try_reconnect(manager, wants_console, &e)? {
+                        return Err(format!("Lost connection to Firefox: {}", e).into());
+                    }
+                }
+                last_heartbeat = Instant::now();
+            }
+
+            if wants_console && last_console_poll.elapsed() >= self.console_interval {
+                match manager.drain_console() {
+                    Ok(records) if !records.is_empty() => {
+                        self.dispatch(&WatchEvent::Console(records), manager)?
+                    }
+                    Err(e) if self.try_reconnect(manager, wants_console, &e)? => {}
+                    Ok(_) => {}
+                    Err(e) => eprintln!("Could not read the browser console: {}", e),
+                }
+                last_console_poll = Instant::now();
+            }
+
+            if let Source::Poll(poller) = &mut source {
+                match poller.poll() {
+                    Some(paths) if !paths.is_empty() => {
+                        self.sender.send(WatchEvent::FilesChanged(paths)).ok();
+                    }
+                    _ => {}
+                }
+            }
+
+            match self.receiver.recv_timeout(Duration::from_millis(100)) {
+                Ok(event) => {
+                    if let Err(e) = self.dispatch(&event, manager) {
+                        if !self.try_reconnect(manager, wants_console, &e)? {
+                            return Err(e);
+                        }
+                    }
+                    match event {
+                        WatchEvent::FilesChanged(paths) => {
+                            let wanted = self.watch_paths();
+                            sync_watches(&mut source, &mut watched, &wanted, &paths);
+                        }
+                        WatchEvent::Shutdown => return Ok(()),
+                        _ => {}
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err("File watcher disconnected".into());
+                }
+            }
+        }
+    }
+
+    fn dispatch(
+        &mut self,
+        event: &WatchEvent,
+        manager: &mut ChromeCSSManager,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        for handler in &mut self.handlers {
+            handler.handle(event, manager)?;
+        }
+        Ok(())
+    }
+
+    /// After `error`, reconnect if the manager's [`ReconnectPolicy`] allows
+    /// it and tell the handlers. Returns whether the session can go on.
+    ///
+    /// [`ReconnectPolicy`]: crate::marionette_client::ReconnectPolicy
+    fn try_reconnect(
+        &mut self,
+        manager: &mut ChromeCSSManager,
+        wants_console: bool,
+        error: &dyn std::fmt::Display,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        if manager.reconnect_policy() == ReconnectPolicy::Never {
+            return Ok(false);
+        }
+        eprintln!("{}; reconnecting...", error);
+        manager
+            .term_status()
+            .update(WatchStatus::Reloading, "reconnecting");
+        if let Err(e) = manager.reconnect() {
+            eprintln!("Could not reconnect: {}", e);
+            manager
+                .term_status()
+                .update(WatchStatus::Error, "could not reconnect");
+            return Ok(false);
+        }
+        tracing::info!(health = "reconnected", "reconnected");
+        if wants_console {
+            manager.start_console_capture()?;
+        }
+        self.dispatch(&WatchEvent::Reconnected, manager)?;
+        Ok(true)
+    }
+
+    fn watch_paths(&self) -> Vec<(PathBuf, RecursiveMode)> {
+        let mut paths: Vec<(PathBuf, RecursiveMode)> = Vec::new();
+        for (path, mode) in self.handlers.iter().flat_map(|h| h.watch_paths()) {
+            match paths.iter_mut().find(|(p, _)| *p == path) {
+                Some(existing) if mode == RecursiveMode::Recursive => existing.1 = mode,
+                Some(_) => {}
+                None => paths.push((path, mode)),
+            }
+        }
+        paths
+    }
+}
+
+/// Where file changes come from: the platform's notifications, or polling.
+enum Source {
+    #[cfg(not(target_family = "wasm"))]
+    Native(notify::RecommendedWatcher),
+    Poll(PollWatcher),
+}
+
+impl Source {
+    /// Poll every `poll` if given, otherwise use the platform's watcher,
+    /// sending its events to `sender`, or poll if there is none.
+    #[cfg(not(target_family = "wasm"))]
+    fn open(poll: Option<Duration>, sender: &Sender<WatchEvent>) -> Self {
+        if let Some(interval) = poll {
+            return Source::Poll(PollWatcher::new(interval));
+        }
+        let sender = sender.clone();
+        let watcher = notify::recommended_watcher(move |res: Result<Event, notify::Error>| {
+            if let Ok(event) = res {
+                if matches!(
+                    event.kind,
+                    EventKind::Modify(_) | EventKind::Create(_) | EventKind::Remove(_)
+                ) {
+                    sender.send(WatchEvent::FilesChanged(event.paths)).ok();
+                }
+            }
+        });
+        match watcher {
+            Ok(watcher) => Source::Native(watcher),
+            Err(e) => Source::fallback(&e),
+        }
+    }
+
+    #[cfg(target_family = "wasm")]
+    fn open(poll: Option<Duration>, _sender: &Sender<WatchEvent>) -> Self {
+        Source::Poll(PollWatcher::new(
+            poll.unwrap_or(poll_watcher::DEFAULT_INTERVAL),
+        ))
+    }
+
+    /// Polling, after the platform's watcher failed with `error`.
+    fn fallback(error: &dyn std::fmt::Display) -> Self {
+        let poller = PollWatcher::new(poll_watcher::DEFAULT_INTERVAL);
+        eprintln!(
+            "Warning: file change notifications are not working ({}); polling every {}s instead",
+            error,
+            poller.interval().as_secs_f64()
+        );
+        Source::Poll(poller)
+    }
+
+    fn watch(
+        &mut self,
+        path: &Path,
+        mode: RecursiveMode,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        match self {
+            #[cfg(not(target_family = "wasm"))]
+            Source::Native(watcher) => {
+                let mode = match mode {
+                    RecursiveMode::Recursive => notify::RecursiveMode::Recursive,
+                    RecursiveMode::NonRecursive => notify::RecursiveMode::NonRecursive,
+                };
+                Ok(watcher.watch(path, mode)?)
+            }
+            Source::Poll(poller) => poller.watch(path, mode),
+        }
+    }
+
+    fn unwatch(&mut self, path: &Path) {
+        match self {
+            #[cfg(not(target_family = "wasm"))]
+            Source::Native(watcher) => {
+                watcher.unwatch(path).ok();
+            }
+            Source::Poll(poller) => poller.unwatch(path),
+        }
+    }
+}
+
+/// Point `source` at exactly `paths`. Paths in `changed` are watched again
+/// even if they were already, since editors that save by replacing the file
+/// leave the old watch on a deleted inode. A path that is missing, as it is
+/// for a moment during such a save, is waited for by watching its directory
+/// until it is back. If the platform's watcher cannot watch a path that
+/// exists, such as on a network mount or past the system's watch limit,
+/// `source` switches to polling.
+fn sync_watches(
+    source: &mut Source,
+    watched: &mut Vec<(PathBuf, RecursiveMode)>,
+    paths: &[(PathBuf, RecursiveMode)],
+    changed: &[PathBuf],
+) {
+    let changed: Vec<PathBuf> = changed.iter().map(|p| normalize_path(p)).collect();
+    watched.retain(|entry| {
+        let keep = paths.contains(entry) && !changed.contains(&entry.0);
+        if !keep {
+            source.unwatch(&entry.0);
+        }
+        keep
+    });
+    for entry in paths {
+        if watched.contains(entry) {
+            continue;
+        }
+        match source.watch(&entry.0, entry.1) {
+            Ok(()) => watched.push(entry.clone()),
+            Err(e) if !matches!(source, Source::Poll(_)) && entry.0.exists() => {
+                *source = Source::fallback(&e);
+                let previous = std::mem::take(watched);
+                sync_watches(source, watched, &previous, &[]);
+                sync_watches(source, watched, paths, &[]);
+                return;
+            }
+            Err(_) if !entry.0.exists() => {
+                let Some(parent) = entry.0.parent() else {
+                    continue;
+                };
+                let parent = (parent.to_path_buf(), RecursiveMode::NonRecursive);
+                if !watched.contains(&parent) && source.watch(&parent.0, parent.1).is_ok() {
+                    watched.push(parent);
+                }
+            }
+            Err(e) => eprintln!("Warning: cannot watch {}: {}", entry.0.display(), e),
+        }
+    }
+}
+
+/// When a reload is undone, so a bad save does not leave the browser UI
+/// unusable. Reloads are kept when neither check is set.
+#[derive(Debug, Clone, Default)]
+pub struct RollbackPolicy {
+    /// Roll back when the new sheet has more CSS errors than this.
+    pub max_errors: Option<usize>,
+    /// A chrome script run after each reload; throwing or returning `false`
+    /// rolls back. Read again each time, so it can be edited while watching.
+    pub verify: Option<PathBuf>,
+}
+
+impl RollbackPolicy {
+    pub fn is_enabled(&self) -> bool {
+        self.max_errors.is_some() || self.verify.is_some()
+    }
+
+    /// Why the sheet just loaded should be rolled back, if it should.
+    fn check(&self, manager: &mut ChromeCSSManager, errors: &[String]) -> Option<String> {
+        if let Some(max) = self.max_errors.filter(|&max| errors.len() > max) {
+            return Some(format!("{} CSS errors (more than {})", errors.len(), max));
+        }
+        let verify = self.verify.as_ref()?;
+        let result = std::fs::read_to_string(verify)
+            .map_err(|e| e.into())
+            .and_then(|js| manager.connection().execute_script(&js, None))
+            .map_err(|e| locate_error(e, script_file_frame(verify)));
+        match result {
+            Ok(serde_json::Value::Bool(false)) => {
+                Some(format!("{} returned false", file_name(verify)))
+            }
+            Ok(_) => None,
+            Err(e) => Some(format!("{} failed: {}", file_name(verify), e)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConnectionState {
+    #[default]
+    Connected,
+    /// The last load failed to reach the browser.
+    Lost,
+}
+
+/// How a watched sheet is doing, printed as a status line after each load
+/// and written to the metrics file.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SheetMetrics {
+    pub id: String,
+    pub file: PathBuf,
+    /// Reloads since the watch started, not counting the initial load.
+    pub reloads: u64,
+    /// When the last load finished, in milliseconds since the Unix epoch.
+    pub last_reload: u64,
+    /// How long the last load took, from reading the files to checking the
+    /// result for CSS errors.
+    pub latency_ms: u64,
+    /// CSS errors in the last version loaded.
+    pub errors: usize,
+    pub rollbacks: u64,
+    pub reconnects: u64,
+    pub connection: ConnectionState,
+}
+
+impl SheetMetrics {
+    fn loaded(&mut self, started: Instant, errors: usize) {
+        self.latency_ms = started.elapsed().as_millis() as u64;
+        self.last_reload = now_millis();
+        self.errors = errors;
+        self.connection = ConnectionState::Connected;
+    }
+
+    pub fn status_line(&self) -> String {
+        let secs = self.last_reload / 1000 % 86400;
+        let load = match self.reloads {
+            0 => "initial load".to_string(),
+            n => format!("reload {}", n),
+        };
+        let mut line = format!(
+            "[{}] {} at {:02}:{:02}:{:02} UTC in {} ms, {} CSS errors",
+            self.id,
+            load,
+            secs / 3600,
+            secs / 60 % 60,
+            secs % 60,
+            self.latency_ms,
+            self.errors
+        );
+        if self.rollbacks > 0 {
+            line.push_str(&format!(", {} rolled back", self.rollbacks));
+        }
+        if self.reconnects > 0 {
+            line.push_str(&format!(", {} reconnects", self.reconnects));
+        }
+        line.push_str(match self.connection {
+            ConnectionState::Connected => ", connected",
+            ConnectionState::Lost => ", connection lost",
+        });
+        line
+    }
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// The metrics of every watched sheet, rewritten as one JSON document after
+/// each load for dashboards to poll.
+#[derive(Debug)]
+pub struct MetricsFile {
+    path: PathBuf,
+    sheets: BTreeMap<String, SheetMetrics>,
+}
+
+#[derive(Serialize)]
+struct MetricsSnapshot<'a> {
+    updated: u64,
+    sheets: Vec<&'a SheetMetrics>,
+}
+
+impl MetricsFile {
+    pub fn new(path: &Path) -> Self {
+        MetricsFile {
+            path: path.to_path_buf(),
+            sheets: BTreeMap::new(),
+        }
+    }
+
+    /// Replace the entry for `metrics.id` and write the file. It is written
+    /// next to the target and renamed over it, so readers never see half.
+    pub fn update(&mut self, metrics: &SheetMetrics) -> Result<(), Box<dyn std::error::Error>> {
+        self.sheets.insert(metrics.id.clone(), metrics.clone());
+        let snapshot = MetricsSnapshot {
+            updated: now_millis(),
+            sheets: self.sheets.values().collect(),
+        };
+        let mut temp = self.path.clone().into_os_string();
+        temp.push(".tmp");
+        std::fs::write(&temp, serde_json::to_string_pretty(&snapshot)?)?;
+        std::fs::rename(&temp, &self.path)?;
+        Ok(())
+    }
+}
+
+/// The local files behind the `url()`s of a watched sheet, so an edited icon
+/// or font shows up without restarting the browser.
+///
+/// The browser caches images and fonts by URL, and reloading the sheet
+/// alone reuses the cached copy. Once a file changes, its URLs get a
+/// `mus-uc=<n>` query, which makes them new to the cache.
+#[derive(Debug, Default)]
+struct AssetTracker {
+    /// Where the `chrome://` and `resource://` URLs registered with the tool
+    /// point.
+    mappings: ChromeManifest,
+    /// URL, without query or fragment, to the file it loads.
+    urls: BTreeMap<String, PathBuf>,
+    /// How many times each file changed.
+    versions: BTreeMap<PathBuf, u32>,
+}
+
+impl AssetTracker {
+    fn refresh_mappings(&mut self, manager: &mut ChromeCSSManager) {
+        match manager.registered_mappings() {
+            Ok(mappings) => self.mappings = mappings,
+            Err(e) => eprintln!(
+                "Warning: cannot tell which files chrome:// URLs load: {}",
+                e
+            ),
+        }
+    }
+
+    /// Record the files `resolved` refers to.
+    fn track(&mut self, resolved: &ResolvedStylesheet) {
+        self.urls = resolved
+            .assets
+            .iter()
+            .map(|asset| (file_url(asset), asset.clone()))
+            .collect();
+        map_urls(&resolved.css, |url| {
+            let base = url.split(['?', '#']).next().unwrap_or(url);
+            if let Some(file) = self.mappings.file_for(base).filter(|f| f.is_file()) {
+                self.urls.insert(base.to_string(), file);
+            }
+            None
+        });
+    }
+
+    fn files(&self) -> impl Iterator<Item = &PathBuf> {
+        self.urls.values()
+    }
+
+    /// Count the tracked files among `paths` as changed, and return them.
+    fn changed(&mut self, paths: &[PathBuf]) -> Vec<PathBuf> {
+        let mut changed = Vec::new();
+        for path in paths.iter().map(|p| normalize_path(p)) {
+            if !changed.contains(&path) && self.urls.values().any(|file| *file == path) {
+                *self.versions.entry(path.clone()).or_default() += 1;
+                changed.push(path);
+            }
+        }
+        changed
+    }
+
+    /// `css` with the URLs of changed files made new to the browser's cache.
+    fn bust(&self, css: &str) -> String {
+        map_urls(css, |url| {
+            let base = url.split(['?', '#']).next().unwrap_or(url);
+            let version = self.versions.get(self.urls.get(base)?)?;
+            Some(cache_busted(url, *version))
+        })
+    }
+}
+
+/// Keeps a stylesheet loaded and reloads it when a file in its import graph,
+/// or under its directory, changes, or an image or font it uses.
+pub struct SheetHandler {
+    path: PathBuf,
+    dir: Option<PathBuf>,
+    /// What is left alone under `dir`.
+    filter: Option<WatchFilter>,
+    id: Option<String>,
+    root: Option<PathBuf>,
+    cache: ImportCache,
+    resolved: Option<ResolvedStylesheet>,
+    /// The last version that passed the rollback checks.
+    last_good: Option<ResolvedStylesheet>,
+    assets: AssetTracker,
+    lock: Option<WatchLock>,
+    /// What the state file records about the sheet.
+    tags: Vec<String>,
+    description: Option<String>,
+    group: Option<String>,
+    metrics: SheetMetrics,
+}
+
+impl SheetHandler {
+    pub fn new(target: &WatchTarget) -> Self {
+        SheetHandler {
+            path: target.file.clone(),
+            dir: target.dir.clone(),
+            filter: target
+                .dir
+                .as_ref()
+                .map(|dir| WatchFilter::new(dir, &target.ignore)),
+            id: target.id.clone(),
+            root: None,
+            cache: ImportCache::new(),
+            resolved: None,
+            last_good: None,
+            assets: AssetTracker::default(),
+            lock: None,
+            tags: target.tags.clone(),
+            description: target.description.clone(),
+            group: target.group.clone(),
+            metrics: SheetMetrics {
+                file: target.file.clone(),
+                ..SheetMetrics::default()
+            },
+        }
+    }
+
+    /// Print the status line and update the metrics file.
+    fn report_metrics(&self, manager: &mut ChromeCSSManager) {
+        println!("{}", self.metrics.status_line());
+        manager.record_metrics(&self.metrics);
+    }
+
+    /// Show in the terminal that the sheet loaded, with `errors` CSS errors.
+    fn show_loaded(&self, manager: &ChromeCSSManager, errors: usize) {
+        let file = file_name(&self.path);
+        match errors {
+            0 => manager.term_status().update(WatchStatus::Ok, &file),
+            1 => manager
+                .term_status()
+                .update(WatchStatus::Warning, &format!("1 CSS error in {}", file)),
+            n => manager.term_status().update(
+                WatchStatus::Warning,
+                &format!("{} CSS errors in {}", n, file),
+            ),
+        }
+    }
+
+    /// Run the hooks for `event` with this sheet and `errors`.
+    fn run_hooks(&self, manager: &ChromeCSSManager, event: HookEvent, errors: &[String]) {
+        manager.run_hooks(
+            event,
+            &HookContext {
+                id: self.id.clone(),
+                file: Some(self.path.clone()),
+                errors: errors.to_vec(),
+            },
+        );
+    }
+
+    /// Record where sheet `id` comes from and how it is tagged in the state
+    /// file. Only done once the watch lock is held, so a watcher that is
+    /// refused leaves the running one's entry alone.
+    fn record(&self, manager: &ChromeCSSManager, id: &str, file: &Path) {
+        let metadata = SheetMetadata {
+            group: self.group.clone(),
+            ..SheetMetadata::new(
+                Some(file.display().to_string()),
+                self.tags.clone(),
+                self.description.clone(),
+            )
+        };
+        let state_dir = manager.state_dir();
+        if let Err(e) = SheetState::update_in(&state_dir, |state| state.record(id, metadata)) {
+            eprintln!(
+                "Warning: could not update {}: {}",
+                state_dir.join("sheets.json").display(),
+                e
+            );
+        }
+    }
+
+    /// Whether `path` is part of the sheet as last resolved.
+    fn is_resolved_file(&self, path: &Path) -> bool {
+        let path = normalize_path(path);
+        self.resolved
+            .as_ref()
+            .is_some_and(|resolved| resolved.files.contains(&path))
+    }
+
+    /// Rebuild the sheet from the cache. Only a lost connection is an error;
+    /// unreadable files are reported and skipped.
+    fn reload(&mut self, manager: &mut ChromeCSSManager) -> Result<(), Box<dyn std::error::Error>> {
+        let id = self.id.clone().unwrap_or_default();
+        let started = Instant::now();
+        let mut resolved = match manager.resolve_file_with_cache(&self.path, &mut self.cache) {
+            Ok(resolved) => resolved,
+            Err(e) => {
+                eprintln!("Error reloading CSS: {}", e);
+                manager.term_status().update(
+                    WatchStatus::Error,
+                    &format!("cannot read {}", file_name(&self.path)),
+                );
+                manager.notifier().error(
+                    &format!("Could not read {}", file_name(&self.path)),
+                    &e.to_string(),
+                );
+                self.run_hooks(manager, HookEvent::ReloadError, &[e.to_string()]);
+                return Ok(());
+            }
+        };
+        self.assets.track(&resolved);
+        resolved.css = self.assets.bust(&resolved.css);
+
+        let profiling = manager.profile_dir().is_some();
+        if profiling {
+            if let Err(e) = manager.start_profiler() {
+                eprintln!("Could not start the profiler: {}", e);
+            }
+        }
+        if let Err(e) = manager.load_resolved(&self.path, &resolved, Some(&id)) {
+            manager
+                .term_status()
+                .update(WatchStatus::Error, "connection lost");
+            manager
+                .notifier()
+                .error("Lost connection to Firefox", &e.to_string());
+            self.metrics.connection = ConnectionState::Lost;
+            self.report_metrics(manager);
+            self.run_hooks(manager, HookEvent::ReloadError, &[e.to_string()]);
+            return Err(e);
+        }
+        if profiling {
+            match save_profile(manager, &id) {
+                Ok(Some(path)) => println!(
+                    "Profile saved to {} (open it at https://profiler.firefox.com/)",
+                    path.display()
+                ),
+                Ok(None) => {}
+                Err(e) => eprintln!("Could not save the profile: {}", e),
+            }
+        }
+
+        report_unresolved(&resolved);
+        self.resolved = Some(resolved.clone());
+        let errors = manager.report_css_errors(&id, self.root.as_deref());
+        self.metrics.reloads += 1;
+        self.metrics.loaded(started, errors.len());
+        let policy = manager.rollback_policy().clone();
+        if let Some(reason) = policy.check(manager, &errors) {
+            let result = self.roll_back(manager, &id, &reason);
+            self.report_metrics(manager);
+            let mut errors = errors;
+            errors.push(reason);
+            self.run_hooks(manager, HookEvent::ReloadError, &errors);
+            return result;
+        }
+        if policy.is_enabled() {
+            self.last_good = Some(resolved);
+        }
+        println!("CSS reloaded successfully: {}", id);
+        self.report_metrics(manager);
+        self.show_loaded(manager, errors.len());
+        if errors.is_empty() {
+            manager
+                .notifier()
+                .info("CSS reloaded", &file_name(&self.path));
+            self.run_hooks(manager, HookEvent::ReloadSuccess, &errors);
+        } else {
+            manager.notify_css_errors(&self.path, &errors);
+            self.run_hooks(manager, HookEvent::ReloadError, &errors);
+        }
+        Ok(())
+    }
+
+    /// Load the last good version again after a reload failed `reason`.
+    fn roll_back(
+        &mut self,
+        manager: &mut ChromeCSSManager,
+        id: &str,
+        reason: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let file = file_name(&self.path);
+        let Some(good) = &self.last_good else {
+            eprintln!(
+                "\n!!! {}: {}, and there is no earlier version to roll back to\n",
+                id, reason
+            );
+            manager
+                .term_status()
+                .update(WatchStatus::Error, &format!("{} is broken", file));
+            manager
+                .notifier()
+                .error(&format!("{} is broken", file), reason);
+            return Ok(());
+        };
+        manager.load_resolved(&self.path, good, Some(id))?;
+        self.metrics.rollbacks += 1;
+        manager
+            .term_status()
+            .update(WatchStatus::Error, &format!("rolled back {}", file));
+        eprintln!(
+            "\n!!! ROLLED BACK {}: {}\n!!! The browser shows the last good version; fix {} and save again.\n",
+            id, reason, file
+        );
+        manager
+            .notifier()
+            .error(&format!("Rolled back {}", file), reason);
+        Ok(())
+    }
+}
+
+impl Handler for SheetHandler {
+    fn start(&mut self, manager: &mut ChromeCSSManager) -> Result<(), Box<dyn std::error::Error>> {
+        let id = match &self.id {
+            Some(id) => id.clone(),
+            None => manager.generate_id(Some(&self.path), "")?,
+        };
+        // Held until the watcher stops, so a second watcher for the same ID is refused
+        self.lock = Some(WatchLock::acquire(&id, &manager.address(), &self.path)?);
+        let file = canonicalize(&self.path)?;
+        self.record(manager, &id, &file);
+        self.root = file.parent().map(Path::to_path_buf);
+        self.assets.refresh_mappings(manager);
+
+        let started = Instant::now();
+        let (_, resolved) =
+            manager.load_css_file_with_cache(&self.path, Some(&id), &mut self.cache)?;
+        report_unresolved(&resolved);
+        self.assets.track(&resolved);
+        println!("Initial CSS loaded with ID: {}", id);
+        let errors = manager.report_css_errors(&id, self.root.as_deref());
+        manager.notify_css_errors(&self.path, &errors);
+        self.metrics.id = id.clone();
+        self.metrics.loaded(started, errors.len());
+
+        self.show_loaded(manager, errors.len());
+
+        let policy = manager.rollback_policy().clone();
+        if policy.is_enabled() {
+            match policy.check(manager, &errors) {
+                Some(reason) => self.roll_back(manager, &id, &reason)?,
+                None => self.last_good = Some(resolved.clone()),
+            }
+        }
+        self.resolved = Some(resolved);
+        self.id = Some(id);
+        self.report_metrics(manager);
+        self.run_hooks(manager, HookEvent::Load, &errors);
+        Ok(())
+    }
+
+    /// The directories under the directory if one was given, less the
+    /// ignored ones, otherwise the entry file and everything it imports, plus
+    /// the images and fonts the sheet uses.
+    fn watch_paths(&self) -> Vec<(PathBuf, RecursiveMode)> {
+        let mut paths = match (&self.filter, &self.resolved) {
+            (Some(filter), _) => filter
+                .directories()
+                .into_iter()
+                .map(|dir| (dir, RecursiveMode::NonRecursive))
+                .collect(),
+            (None, Some(resolved)) => resolved
+                .files
+                .iter()
+                .map(|file| (file.clone(), RecursiveMode::NonRecursive))
+                .collect(),
+            (None, None) => Vec::new(),
+        };
+        let outside = |file: &&PathBuf| match &self.dir {
+            Some(dir) => !file.starts_with(dir),
+            None => true,
+        };
+        paths.extend(
+            self.assets
+                .files()
+                .filter(outside)
+                .map(|file| (file.clone(), RecursiveMode::NonRecursive)),
+        );
+        paths
+    }
+
+    fn handle(
+        &mut self,
+        event: &WatchEvent,
+        manager: &mut ChromeCSSManager,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        match event {
+            WatchEvent::FilesChanged(paths) => {
+                let paths: Vec<PathBuf> = paths
+                    .iter()
+                    .filter(|p| {
+                        !self
+                            .filter
+                            .as_ref()
+                            .is_some_and(|filter| filter.ignores(p, p.is_dir()))
+                    })
+                    .cloned()
+                    .collect();
+                // A file whose read failed has left the cache, but is still
+                // watched from the last good resolve
+                let changed: Vec<PathBuf> = paths
+                    .iter()
+                    .filter(|p| self.cache.invalidate(p) || self.is_resolved_file(p))
+                    .cloned()
+                    .collect();
+                let assets = self.assets.changed(&paths);
+                if changed.is_empty() && assets.is_empty() {
+                    return Ok(());
+                }
+                manager
+                    .term_status()
+                    .update(WatchStatus::Reloading, &file_name(&self.path));
+                for file in &changed {
+                    println!("{} changed, reloading CSS...", file.display());
+                }
+                for file in &assets {
+                    println!("{} changed, refreshing it...", file.display());
+                }
+                self.reload(manager)
+            }
+            WatchEvent::ConnectionLost(error) => {
+                manager
+                    .term_status()
+                    .update(WatchStatus::Error, "connection lost");
+                manager
+                    .notifier()
+                    .error("Lost connection to Firefox", error);
+                self.metrics.connection = ConnectionState::Lost;
+                self.report_metrics(manager);
+                Ok(())
+            }
+            WatchEvent::Reconnected => {
+                println!("Reconnected, reloading {}...", self.path.display());
+                self.metrics.reconnects += 1;
+                self.cache.clear();
+                self.assets.refresh_mappings(manager);
+                self.reload(manager)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// How long the profiler keeps running after a reload, to catch the restyle
+/// and paint that follow it.
+const PROFILE_SETTLE: Duration = Duration::from_millis(500);
+
+/// Stop the profiler started before a reload of `id` and write the profile
+/// to the manager's profile directory.
+fn save_profile(
+    manager: &mut ChromeCSSManager,
+    id: &str,
+) -> Result<Option<PathBuf>, Box<dyn std::error::Error>> {
+    std::thread::sleep(PROFILE_SETTLE);
+    let Some(profile) = manager.stop_profiler()? else {
+        return Ok(None);
+    };
+    let Some(dir) = manager.profile_dir() else {
+        return Ok(None);
+    };
+    std::fs::create_dir_all(dir)?;
+    let stamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_millis();
+    let path = dir.join(format!("{}-{}.json", id, stamp));
+    std::fs::write(&path, profile)?;
+    Ok(Some(path))
+}
+
+/// Runs a script in the chrome context at the start and each time it changes.
+pub struct ScriptHandler {
+    path: PathBuf,
+}
+
+impl ScriptHandler {
+    pub fn new(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(ScriptHandler {
+            path: canonicalize(path)?,
+        })
+    }
+
+    /// Failures are reported, not returned, so a script with a typo does not
+    /// end the session.
+    fn run(&self, manager: &mut ChromeCSSManager) {
+        let result = std::fs::read_to_string(&self.path)
+            .map_err(|e| e.into())
+            .and_then(|js| manager.connection().execute_script(&js, None))
+            .map_err(|e| locate_error(e, script_file_frame(&self.path)));
+        match result {
+            Ok(serde_json::Value::Null) => println!("Ran {}", self.path.display()),
+            Ok(value) => println!("Ran {}: {}", self.path.display(), value),
+            Err(e) => {
+                eprintln!("Error running {}: {}", self.path.display(), e);
+                manager.term_status().update(
+                    WatchStatus::Error,
+                    &format!("{} failed", file_name(&self.path)),
+                );
+                manager.notifier().error(
+                    &format!("Script failed: {}", file_name(&self.path)),
+                    &e.to_string(),
+                );
+            }
+        }
+    }
+}
+
+impl Handler for ScriptHandler {
+    fn start(&mut self, manager: &mut ChromeCSSManager) -> Result<(), Box<dyn std::error::Error>> {
+        self.run(manager);
+        Ok(())
+    }
+
+    fn watch_paths(&self) -> Vec<(PathBuf, RecursiveMode)> {
+        vec![(self.path.clone(), RecursiveMode::NonRecursive)]
+    }
+
+    fn handle(
+        &mut self,
+        event: &WatchEvent,
+        manager: &mut ChromeCSSManager,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        match event {
+            WatchEvent::FilesChanged(paths)
+                if paths.iter().any(|p| normalize_path(p) == self.path) =>
+            {
+                println!("{} changed, running script...", self.path.display());
+                self.run(manager);
+            }
+            WatchEvent::Reconnected => self.run(manager),
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// Appends browser console messages to a [`ConsoleLog`].
+pub struct ConsoleLogHandler {
+    log: ConsoleLog,
+}
+
+impl ConsoleLogHandler {
+    pub fn new(log: ConsoleLog) -> Self {
+        ConsoleLogHandler { log }
+    }
+}
+
+impl Handler for ConsoleLogHandler {
+    fn wants_console(&self) -> bool {
+        true
+    }
+
+    fn handle(
+        &mut self,
+        event: &WatchEvent,
+        _manager: &mut ChromeCSSManager,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if let WatchEvent::Console(records) = event {
+            if let Err(e) = self.log.append(records) {
+                eprintln!("Could not write the console log: {}", e);
+            }
+        }
+        Ok(())
+    }
+}
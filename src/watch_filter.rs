@@ -0,0 +1,190 @@
+//! Files under a watched directory that `watch` leaves alone, for
+//! `watch --dir ... --ignore PATTERN` and the `ignore` key of `[[watch]]`.
+//!
+//! Patterns work like lines of a `.gitignore`: `*` and `?` match within one
+//! path component and `**` across any number of them; a pattern without a
+//! `/` matches a component at any depth, one with a `/` matches from the
+//! watched directory; a trailing `/` matches directories only, and a leading
+//! `!` takes a file back in. The last pattern that matches decides.
+//!
+//! Dotfiles and dot-directories such as `.git`, and the temporary files
+//! editors write next to the file being saved (Emacs `*~`, `.#*` and `#*#`,
+//! Vim `*.swp`, `*.swx` and its `4913` probe), are ignored before the given
+//! patterns, which can take them back in.
+
+use crate::chrome_manifest::normalize_path;
+use crate::paths::canonicalize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Ignored by every filter, before its own patterns.
+pub const DEFAULT_IGNORES: &[&str] = &[".*", "*~", "#*#", "*.swp", "*.swx", "4913"];
+
+#[derive(Debug, Clone)]
+pub struct WatchFilter {
+    root: PathBuf,
+    patterns: Vec<Pattern>,
+}
+
+#[derive(Debug, Clone)]
+struct Pattern {
+    /// Path components, with `**` standing for any number of them.
+    segments: Vec<String>,
+    /// Whether the pattern matches from the root rather than at any depth.
+    anchored: bool,
+    dir_only: bool,
+    negated: bool,
+}
+
+impl WatchFilter {
+    /// A filter for the directory `root` with `patterns` after the defaults.
+    pub fn new(root: &Path, patterns: &[String]) -> Self {
+        let patterns = DEFAULT_IGNORES
+            .iter()
+            .copied()
+            .chain(patterns.iter().map(String::as_str))
+            .filter_map(Pattern::parse)
+            .collect();
+        WatchFilter {
+            root: canonicalize(root).unwrap_or_else(|_| absolute(root)),
+            patterns,
+        }
+    }
+
+    /// Whether `path` is ignored. Paths outside the root never are.
+    pub fn ignores(&self, path: &Path, is_dir: bool) -> bool {
+        let path = absolute(path);
+        let canonical = canonicalize(&path).ok();
+        let Some(relative) = [Some(&path), canonical.as_ref()]
+            .into_iter()
+            .flatten()
+            .find_map(|path| path.strip_prefix(&self.root).ok())
+        else {
+            return false;
+        };
+        let components: Vec<String> = relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy().into_owned())
+            .collect();
+        if components.is_empty() {
+            return false;
+        }
+        let mut ignored = false;
+        for pattern in &self.patterns {
+            if pattern.matches(&components, is_dir) {
+                ignored = !pattern.negated;
+            }
+        }
+        ignored
+    }
+
+    /// The root and every directory under it that is not ignored, to watch
+    /// one by one instead of the whole tree, so ignored directories such as
+    /// `node_modules` cost no watches.
+    pub fn directories(&self) -> Vec<PathBuf> {
+        let mut found = Vec::new();
+        let mut pending = vec![self.root.clone()];
+        while let Some(dir) = pending.pop() {
+            if let Ok(entries) = fs::read_dir(&dir) {
+                for entry in entries.flatten() {
+                    let path = entry.path();
+                    if entry.file_type().is_ok_and(|t| t.is_dir()) && !self.ignores(&path, true) {
+                        pending.push(path);
+                    }
+                }
+            }
+            found.push(dir);
+        }
+        found.sort();
+        found
+    }
+}
+
+/// `path` made absolute without touching the filesystem, as notify reports
+/// the paths of events.
+fn absolute(path: &Path) -> PathBuf {
+    normalize_path(&std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf()))
+}
+
+impl Pattern {
+    fn parse(text: &str) -> Option<Self> {
+        let text = text.trim();
+        let (negated, text) = match text.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, text),
+        };
+        let (dir_only, text) = match text.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, text),
+        };
+        let anchored = text.contains('/');
+        let segments: Vec<String> = text
+            .trim_start_matches('/')
+            .split('/')
+            .filter(|s| !s.is_empty())
+            .map(String::from)
+            .collect();
+        (!segments.is_empty()).then_some(Pattern {
+            segments,
+            anchored,
+            dir_only,
+            negated,
+        })
+    }
+
+    /// Whether the pattern matches `components`, or a directory they are in.
+    fn matches(&self, components: &[String], is_dir: bool) -> bool {
+        (1..=components.len()).any(|len| {
+            let prefix = &components[..len];
+            if self.dir_only && len == components.len() && !is_dir {
+                return false;
+            }
+            if self.anchored {
+                match_segments(&self.segments, prefix)
+            } else {
+                self.segments.len() == 1 && match_component(&self.segments[0], &prefix[len - 1])
+            }
+        })
+    }
+}
+
+fn match_segments(pattern: &[String], path: &[String]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((first, rest)) if first == "**" => {
+            (0..=path.len()).any(|skip| match_segments(rest, &path[skip..]))
+        }
+        Some((first, rest)) => {
+            !path.is_empty() && match_component(first, &path[0]) && match_segments(rest, &path[1..])
+        }
+    }
+}
+
+/// Match one path component against a pattern with `*` and `?`.
+fn match_component(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    let mut backtrack = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star, at)) => {
+                    p = star + 1;
+                    n = at + 1;
+                    backtrack = Some((star, at + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
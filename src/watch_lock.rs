@@ -0,0 +1,160 @@
+//! Single-instance locking for watched sheets.
+//!
+//! Each running watcher holds an exclusive lock on
+//! `<temp dir>/mus-uc-devtools/watch-<id>@<browser>.lock`, which also records
+//! who holds it. Watchers of the same ID in different browsers do not clash. The operating system drops the lock when the process exits, so a file
+//! left behind by a killed watcher is simply reused. Lock files are never
+//! deleted: removing one while another process is opening it would let two
+//! watchers lock different files for the same ID.
+
+use crate::paths::canonicalize;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{ErrorKind, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WatchLockInfo {
+    pub pid: u32,
+    pub id: String,
+    /// Address of the browser the sheet is watched in.
+    #[serde(default)]
+    pub browser: String,
+    pub file: String,
+    /// Seconds since the Unix epoch.
+    pub started: u64,
+}
+
+/// Held for as long as a watcher runs; released on drop.
+#[derive(Debug)]
+pub struct WatchLock {
+    file: File,
+}
+
+impl WatchLock {
+    /// Claim sheet `id` in the browser at `browser` for a watcher of
+    /// `watched`. Fails if another live watcher has the same ID there.
+    pub fn acquire(
+        id: &str,
+        browser: &str,
+        watched: &Path,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let dir = lock_dir();
+        fs::create_dir_all(&dir)?;
+        let path = dir.join(format!("watch-{}@{}.lock", sanitize(id), sanitize(browser)));
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                let holder = read_info(&mut file);
+                return Err(match holder {
+                    Some(info) => format!(
+                        "Sheet ID '{}' is already being watched by process {} ({}). \
+                         Stop that watcher or pick another ID with --id.",
+                        id, info.pid, info.file
+                    ),
+                    None => format!("Sheet ID '{}' is already being watched", id),
+                }
+                .into());
+            }
+            // Platforms without file locking (such as WASI) run unguarded
+            Err(TryLockError::Error(e)) if e.kind() == ErrorKind::Unsupported => {}
+            Err(TryLockError::Error(e)) => return Err(e.into()),
+        }
+
+        let info = WatchLockInfo {
+            pid: std::process::id(),
+            id: id.to_string(),
+            browser: browser.to_string(),
+            file: canonicalize(watched)
+                .unwrap_or_else(|_| watched.to_path_buf())
+                .display()
+                .to_string(),
+            started: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+        };
+        file.set_len(0)?;
+        file.rewind()?;
+        file.write_all(serde_json::to_string(&info)?.as_bytes())?;
+        file.flush()?;
+
+        Ok(WatchLock { file })
+    }
+}
+
+impl Drop for WatchLock {
+    fn drop(&mut self) {
+        self.file.set_len(0).ok();
+        self.file.unlock().ok();
+    }
+}
+
+/// Watchers currently running on this machine.
+pub fn active_watchers() -> Vec<WatchLockInfo> {
+    let Ok(entries) = fs::read_dir(lock_dir()) else {
+        return Vec::new();
+    };
+    let mut watchers = Vec::new();
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("lock") {
+            continue;
+        }
+        let Ok(mut file) = File::open(&path) else {
+            continue;
+        };
+        // An unlocked file belongs to a watcher that has exited
+        if let Err(TryLockError::WouldBlock) = file.try_lock_shared() {
+            watchers.extend(read_info(&mut file));
+        }
+    }
+    watchers.sort_by(|a, b| a.id.cmp(&b.id));
+    watchers
+}
+
+fn lock_dir() -> PathBuf {
+    std::env::temp_dir().join("mus-uc-devtools")
+}
+
+fn read_info(file: &mut File) -> Option<WatchLockInfo> {
+    let mut content = String::new();
+    file.rewind().ok()?;
+    file.read_to_string(&mut content).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+/// Keep IDs usable as file names. Everything but ASCII letters, digits,
+/// `-`, `_` and `.` is percent-encoded, so distinct IDs never share a lock.
+fn sanitize(id: &str) -> String {
+    let mut encoded = String::with_capacity(id.len());
+    for byte in id.bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'_' | b'.') {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::sanitize;
+
+    #[test]
+    fn distinct_ids_get_distinct_names() {
+        assert_eq!(sanitize("my-sheet_1.css"), "my-sheet_1.css");
+        assert_ne!(sanitize("a/b"), sanitize("a_b"));
+        assert_ne!(sanitize("a%2Fb"), sanitize("a/b"));
+        assert_eq!(sanitize("127.0.0.1:2828"), "127.0.0.1%3A2828");
+    }
+}
@@ -0,0 +1,134 @@
+//! Browser window geometry and UI density, so screenshots come out the same
+//! on every machine.
+
+use crate::chrome_js;
+use crate::marionette_client::MarionetteConnection;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+/// Position and outer size of the browser window, in CSS pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct WindowRect {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// The toolbar density chosen in Customize mode (`browser.uidensity`).
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Density {
+    #[default]
+    Normal,
+    Compact,
+    Touch,
+}
+
+impl Density {
+    pub fn parse(name: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        match name {
+            "normal" => Ok(Density::Normal),
+            "compact" => Ok(Density::Compact),
+            "touch" => Ok(Density::Touch),
+            other => Err(format!(
+                "Unknown density '{}' (expected normal, compact or touch)",
+                other
+            )
+            .into()),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Density::Normal => "normal",
+            Density::Compact => "compact",
+            Density::Touch => "touch",
+        }
+    }
+
+    fn pref_value(self) -> i32 {
+        match self {
+            Density::Normal => 0,
+            Density::Compact => 1,
+            Density::Touch => 2,
+        }
+    }
+}
+
+/// Parse a size written as `WIDTHxHEIGHT`, e.g. `1280x800`.
+pub fn parse_size(size: &str) -> Result<(u32, u32), Box<dyn std::error::Error>> {
+    let invalid = || {
+        format!(
+            "Invalid size '{}' (expected WIDTHxHEIGHT, e.g. 1280x800)",
+            size
+        )
+    };
+    let (width, height) = size.split_once(['x', 'X']).ok_or_else(invalid)?;
+    let width: u32 = width.trim().parse().map_err(|_| invalid())?;
+    let height: u32 = height.trim().parse().map_err(|_| invalid())?;
+    if width == 0 || height == 0 {
+        return Err(invalid().into());
+    }
+    Ok((width, height))
+}
+
+pub fn window_rect(
+    connection: &mut MarionetteConnection,
+) -> Result<WindowRect, Box<dyn std::error::Error>> {
+    let result = connection.send_command("WebDriver:GetWindowRect", Value::Null)?;
+    Ok(serde_json::from_value(result)?)
+}
+
+/// Resize the browser window. Firefox may clamp the size to the screen;
+/// the returned rect is the size it actually got.
+pub fn set_window_size(
+    connection: &mut MarionetteConnection,
+    width: u32,
+    height: u32,
+) -> Result<WindowRect, Box<dyn std::error::Error>> {
+    let result = connection.send_command(
+        "WebDriver:SetWindowRect",
+        json!({ "width": width, "height": height }),
+    )?;
+    Ok(serde_json::from_value(result)?)
+}
+
+pub fn maximize_window(
+    connection: &mut MarionetteConnection,
+) -> Result<WindowRect, Box<dyn std::error::Error>> {
+    let result = connection.send_command("WebDriver:MaximizeWindow", Value::Null)?;
+    Ok(serde_json::from_value(result)?)
+}
+
+fn switch_density(
+    connection: &mut MarionetteConnection,
+    density: Option<Density>,
+) -> Result<Density, Box<dyn std::error::Error>> {
+    let window_type = connection.app_profile()?.window_type;
+    let script = chrome_js::render(
+        chrome_js::DENSITY,
+        &[
+            ("windowType", Value::from(window_type)),
+            ("density", json!(density.map(Density::pref_value))),
+        ],
+    );
+    let name = connection.execute_script(&script, None)?;
+    Density::parse(name.as_str().unwrap_or_default())
+}
+
+/// Switch the toolbar density, and wait until the browser windows have
+/// restyled for it. The connection must be in the chrome context.
+pub fn set_density(
+    connection: &mut MarionetteConnection,
+    density: Density,
+) -> Result<(), Box<dyn std::error::Error>> {
+    switch_density(connection, Some(density))?;
+    Ok(())
+}
+
+/// The toolbar density in use. The connection must be in the chrome context.
+pub fn density(
+    connection: &mut MarionetteConnection,
+) -> Result<Density, Box<dyn std::error::Error>> {
+    switch_density(connection, None)
+}
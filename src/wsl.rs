@@ -0,0 +1,38 @@
+//! Reaching Firefox on Windows from inside WSL.
+//!
+//! Under WSL 2's default NAT networking, `localhost` in Linux is not the
+//! Windows host. Windows is reachable at the Linux side's default gateway,
+//! which changes between restarts, so [`windows_host`] looks it up.
+
+use std::fs;
+use std::net::Ipv4Addr;
+
+/// Whether this process runs inside WSL.
+pub fn is_wsl() -> bool {
+    std::path::Path::new("/proc/sys/fs/binfmt_misc/WSLInterop").exists()
+        || fs::read_to_string("/proc/version")
+            .map(|v| v.to_lowercase().contains("microsoft"))
+            .unwrap_or(false)
+}
+
+/// The address of the Windows host, as seen from WSL.
+pub fn windows_host() -> Result<Ipv4Addr, Box<dyn std::error::Error>> {
+    if !is_wsl() {
+        return Err("--wsl only works inside WSL".into());
+    }
+    let routes = fs::read_to_string("/proc/net/route")?;
+    default_gateway(&routes).ok_or_else(|| "No default route to the Windows host".into())
+}
+
+/// The gateway of the default route in a `/proc/net/route` table, where
+/// addresses are little-endian hex.
+fn default_gateway(routes: &str) -> Option<Ipv4Addr> {
+    routes.lines().skip(1).find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.get(1) != Some(&"00000000") {
+            return None;
+        }
+        let gateway = u32::from_str_radix(fields.get(2)?, 16).ok()?;
+        Some(Ipv4Addr::from(gateway.to_le_bytes()))
+    })
+}
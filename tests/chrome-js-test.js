@@ -0,0 +1,36 @@
+/**
+ * Syntax check for the chrome-context scripts under src/js
+ *
+ * Marionette runs each script as the body of a function, so they are parsed
+ * the same way here. Template placeholders ({{name}}) stand where an
+ * expression can and are replaced with null first. This does not need Firefox.
+ */
+
+import { readdirSync, readFileSync } from 'fs';
+import { fileURLToPath } from 'url';
+import { dirname, join } from 'path';
+
+const __filename = fileURLToPath(import.meta.url);
+const __dirname = dirname(__filename);
+const scriptsDir = join(__dirname, '..', 'src', 'js');
+
+console.log('Checking chrome scripts...\n');
+
+let failures = 0;
+for (const name of readdirSync(scriptsDir).filter(f => f.endsWith('.js')).sort()) {
+  const source = readFileSync(join(scriptsDir, name), 'utf8')
+    .replace(/\{\{\w+\}\}/g, 'null');
+  try {
+    new Function(source);
+    console.log(`   ✓ ${name}`);
+  } catch (e) {
+    failures++;
+    console.error(`   ✗ ${name}: ${e.message}`);
+  }
+}
+
+if (failures > 0) {
+  console.error(`\n${failures} script(s) failed to parse`);
+  process.exit(1);
+}
+console.log('\nAll chrome scripts parse');
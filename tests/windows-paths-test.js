@@ -0,0 +1,142 @@
+/**
+ * Path handling test for mus-uc-devtools
+ *
+ * Runs the release binary against files in a directory whose name needs
+ * escaping (a space and a #), written with CRLF line endings, and against a
+ * mock Marionette server that records the scripts it is sent. On Windows this
+ * covers drive letters and backslashes; CI runs it there as well as on Linux.
+ * This does not need Firefox.
+ */
+
+import { spawn, spawnSync } from 'child_process';
+import { mkdtempSync, mkdirSync, readFileSync, realpathSync, rmSync, writeFileSync } from 'fs';
+import { createServer } from 'net';
+import { tmpdir } from 'os';
+import { fileURLToPath, pathToFileURL } from 'url';
+import { dirname, join } from 'path';
+
+const __filename = fileURLToPath(import.meta.url);
+const __dirname = dirname(__filename);
+const exe = join(__dirname, '..', 'target', 'release',
+  process.platform === 'win32' ? 'mus-uc.exe' : 'mus-uc');
+
+let failures = 0;
+function check(name, ok, detail) {
+  if (ok) {
+    console.log(`   ✓ ${name}`);
+  } else {
+    failures++;
+    console.error(`   ✗ ${name}${detail ? `\n${detail}` : ''}`);
+  }
+}
+
+function run(args) {
+  return spawnSync(exe, args, { encoding: 'utf8', timeout: 30000 });
+}
+
+/** Like `run`, but leaves the event loop free for the mock server. */
+function runAsync(args) {
+  return new Promise(resolve => {
+    const child = spawn(exe, args, { timeout: 30000 });
+    let stderr = '';
+    child.stderr.on('data', data => { stderr += data; });
+    child.on('close', status => resolve({ status, stderr }));
+  });
+}
+
+const browserInfo = {
+  name: 'Firefox',
+  app_id: '{ec8030f7-c20a-464f-9b0e-13a3a9e97384}',
+  version: '128.0',
+  platform_version: '128.0',
+  build_id: '20240701000000',
+  channel: 'release',
+  platform: process.platform === 'win32' ? 'WINNT' : 'Linux',
+  os_version: '',
+  abi: 'x86_64-gcc3',
+  headless: true,
+};
+
+/**
+ * Speaks enough Marionette for one command: the browser info script gets
+ * `browserInfo`, every other script returns a success, and the scripts are
+ * kept in `scripts`.
+ */
+function mockMarionette() {
+  const scripts = [];
+  const server = createServer(socket => {
+    socket.write('{"applicationType":"gecko","marionetteProtocol":3}\n');
+    let buffer = '';
+    socket.on('data', data => {
+      buffer += data.toString();
+      for (;;) {
+        const colon = buffer.indexOf(':');
+        if (colon < 0) return;
+        const length = Number(buffer.slice(0, colon));
+        if (buffer.length < colon + 1 + length) return;
+        const message = JSON.parse(buffer.slice(colon + 1, colon + 1 + length));
+        buffer = buffer.slice(colon + 1 + length);
+        if (message.name === 'WebDriver:ExecuteScript') {
+          scripts.push(message.parameters);
+        }
+        const value = message.parameters?.script?.includes('Services.appinfo.name')
+          ? browserInfo
+          : { success: true };
+        const reply = JSON.stringify({ value });
+        socket.write(`${Buffer.byteLength(reply)}:${reply}\n`);
+      }
+    });
+  });
+  return new Promise(resolve => {
+    server.listen(0, '127.0.0.1', () => resolve({ server, scripts, port: server.address().port }));
+  });
+}
+
+const root = realpathSync.native(mkdtempSync(join(tmpdir(), 'mus-uc paths #')));
+try {
+  console.log(`Testing paths under ${root}\n`);
+
+  const parts = join(root, 'parts');
+  mkdirSync(parts);
+  writeFileSync(join(parts, 'toolbar.css'), '#nav-bar {\r\n  background: url("icon one.png");\r\n}\r\n');
+  writeFileSync(join(parts, 'icon one.png'), Buffer.from([0x89, 0x50, 0x4e, 0x47]));
+  const partsUrl = pathToFileURL(join(parts, 'tabs.css')).href;
+  writeFileSync(join(parts, 'tabs.css'), '.tab { color: red; }\r\n');
+  writeFileSync(join(root, 'main.css'), [
+    '@import "parts/toolbar.css";',
+    `@import url("${partsUrl}");`,
+    '#urlbar { color: blue; }',
+    '',
+  ].join('\r\n'));
+
+  mkdirSync(join(root, 'out'));
+  const exported = run(['export', '-f', join(root, 'main.css'), '-o', join(root, 'out', 'bundle.css')]);
+  check('export succeeds', exported.status === 0, exported.stderr);
+  if (exported.status === 0) {
+    const bundle = readFileSync(join(root, 'out', 'bundle.css'), 'utf8');
+    check('relative import is bundled', bundle.includes('#nav-bar'), bundle);
+    check('file:// import is bundled', bundle.includes('.tab { color: red; }'), bundle);
+    check('asset URL is relative and encoded', bundle.includes('../parts/icon%20one.png'), bundle);
+  }
+
+  const manifest = join(root, 'chrome.manifest');
+  writeFileSync(manifest, 'content mytheme parts/\r\n');
+  const { server, scripts, port } = await mockMarionette();
+  const registered = await runAsync(['--port', String(port), 'register-manifest', '-m', manifest]);
+  server.close();
+  check('register-manifest succeeds', registered.status === 0, registered.stderr);
+  const register = scripts.find(s => s.script.includes('autoRegister'));
+  check('manifest path reaches initWithPath as a JSON string',
+    register && register.script.includes(`initWithPath(${JSON.stringify(manifest)})`),
+    register && register.script);
+  check('manifest path has no \\\\?\\ prefix',
+    register && !register.script.includes('\\\\\\\\?\\\\'));
+} finally {
+  rmSync(root, { recursive: true, force: true });
+}
+
+if (failures > 0) {
+  console.error(`\n${failures} check(s) failed`);
+  process.exit(1);
+}
+console.log('\nAll path checks passed');